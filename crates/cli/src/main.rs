@@ -1,26 +1,73 @@
+use beeno_core::artifacts::{
+    list_suggestions, load_suggestion, save_suggestion, BlockCache, SuggestionRecord,
+};
 use beeno_core::engine::{
-    execute_request, ContextSummarizer, DefaultRiskPolicy, Engine, EngineError,
-    RollingContextSummarizer,
+    analyze_permissions, classify_and_explain, classify_input, media_type_for_path,
+    module_extension_for_path, needs_confirmation, parse_js, permission_args, type_check_request,
+    ClassificationError, ContextSummarizer, DefaultRiskPolicy, Engine, EngineError, MediaType,
+    PermissionReport, PermissionTrigger, ProcessedScript, RiskPolicy, RollingContextSummarizer,
+};
+use beeno_core::heal::{
+    create_session_dir, review_fix, save_fix_artifact, unified_diff, AppliedFix, HealReport,
+    HEAL_DIR,
 };
+use beeno_core::parse_cache;
+use beeno_core::progress::{timing_line, Spinner};
 #[cfg(feature = "provider-http")]
 use beeno_core::providers::HttpProvider;
 #[cfg(feature = "provider-ollama")]
 use beeno_core::providers::OllamaProvider;
 #[cfg(feature = "provider-openai-compat")]
 use beeno_core::providers::OpenAICompatProvider;
-use beeno_core::providers::{MockProvider, TranslatorProvider};
-use beeno_core::repl::run_repl;
-use beeno_core::server::ServerManager;
+#[cfg(any(
+    feature = "provider-http",
+    feature = "provider-openai-compat",
+    feature = "provider-ollama"
+))]
+use beeno_core::providers::RateLimiter;
+use beeno_core::providers::COMMAND_PROMPT_TEMPLATE;
+use beeno_core::providers::EXPLAIN_MODE;
+#[cfg(feature = "provider-http")]
+use beeno_core::providers::HTTP_PROMPT_TEMPLATE;
+#[cfg(feature = "provider-ollama")]
+use beeno_core::providers::OLLAMA_PROMPT_TEMPLATE;
+#[cfg(feature = "provider-openai-compat")]
+use beeno_core::providers::OPENAI_PROMPT_TEMPLATE;
+use beeno_core::providers::{
+    is_loopback_endpoint, FallbackProvider, JavaScriptTargetProvider, LocalBinaryProvider,
+    MockProvider, PromptTemplate, ProviderError, TranslatorProvider,
+};
+#[cfg(feature = "provider-azure")]
+use beeno_core::providers::{AzureOpenAIProvider, AZURE_DEFAULT_API_VERSION};
+use beeno_core::repl::{
+    ambiguous_input_mode, refine_prompt, run_repl, update_server_env, ProviderSelection, ReplInput,
+};
+use beeno_core::rpc::serve_rpc;
+use beeno_core::server::{
+    offer_orphan_cleanup, server_pid_dir, server_pid_path, server_source_warning, ReadyCheck,
+    ServerManager,
+};
+use beeno_core::sourcemap::StackMapping;
 use beeno_core::types::{
-    AppConfig, DenoPermissions, ExecutionRequest, FileMetadata, JsonEnvelope, ServerContext,
-    SessionSummary,
+    fallback_entry, new_trace_id, parse_env_assignment, AppConfig, ArtifactConfig, ConfirmMode,
+    DenoPermissions, ExecutionFailure, ExecutionOutput, ExecutionRequest, FileMetadata,
+    JsonEnvelope, PermissionsConfig, RiskLevel, RiskReport, ServerContext, SessionSummary,
+    TargetLanguage, TranslateRequest, TranslateResult, Verbosity, Warning, WarningKind,
 };
+use beeno_core::util::{print_warnings, redact, register_secret};
+#[cfg(feature = "wasm-policy")]
+use beeno_core::wasm_policy::WasmPolicy;
 use clap::{Parser, Subcommand};
+use serde::Serialize;
 use serde_json::json;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::{self, Write};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use toml::Value;
 
 #[derive(Debug, Parser)]
@@ -32,6 +79,37 @@ use toml::Value;
 struct Cli {
     #[arg(long, global = true)]
     json: bool,
+    /// Log provider calls, policy decisions, and timings to stderr, and print
+    /// translation and execution times.
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+    /// Stream newline-delimited phase events to stderr, or to PATH with `--events=PATH`.
+    #[arg(
+        long,
+        global = true,
+        num_args = 0..=1,
+        require_equals = true,
+        value_name = "PATH"
+    )]
+    events: Option<Option<PathBuf>>,
+    /// Accept every confirmation prompt without reading stdin (also `BEENO_ASSUME_YES`).
+    #[arg(long, visible_alias = "no-confirm", global = true)]
+    yes: bool,
+    /// Apply the `[profiles.<name>]` config table (overrides `BEENO_PROFILE`).
+    #[arg(long, global = true)]
+    profile: Option<String>,
+    /// Only use providers on this machine; never fetch remote policies or open a browser
+    /// (also `BEENO_OFFLINE`).
+    #[arg(long, global = true)]
+    offline: bool,
+    /// Print only errors and the program's own output: no banners, status
+    /// acknowledgements, warnings, or `✓ executed` line.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Print the resolved policy before checking source and tag each reason with
+    /// the pattern list it came from.
+    #[arg(long, global = true)]
+    explain_policy: bool,
     #[command(subcommand)]
     cmd: Commands,
 }
@@ -42,6 +120,15 @@ enum Commands {
         #[arg(long, default_value_t = false)]
         force: bool,
     },
+    Doctor,
+    /// Explain whether input would be run as code or translated as pseudocode.
+    Classify {
+        input: String,
+    },
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
     Repl {
         #[arg(long)]
         provider: Option<String>,
@@ -49,9 +136,36 @@ enum Commands {
         model: Option<String>,
         #[arg(long)]
         policy: Option<PathBuf>,
+        /// Reload the session saved at `~/.beeno/session.json` (also `BEENO_RESUME`).
+        #[arg(long, default_value_t = false)]
+        resume: bool,
+        /// Replay REPL commands from a file (one per line, `#` comments), then exit.
+        #[arg(long)]
+        script: Option<PathBuf>,
+        /// Keep reading from stdin after the `--script` finishes.
+        #[arg(long, requires = "script", default_value_t = false)]
+        interactive: bool,
+        /// Sampling temperature for this invocation (0-2), overriding config and env.
+        #[arg(long, value_parser = parse_temperature)]
+        temperature: Option<f32>,
+        /// Completion token limit for this invocation, overriding config and env.
+        #[arg(long = "max-tokens", value_parser = clap::value_parser!(u32).range(1..))]
+        max_tokens: Option<u32>,
+        /// Ask before using more than this many completion tokens (0 disables the check).
+        #[arg(long = "max-tokens-warn")]
+        max_tokens_warn: Option<u32>,
     },
     Eval {
-        input: String,
+        /// Pseudocode or JS/TS to run, or `-` to read stdin.
+        input: Option<String>,
+        /// Read the input from this file (or `-` for stdin) as a single prompt.
+        #[arg(long = "input-file")]
+        input_file: Option<PathBuf>,
+        #[arg(long, default_value_t = false)]
+        explain: bool,
+        /// Ask the model for a plain-language plan and print it; nothing is generated or run.
+        #[arg(long = "explain-only", default_value_t = false)]
+        explain_only: bool,
         #[arg(long = "allow-read")]
         allow_read: Vec<String>,
         #[arg(long = "allow-write")]
@@ -62,9 +176,70 @@ enum Commands {
         allow_env: bool,
         #[arg(long = "allow-run", default_value_t = false)]
         allow_run: bool,
+        /// Sampling temperature for this invocation (0-2), overriding config and env.
+        #[arg(long, value_parser = parse_temperature)]
+        temperature: Option<f32>,
+        /// Completion token limit for this invocation, overriding config and env.
+        #[arg(long = "max-tokens", value_parser = clap::value_parser!(u32).range(1..))]
+        max_tokens: Option<u32>,
+        /// Ask before using more than this many completion tokens (0 disables the check).
+        #[arg(long = "max-tokens-warn")]
+        max_tokens_warn: Option<u32>,
+        /// Ignore `[permissions]` defaults and grant only the flags given here.
+        #[arg(long = "no-default-perms", default_value_t = false)]
+        no_default_perms: bool,
+        /// Set an environment variable on executed code (repeatable, `KEY=VALUE`).
+        #[arg(long = "env", value_parser = parse_env_assignment)]
+        env: Vec<(String, String)>,
+        /// Deno config file (`deno.json`) passed to Deno as `--config`.
+        #[arg(long = "deno-config")]
+        deno_config: Option<String>,
+        /// Import map passed to Deno as `--import-map`.
+        #[arg(long = "import-map")]
+        import_map: Option<String>,
+        /// Only allow local imports (Deno `--no-remote`).
+        #[arg(long = "no-remote")]
+        no_remote: bool,
+        /// Only allow remote imports already in Deno's cache (Deno `--cached-only`).
+        #[arg(long = "cached-only")]
+        cached_only: bool,
+        /// Execute the translated program this many times (translation runs once).
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        repeat: u32,
+        /// With `--repeat`, keep executing after a failed run.
+        #[arg(long = "keep-going", requires = "repeat")]
+        keep_going: bool,
+        /// Run generated code without showing it and asking first.
+        #[arg(long = "no-preview")]
+        no_preview: bool,
+        /// Print the permissions the code needs versus those granted, without running it.
+        #[arg(long)]
+        perms: bool,
+        /// Also write the program's stdout and stderr to this file.
+        #[arg(long, value_name = "FILE")]
+        tee: Option<PathBuf>,
+        /// Ask the provider to fix a failed run, overriding `self_heal.enabled`.
+        #[arg(long, conflicts_with = "no_heal")]
+        heal: bool,
+        /// Never attempt self-heal fixes for this run.
+        #[arg(long = "no-heal")]
+        no_heal: bool,
+        /// Fixes to request before giving up, overriding `self_heal.max_attempts`.
+        #[arg(long = "heal-attempts", value_parser = clap::value_parser!(u8).range(1..))]
+        heal_attempts: Option<u8>,
     },
     Run {
         file: PathBuf,
+        #[arg(long)]
+        out: Option<PathBuf>,
+        #[arg(long = "no-exec", default_value_t = false)]
+        no_exec: bool,
+        /// Translate every `/*nl` block again instead of reusing cached translations.
+        #[arg(long, default_value_t = false)]
+        refresh: bool,
+        /// Run `deno check` on the inlined script and refuse to execute it on type errors.
+        #[arg(long = "type-check", default_value_t = false)]
+        type_check: bool,
         #[arg(long = "allow-read")]
         allow_read: Vec<String>,
         #[arg(long = "allow-write")]
@@ -75,39 +250,273 @@ enum Commands {
         allow_env: bool,
         #[arg(long = "allow-run", default_value_t = false)]
         allow_run: bool,
+        /// Sampling temperature for this invocation (0-2), overriding config and env.
+        #[arg(long, value_parser = parse_temperature)]
+        temperature: Option<f32>,
+        /// Completion token limit for this invocation, overriding config and env.
+        #[arg(long = "max-tokens", value_parser = clap::value_parser!(u32).range(1..))]
+        max_tokens: Option<u32>,
+        /// Ask before using more than this many completion tokens (0 disables the check).
+        #[arg(long = "max-tokens-warn")]
+        max_tokens_warn: Option<u32>,
+        /// Ignore `[permissions]` defaults and grant only the flags given here.
+        #[arg(long = "no-default-perms", default_value_t = false)]
+        no_default_perms: bool,
+        /// Set an environment variable on executed code (repeatable, `KEY=VALUE`).
+        #[arg(long = "env", value_parser = parse_env_assignment)]
+        env: Vec<(String, String)>,
+        /// Deno config file (`deno.json`) passed to Deno as `--config`.
+        #[arg(long = "deno-config")]
+        deno_config: Option<String>,
+        /// Import map passed to Deno as `--import-map`.
+        #[arg(long = "import-map")]
+        import_map: Option<String>,
+        /// Only allow local imports (Deno `--no-remote`).
+        #[arg(long = "no-remote")]
+        no_remote: bool,
+        /// Only allow remote imports already in Deno's cache (Deno `--cached-only`).
+        #[arg(long = "cached-only")]
+        cached_only: bool,
+        /// Execute the translated program this many times (translation runs once).
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        repeat: u32,
+        /// With `--repeat`, keep executing after a failed run.
+        #[arg(long = "keep-going", requires = "repeat")]
+        keep_going: bool,
+        /// Run generated code without showing it and asking first.
+        #[arg(long = "no-preview")]
+        no_preview: bool,
+        /// Print the permissions the code needs versus those granted, without running it.
+        #[arg(long)]
+        perms: bool,
+        /// Also write the program's stdout and stderr to this file.
+        #[arg(long, value_name = "FILE")]
+        tee: Option<PathBuf>,
+        /// Ask the provider to fix a failed run, overriding `self_heal.enabled`.
+        #[arg(long, conflicts_with = "no_heal")]
+        heal: bool,
+        /// Never attempt self-heal fixes for this run.
+        #[arg(long = "no-heal")]
+        no_heal: bool,
+        /// Fixes to request before giving up, overriding `self_heal.max_attempts`.
+        #[arg(long = "heal-attempts", value_parser = clap::value_parser!(u8).range(1..))]
+        heal_attempts: Option<u8>,
+    },
+    ServeRpc,
+    /// List saved suggestions, newest first, or replay one.
+    History {
+        /// Show at most this many entries.
+        #[arg(long)]
+        limit: Option<usize>,
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+    },
+    /// Run the risk policy over JS/TS source without translating or executing it.
+    Analyze {
+        /// Source file to check, or `-` to read stdin.
+        file: Option<PathBuf>,
+        /// Inline source to check instead of a file.
+        #[arg(long, conflicts_with = "file")]
+        input: Option<String>,
+        #[arg(long)]
+        policy: Option<PathBuf>,
+    },
+    /// Translate a prompt set through several providers and compare the results.
+    Bench {
+        /// Newline-delimited pseudocode prompts; blank lines are skipped.
+        prompts: PathBuf,
+        /// Providers to compare as `name` or `name:model` (default: the configured provider).
+        #[arg(long = "provider")]
+        providers: Vec<String>,
+    },
+    /// List the models the configured provider's endpoint offers.
+    Models {
+        /// Query this provider instead of `llm.provider`.
+        #[arg(long)]
+        provider: Option<String>,
+    },
+    /// Generate a small multi-file project from one pseudocode prompt.
+    Scaffold {
+        prompt: String,
+        /// Directory the generated files are written into.
+        #[arg(long)]
+        out: PathBuf,
+        /// Overwrite files that already exist in `--out`.
+        #[arg(long, default_value_t = false)]
+        force: bool,
     },
     Dev {
         #[arg(long)]
         file: Option<PathBuf>,
+        /// Port passed to the server as `PORT`; `0` picks a free port.
         #[arg(long, default_value_t = 8080)]
         port: u16,
         #[arg(long, default_value_t = false)]
         open: bool,
+        /// Wait for `GET <path>` to answer before reporting the server as running.
+        #[arg(long = "ready-path")]
+        ready_path: Option<String>,
+        /// The server is a WebSocket endpoint: verify readiness with an upgrade handshake
+        /// (on `--ready-path`, default `/`).
+        #[arg(long, default_value_t = false)]
+        ws: bool,
+        /// Set an environment variable on executed code (repeatable, `KEY=VALUE`).
+        #[arg(long = "env", value_parser = parse_env_assignment)]
+        env: Vec<(String, String)>,
+        /// Deno config file (`deno.json`) passed to Deno as `--config`.
+        #[arg(long = "deno-config")]
+        deno_config: Option<String>,
+        /// Import map passed to Deno as `--import-map`.
+        #[arg(long = "import-map")]
+        import_map: Option<String>,
+        /// Only allow local imports (Deno `--no-remote`).
+        #[arg(long = "no-remote")]
+        no_remote: bool,
+        /// Only allow remote imports already in Deno's cache (Deno `--cached-only`).
+        #[arg(long = "cached-only")]
+        cached_only: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum HistoryAction {
+    /// Execute a saved suggestion's code after confirmation.
+    Replay {
+        id: String,
+        #[arg(long = "allow-read")]
+        allow_read: Vec<String>,
+        #[arg(long = "allow-write")]
+        allow_write: Vec<String>,
+        #[arg(long = "allow-net")]
+        allow_net: Vec<String>,
+        #[arg(long = "allow-env", default_value_t = false)]
+        allow_env: bool,
+        #[arg(long = "allow-run", default_value_t = false)]
+        allow_run: bool,
     },
 }
 
+#[derive(Debug, Subcommand)]
+enum ConfigAction {
+    /// Print the fully-resolved configuration.
+    Show,
+    /// Print config file locations in precedence order.
+    Path,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    let result = run(Cli::parse()).await;
+    // A failed Deno run exits with the child's own code so CI sees the real status,
+    // and a missing Deno binary with RUNTIME_MISSING_EXIT_CODE.
+    if let Some(code) = result.as_ref().err().and_then(child_exit_code) {
+        eprintln!("Error: {:?}", result.unwrap_err());
+        std::process::exit(code);
+    }
+    result
+}
 
-    match cli.cmd {
-        Commands::InitConfig { force } => {
-            init_config_file(Path::new(".beeno.toml"), force)?;
-            println!("initialized .beeno.toml");
-            return Ok(());
+/// Exit code when `deno` is not installed, matching the shell's "command not found".
+const RUNTIME_MISSING_EXIT_CODE: i32 = 127;
+
+/// Deno's exit code when `err` is a failed run, or [`RUNTIME_MISSING_EXIT_CODE`]
+/// when Deno could not be found, rather than a Beeno error.
+fn child_exit_code(err: &anyhow::Error) -> Option<i32> {
+    match err.downcast_ref::<EngineError>()? {
+        EngineError::RuntimeFailure(failure) => failure.exit_code.filter(|code| *code != 0),
+        EngineError::RuntimeMissing => Some(RUNTIME_MISSING_EXIT_CODE),
+        _ => None,
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    init_tracing(cli.verbose);
+    beeno_core::prompt::set_assume_yes(
+        cli.yes
+            || std::env::var("BEENO_ASSUME_YES")
+                .ok()
+                .and_then(|v| parse_bool(&v))
+                .unwrap_or(false),
+    );
+
+    if let Commands::InitConfig { force } = cli.cmd {
+        init_config_file(Path::new(".beeno.toml"), force)?;
+        println!("initialized .beeno.toml");
+        return Ok(());
+    }
+
+    if let Commands::Doctor = cli.cmd {
+        let checks = doctor_checks(
+            &load_config(cli.profile.as_deref()),
+            |k| std::env::var(k).ok(),
+            deno_version(),
+        );
+        print_doctor_report(&checks, cli.json)?;
+        if checks.iter().any(|c| c.critical && !c.ok) {
+            std::process::exit(1);
         }
-        _ => {}
+        return Ok(());
+    }
+
+    if let Commands::Classify { input } = &cli.cmd {
+        let classification = classify_and_explain(input);
+        if cli.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&JsonEnvelope::new(
+                    "ok",
+                    "classify",
+                    classification.reason.clone(),
+                    json!(classification),
+                ))?
+            );
+        } else {
+            println!("{}", classification.reason);
+        }
+        return Ok(());
+    }
+
+    if let Commands::Config { action } = &cli.cmd {
+        match action {
+            ConfigAction::Show => show_config(
+                &load_config(cli.profile.as_deref())?,
+                |k| std::env::var(k).ok(),
+                cli.json,
+            )?,
+            ConfigAction::Path => show_config_paths(cli.json)?,
+        }
+        return Ok(());
     }
 
-    let mut cfg = load_config()?;
+    let mut cfg = load_config(cli.profile.as_deref())?;
+    cfg.offline |= cli.offline;
+    cfg.policy.explain |= cli.explain_policy;
+    cfg.verbosity = Verbosity::from_flags(cli.quiet, cli.verbose);
+    if let Ok(key) = std::env::var(&cfg.llm.api_key_env_var) {
+        register_secret(&key);
+    }
+    let events = EventSink::new(cli.events.clone(), &cfg.protect.deny)?;
 
     match cli.cmd {
-        Commands::InitConfig { .. } => {}
+        Commands::InitConfig { .. }
+        | Commands::Doctor
+        | Commands::Classify { .. }
+        | Commands::Config { .. } => {}
         Commands::Repl {
             provider,
             model,
             policy,
+            resume,
+            script,
+            interactive,
+            temperature,
+            max_tokens,
+            max_tokens_warn,
         } => {
+            apply_llm_flags(&mut cfg, temperature, max_tokens, max_tokens_warn);
+            if resume {
+                cfg.repl.resume = true;
+            }
             if let Some(p) = provider {
                 cfg.llm.provider = p;
             }
@@ -118,17 +527,70 @@ async fn main() -> anyhow::Result<()> {
                 cfg.policy.policy_path = Some(path.to_string_lossy().to_string());
             }
 
-            let provider = build_provider(&cfg, |k| std::env::var(k).ok());
-            run_repl(provider, cfg.policy.confirm_risky).await?;
+            let input = match script {
+                Some(path) => ReplInput::script(fs::read_to_string(&path)?, interactive),
+                None => ReplInput::default(),
+            };
+            let provider = build_provider(&cfg, |k| std::env::var(k).ok())?;
+            let selection = ProviderSelection {
+                provider: cfg.llm.provider.clone(),
+                model: cfg.llm.resolve_model(&cfg.llm.model).to_string(),
+            };
+            run_repl(
+                provider,
+                &cfg,
+                selection,
+                |next| {
+                    let mut next_cfg = cfg.clone();
+                    next_cfg.llm.provider = next.provider.clone();
+                    next_cfg.llm.model = next.model.clone();
+                    build_provider(&next_cfg, |k| std::env::var(k).ok())
+                },
+                input,
+            )
+            .await?;
         }
         Commands::Eval {
             input,
+            input_file,
+            explain,
+            explain_only,
             allow_read,
             allow_write,
             allow_net,
             allow_env,
             allow_run,
+            temperature,
+            max_tokens,
+            max_tokens_warn,
+            no_default_perms,
+            env,
+            deno_config,
+            import_map,
+            no_remote,
+            cached_only,
+            repeat,
+            keep_going,
+            no_preview,
+            perms,
+            tee,
+            heal,
+            no_heal,
+            heal_attempts,
         } => {
+            apply_llm_flags(&mut cfg, temperature, max_tokens, max_tokens_warn);
+            apply_heal_flags(&mut cfg, heal, no_heal, heal_attempts);
+            let input = read_eval_input(input, input_file.as_deref(), &mut io::stdin().lock())?;
+            if explain_only {
+                return explain_with_provider(&cfg, &input, cli.json).await;
+            }
+            apply_runtime_flags(&mut cfg, env, deno_config, import_map);
+            cfg.runtime.no_remote |= no_remote;
+            cfg.runtime.cached_only |= cached_only;
+            if no_default_perms {
+                cfg.permissions = PermissionsConfig::default();
+            }
+            let tee = start_tee(tee, &cfg.protect.deny)?;
             execute_with_provider(
                 &cfg,
                 &input,
@@ -141,18 +603,81 @@ async fn main() -> anyhow::Result<()> {
                     allow_env,
                     allow_run,
                 },
-                cli.json,
+                &PipelineOptions {
+                    json_output: cli.json,
+                    explain,
+                    confirm_mode: cfg.policy.effective_confirm_mode(),
+                    min_confidence: cfg.policy.min_confidence,
+                    events: events.clone(),
+                    success_line: show_success_line(cli.json, cli.quiet),
+                    spinner: !cli.json && !cli.quiet,
+                    preview: show_preview(
+                        cli.json,
+                        no_preview,
+                        cfg.policy.effective_confirm_mode(),
+                    ),
+                    env: cfg.runtime.resolved_env(|k| std::env::var(k).ok()),
+                    deno_config: cfg.runtime.deno_config.clone(),
+                    import_map: cfg.runtime.import_map.clone(),
+                    no_remote: cfg.runtime.no_remote,
+                    cached_only: cfg.runtime.cached_only,
+                    artifacts: cfg.artifacts.clone(),
+                    repeat: Repeat { repeat, keep_going },
+                    perms,
+                    tee,
+                    deny: cfg.protect.deny.clone(),
+                    heal: HealOptions::from_config(&cfg),
+                    verbosity: cfg.verbosity,
+                },
             )
-            .await?;
+            .await
+            .inspect_err(|e| events.emit_error(e))?;
         }
         Commands::Run {
             file,
+            out,
+            no_exec,
+            refresh,
+            type_check,
             allow_read,
             allow_write,
             allow_net,
             allow_env,
             allow_run,
+            temperature,
+            max_tokens,
+            max_tokens_warn,
+            no_default_perms,
+            env,
+            deno_config,
+            import_map,
+            no_remote,
+            cached_only,
+            repeat,
+            keep_going,
+            no_preview,
+            perms,
+            tee,
+            heal,
+            no_heal,
+            heal_attempts,
         } => {
+            apply_llm_flags(&mut cfg, temperature, max_tokens, max_tokens_warn);
+            apply_heal_flags(&mut cfg, heal, no_heal, heal_attempts);
+            apply_runtime_flags(&mut cfg, env, deno_config, import_map);
+            cfg.runtime.no_remote |= no_remote;
+            cfg.runtime.cached_only |= cached_only;
+            if no_default_perms {
+                cfg.permissions = PermissionsConfig::default();
+            }
+            cfg.runtime.type_check_before_run |= type_check;
+            if cfg.runtime.deno_config.is_none() {
+                cfg.runtime.deno_config = sibling_deno_config(&file);
+            }
+            if let Some(path) = &out {
+                ensure_not_protected(path, &cfg.protect.deny)?;
+            }
+            let tee = start_tee(tee, &cfg.protect.deny)?;
             let script = fs::read_to_string(&file)?;
             execute_run_with_provider(
                 &cfg,
@@ -165,63 +690,301 @@ async fn main() -> anyhow::Result<()> {
                     allow_env,
                     allow_run,
                 },
-                cli.json,
+                &RunOptions {
+                    out,
+                    no_exec,
+                    refresh,
+                    json_output: cli.json,
+                    events: events.clone(),
+                    success_line: show_success_line(cli.json, cli.quiet),
+                    spinner: !cli.json && !cli.quiet,
+                    preview: show_preview(
+                        cli.json,
+                        no_preview,
+                        cfg.policy.effective_confirm_mode(),
+                    ),
+                    repeat: Repeat { repeat, keep_going },
+                    perms,
+                    tee,
+                    heal: HealOptions::from_config(&cfg),
+                },
+            )
+            .await
+            .inspect_err(|e| events.emit_error(e))?;
+        }
+        Commands::ServeRpc => {
+            let provider = build_provider(&cfg, |k| std::env::var(k).ok())?;
+            let engine = Engine::new(provider, policy_from_cfg(&cfg).await?)
+                .with_limits(cfg.limits.clone())
+                .with_auto_continue(cfg.llm.auto_continue)
+                .with_prelude(cfg.runtime.prelude.clone());
+            serve_rpc(
+                &engine,
+                tokio::io::BufReader::new(tokio::io::stdin()),
+                tokio::io::stdout(),
             )
             .await?;
         }
-        Commands::Dev { file, port, open } => {
-            run_dev_with_provider(&cfg, file, port, open).await?;
+        Commands::History { limit, action } => match action {
+            None => show_history(&cfg.artifacts, limit, cli.json)?,
+            Some(HistoryAction::Replay {
+                id,
+                allow_read,
+                allow_write,
+                allow_net,
+                allow_env,
+                allow_run,
+            }) => {
+                replay_suggestion(
+                    &cfg,
+                    &id,
+                    DenoPermissions {
+                        allow_read,
+                        allow_write,
+                        allow_net,
+                        allow_env,
+                        allow_run,
+                    },
+                )
+                .await?
+            }
+        },
+        Commands::Analyze {
+            file,
+            input,
+            policy,
+        } => {
+            if let Some(path) = policy {
+                cfg.policy.policy_path = Some(path.to_string_lossy().to_string());
+            }
+            let source = read_analyze_source(file.as_deref(), input, &mut io::stdin().lock())?;
+            let media_type = file
+                .as_deref()
+                .map_or(MediaType::TypeScript, media_type_for_path);
+            let risk = policy_from_cfg(&cfg)
+                .await?
+                .analyze_with_media(&source, media_type)
+                .await;
+            print_risk_report(&risk, cli.json, cfg.policy.explain)?;
+            if risk.level == RiskLevel::Blocked {
+                std::process::exit(1);
+            }
+        }
+        Commands::Bench { prompts, providers } => {
+            run_bench(&cfg, &prompts, &providers, cli.json).await?;
+        }
+        Commands::Models { provider } => {
+            if let Some(p) = provider {
+                cfg.llm.provider = p;
+            }
+            list_provider_models(&cfg, cli.json).await?;
+        }
+        Commands::Scaffold { prompt, out, force } => {
+            run_scaffold(&cfg, &prompt, &out, force, cli.json).await?;
+        }
+        Commands::Dev {
+            file,
+            port,
+            open,
+            ready_path,
+            ws,
+            env,
+            deno_config,
+            import_map,
+            no_remote,
+            cached_only,
+        } => {
+            apply_runtime_flags(&mut cfg, env, deno_config, import_map);
+            cfg.runtime.no_remote |= no_remote;
+            cfg.runtime.cached_only |= cached_only;
+            if let (None, Some(path)) = (&cfg.runtime.deno_config, &file) {
+                cfg.runtime.deno_config = sibling_deno_config(path);
+            }
+            run_dev_with_provider(
+                &cfg,
+                file,
+                port,
+                open,
+                ready_check(ready_path, ws),
+                events.clone(),
+            )
+            .await
+            .inspect_err(|e| events.emit_error(e))?;
         }
     }
 
     Ok(())
 }
 
+/// Installs a stderr subscriber; `RUST_LOG` wins, otherwise `--verbose` enables debug output.
+fn init_tracing(verbose: bool) {
+    let default_filter = if verbose {
+        "beeno=debug,beeno_core=debug"
+    } else {
+        "warn"
+    };
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(default_filter));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(|| RedactedStderr)
+        .try_init();
+}
+
+/// Log writer that masks secrets (see [`redact`]) before they reach stderr.
+struct RedactedStderr;
+
+impl Write for RedactedStderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stderr().write_all(redact(&String::from_utf8_lossy(buf)).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// The dev server readiness check selected by `--ready-path` / `--ws`.
+fn ready_check(ready_path: Option<String>, ws: bool) -> ReadyCheck {
+    let ready_path = ready_path.map(|path| {
+        if path.starts_with('/') {
+            path
+        } else {
+            format!("/{path}")
+        }
+    });
+    match (ready_path, ws) {
+        (path, true) => ReadyCheck::WebSocket {
+            path: path.unwrap_or_else(|| "/".to_string()),
+        },
+        (Some(path), false) => ReadyCheck::HttpGet { path },
+        (None, false) => ReadyCheck::TcpConnect,
+    }
+}
+
+/// Arguments after `command` in a dev-loop `line`, or `None` when the line is a
+/// different command that only starts with the same text (`/healthz`).
+fn command_args<'a>(line: &'a str, command: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(command)?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then_some(rest)
+}
+
 async fn run_dev_with_provider(
     cfg: &AppConfig,
     file: Option<PathBuf>,
     port: u16,
     open: bool,
+    ready_check: ReadyCheck,
+    events: EventSink,
 ) -> anyhow::Result<()> {
-    let provider = build_provider(cfg, |k| std::env::var(k).ok());
-    let engine = Engine::new(provider, policy_from_cfg(cfg)?);
-    let mut summarizer = RollingContextSummarizer::new(cfg.repl.summary_window);
-    let mut server_manager = ServerManager::default();
+    let websocket = match &ready_check {
+        ReadyCheck::WebSocket { path } => Some(path.clone()),
+        _ => None,
+    };
+    let provider = build_provider(cfg, |k| std::env::var(k).ok())?;
+    let engine = Engine::new(provider, policy_from_cfg(cfg).await?)
+        .with_limits(cfg.limits.clone())
+        .with_auto_continue(cfg.llm.auto_continue)
+        .with_prelude(cfg.runtime.prelude.clone());
+    let mut summarizer = RollingContextSummarizer::new(cfg.repl.summary_window)
+        .with_char_limits(cfg.repl.max_entry_chars, cfg.repl.max_summary_chars);
+    if let Some(dir) = server_pid_dir() {
+        offer_orphan_cleanup(&dir)?;
+    }
+    let mut server_manager = ServerManager::default()
+        .with_stop_grace(Duration::from_millis(cfg.timeouts.server_grace_ms))
+        .with_env(cfg.runtime.resolved_env(|k| std::env::var(k).ok()))
+        .with_deno_config(
+            cfg.runtime.deno_config.clone(),
+            cfg.runtime.import_map.clone(),
+        )
+        .with_remote_imports(cfg.runtime.no_remote, cfg.runtime.cached_only)
+        .with_ready_check(ready_check)
+        .with_pid_file(server_pid_path());
 
     let (initial_code, mode) = match file {
         Some(path) => {
             let script = fs::read_to_string(&path)?;
-            if script.contains("/*nl") {
-                let (processed, warnings) = engine
+            let tagged_blocks = script.matches("/*nl").count();
+            events.emit(
+                "classify",
+                "dev source classified",
+                json!({"source": "file", "file": path, "tagged_blocks": tagged_blocks}),
+            )?;
+            if tagged_blocks > 0 {
+                let processed = engine
                     .process_tagged_script(
                         &script,
                         current_summary_with_server(&mut summarizer, &mut server_manager),
                         Some(path.to_string_lossy().to_string()),
+                        None,
                     )
                     .await
                     .map_err(render_engine_error)?;
-                for warning in warnings {
-                    eprintln!("warning: {warning}");
-                }
-                (processed, "file-nl".to_string())
+                print_warnings(&processed.warnings, !cfg.verbosity.chatty());
+                events.emit(
+                    "translate",
+                    "tagged blocks translated",
+                    json!({"tagged_blocks": tagged_blocks, "warnings": processed.warnings}),
+                )?;
+                events.emit(
+                    "policy",
+                    "translated blocks passed policy",
+                    json!({"checked_blocks": tagged_blocks}),
+                )?;
+                (processed.source, "file-nl".to_string())
             } else {
                 (script, "file".to_string())
             }
         }
-        None => (default_dev_server_source(), "scaffold".to_string()),
+        None => {
+            events.emit(
+                "classify",
+                "dev source classified",
+                json!({"source": "scaffold"}),
+            )?;
+            (default_dev_server_source(), "scaffold".to_string())
+        }
     };
 
+    events.emit(
+        "execute-start",
+        "starting dev server",
+        json!({"port": port, "mode": mode}),
+    )?;
+    let warnings: Vec<Warning> = server_source_warning(&initial_code).into_iter().collect();
+    print_warnings(&warnings, !cfg.verbosity.chatty());
+    let started = Instant::now();
     let status = server_manager
         .start_with_code(initial_code, port, &mode)
         .await?;
-    println!("Beeno Dev");
-    println!("server running at {}", status.url);
-    println!("type /help for dev commands");
+    events.emit(
+        "execute-end",
+        "dev server running",
+        json!({"url": status.url, "port": status.port, "mode": status.mode}),
+    )?;
+    // With `--port 0`, restarts keep the port that was picked.
+    let port = status.port;
+    // The last `/hotfix-nl` prompt, regenerated by `/serve-restart-nl`.
+    let mut last_nl_prompt: Option<String> = None;
+    if cfg.verbosity.chatty() {
+        println!("Beeno Dev");
+        println!("server running at {}", status.url);
+        if let Some(path) = &websocket {
+            println!("websocket endpoint: ws://127.0.0.1:{port}{path}");
+        }
+        if cfg.verbosity.is_verbose() {
+            println!("server ready after {:.1}s", started.elapsed().as_secs_f64());
+        }
+        println!("type /help for dev commands");
+    } else {
+        // Quiet mode still prints the address, bare, for scripts using `--port 0`.
+        println!("{}", status.url);
+    }
 
-    if open {
-        open_in_browser(&status.url)?;
-    } else if prompt_confirm("open hosted webpage in your default browser?")? {
-        open_in_browser(&status.url)?;
+    if open || (!cfg.offline && prompt_confirm("open hosted webpage in your default browser?")?) {
+        open_url(cfg, &status.url)?;
     }
 
     loop {
@@ -248,15 +1011,69 @@ async fn run_dev_with_provider(
         if line == "/status" {
             if let Some(s) = server_manager.status() {
                 println!("running: {} ({})", s.url, s.mode);
+            } else if let Some(err) = server_manager.last_error() {
+                println!("server is stopped (last error: {err})");
             } else {
                 println!("server is stopped");
             }
             continue;
         }
 
+        if let Some(args) = command_args(line, "/perms") {
+            let args = args.trim();
+            if !args.is_empty() {
+                let (grant, value) = args.split_once(' ').unwrap_or((args, ""));
+                let mut perms = server_manager.permissions().clone();
+                if let Err(e) = perms.set_grant(grant, value) {
+                    println!("{e}");
+                    println!("usage: /perms <read|write|net|env|run> <on|off|list>");
+                    continue;
+                }
+                server_manager.set_permissions(perms);
+                println!("permissions updated; applied on next restart or hotfix");
+            }
+            println!(
+                "server permissions: {}",
+                permission_args(server_manager.permissions()).join(" ")
+            );
+            continue;
+        }
+
+        if line == "/serve-env-clear" {
+            server_manager.clear_extra_env();
+            if cfg.verbosity.chatty() {
+                println!("server env cleared; applied on next restart or hotfix");
+            }
+            continue;
+        }
+
+        if let Some(args) = command_args(line, "/serve-env") {
+            update_server_env(&mut server_manager, args.trim());
+            continue;
+        }
+
+        if let Some(path) = command_args(line, "/health") {
+            match server_manager.health(path).await {
+                Ok(report) => println!("{report}"),
+                Err(e) => println!("error: {e}"),
+            }
+            continue;
+        }
+
+        if line == "/logs" {
+            let logs = server_manager.logs();
+            if logs.is_empty() {
+                println!("no server output captured yet");
+            }
+            for log_line in logs {
+                println!("{log_line}");
+            }
+            continue;
+        }
+
         if line == "/open" {
             if let Some(s) = server_manager.status() {
-                open_in_browser(&s.url)?;
+                open_url(cfg, &s.url)?;
             } else {
                 println!("server is stopped");
             }
@@ -265,7 +1082,30 @@ async fn run_dev_with_provider(
 
         if line == "/stop" {
             server_manager.stop().await?;
-            println!("server stopped");
+            if cfg.verbosity.chatty() {
+                println!("server stopped");
+            }
+            continue;
+        }
+
+        if line == "/undo" || line == "/redo" {
+            let result = if line == "/undo" {
+                server_manager.undo().await
+            } else {
+                server_manager.redo().await
+            };
+            match result {
+                Ok(Some(s)) => {
+                    let (undo, redo) = server_manager.history_depth();
+                    println!(
+                        "server restarted with {} source: {} ({undo} undo, {redo} redo left)",
+                        if line == "/undo" { "previous" } else { "next" },
+                        s.url
+                    );
+                }
+                Ok(None) => println!("nothing to {}", &line[1..]),
+                Err(e) => println!("error: {e}"),
+            }
             continue;
         }
 
@@ -293,41 +1133,66 @@ async fn run_dev_with_provider(
             continue;
         }
 
-        if let Some(code) = line.strip_prefix("/hotfix-js") {
+        if let Some(code) = command_args(line, "/hotfix-js") {
             let src = code.trim();
             if src.is_empty() {
                 println!("usage: /hotfix-js <code>");
                 continue;
             }
-            let s = server_manager
+            let warnings: Vec<Warning> = server_source_warning(src).into_iter().collect();
+            print_warnings(&warnings, !cfg.verbosity.chatty());
+            match server_manager
                 .hotfix_with_code(src.to_string(), "js-hotfix")
-                .await?;
-            summarizer.update(src).await;
-            println!("hotfix applied: {}", s.url);
+                .await
+            {
+                Ok(s) => {
+                    summarizer.update(src).await;
+                    println!("hotfix applied: {}", s.url);
+                }
+                Err(e) => println!("error: {e}"),
+            }
             continue;
         }
 
-        if let Some(prompt) = line.strip_prefix("/hotfix-nl") {
+        let nl_request = if let Some(hint) = command_args(line, "/serve-restart-nl") {
+            let Some(previous) = &last_nl_prompt else {
+                println!("no previous /hotfix-nl prompt to regenerate the server from");
+                continue;
+            };
+            Some((refine_prompt(previous, hint.trim()), "nl-restart"))
+        } else if let Some(prompt) = command_args(line, "/hotfix-nl") {
             let src = prompt.trim();
             if src.is_empty() {
                 println!("usage: /hotfix-nl <prompt>");
                 continue;
             }
+            Some((src.to_string(), "nl-hotfix"))
+        } else {
+            None
+        };
+        if let Some((src, source_mode)) = nl_request {
+            let src = src.as_str();
             let summary = current_summary_with_server(&mut summarizer, &mut server_manager);
-            let (code, _, risk) = engine
-                .prepare_source(src, "force_nl", summary, None)
+            let (code, _, risk, mut warnings) = engine
+                .prepare_source(src, "force_nl", summary, None, None)
                 .await
                 .map_err(render_engine_error)?;
-            if risk.requires_confirmation
-                && cfg.policy.confirm_risky
-                && !prompt_confirm("risky hotfix generated, apply?")?
+            if needs_confirmation(cfg.policy.effective_confirm_mode(), &risk, &code)
+                && !prompt_confirm("hotfix generated, apply?")?
             {
                 println!("hotfix skipped");
                 continue;
             }
-            let s = server_manager.hotfix_with_code(code, "nl-hotfix").await?;
-            summarizer.update(src).await;
-            println!("hotfix applied: {}", s.url);
+            warnings.extend(server_source_warning(&code));
+            print_warnings(&warnings, !cfg.verbosity.chatty());
+            match server_manager.hotfix_with_code(code, source_mode).await {
+                Ok(s) => {
+                    summarizer.update(src).await;
+                    last_nl_prompt = Some(src.to_string());
+                    println!("hotfix applied: {}", s.url);
+                }
+                Err(e) => println!("error: {e}"),
+            }
             continue;
         }
 
@@ -338,6 +1203,147 @@ async fn run_dev_with_provider(
     Ok(())
 }
 
+/// Single environment check reported by `beeno doctor`.
+#[derive(Debug, Serialize)]
+struct DoctorCheck {
+    name: String,
+    ok: bool,
+    critical: bool,
+    detail: String,
+}
+
+impl DoctorCheck {
+    fn new(name: &str, ok: bool, critical: bool, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            ok,
+            critical,
+            detail: detail.into(),
+        }
+    }
+}
+
+fn deno_version() -> Option<String> {
+    let output = Command::new("deno").arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(|line| line.trim().to_string())
+}
+
+fn doctor_checks<F>(
+    cfg: &anyhow::Result<AppConfig>,
+    env_get: F,
+    deno_version: Option<String>,
+) -> Vec<DoctorCheck>
+where
+    F: Fn(&str) -> Option<String> + Copy,
+{
+    let mut checks = vec![match deno_version {
+        Some(version) => DoctorCheck::new("deno", true, true, version),
+        None => DoctorCheck::new(
+            "deno",
+            false,
+            true,
+            "deno binary not found on PATH; install from https://deno.land",
+        ),
+    }];
+
+    let cfg = match cfg {
+        Ok(cfg) => {
+            checks.push(DoctorCheck::new(
+                "config",
+                true,
+                true,
+                "configuration parsed",
+            ));
+            cfg
+        }
+        Err(e) => {
+            checks.push(DoctorCheck::new("config", false, true, e.to_string()));
+            return checks;
+        }
+    };
+
+    let endpoint = resolve_provider_endpoint(cfg, env_get)
+        .or_else(|| cfg.llm.endpoint_for(&cfg.llm.provider).map(str::to_string))
+        .unwrap_or_else(|| "(provider default)".to_string());
+    checks.push(DoctorCheck::new(
+        "provider",
+        true,
+        false,
+        format!(
+            "{} (model: {}, endpoint: {endpoint})",
+            cfg.llm.provider, cfg.llm.model
+        ),
+    ));
+
+    let provider = cfg.llm.provider.to_ascii_lowercase();
+    let key_var = &cfg.llm.api_key_env_var;
+    let key_set = env_get(key_var).is_some_and(|v| !v.trim().is_empty());
+    let key_required = matches!(
+        provider.as_str(),
+        "chatgpt" | "openrouter" | "openai_compat" | "azure"
+    );
+    if matches!(provider.as_str(), "mock" | "ollama") {
+        checks.push(DoctorCheck::new(
+            "api key",
+            true,
+            false,
+            format!("not required for provider `{provider}`"),
+        ));
+    } else if key_set {
+        checks.push(DoctorCheck::new(
+            "api key",
+            true,
+            key_required,
+            format!("{key_var} is set"),
+        ));
+    } else {
+        checks.push(DoctorCheck::new(
+            "api key",
+            false,
+            key_required,
+            format!("{key_var} is not set"),
+        ));
+    }
+
+    checks
+}
+
+fn print_doctor_report(checks: &[DoctorCheck], json_output: bool) -> anyhow::Result<()> {
+    let healthy = !checks.iter().any(|c| c.critical && !c.ok);
+    if json_output {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                if healthy { "ok" } else { "error" },
+                "doctor",
+                if healthy {
+                    "environment looks good"
+                } else {
+                    "critical checks failed"
+                },
+                json!({ "checks": checks }),
+            ))?
+        );
+        return Ok(());
+    }
+
+    for check in checks {
+        let mark = match (check.ok, check.critical) {
+            (true, _) => "ok  ",
+            (false, true) => "FAIL",
+            (false, false) => "warn",
+        };
+        println!("[{mark}] {}: {}", check.name, check.detail);
+    }
+    Ok(())
+}
+
 fn default_dev_server_source() -> String {
     r#"const port = Number(Deno.env.get("PORT") ?? "8080");
 Deno.serve({ port }, () => new Response("Beeno dev server running"));
@@ -349,12 +1355,20 @@ fn print_dev_help() {
     println!("Beeno Dev Commands");
     println!("  /help                    show command list");
     println!("  /status                  show server status");
+    println!("  /logs                    show recent server output");
+    println!("  /health [path]           GET a server path (default /) and show the response");
+    println!("  /perms [grant value]     show or set server permissions (e.g. write on)");
+    println!("  /serve-env [KEY=VALUE]   show or set a server environment variable");
+    println!("  /serve-env-clear         drop variables set with /serve-env");
     println!("  /open                    open current server URL in browser");
     println!("  /restart                 restart server with current source");
     println!("  /hotfix-js <code>        hotfix server using JS/TS");
     println!("  /hotfix-nl <prompt>      hotfix server using LLM translation");
+    println!("  /serve-restart-nl [hint] regenerate from the last /hotfix-nl prompt and restart");
     println!("  /stop                    stop server");
     println!("  /start                   start stopped server with last source");
+    println!("  /undo                    restart server with the previous source");
+    println!("  /redo                    re-apply the source reverted by /undo");
     println!("  /quit                    exit dev mode");
 }
 
@@ -373,11 +1387,16 @@ fn current_summary_with_server(
 }
 
 fn prompt_confirm(prompt: &str) -> anyhow::Result<bool> {
-    print!("{prompt} [y/N]: ");
-    io::stdout().flush()?;
-    let mut answer = String::new();
-    io::stdin().read_line(&mut answer)?;
-    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "YES"))
+    Ok(beeno_core::prompt::confirm(prompt)?)
+}
+
+/// Opens `url` in a browser unless offline mode forbids it.
+fn open_url(cfg: &AppConfig, url: &str) -> anyhow::Result<()> {
+    if cfg.offline {
+        println!("offline mode: not opening a browser; visit {url}");
+        return Ok(());
+    }
+    open_in_browser(url)
 }
 
 fn open_in_browser(url: &str) -> anyhow::Result<()> {
@@ -409,507 +1428,3833 @@ fn open_in_browser(url: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn execute_with_provider(
-    cfg: &AppConfig,
-    input: &str,
-    mode: &str,
-    file_metadata: Option<FileMetadata>,
-    permissions: DenoPermissions,
-    json_output: bool,
-) -> anyhow::Result<()> {
-    let provider = build_provider(cfg, |k| std::env::var(k).ok());
-    execute_pipeline(
-        Engine::new(provider, policy_from_cfg(cfg)?),
-        input,
-        mode,
-        file_metadata,
-        permissions,
-        json_output,
-    )
-    .await
+/// Writes newline-delimited [`JsonEnvelope`] phase events when `--events` is set.
+///
+/// Events go to stderr, or to the `--events=<path>` file, so they never mix with
+/// the executed program's stdout.
+#[derive(Debug, Clone)]
+struct EventSink {
+    target: Option<EventTarget>,
+    trace_id: Option<String>,
 }
 
-async fn execute_pipeline<P: TranslatorProvider>(
-    engine: Engine<P, DefaultRiskPolicy>,
-    input: &str,
-    mode: &str,
-    file_metadata: Option<FileMetadata>,
-    permissions: DenoPermissions,
-    json_output: bool,
-) -> anyhow::Result<()> {
-    let (source, _, risk) = engine
-        .prepare_source(input, mode, SessionSummary::default(), file_metadata)
-        .await
-        .map_err(render_engine_error)?;
+#[derive(Debug, Clone)]
+enum EventTarget {
+    Stderr,
+    File(Arc<Mutex<fs::File>>),
+}
 
-    if risk.requires_confirmation {
-        eprintln!("risky output detected; add interactive repl to confirm.");
+impl EventSink {
+    /// A sink for the `--events` flag: `None` when absent, stderr when given
+    /// without a path. An events file is checked against `protect.deny` and emptied.
+    fn new(events: Option<Option<PathBuf>>, deny: &[String]) -> anyhow::Result<Self> {
+        let target = match events {
+            None => None,
+            Some(None) => Some(EventTarget::Stderr),
+            Some(Some(path)) => {
+                ensure_not_protected(&path, deny)?;
+                let file = fs::File::create(&path).map_err(|e| {
+                    anyhow::anyhow!("failed to create events file {}: {e}", path.display())
+                })?;
+                Some(EventTarget::File(Arc::new(Mutex::new(file))))
+            }
+        };
+        Ok(Self {
+            target,
+            trace_id: None,
+        })
     }
 
-    execute_request(ExecutionRequest {
-        source,
-        deno_permissions: permissions,
-        origin: mode.to_string(),
-    })
-    .await
-    .map_err(render_engine_error)?;
+    /// Returns a sink that stamps `trace_id` into every event's details.
+    fn with_trace(&self, trace_id: &str) -> Self {
+        Self {
+            target: self.target.clone(),
+            trace_id: Some(trace_id.to_string()),
+        }
+    }
 
-    if json_output {
-        println!(
-            "{}",
-            serde_json::to_string_pretty(&JsonEnvelope {
-                status: "ok".to_string(),
-                phase: "execute".to_string(),
-                message: "execution completed".to_string(),
-                details: json!({"mode": mode}),
-            })?
-        );
+    fn emit(&self, phase: &str, message: &str, details: serde_json::Value) -> anyhow::Result<()> {
+        self.write(JsonEnvelope::new("ok", phase, message, details))
     }
 
-    Ok(())
+    /// Emits the terminal `error` event for a failed command. Best effort: the
+    /// command's own error is what gets reported if the event cannot be written.
+    fn emit_error(&self, err: &anyhow::Error) {
+        let details = json!({"exit_code": child_exit_code(err)});
+        let _ = self.write(JsonEnvelope::new(
+            "error",
+            "error",
+            format!("{err:#}"),
+            details,
+        ));
+    }
+
+    fn write(&self, mut event: JsonEnvelope) -> anyhow::Result<()> {
+        let Some(target) = &self.target else {
+            return Ok(());
+        };
+        if let (Some(trace_id), Some(map)) = (&self.trace_id, event.details.as_object_mut()) {
+            map.insert("trace_id".to_string(), json!(trace_id));
+        }
+        let line = serde_json::to_string(&event)?;
+        match target {
+            EventTarget::Stderr => eprintln!("{line}"),
+            EventTarget::File(file) => {
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                writeln!(file, "{line}")?;
+                file.flush()?;
+            }
+        }
+        Ok(())
+    }
 }
 
-async fn execute_run_with_provider(
-    cfg: &AppConfig,
-    script: &str,
-    file: PathBuf,
-    permissions: DenoPermissions,
+/// Output and confirmation settings for one-shot `eval` pipelines.
+struct PipelineOptions {
     json_output: bool,
-) -> anyhow::Result<()> {
-    let policy = policy_from_cfg(cfg)?;
-    let provider = build_provider(cfg, |k| std::env::var(k).ok());
-    let engine = Engine::new(provider, policy);
-    let (processed, warnings) = engine
-        .process_tagged_script(
-            script,
-            SessionSummary::default(),
-            Some(file.to_string_lossy().to_string()),
-        )
-        .await
-        .map_err(render_engine_error)?;
-    for warning in warnings {
-        eprintln!("warning: {warning}");
+    explain: bool,
+    confirm_mode: ConfirmMode,
+    min_confidence: f32,
+    events: EventSink,
+    /// Resolved `[runtime] env` plus `--env` values.
+    env: BTreeMap<String, String>,
+    deno_config: Option<String>,
+    import_map: Option<String>,
+    no_remote: bool,
+    cached_only: bool,
+    /// Where translated suggestions are saved for `beeno history`.
+    artifacts: ArtifactConfig,
+    repeat: Repeat,
+    /// Print `✓ executed (...)` to stderr after a successful run.
+    success_line: bool,
+    /// Show an elapsed-time spinner while waiting for the translation.
+    spinner: bool,
+    /// Show translated code and ask before running it.
+    preview: bool,
+    /// Print a [`PermissionReport`] instead of running the code.
+    perms: bool,
+    /// File that also receives the program's output (`--tee`).
+    tee: Option<PathBuf>,
+    /// `protect.deny` patterns that permissions granted at the prompt must respect.
+    deny: Vec<String>,
+    /// Self-heal settings, or `None` when failed runs are not healed.
+    heal: Option<HealOptions>,
+    /// Quiet hides warnings; verbose adds a timing line.
+    verbosity: Verbosity,
+}
+
+/// Output settings for `run`.
+struct RunOptions {
+    out: Option<PathBuf>,
+    no_exec: bool,
+    /// Skip the tagged-block translation cache.
+    refresh: bool,
+    json_output: bool,
+    events: EventSink,
+    repeat: Repeat,
+    success_line: bool,
+    /// Show an elapsed-time spinner while tagged blocks are translated.
+    spinner: bool,
+    /// Show code produced from tagged blocks and ask before running it.
+    preview: bool,
+    /// Print a [`PermissionReport`] instead of running the script.
+    perms: bool,
+    /// File that also receives the program's output (`--tee`).
+    tee: Option<PathBuf>,
+    /// Self-heal settings, or `None` when failed runs are not healed.
+    heal: Option<HealOptions>,
+}
+
+/// `[self_heal]` settings for one `eval` or `run`.
+#[derive(Debug, Clone, PartialEq)]
+struct HealOptions {
+    max_attempts: u8,
+    /// Apply fixes without showing the diff and asking.
+    apply_by_default: bool,
+    /// Where each attempt's fix and patch are saved.
+    artifacts_dir: PathBuf,
+}
+
+impl HealOptions {
+    /// Self-heal settings when it is enabled for failed runs, otherwise `None`.
+    fn from_config(cfg: &AppConfig) -> Option<Self> {
+        let heal = &cfg.self_heal;
+        (heal.enabled && heal.auto_on_run_failure).then(|| Self {
+            max_attempts: heal.max_attempts,
+            apply_by_default: heal.apply_fixes_default,
+            artifacts_dir: Path::new(&cfg.artifacts.dir).join(HEAL_DIR),
+        })
     }
-    execute_request(ExecutionRequest {
-        source: processed,
-        deno_permissions: permissions,
-        origin: "run".to_string(),
-    })
-    .await
-    .map_err(render_engine_error)?;
+}
+
+/// Whether to print the success line: interactive, non-JSON, and not `--quiet`.
+fn show_success_line(json_output: bool, quiet: bool) -> bool {
+    use std::io::IsTerminal;
+    !json_output && !quiet && io::stderr().is_terminal()
+}
+
+/// Whether to preview generated code before running it: interactive, non-JSON,
+/// confirmations enabled, and not `--no-preview` or `--yes`.
+fn show_preview(json_output: bool, no_preview: bool, confirm_mode: ConfirmMode) -> bool {
+    !json_output
+        && !no_preview
+        && confirm_mode != ConfirmMode::Never
+        && beeno_core::prompt::is_interactive()
+        && !beeno_core::prompt::assume_yes()
+}
+
+/// Generated source and its risk reasons, shown before asking to run it.
+fn format_preview(source: &str, risk: &RiskReport) -> String {
+    let mut out = format!(
+        "--- generated code ---\n{}\n----------------------\nrisk: {:?}",
+        source.trim_end(),
+        risk.level
+    );
+    for reason in &risk.reasons {
+        out.push_str(&format!("\n- {reason}"));
+    }
+    out
+}
+
+/// Each permission as needed/granted, then the flags still missing.
+fn format_permission_report(report: &PermissionReport) -> String {
+    let granted = &report.granted;
+    let list = |allowed: &[String]| (!allowed.is_empty()).then(|| allowed.join(","));
+    let flag = |allowed: bool| allowed.then(String::new);
+    let rows = [
+        ("read", report.needs_read, list(&granted.allow_read)),
+        ("write", report.needs_write, list(&granted.allow_write)),
+        ("net", report.needs_net, list(&granted.allow_net)),
+        ("env", report.needs_env, flag(granted.allow_env)),
+        ("run", report.needs_run, flag(granted.allow_run)),
+    ];
+    let mut out = "permissions:".to_string();
+    for (name, needed, allowed) in rows {
+        let needed = if needed { "needed" } else { "not needed" };
+        let allowed = match allowed {
+            Some(list) if !list.is_empty() => format!("granted ({list})"),
+            Some(_) => "granted".to_string(),
+            None => "not granted".to_string(),
+        };
+        out.push_str(&format!("\n  {name:<5} {needed:<10}  {allowed}"));
+    }
+    if report.missing.is_empty() {
+        out.push_str("\nmissing: none");
+    } else {
+        out.push_str(&format!("\nmissing: {}", report.missing.join(" ")));
+    }
+    out
+}
 
+/// Prints `report` as text or a JSON envelope with phase `permissions`.
+fn print_permission_report(report: &PermissionReport, json_output: bool) -> anyhow::Result<()> {
     if json_output {
         println!(
             "{}",
-            serde_json::to_string_pretty(&JsonEnvelope {
-                status: "ok".to_string(),
-                phase: "execute".to_string(),
-                message: "run completed".to_string(),
-                details: json!({"file": file}),
-            })?
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                "ok",
+                "permissions",
+                "permission report",
+                json!(report),
+            ))?
         );
+    } else {
+        println!("{}", format_permission_report(report));
     }
-
     Ok(())
 }
 
-fn build_provider<F>(cfg: &AppConfig, env_get: F) -> Box<dyn TranslatorProvider>
-where
-    F: Fn(&str) -> Option<String> + Copy,
-{
-    let provider = cfg.llm.provider.to_ascii_lowercase();
-    let endpoint = resolve_provider_endpoint(cfg, env_get);
-    let api_key = env_get(&cfg.llm.api_key_env_var);
-
-    match provider.as_str() {
-        "mock" => Box::new(MockProvider),
-        #[cfg(feature = "provider-ollama")]
-        "ollama" => Box::new(OllamaProvider::new(
-            endpoint.unwrap_or_else(|| "http://127.0.0.1:11434/api/generate".to_string()),
-            cfg.llm.model.clone(),
-            cfg.llm.temperature,
-            cfg.llm.max_tokens,
-        )),
-        #[cfg(feature = "provider-openai-compat")]
-        "chatgpt" => Box::new(OpenAICompatProvider::new(
-            endpoint.unwrap_or_else(|| "https://api.openai.com/v1/chat/completions".to_string()),
-            api_key,
-            cfg.llm.model.clone(),
-            cfg.llm.temperature,
-            cfg.llm.max_tokens,
-        )),
-        #[cfg(feature = "provider-openai-compat")]
-        "openrouter" => Box::new(OpenAICompatProvider::new(
-            endpoint.unwrap_or_else(|| "https://openrouter.ai/api/v1/chat/completions".to_string()),
-            api_key,
-            cfg.llm.model.clone(),
-            cfg.llm.temperature,
-            cfg.llm.max_tokens,
-        )),
-        #[cfg(feature = "provider-openai-compat")]
-        "openai_compat" => Box::new(OpenAICompatProvider::new(
-            endpoint.unwrap_or_else(|| "https://api.openai.com/v1/chat/completions".to_string()),
-            api_key,
-            cfg.llm.model.clone(),
-            cfg.llm.temperature,
-            cfg.llm.max_tokens,
-        )),
-        #[cfg(feature = "provider-http")]
-        _ => Box::new(HttpProvider::new(
-            endpoint.unwrap_or_else(|| "http://localhost:8080/translate".to_string()),
-            api_key,
-            cfg.llm.model.clone(),
-            cfg.llm.temperature,
-            cfg.llm.max_tokens,
-        )),
-        #[cfg(not(feature = "provider-http"))]
-        _ => Box::new(MockProvider),
+/// Offers to grant, for this run only, each permission `source` needs but
+/// `permissions` lacks, showing the call that needs it first.
+///
+/// Only asked on a terminal without `--json` or `--yes`; declined or unasked
+/// flags still fail permission alignment at execution.
+fn offer_missing_permissions(
+    source: &str,
+    permissions: &mut DenoPermissions,
+    deny: &[String],
+    json_output: bool,
+) -> anyhow::Result<()> {
+    if json_output || !beeno_core::prompt::is_interactive() || beeno_core::prompt::assume_yes() {
+        return Ok(());
+    }
+    let report = analyze_permissions(source, permissions);
+    for flag in &report.missing {
+        if let Some(trigger) = report.trigger(flag) {
+            eprintln!("{}", describe_trigger(trigger));
+        }
+        if !can_offer_grant(flag, deny) {
+            eprintln!(
+                "not offering {flag}: an unrestricted grant would expose files guarded by \
+                 protect.deny; pass {flag}=<path> for the paths the code needs"
+            );
+            continue;
+        }
+        if prompt_confirm(&format!("grant {flag} for this run?"))? {
+            grant_permission(permissions, flag)?;
+        }
     }
+    ensure_permissions_unprotected(permissions, deny)
 }
 
-fn resolve_provider_endpoint<F>(cfg: &AppConfig, env_get: F) -> Option<String>
-where
-    F: Fn(&str) -> Option<String>,
-{
-    cfg.llm
-        .endpoint
-        .clone()
-        .filter(|v| !v.trim().is_empty())
-        .or_else(|| env_get(&cfg.llm.endpoint_env_var))
-        .filter(|v| !v.trim().is_empty())
+/// Whether `flag` can be granted without an allow list: file access cannot while
+/// `protect.deny` has patterns, since the grant would cover the protected files.
+fn can_offer_grant(flag: &str, deny: &[String]) -> bool {
+    !matches!(flag, "--allow-read" | "--allow-write") || deny.is_empty()
 }
 
-fn policy_from_cfg(cfg: &AppConfig) -> anyhow::Result<DefaultRiskPolicy> {
-    if let Some(path) = &cfg.policy.policy_path {
-        if path.trim().is_empty() {
-            return Ok(DefaultRiskPolicy::default());
-        }
-        DefaultRiskPolicy::from_path(Path::new(path))
-    } else {
-        Ok(DefaultRiskPolicy::default())
-    }
+/// Names the call behind a missing permission, e.g.
+/// `` `fetch(` on line 2 needs --allow-net: await fetch(url); ``.
+fn describe_trigger(trigger: &PermissionTrigger) -> String {
+    format!(
+        "`{}` on line {} needs {}: {}",
+        trigger.op, trigger.line, trigger.flag, trigger.code
+    )
 }
 
-fn load_config() -> anyhow::Result<AppConfig> {
-    let local_path = PathBuf::from(".beeno.toml");
-    let home_path = std::env::var("HOME")
-        .ok()
-        .map(|home| PathBuf::from(home).join(".beeno.toml"));
+/// Grants `flag` (e.g. `--allow-net`) without an allow list.
+fn grant_permission(permissions: &mut DenoPermissions, flag: &str) -> anyhow::Result<()> {
+    permissions
+        .set_grant(flag.trim_start_matches("--allow-"), "on")
+        .map_err(anyhow::Error::msg)
+}
 
-    let home = match &home_path {
-        Some(path) => read_config_value(path)?,
-        None => None,
-    };
-    let local = read_config_value(&local_path)?;
+/// The line printed after a successful execution, e.g. `✓ executed (mode=eval, 1.2s)`.
+fn success_line(mode: &str, elapsed: Duration) -> String {
+    format!("✓ executed (mode={mode}, {:.1}s)", elapsed.as_secs_f64())
+}
 
-    resolve_config(home, local, |k| std::env::var(k).ok())
+/// `--repeat` / `--keep-going` settings shared by `eval` and `run`.
+#[derive(Debug, Clone, Copy)]
+struct Repeat {
+    repeat: u32,
+    keep_going: bool,
 }
 
-fn resolve_config<F>(
-    home: Option<Value>,
-    local: Option<Value>,
-    env_get: F,
-) -> anyhow::Result<AppConfig>
+/// Outcome of one execution under `--repeat`.
+#[derive(Debug, Serialize)]
+struct RunResult {
+    run: u32,
+    ok: bool,
+    /// Deno's exit code for failed runs; `None` on success or when no process ran.
+    exit_code: Option<i32>,
+    elapsed_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Calls `execute` up to `repeat.repeat` times, stopping after the first failure
+/// unless `keep_going`; returns each run's result and the first error.
+async fn execute_repeated<F, Fut>(
+    repeat: Repeat,
+    json_output: bool,
+    mut execute: F,
+) -> (Vec<RunResult>, Option<EngineError>)
 where
-    F: Fn(&str) -> Option<String>,
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<(), EngineError>>,
 {
-    let mut merged = Value::try_from(AppConfig::default())?;
-    if let Some(home_value) = home {
-        merge_toml(&mut merged, home_value);
+    let mut results = Vec::new();
+    let mut first_error = None;
+    for run in 1..=repeat.repeat {
+        let started = Instant::now();
+        let outcome = execute().await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        let result = match &outcome {
+            Ok(()) => RunResult {
+                run,
+                ok: true,
+                exit_code: None,
+                elapsed_ms,
+                error: None,
+            },
+            Err(e) => RunResult {
+                run,
+                ok: false,
+                exit_code: match e {
+                    EngineError::RuntimeFailure(failure) => failure.exit_code,
+                    _ => None,
+                },
+                elapsed_ms,
+                error: Some(e.to_string()),
+            },
+        };
+        if repeat.repeat > 1 && !json_output {
+            eprintln!("{}", describe_run(&result, repeat.repeat));
+        }
+        results.push(result);
+        if let Err(e) = outcome {
+            first_error.get_or_insert(e);
+            if !repeat.keep_going {
+                break;
+            }
+        }
     }
-    if let Some(local_value) = local {
-        merge_toml(&mut merged, local_value);
+    if repeat.repeat > 1 && !json_output {
+        eprintln!("{}", summarize_runs(&results));
     }
+    (results, first_error)
+}
 
-    let mut cfg: AppConfig = merged.try_into()?;
-    apply_env_overrides(&mut cfg, env_get);
-    Ok(cfg)
+fn describe_run(result: &RunResult, total: u32) -> String {
+    let status = result.error.as_deref().unwrap_or("ok");
+    format!(
+        "run {}/{total}: {status} ({}ms)",
+        result.run, result.elapsed_ms
+    )
 }
 
-fn read_config_value(path: &Path) -> anyhow::Result<Option<Value>> {
-    if !path.exists() {
-        return Ok(None);
+/// Aggregate line printed after repeated runs.
+fn summarize_runs(results: &[RunResult]) -> String {
+    let ok = results.iter().filter(|r| r.ok).count();
+    let times = results.iter().map(|r| r.elapsed_ms);
+    let total: u64 = times.clone().sum();
+    format!(
+        "{} runs: {ok} ok, {} failed; total {total}ms, mean {}ms, min {}ms, max {}ms",
+        results.len(),
+        results.len() - ok,
+        total / results.len().max(1) as u64,
+        times.clone().min().unwrap_or(0),
+        times.max().unwrap_or(0),
+    )
+}
+
+/// Prints the provider's plan for `input` (`eval --explain-only`).
+///
+/// Nothing is executed, policy-checked, or recorded in history.
+async fn explain_with_provider(
+    cfg: &AppConfig,
+    input: &str,
+    json_output: bool,
+) -> anyhow::Result<()> {
+    if input.trim().is_empty() {
+        return Err(render_engine_error(ClassificationError::Empty.into()));
+    }
+    let provider = build_provider(cfg, |k| std::env::var(k).ok())?;
+    let engine = Engine::new(provider, DefaultRiskPolicy::default());
+    let result = engine
+        .translate_only(input, EXPLAIN_MODE, SessionSummary::default(), None)
+        .await
+        .map_err(render_engine_error)?;
+    let plan = result.explanation.unwrap_or_default();
+    if json_output {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                "ok",
+                "explain",
+                "plan generated",
+                json!({"explanation": plan, "tokens": result.tokens}),
+            ))?
+        );
+    } else {
+        println!("{plan}");
     }
+    Ok(())
+}
 
-    let raw = fs::read_to_string(path)?;
-    let parsed = raw.parse::<Value>()?;
-    Ok(Some(parsed))
+async fn execute_with_provider(
+    cfg: &AppConfig,
+    input: &str,
+    mode: &str,
+    file_metadata: Option<FileMetadata>,
+    permissions: DenoPermissions,
+    opts: &PipelineOptions,
+) -> anyhow::Result<()> {
+    let permissions = cfg.permissions.for_origin(mode).merged_with(permissions);
+    ensure_permissions_unprotected(&permissions, &cfg.protect.deny)?;
+    let provider = build_provider(cfg, |k| std::env::var(k).ok())?;
+    execute_pipeline(
+        Engine::new(provider, policy_from_cfg(cfg).await?)
+            .with_limits(cfg.limits.clone())
+            .with_auto_continue(cfg.llm.auto_continue)
+            .with_prelude(cfg.runtime.prelude.clone()),
+        input,
+        mode,
+        file_metadata,
+        permissions,
+        opts,
+    )
+    .await
 }
 
-fn merge_toml(base: &mut Value, overlay: Value) {
-    match (base, overlay) {
-        (Value::Table(base_map), Value::Table(overlay_map)) => {
-            for (key, value) in overlay_map {
-                if let Some(base_value) = base_map.get_mut(&key) {
-                    merge_toml(base_value, value);
-                } else {
-                    base_map.insert(key, value);
-                }
+async fn execute_pipeline<P: TranslatorProvider, R: RiskPolicy>(
+    engine: Engine<P, R>,
+    input: &str,
+    mode: &str,
+    file_metadata: Option<FileMetadata>,
+    mut permissions: DenoPermissions,
+    opts: &PipelineOptions,
+) -> anyhow::Result<()> {
+    let trace_id = new_trace_id();
+    let events = opts.events.with_trace(&trace_id);
+    events.emit(
+        "classify",
+        "input classified",
+        json!({"mode": mode, "kind": format!("{:?}", classify_input(input)).to_lowercase()}),
+    )?;
+    let translate_started = Instant::now();
+    let spinner = Spinner::start("translating", opts.spinner);
+    let prepared = engine
+        .prepare_source(
+            input,
+            mode,
+            SessionSummary::default(),
+            file_metadata.clone(),
+            Some(trace_id.clone()),
+        )
+        .await;
+    spinner.finish();
+    let (source, translated, risk, mut warnings) = match prepared {
+        Err(EngineError::Classification(ClassificationError::Ambiguous { reason })) => {
+            let mode = ambiguous_input_mode(input, &reason)?;
+            let spinner = Spinner::start("translating", opts.spinner);
+            let prepared = engine
+                .prepare_source(
+                    input,
+                    mode,
+                    SessionSummary::default(),
+                    file_metadata,
+                    Some(trace_id.clone()),
+                )
+                .await;
+            spinner.finish();
+            prepared
+        }
+        other => other,
+    }
+    .map_err(render_engine_error)?;
+    let translate_elapsed = translate_started.elapsed();
+    events.emit(
+        "translate",
+        if translated.is_some() {
+            "input translated"
+        } else {
+            "native source, no translation"
+        },
+        json!({"translation": translated, "source": source}),
+    )?;
+    events.emit("policy", "policy checked", json!(risk))?;
+    if translated.is_some() {
+        record_suggestion(&opts.artifacts, mode, input, risk.level, &source, &trace_id);
+    }
+    if opts.perms {
+        let report = analyze_permissions(&source, &permissions);
+        return print_permission_report(&report, opts.json_output);
+    }
+
+    let hide_warnings = opts.json_output || !opts.verbosity.chatty();
+    let previewed = opts.preview && translated.is_some();
+    if previewed {
+        // The preview lists the risk reasons itself.
+        let caveats: Vec<Warning> = warnings
+            .iter()
+            .filter(|warning| warning.kind != WarningKind::Risky)
+            .cloned()
+            .collect();
+        print_warnings(&caveats, hide_warnings);
+        eprintln!("{}", format_preview(&source, &risk));
+        if !prompt_confirm("run this?")? {
+            eprintln!("execution skipped by user");
+            return Ok(());
+        }
+    } else {
+        print_warnings(&warnings, hide_warnings);
+    }
+    if !previewed && needs_confirmation(opts.confirm_mode, &risk, &source) {
+        let question = if risk.requires_confirmation {
+            "risky output detected, execute?"
+        } else {
+            "execute generated code?"
+        };
+        if !prompt_confirm(question)? {
+            eprintln!("execution skipped by user");
+            return Ok(());
+        }
+    }
+
+    if let Some(translated) = &translated {
+        if opts.explain && !opts.json_output {
+            eprintln!("{}", describe_translation(translated));
+        }
+        if let Some(confidence) = translated.confidence.filter(|c| *c < opts.min_confidence) {
+            let warning = Warning::new(
+                WarningKind::LowConfidence,
+                format!(
+                    "low translation confidence {confidence:.2} (threshold {:.2})",
+                    opts.min_confidence
+                ),
+            );
+            print_warnings(std::slice::from_ref(&warning), hide_warnings);
+            warnings.push(warning);
+            if opts.confirm_mode != ConfirmMode::Never
+                && !previewed
+                && !prompt_confirm("low-confidence translation, execute?")?
+            {
+                eprintln!("execution skipped by user");
+                return Ok(());
             }
         }
-        (base_value, overlay_value) => {
-            *base_value = overlay_value;
+    }
+
+    offer_missing_permissions(&source, &mut permissions, &opts.deny, opts.json_output)?;
+    events.emit(
+        "execute-start",
+        "executing with deno",
+        json!({"origin": mode}),
+    )?;
+    let request = ExecutionRequest {
+        trace_id: Some(trace_id.clone()),
+        env: opts.env.clone(),
+        deno_config: opts.deno_config.clone(),
+        import_map: opts.import_map.clone(),
+        no_remote: opts.no_remote,
+        cached_only: opts.cached_only,
+        tee: opts.tee.clone(),
+        capture_output: opts.heal.is_some(),
+        live: true,
+        ..ExecutionRequest::new(source, permissions, mode)
+    };
+    let started = Instant::now();
+    let (runs, mut error) = execute_repeated(opts.repeat, opts.json_output, || {
+        let (engine, request) = (&engine, request.clone());
+        async move { engine.execute(request).await?.into_result() }
+    })
+    .await;
+    if opts.verbosity.is_verbose() && !opts.json_output {
+        eprintln!("{}", timing_line(translate_elapsed, started.elapsed()));
+    }
+    let mut heal = None;
+    if let (Some(options), Some(failure)) = (&opts.heal, healable_failure(&error, opts.repeat)) {
+        let (report, outcome) =
+            heal_failed_run(&engine, request, failure, options, opts.json_output, false).await;
+        print_heal_report(&report, opts.json_output);
+        error = outcome;
+        heal = Some(report);
+    }
+    if error.is_none() && opts.success_line {
+        eprintln!("{}", success_line(mode, started.elapsed()));
+    }
+    if opts.repeat.repeat == 1 {
+        if let Some(e) = error {
+            return Err(render_engine_error(e));
         }
     }
-}
+    events.emit(
+        "execute-end",
+        "execution completed",
+        json!({"origin": mode}),
+    )?;
 
-fn apply_env_overrides<F>(cfg: &mut AppConfig, env_get: F)
-where
-    F: Fn(&str) -> Option<String>,
-{
-    if let Some(v) = env_get("BEENO_PROVIDER") {
-        cfg.llm.provider = v;
+    if opts.json_output {
+        let mut data = json!({"mode": mode, "trace_id": trace_id, "warnings": warnings});
+        if opts.repeat.repeat > 1 {
+            data["runs"] = json!(runs);
+        }
+        if let Some(heal) = &heal {
+            data["heal"] = json!(heal);
+        }
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                if error.is_some() { "error" } else { "ok" },
+                "execute",
+                "execution completed",
+                data,
+            ))?
+        );
     }
-    if let Some(v) = env_get("BEENO_MODEL") {
-        cfg.llm.model = v;
+
+    error.map_or(Ok(()), |e| Err(render_engine_error(e)))
+}
+
+/// Renders a one-line summary of the provider's explanation and confidence.
+fn describe_translation(translated: &TranslateResult) -> String {
+    let mut line = "translated".to_string();
+    if let Some(confidence) = translated.confidence {
+        line.push_str(&format!(" (confidence {confidence:.2})"));
     }
-    if let Some(v) = env_get("BEENO_ENDPOINT") {
-        cfg.llm.endpoint = Some(v);
+    match translated.explanation.as_deref().map(str::trim) {
+        Some(explanation) if !explanation.is_empty() => {
+            line.push_str(": ");
+            line.push_str(explanation);
+        }
+        _ => {}
     }
-    if let Some(v) = env_get("BEENO_TEMPERATURE").and_then(|v| v.parse::<f32>().ok()) {
-        cfg.llm.temperature = v;
+    line
+}
+
+async fn execute_run_with_provider(
+    cfg: &AppConfig,
+    script: &str,
+    file: PathBuf,
+    permissions: DenoPermissions,
+    opts: &RunOptions,
+) -> anyhow::Result<()> {
+    let RunOptions {
+        out,
+        no_exec,
+        refresh,
+        json_output,
+        events,
+        repeat,
+        success_line: show_success,
+        spinner,
+        preview,
+        perms,
+        tee,
+        heal: heal_options,
+    } = opts;
+    let (no_exec, json_output) = (*no_exec, *json_output);
+    let mut permissions = cfg.permissions.for_origin("run").merged_with(permissions);
+    ensure_permissions_unprotected(&permissions, &cfg.protect.deny)?;
+    let trace_id = new_trace_id();
+    let events = events.with_trace(&trace_id);
+    let policy = policy_from_cfg(cfg).await?;
+    let provider = build_provider(cfg, |k| std::env::var(k).ok())?;
+    let engine = Engine::new(provider, policy)
+        .with_limits(cfg.limits.clone())
+        .with_auto_continue(cfg.llm.auto_continue)
+        .with_block_cache(Some(block_cache(cfg, *refresh)))
+        .with_prelude(cfg.runtime.prelude.clone());
+    let tagged_blocks = script.matches("/*nl").count();
+    events.emit(
+        "classify",
+        "script classified",
+        json!({"file": file, "tagged_blocks": tagged_blocks}),
+    )?;
+    let translate_started = Instant::now();
+    let progress = Spinner::start("translating", *spinner && tagged_blocks > 0);
+    let processed = engine
+        .process_tagged_script(
+            script,
+            SessionSummary::default(),
+            Some(file.to_string_lossy().to_string()),
+            Some(trace_id.clone()),
+        )
+        .await;
+    progress.finish();
+    let ProcessedScript {
+        source: processed,
+        warnings,
+        source_map,
+        cached_blocks,
+    } = processed.map_err(render_engine_error)?;
+    let translate_elapsed = translate_started.elapsed();
+    print_warnings(&warnings, json_output || !cfg.verbosity.chatty());
+    events.emit(
+        "translate",
+        "tagged blocks translated",
+        json!({"tagged_blocks": tagged_blocks, "cached_blocks": cached_blocks, "warnings": warnings}),
+    )?;
+    events.emit(
+        "policy",
+        "translated blocks passed policy",
+        json!({"checked_blocks": tagged_blocks}),
+    )?;
+    let risk = if tagged_blocks > 0 {
+        let risk = engine
+            .analyze_as(&processed, media_type_for_path(&file))
+            .await;
+        record_suggestion(
+            &cfg.artifacts,
+            "run",
+            &file.to_string_lossy(),
+            risk.level,
+            &processed,
+            &trace_id,
+        );
+        Some(risk)
+    } else {
+        None
+    };
+    if let Some(path) = out {
+        fs::write(path, &processed)?;
+        if !json_output && cfg.verbosity.chatty() {
+            eprintln!("wrote translated script to {}", path.display());
+        }
     }
-    if let Some(v) = env_get("BEENO_MAX_TOKENS").and_then(|v| v.parse::<u32>().ok()) {
-        cfg.llm.max_tokens = v;
+    if *perms {
+        let report = analyze_permissions(&processed, &permissions);
+        return print_permission_report(&report, json_output);
     }
-    if let Some(v) = env_get("BEENO_ENDPOINT_ENV_VAR") {
-        cfg.llm.endpoint_env_var = v;
+    if let Some(risk) = risk.as_ref().filter(|_| *preview && !no_exec) {
+        eprintln!("{}", format_preview(&processed, risk));
+        if !prompt_confirm("run this?")? {
+            eprintln!("execution skipped by user");
+            return Ok(());
+        }
     }
-    if let Some(v) = env_get("BEENO_API_KEY_ENV_VAR") {
-        cfg.llm.api_key_env_var = v;
+    let mut runs = Vec::new();
+    let mut run_error = None;
+    let mut heal = None;
+    if !no_exec {
+        offer_missing_permissions(&processed, &mut permissions, &cfg.protect.deny, json_output)?;
+        events.emit(
+            "execute-start",
+            "executing with deno",
+            json!({"origin": "run"}),
+        )?;
+        let request = ExecutionRequest {
+            trace_id: Some(trace_id.clone()),
+            env: cfg.runtime.resolved_env(|k| std::env::var(k).ok()),
+            deno_config: cfg.runtime.deno_config.clone(),
+            import_map: cfg.runtime.import_map.clone(),
+            no_remote: cfg.runtime.no_remote,
+            cached_only: cfg.runtime.cached_only,
+            tee: tee.clone(),
+            capture_output: heal_options.is_some(),
+            live: true,
+            // Inlined blocks and the prelude both shift the script's lines.
+            stack_mapping: (processed != *script).then(|| StackMapping {
+                source_map,
+                original: file.to_string_lossy().into_owned(),
+            }),
+            module_extension: module_extension_for_path(&file),
+            ..ExecutionRequest::new(processed, permissions, "run")
+        };
+        let type_check = cfg.runtime.type_check_before_run;
+        let mut error = None;
+        if type_check {
+            if let Some(diagnostics) = type_check_request(&request)
+                .await
+                .map_err(render_engine_error)?
+            {
+                error = Some(EngineError::TypeCheck(diagnostics));
+            }
+        }
+        if heal_options.is_none() || repeat.repeat > 1 {
+            if let Some(type_errors) = error {
+                return Err(render_engine_error(type_errors));
+            }
+        }
+        let started = Instant::now();
+        // Nothing runs until the script type-checks; self-heal below may still fix it.
+        let results = if error.is_some() {
+            Vec::new()
+        } else {
+            let (results, run_error) = execute_repeated(*repeat, json_output, || {
+                let (engine, request) = (&engine, request.clone());
+                async move { engine.execute(request).await?.into_result() }
+            })
+            .await;
+            error = run_error;
+            results
+        };
+        if cfg.verbosity.is_verbose() && !json_output {
+            eprintln!("{}", timing_line(translate_elapsed, started.elapsed()));
+        }
+        if let (Some(options), Some(failure)) = (heal_options, healable_failure(&error, *repeat)) {
+            let (report, outcome) =
+                heal_failed_run(&engine, request, failure, options, json_output, type_check).await;
+            print_heal_report(&report, json_output);
+            error = outcome;
+            heal = Some(report);
+        }
+        if repeat.repeat == 1 {
+            if let Some(e) = error {
+                return Err(render_engine_error(e));
+            }
+        }
+        if error.is_none() && *show_success {
+            eprintln!("{}", success_line("run", started.elapsed()));
+        }
+        events.emit(
+            "execute-end",
+            "execution completed",
+            json!({"origin": "run"}),
+        )?;
+        runs = results;
+        run_error = error;
+    }
+
+    if json_output {
+        let (phase, message) = if no_exec {
+            ("translate", "translated script written")
+        } else {
+            ("execute", "run completed")
+        };
+        let mut data = json!({
+            "file": file,
+            "out": out,
+            "executed": !no_exec,
+            "trace_id": trace_id,
+            "warnings": warnings,
+        });
+        if repeat.repeat > 1 && !no_exec {
+            data["runs"] = json!(runs);
+        }
+        if let Some(heal) = &heal {
+            data["heal"] = json!(heal);
+        }
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                if run_error.is_some() { "error" } else { "ok" },
+                phase,
+                message,
+                data,
+            ))?
+        );
+    }
+
+    run_error.map_or(Ok(()), |e| Err(render_engine_error(e)))
+}
+
+/// What self-heal is fixing: a failed run, or `deno check` diagnostics that kept
+/// the script from running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HealTarget {
+    Runtime(ExecutionFailure),
+    TypeCheck(String),
+}
+
+impl HealTarget {
+    /// The failure shown to the provider; `deno check` exits with 1 on type errors.
+    fn failure(&self) -> ExecutionFailure {
+        match self {
+            Self::Runtime(failure) => failure.clone(),
+            Self::TypeCheck(diagnostics) => ExecutionFailure {
+                exit_code: Some(1),
+                stdout: String::new(),
+                stderr: diagnostics.clone(),
+            },
+        }
+    }
+
+    fn into_error(self) -> EngineError {
+        match self {
+            Self::Runtime(failure) => EngineError::RuntimeFailure(failure),
+            Self::TypeCheck(diagnostics) => EngineError::TypeCheck(diagnostics),
+        }
+    }
+}
+
+/// The failure self-heal should try to fix: only single runs are healed.
+fn healable_failure(error: &Option<EngineError>, repeat: Repeat) -> Option<HealTarget> {
+    match error {
+        Some(EngineError::RuntimeFailure(failure)) if repeat.repeat == 1 => {
+            Some(HealTarget::Runtime(failure.clone()))
+        }
+        Some(EngineError::TypeCheck(diagnostics)) if repeat.repeat == 1 => {
+            Some(HealTarget::TypeCheck(diagnostics.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// Asks the provider to fix `request` after `failure` and re-runs each applied fix,
+/// until one succeeds, the user skips a fix, or `options.max_attempts` fixes were
+/// requested. Returns what happened and the error left, if any.
+///
+/// With `type_check`, each fix must pass `deno check` before it runs; its
+/// diagnostics are fed back like a runtime failure.
+///
+/// Fixes are reviewed as a diff unless `self_heal.apply_fixes_default` or `--yes`
+/// applies them; with `--json` nothing can be reviewed, so unreviewed fixes stop
+/// the run. Every proposed fix is saved under `options.artifacts_dir`.
+async fn heal_failed_run<P: TranslatorProvider, R: RiskPolicy>(
+    engine: &Engine<P, R>,
+    mut request: ExecutionRequest,
+    mut failure: HealTarget,
+    options: &HealOptions,
+    json_output: bool,
+    type_check: bool,
+) -> (HealReport, Option<EngineError>) {
+    let mut report = HealReport::default();
+    let label = format!("{}.ts", request.origin);
+    let apply_by_default = options.apply_by_default || beeno_core::prompt::assume_yes();
+    let session = create_session_dir(&options.artifacts_dir)
+        .map_err(|e| eprintln!("warning: could not save heal attempts: {e}"))
+        .ok();
+    while report.attempts < options.max_attempts {
+        report.attempts += 1;
+        let attempt = report.attempts;
+        if !json_output {
+            eprintln!(
+                "self-heal: requesting fix {attempt}/{}",
+                options.max_attempts
+            );
+        }
+        let proposed = match engine
+            .propose_fix(
+                &request.source,
+                &failure.failure(),
+                request.trace_id.clone(),
+            )
+            .await
+        {
+            Ok((proposed, _)) => proposed,
+            Err(e) => return (report, Some(e)),
+        };
+        let diff = unified_diff(&request.source, &proposed, &label);
+        let patch = session.as_deref().and_then(|dir| {
+            save_fix_artifact(dir, attempt, &proposed, &diff)
+                .map_err(|e| eprintln!("warning: could not save heal attempt {attempt}: {e}"))
+                .ok()
+        });
+        let next = if apply_by_default {
+            Some(proposed)
+        } else if json_output {
+            None
+        } else {
+            match review_fix(&request.source, &proposed, &label, false) {
+                Ok(next) => next,
+                Err(e) => return (report, Some(EngineError::Execution(e.to_string()))),
+            }
+        };
+        let Some(next) = next else {
+            return (report, Some(failure.into_error()));
+        };
+        report.applied_fixes.push(AppliedFix {
+            attempt,
+            diff: unified_diff(&request.source, &next, &label),
+            patch,
+        });
+        request.source = next;
+        // The fixed program no longer lines up with the original script.
+        request.stack_mapping = None;
+        let checked = if type_check {
+            type_check_request(&request).await
+        } else {
+            Ok(None)
+        };
+        let outcome = match checked {
+            Ok(Some(diagnostics)) => Err(EngineError::TypeCheck(diagnostics)),
+            Ok(None) => engine
+                .execute(request.clone())
+                .await
+                .and_then(ExecutionOutput::into_result),
+            Err(e) => Err(e),
+        };
+        match outcome {
+            Ok(()) => {
+                report.fixed = true;
+                return (report, None);
+            }
+            Err(EngineError::RuntimeFailure(next)) => failure = HealTarget::Runtime(next),
+            Err(EngineError::TypeCheck(next)) => failure = HealTarget::TypeCheck(next),
+            Err(e) => return (report, Some(e)),
+        }
+    }
+    (report, Some(failure.into_error()))
+}
+
+/// Prints the self-heal outcome and the diff of each applied fix to stderr.
+fn print_heal_report(report: &HealReport, json_output: bool) {
+    if json_output {
+        return;
+    }
+    eprintln!("self-heal: {}", report.summary());
+    for fix in &report.applied_fixes {
+        eprintln!("fix from attempt {}:\n{}", fix.attempt, fix.diff.trim_end());
+    }
+}
+
+/// Saves a translated suggestion for `beeno history`; failures only warn.
+fn record_suggestion(
+    artifacts: &ArtifactConfig,
+    mode: &str,
+    input: &str,
+    risk: RiskLevel,
+    code: &str,
+    trace_id: &str,
+) {
+    let mut record = SuggestionRecord::new(mode, input, risk, code);
+    record.trace_id = Some(trace_id.to_string());
+    if let Err(e) = save_suggestion(Path::new(&artifacts.dir), record, artifacts.keep_last) {
+        eprintln!("warning: could not save suggestion history: {e}");
+    }
+}
+
+fn show_history(
+    artifacts: &ArtifactConfig,
+    limit: Option<usize>,
+    json_output: bool,
+) -> anyhow::Result<()> {
+    let mut records = list_suggestions(Path::new(&artifacts.dir))?;
+    if let Some(limit) = limit {
+        records.truncate(limit);
+    }
+    if json_output {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                "ok",
+                "history",
+                format!("{} saved suggestions", records.len()),
+                json!({ "dir": artifacts.dir, "suggestions": records }),
+            ))?
+        );
+        return Ok(());
+    }
+    if records.is_empty() {
+        println!("no saved suggestions in {}", artifacts.dir);
+        return Ok(());
+    }
+    for record in &records {
+        println!(
+            "{}  {}  {:<5} {:<7} {}",
+            record.id,
+            format_utc(record.timestamp_ms),
+            record.mode,
+            format!("{:?}", record.risk).to_lowercase(),
+            record.first_line()
+        );
+    }
+    Ok(())
+}
+
+async fn replay_suggestion(
+    cfg: &AppConfig,
+    id: &str,
+    permissions: DenoPermissions,
+) -> anyhow::Result<()> {
+    let record = load_suggestion(Path::new(&cfg.artifacts.dir), id)?;
+    let policy = policy_from_cfg(cfg).await?;
+    let risk = policy.analyze(&record.code).await;
+    if risk.level == RiskLevel::Blocked {
+        return Err(render_engine_error(EngineError::Blocked(risk.reasons)));
+    }
+    println!("{}", record.code);
+    if !prompt_confirm(&format!(
+        "replay suggestion {id} ({} from {})?",
+        record.mode,
+        format_utc(record.timestamp_ms)
+    ))? {
+        println!("replay skipped");
+        return Ok(());
+    }
+    // A replay never translates, so the engine's provider goes unused.
+    let engine = Engine::new(MockProvider, policy);
+    engine
+        .execute(ExecutionRequest {
+            trace_id: record.trace_id,
+            env: cfg.runtime.resolved_env(|k| std::env::var(k).ok()),
+            deno_config: cfg.runtime.deno_config.clone(),
+            import_map: cfg.runtime.import_map.clone(),
+            no_remote: cfg.runtime.no_remote,
+            cached_only: cfg.runtime.cached_only,
+            live: true,
+            ..ExecutionRequest::new(record.code, permissions, "replay")
+        })
+        .await
+        .and_then(ExecutionOutput::into_result)
+        .map_err(render_engine_error)
+}
+
+/// Formats unix milliseconds as `YYYY-MM-DD HH:MM:SS` UTC.
+fn format_utc(timestamp_ms: u64) -> String {
+    let secs = timestamp_ms / 1000;
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil-from-days (Howard Hinnant), valid for dates after 1970.
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// Per-provider outcome of `beeno bench`.
+#[derive(Debug, Serialize)]
+struct BenchResult {
+    provider: String,
+    prompts: usize,
+    parsed: usize,
+    blocked: usize,
+    errors: usize,
+    success_rate: f64,
+    mean_latency_ms: f64,
+    total_tokens: u64,
+    /// Sources handed to the parser across all prompts.
+    parses: usize,
+    /// Parses skipped because the same code was already parsed for that prompt.
+    parse_cache_hits: usize,
+}
+
+/// Reads `analyze` input from `--input`, a file, or stdin when the file is `-`.
+fn read_analyze_source(
+    file: Option<&Path>,
+    input: Option<String>,
+    stdin: &mut impl io::Read,
+) -> anyhow::Result<String> {
+    match (file, input) {
+        (_, Some(input)) => Ok(input),
+        (Some(path), None) if path == Path::new("-") => {
+            let mut source = String::new();
+            stdin.read_to_string(&mut source)?;
+            Ok(source)
+        }
+        (Some(path), None) => Ok(fs::read_to_string(path)?),
+        (None, None) => anyhow::bail!("provide a file, `-` for stdin, or --input <source>"),
+    }
+}
+
+/// Reads `eval` input from the argument, `--input-file`, or stdin when either is `-`.
+///
+/// A file is one prompt; unlike `run`, its `/*nl` tags are not processed.
+fn read_eval_input(
+    input: Option<String>,
+    file: Option<&Path>,
+    stdin: &mut impl io::Read,
+) -> anyhow::Result<String> {
+    let read_stdin = |stdin: &mut dyn io::Read| -> anyhow::Result<String> {
+        let mut input = String::new();
+        stdin.read_to_string(&mut input)?;
+        Ok(input)
+    };
+    let input = match (input, file) {
+        (Some(_), Some(_)) => {
+            anyhow::bail!("give the input either as an argument or with --input-file, not both")
+        }
+        (Some(input), None) if input == "-" => read_stdin(stdin)?,
+        (Some(input), None) => input,
+        (None, Some(path)) if path == Path::new("-") => read_stdin(stdin)?,
+        (None, Some(path)) => fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read input file {}: {e}", path.display()))?,
+        (None, None) => {
+            anyhow::bail!("provide the input as an argument, `-` for stdin, or --input-file <path>")
+        }
+    };
+    if input.trim().is_empty() {
+        anyhow::bail!("eval input is empty");
+    }
+    Ok(input)
+}
+
+fn print_risk_report(risk: &RiskReport, json_output: bool, explain: bool) -> anyhow::Result<()> {
+    if json_output {
+        let status = if risk.level == RiskLevel::Blocked {
+            "error"
+        } else {
+            "ok"
+        };
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                status,
+                "analyze",
+                format!("policy level {:?}", risk.level),
+                json!(risk),
+            ))?
+        );
+        return Ok(());
+    }
+    println!("level: {:?}", risk.level);
+    for (i, reason) in risk.reasons.iter().enumerate() {
+        match risk.sources.get(i).filter(|_| explain) {
+            Some(source) => println!("- {reason} [{source}]"),
+            None => println!("- {reason}"),
+        }
+    }
+    if risk.requires_confirmation {
+        println!("requires confirmation before execution");
+    }
+    Ok(())
+}
+
+/// Generates a project with [`Engine::scaffold`] and writes it under `out`.
+///
+/// Every target path is checked against `protect.deny`, existing files are kept
+/// unless `force`, and risky files ask for confirmation before anything is written.
+async fn run_scaffold(
+    cfg: &AppConfig,
+    prompt: &str,
+    out: &Path,
+    force: bool,
+    json_output: bool,
+) -> anyhow::Result<()> {
+    let provider = build_provider(cfg, |k| std::env::var(k).ok())?;
+    let engine = Engine::new(provider, policy_from_cfg(cfg).await?).with_limits(cfg.limits.clone());
+    let plan = engine
+        .scaffold(prompt, None)
+        .await
+        .map_err(render_engine_error)?;
+    for file in &plan.files {
+        let target = out.join(&file.path);
+        ensure_not_protected(&target, &cfg.protect.deny)?;
+        if !force && target.exists() {
+            anyhow::bail!(
+                "{} already exists; pass --force to overwrite",
+                target.display()
+            );
+        }
+    }
+
+    let warnings: Vec<Warning> = plan
+        .files
+        .iter()
+        .zip(&plan.risks)
+        .filter_map(|(file, risk)| Some((file, risk.as_ref()?)))
+        .filter(|(_, risk)| risk.requires_confirmation)
+        .flat_map(|(file, risk)| {
+            risk.reasons
+                .iter()
+                .map(move |reason| Warning::new(WarningKind::Risky, reason).at(&file.path))
+        })
+        .collect();
+    if !warnings.is_empty() {
+        print_warnings(&warnings, json_output);
+        if cfg.policy.effective_confirm_mode() != ConfirmMode::Never
+            && !prompt_confirm("risky files generated, write them?")?
+        {
+            eprintln!("scaffold skipped by user");
+            return Ok(());
+        }
+    }
+
+    let written = beeno_core::scaffold::write_files(out, &plan.files)?;
+    if json_output {
+        let files: Vec<_> = plan
+            .files
+            .iter()
+            .zip(&written)
+            .map(|(file, path)| json!({"path": path, "bytes": file.contents.len()}))
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                "ok",
+                "scaffold",
+                format!("wrote {} files", written.len()),
+                json!({"out": out, "files": files, "warnings": warnings}),
+            ))?
+        );
+    } else {
+        println!("{}", scaffold_summary(out, &plan.files));
+    }
+    Ok(())
+}
+
+/// Lists written files with their sizes, e.g. `wrote 2 files to app/`.
+fn scaffold_summary(out: &Path, files: &[beeno_core::scaffold::ScaffoldFile]) -> String {
+    let mut summary = format!(
+        "wrote {} file{} to {}",
+        files.len(),
+        if files.len() == 1 { "" } else { "s" },
+        out.display()
+    );
+    for file in files {
+        summary.push_str(&format!(
+            "\n  {} ({} bytes)",
+            file.path,
+            file.contents.len()
+        ));
+    }
+    summary
+}
+
+async fn run_bench(
+    cfg: &AppConfig,
+    prompts_path: &Path,
+    providers: &[String],
+    json_output: bool,
+) -> anyhow::Result<()> {
+    let prompts = read_bench_prompts(prompts_path)?;
+    if prompts.is_empty() {
+        anyhow::bail!("no prompts found in {}", prompts_path.display());
+    }
+    let policy = policy_from_cfg(cfg).await?;
+    let entries = if providers.is_empty() {
+        vec![cfg.llm.provider.clone()]
+    } else {
+        providers.to_vec()
+    };
+
+    let backends: Vec<String> = entries
+        .iter()
+        .map(|entry| fallback_entry(entry).0.to_ascii_lowercase())
+        .collect();
+    confirm_max_tokens(cfg, &backends)?;
+
+    let mut results = Vec::new();
+    for entry in &entries {
+        let (name, model) = fallback_entry(entry);
+        let name = name.to_ascii_lowercase();
+        // Only the configured provider gets the configured endpoint override.
+        let endpoint = if name == cfg.llm.provider.to_ascii_lowercase() {
+            resolve_provider_endpoint(cfg, |k| std::env::var(k).ok())
+        } else {
+            None
+        };
+        let provider = build_named_provider(
+            cfg,
+            &name,
+            model.unwrap_or(&cfg.llm.model),
+            endpoint,
+            std::env::var(&cfg.llm.api_key_env_var).ok(),
+        )?;
+        results.push(bench_provider(entry, provider.as_ref(), policy.as_ref(), &prompts).await);
+    }
+
+    if json_output {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                "ok",
+                "bench",
+                format!("benchmarked {} providers", results.len()),
+                json!({ "prompts": prompts.len(), "results": results }),
+            ))?
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{:<28} {:>9} {:>8} {:>8} {:>11} {:>8} {:>8} {:>8}",
+        "provider", "success", "blocked", "errors", "mean ms", "tokens", "parses", "reused"
+    );
+    for r in &results {
+        println!(
+            "{:<28} {:>8.0}% {:>8} {:>8} {:>11.1} {:>8} {:>8} {:>8}",
+            r.provider,
+            r.success_rate * 100.0,
+            r.blocked,
+            r.errors,
+            r.mean_latency_ms,
+            r.total_tokens,
+            r.parses,
+            r.parse_cache_hits
+        );
+    }
+    Ok(())
+}
+
+/// Prints the model IDs reported by the configured provider's list endpoint.
+async fn list_provider_models(cfg: &AppConfig, json_output: bool) -> anyhow::Result<()> {
+    let provider = build_configured_provider(cfg, |k| std::env::var(k).ok())?;
+    let models = provider.list_models().await?;
+    if json_output {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                "ok",
+                "models",
+                format!("{} models available", models.len()),
+                json!({ "provider": cfg.llm.provider, "models": models }),
+            ))?
+        );
+    } else {
+        for model in &models {
+            println!("{model}");
+        }
+    }
+    Ok(())
+}
+
+fn read_bench_prompts(path: &Path) -> anyhow::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Translates every prompt with `provider`, then checks that the output parses and passes policy.
+///
+/// Each prompt runs in its own [`parse_cache::scope`]; the counts show how many
+/// re-parses of the same reply the cache saved.
+async fn bench_provider(
+    label: &str,
+    provider: &dyn TranslatorProvider,
+    policy: &dyn RiskPolicy,
+    prompts: &[String],
+) -> BenchResult {
+    let mut result = BenchResult {
+        provider: label.to_string(),
+        prompts: prompts.len(),
+        parsed: 0,
+        blocked: 0,
+        errors: 0,
+        success_rate: 0.0,
+        mean_latency_ms: 0.0,
+        total_tokens: 0,
+        parses: 0,
+        parse_cache_hits: 0,
+    };
+    let mut total_latency = Duration::ZERO;
+    for prompt in prompts {
+        let req = TranslateRequest {
+            input: prompt.clone(),
+            mode: "force_nl".to_string(),
+            session_summary: SessionSummary::default(),
+            file_metadata: None,
+            trace_id: Some(new_trace_id()),
+        };
+        let ((), parse_stats) = parse_cache::scope(async {
+            let started = Instant::now();
+            let translated = provider.translate(req).await;
+            total_latency += started.elapsed();
+            let translated = match translated {
+                Ok(translated) => translated,
+                Err(err) => {
+                    tracing::debug!(provider = label, error = %err, "bench translation failed");
+                    result.errors += 1;
+                    return;
+                }
+            };
+            result.total_tokens += u64::from(translated.tokens.unwrap_or(0));
+            if parse_js(&translated.code).is_err() {
+                return;
+            }
+            result.parsed += 1;
+            if policy.analyze(&translated.code).await.level == RiskLevel::Blocked {
+                result.blocked += 1;
+            }
+        })
+        .await;
+        result.parses += parse_stats.parses;
+        result.parse_cache_hits += parse_stats.hits;
+    }
+    result.success_rate = result.parsed as f64 / prompts.len() as f64;
+    result.mean_latency_ms = total_latency.as_secs_f64() * 1000.0 / prompts.len() as f64;
+    result
+}
+
+/// Builds the configured provider, warning when `llm.max_tokens` exceeds its context window.
+///
+/// Fails in offline mode when the provider would reach a non-loopback endpoint.
+fn build_provider<F>(cfg: &AppConfig, env_get: F) -> anyhow::Result<Box<dyn TranslatorProvider>>
+where
+    F: Fn(&str) -> Option<String> + Copy,
+{
+    confirm_max_tokens(cfg, &configured_backends(cfg))?;
+    let mut provider = build_configured_provider(cfg, env_get)?;
+    if cfg.llm.target_language == TargetLanguage::JavaScript {
+        provider = Box::new(JavaScriptTargetProvider::new(provider));
+    }
+    if let Some(warning) = provider
+        .capabilities()
+        .max_tokens_warning(cfg.llm.max_tokens)
+        .filter(|_| cfg.verbosity.chatty())
+    {
+        eprintln!("warning: {warning}");
+    }
+    Ok(provider)
+}
+
+/// Describes why `llm.max_tokens` needs confirmation, if it exceeds `llm.max_tokens_warn`.
+fn max_tokens_over_cap(cfg: &AppConfig) -> Option<String> {
+    let (max_tokens, cap) = (cfg.llm.max_tokens, cfg.llm.max_tokens_warn);
+    (cap > 0 && max_tokens > cap)
+        .then(|| format!("llm.max_tokens ({max_tokens}) exceeds llm.max_tokens_warn ({cap})"))
+}
+
+/// Set once the user accepts `llm.max_tokens` over the cap, so rebuilding the
+/// provider (REPL `/model`, `/provider`) does not ask again.
+static MAX_TOKENS_CONFIRMED: AtomicBool = AtomicBool::new(false);
+
+/// Provider names `cfg` builds: the fallback chain's entries, or `llm.provider`.
+fn configured_backends(cfg: &AppConfig) -> Vec<String> {
+    let provider = cfg.llm.provider.to_ascii_lowercase();
+    if provider != "fallback" {
+        return vec![provider];
+    }
+    cfg.llm
+        .fallback
+        .iter()
+        .map(|entry| fallback_entry(entry).0.to_ascii_lowercase())
+        .collect()
+}
+
+/// Asks, once per process, before an HTTP provider in `backends` may spend more
+/// than `llm.max_tokens_warn` tokens; `mock` and `command` cost nothing and never ask.
+///
+/// Without a terminal the prompt answers no, so scripted runs fail unless `--yes` is given.
+fn confirm_max_tokens(cfg: &AppConfig, backends: &[String]) -> anyhow::Result<()> {
+    let metered = backends
+        .iter()
+        .any(|name| !matches!(name.as_str(), "mock" | "command"));
+    if !metered || MAX_TOKENS_CONFIRMED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let Some(reason) = max_tokens_over_cap(cfg) else {
+        return Ok(());
+    };
+    if prompt_confirm(&format!("{reason}; continue?"))? {
+        MAX_TOKENS_CONFIRMED.store(true, Ordering::Relaxed);
+        return Ok(());
+    }
+    anyhow::bail!(
+        "{reason}; lower llm.max_tokens, raise the cap with --max-tokens-warn, or pass --yes"
+    )
+}
+
+fn build_configured_provider<F>(
+    cfg: &AppConfig,
+    env_get: F,
+) -> anyhow::Result<Box<dyn TranslatorProvider>>
+where
+    F: Fn(&str) -> Option<String> + Copy,
+{
+    let provider = cfg.llm.provider.to_ascii_lowercase();
+    let endpoint = resolve_provider_endpoint(cfg, env_get);
+    let api_key = env_get(&cfg.llm.api_key_env_var);
+
+    if provider == "fallback" {
+        // Chain entries use their provider's default endpoint; an explicit
+        // `llm.endpoint` targets a single backend and would be wrong for the others.
+        return Ok(Box::new(FallbackProvider::new(
+            cfg.llm
+                .fallback
+                .iter()
+                .map(|entry| {
+                    let (name, model) = fallback_entry(entry);
+                    build_named_provider(
+                        cfg,
+                        &name.to_ascii_lowercase(),
+                        model.unwrap_or(&cfg.llm.model),
+                        None,
+                        api_key.clone(),
+                    )
+                })
+                .collect::<anyhow::Result<_>>()?,
+        )));
+    }
+    build_named_provider(cfg, &provider, &cfg.llm.model, endpoint, api_key)
+}
+
+/// Error for a known provider whose cargo feature this build left out, so it is
+/// not mistaken for a generic HTTP endpoint.
+#[cfg(not(all(
+    feature = "provider-ollama",
+    feature = "provider-openai-compat",
+    feature = "provider-azure"
+)))]
+fn provider_not_compiled_in(provider: &str, feature: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "provider `{provider}` needs the `{feature}` feature, which is not compiled in; \
+         reinstall with `cargo install --features {feature}`"
+    )
+}
+
+fn build_named_provider(
+    cfg: &AppConfig,
+    provider: &str,
+    model: &str,
+    endpoint: Option<String>,
+    api_key: Option<String>,
+) -> anyhow::Result<Box<dyn TranslatorProvider>> {
+    let model = cfg.llm.resolve_model(model);
+    let endpoint = endpoint.or_else(|| default_endpoint(cfg, provider));
+    if cfg.offline {
+        ensure_offline_provider(provider, endpoint.as_deref())?;
+    }
+    Ok(match provider {
+        "mock" => Box::new(MockProvider),
+        "command" => Box::new(
+            LocalBinaryProvider::new(
+                cfg.llm.command.command.clone(),
+                cfg.llm.command.args.clone(),
+            )
+            .with_prompt_template(prompt_template(cfg, COMMAND_PROMPT_TEMPLATE)),
+        ),
+        #[cfg(feature = "provider-ollama")]
+        "ollama" => Box::new(
+            OllamaProvider::new(
+                endpoint.unwrap_or_default(),
+                model.to_string(),
+                cfg.llm.temperature,
+                cfg.llm.max_tokens,
+            )
+            .with_prompt_template(prompt_template(cfg, OLLAMA_PROMPT_TEMPLATE))
+            .with_streaming(cfg.llm.stream)
+            .with_rate_limiter(rate_limiter(cfg)),
+        ),
+        #[cfg(feature = "provider-openai-compat")]
+        "chatgpt" => Box::new(
+            OpenAICompatProvider::new(
+                endpoint.unwrap_or_default(),
+                api_key,
+                model.to_string(),
+                cfg.llm.temperature,
+                cfg.llm.max_tokens,
+            )
+            .with_json_mode(cfg.llm.json_mode)
+            .with_prompt_template(prompt_template(cfg, OPENAI_PROMPT_TEMPLATE))
+            .with_rate_limiter(rate_limiter(cfg)),
+        ),
+        #[cfg(feature = "provider-openai-compat")]
+        "openrouter" => Box::new(
+            OpenAICompatProvider::new(
+                endpoint.unwrap_or_default(),
+                api_key,
+                model.to_string(),
+                cfg.llm.temperature,
+                cfg.llm.max_tokens,
+            )
+            .with_json_mode(cfg.llm.json_mode)
+            .with_prompt_template(prompt_template(cfg, OPENAI_PROMPT_TEMPLATE))
+            .with_rate_limiter(rate_limiter(cfg)),
+        ),
+        #[cfg(feature = "provider-openai-compat")]
+        "openai_compat" => Box::new(
+            OpenAICompatProvider::new(
+                endpoint.unwrap_or_default(),
+                api_key,
+                model.to_string(),
+                cfg.llm.temperature,
+                cfg.llm.max_tokens,
+            )
+            .with_json_mode(cfg.llm.json_mode)
+            .with_prompt_template(prompt_template(cfg, OPENAI_PROMPT_TEMPLATE))
+            .with_rate_limiter(rate_limiter(cfg)),
+        ),
+        #[cfg(feature = "provider-azure")]
+        "azure" => Box::new(
+            AzureOpenAIProvider::new(
+                endpoint.unwrap_or_default(),
+                cfg.llm.deployment.clone().unwrap_or_default(),
+                cfg.llm
+                    .api_version
+                    .clone()
+                    .unwrap_or_else(|| AZURE_DEFAULT_API_VERSION.to_string()),
+                api_key,
+                cfg.llm.temperature,
+                cfg.llm.max_tokens,
+            )
+            .with_json_mode(cfg.llm.json_mode)
+            .with_prompt_template(prompt_template(cfg, OPENAI_PROMPT_TEMPLATE))
+            .with_rate_limiter(rate_limiter(cfg)),
+        ),
+        #[cfg(not(feature = "provider-ollama"))]
+        "ollama" => return Err(provider_not_compiled_in(provider, "provider-ollama")),
+        #[cfg(not(feature = "provider-openai-compat"))]
+        "chatgpt" | "openrouter" | "openai_compat" => {
+            return Err(provider_not_compiled_in(provider, "provider-openai-compat"))
+        }
+        #[cfg(not(feature = "provider-azure"))]
+        "azure" => return Err(provider_not_compiled_in(provider, "provider-azure")),
+        #[cfg(feature = "provider-http")]
+        _ => Box::new(
+            HttpProvider::new(
+                endpoint.unwrap_or_default(),
+                api_key,
+                model.to_string(),
+                cfg.llm.temperature,
+                cfg.llm.max_tokens,
+            )
+            .with_prompt_template(prompt_template(cfg, HTTP_PROMPT_TEMPLATE))
+            .with_rate_limiter(rate_limiter(cfg)),
+        ),
+        #[cfg(not(feature = "provider-http"))]
+        _ => Box::new(MockProvider),
+    })
+}
+
+/// The endpoint `provider` talks to when none is configured: its
+/// `[llm.endpoints]` entry, else the built-in default.
+fn default_endpoint(cfg: &AppConfig, provider: &str) -> Option<String> {
+    if matches!(provider, "mock" | "command") {
+        return None;
+    }
+    if let Some(url) = cfg.llm.endpoint_for(provider) {
+        return Some(url.to_string());
+    }
+    let url = match provider {
+        "ollama" => "http://127.0.0.1:11434/api/generate",
+        "chatgpt" | "openai_compat" => "https://api.openai.com/v1/chat/completions",
+        "openrouter" => "https://openrouter.ai/api/v1/chat/completions",
+        // Azure has no default endpoint, so fallback chains still use `llm.endpoint`.
+        "azure" => return cfg.llm.endpoint.clone(),
+        _ => "http://localhost:8080/translate",
+    };
+    Some(url.to_string())
+}
+
+/// Providers that run entirely on this machine when their endpoint is loopback.
+const OFFLINE_PROVIDERS: &[&str] = &["mock", "ollama", "command"];
+
+/// Refuses providers that could reach beyond this machine in offline mode.
+fn ensure_offline_provider(provider: &str, endpoint: Option<&str>) -> anyhow::Result<()> {
+    if !OFFLINE_PROVIDERS.contains(&provider) {
+        anyhow::bail!(
+            "offline mode: provider `{provider}` is not allowed; use one of: {}",
+            OFFLINE_PROVIDERS.join(", ")
+        );
+    }
+    match endpoint {
+        None => Ok(()),
+        Some(url) if is_loopback_endpoint(url) => Ok(()),
+        Some(url) => anyhow::bail!(
+            "offline mode: provider `{provider}` endpoint {url} is not a loopback address"
+        ),
+    }
+}
+
+/// The tagged-block cache under `artifacts.dir`, keyed to the configured translator.
+fn block_cache(cfg: &AppConfig, refresh: bool) -> BlockCache {
+    let provider = cfg.llm.provider.to_ascii_lowercase();
+    let provider = if provider == "fallback" {
+        format!("fallback:{}", cfg.llm.fallback.join(","))
+    } else {
+        provider
+    };
+    BlockCache::new(Path::new(&cfg.artifacts.dir))
+        .with_refresh(refresh)
+        .with_translator(
+            &provider,
+            cfg.llm.resolve_model(&cfg.llm.model),
+            cfg.prompts.template.as_deref().unwrap_or_default(),
+        )
+}
+
+/// The configured `prompts.template`, or the provider's `default` wording.
+fn prompt_template(cfg: &AppConfig, default: &str) -> PromptTemplate {
+    PromptTemplate::new(cfg.prompts.template.as_deref().unwrap_or(default))
+        .with_max_context_chars(cfg.prompts.max_context_chars)
+}
+
+/// A fresh limiter for one provider when `llm.requests_per_minute` is set.
+///
+/// Waiting for a slot counts against `timeouts.translate_ms`.
+#[cfg(any(
+    feature = "provider-http",
+    feature = "provider-openai-compat",
+    feature = "provider-ollama"
+))]
+fn rate_limiter(cfg: &AppConfig) -> Option<Arc<RateLimiter>> {
+    RateLimiter::per_minute(
+        cfg.llm.requests_per_minute,
+        Duration::from_millis(cfg.timeouts.translate_ms),
+    )
+    .map(Arc::new)
+}
+
+fn resolve_provider_endpoint<F>(cfg: &AppConfig, env_get: F) -> Option<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    cfg.llm
+        .endpoint
+        .clone()
+        .filter(|v| !v.trim().is_empty())
+        .or_else(|| env_get(&cfg.llm.endpoint_env_var))
+        .filter(|v| !v.trim().is_empty())
+}
+
+/// Directory holding the last successfully fetched copy of remote policies.
+const POLICY_CACHE_DIR: &str = ".beeno/policy-cache";
+
+async fn policy_from_cfg(cfg: &AppConfig) -> anyhow::Result<Box<dyn RiskPolicy>> {
+    let policy_path = cfg.policy.policy_path.as_deref().map(str::trim);
+    let policy = match policy_path {
+        None | Some("") => DefaultRiskPolicy::default(),
+        // Plugins add to the built-in rules, so they run on top of the default policy.
+        Some(path) if is_wasm_policy(path) => DefaultRiskPolicy::default(),
+        Some(path) if path.starts_with("http://") || path.starts_with("https://") => {
+            if cfg.offline {
+                DefaultRiskPolicy::from_cached_url(path, Path::new(POLICY_CACHE_DIR))
+                    .map_err(|e| e.context("offline mode: remote policies are not fetched"))?
+            } else {
+                DefaultRiskPolicy::from_url(path, Path::new(POLICY_CACHE_DIR)).await?
+            }
+        }
+        Some(path) => DefaultRiskPolicy::from_path(Path::new(path))?,
+    };
+    if cfg.policy.explain {
+        eprint!("{}", explain_policy(&policy)?);
+    }
+    let policy = policy.with_allow_risky(cfg.policy.allow_risky.clone());
+    match policy_path {
+        Some(path) if is_wasm_policy(path) => wasm_policy_from_path(cfg, path, policy),
+        _ => Ok(Box::new(policy)),
+    }
+}
+
+/// Whether a local `policy_path` names a WebAssembly policy plugin.
+fn is_wasm_policy(path: &str) -> bool {
+    !path.starts_with("http://")
+        && !path.starts_with("https://")
+        && Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wasm"))
+}
+
+#[cfg(feature = "wasm-policy")]
+fn wasm_policy_from_path(
+    cfg: &AppConfig,
+    path: &str,
+    base: DefaultRiskPolicy,
+) -> anyhow::Result<Box<dyn RiskPolicy>> {
+    let policy = WasmPolicy::from_path(Path::new(path))?.with_base(base);
+    if cfg.policy.explain {
+        eprintln!("plugin source: {} (WebAssembly plugin)", policy.source());
+    }
+    Ok(Box::new(policy))
+}
+
+/// Without a WebAssembly runtime the plugin cannot run, so nothing does.
+#[cfg(not(feature = "wasm-policy"))]
+fn wasm_policy_from_path(
+    _cfg: &AppConfig,
+    path: &str,
+    _base: DefaultRiskPolicy,
+) -> anyhow::Result<Box<dyn RiskPolicy>> {
+    anyhow::bail!(
+        "policy {path} is a WebAssembly plugin, but beeno was built without the \
+         `wasm-policy` feature"
+    )
+}
+
+/// Renders the policy source and its resolved pattern lists for `--explain-policy`.
+fn explain_policy(policy: &DefaultRiskPolicy) -> anyhow::Result<String> {
+    Ok(format!(
+        "policy source: {}\n{}",
+        policy.source(),
+        toml::to_string_pretty(policy.config())?
+    ))
+}
+
+/// Config file names looked for in each location, in precedence order: when one
+/// directory holds several, only the first that exists is loaded and the others
+/// are ignored (TOML over JSON over YAML).
+const CONFIG_FILE_NAMES: [&str; 3] = [".beeno.toml", ".beeno.json", ".beeno.yaml"];
+
+/// The config file `dir` provides, or `dir/.beeno.toml` when it has none.
+fn config_file_in(dir: &Path) -> PathBuf {
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| dir.join(CONFIG_FILE_NAMES[0]))
+}
+
+fn local_config_path() -> PathBuf {
+    config_file_in(Path::new(""))
+}
+
+fn home_config_path() -> Option<PathBuf> {
+    std::env::var("HOME")
+        .ok()
+        .map(|home| config_file_in(Path::new(&home)))
+}
+
+fn load_config(profile: Option<&str>) -> anyhow::Result<AppConfig> {
+    let home = match &home_config_path() {
+        Some(path) => read_config_value(path)?,
+        None => None,
+    };
+    let local = read_config_value(&local_config_path())?;
+
+    let cfg = resolve_config(home, local, profile, |k| std::env::var(k).ok())?;
+    cfg.validate().map_err(|problems| {
+        anyhow::anyhow!("invalid configuration:\n  - {}", problems.join("\n  - "))
+    })?;
+    Ok(cfg)
+}
+
+/// Environment variables consulted by `apply_env_overrides`, with the key they set.
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("BEENO_PROVIDER", "llm.provider"),
+    ("BEENO_MODEL", "llm.model"),
+    ("BEENO_ENDPOINT", "llm.endpoint"),
+    ("BEENO_TEMPERATURE", "llm.temperature"),
+    ("BEENO_MAX_TOKENS", "llm.max_tokens"),
+    ("BEENO_MAX_TOKENS_WARN", "llm.max_tokens_warn"),
+    ("BEENO_ENDPOINT_ENV_VAR", "llm.endpoint_env_var"),
+    ("BEENO_API_KEY_ENV_VAR", "llm.api_key_env_var"),
+    ("BEENO_JSON_MODE", "llm.json_mode"),
+    ("BEENO_STREAM", "llm.stream"),
+    ("BEENO_AUTO_CONTINUE", "llm.auto_continue"),
+    ("BEENO_DEPLOYMENT", "llm.deployment"),
+    ("BEENO_API_VERSION", "llm.api_version"),
+    ("BEENO_REQUESTS_PER_MINUTE", "llm.requests_per_minute"),
+    ("BEENO_TARGET_LANGUAGE", "llm.target_language"),
+    ("BEENO_OFFLINE", "offline"),
+    ("BEENO_POLICY_PATH", "policy.policy_path"),
+    ("BEENO_CONFIRM_RISKY", "policy.confirm_risky"),
+    ("BEENO_CONFIRM_MODE", "policy.confirm_mode"),
+    ("BEENO_MIN_CONFIDENCE", "policy.min_confidence"),
+    ("BEENO_RESUME", "repl.resume"),
+    ("BEENO_SELF_HEAL_ENABLED", "self_heal.enabled"),
+    (
+        "BEENO_SELF_HEAL_AUTO_ON_RUN_FAILURE",
+        "self_heal.auto_on_run_failure",
+    ),
+    ("BEENO_APPLY_FIXES_DEFAULT", "self_heal.apply_fixes_default"),
+    ("BEENO_SELF_HEAL_MAX_ATTEMPTS", "self_heal.max_attempts"),
+    ("BEENO_ARTIFACT_DIR", "artifacts.dir"),
+    ("BEENO_ARTIFACT_KEEP_LAST", "artifacts.keep_last"),
+    ("BEENO_MAX_FILES", "limits.max_files"),
+    ("BEENO_MAX_CHANGED_LINES", "limits.max_changed_lines"),
+    ("BEENO_MAX_SOURCE_BYTES", "limits.max_source_bytes"),
+    ("BEENO_PROTECT_DENY", "protect.deny"),
+    ("BEENO_SERVER_GRACE_MS", "timeouts.server_grace_ms"),
+    (
+        "BEENO_TYPE_CHECK_BEFORE_RUN",
+        "runtime.type_check_before_run",
+    ),
+    ("BEENO_NO_REMOTE", "runtime.no_remote"),
+    ("BEENO_CACHED_ONLY", "runtime.cached_only"),
+];
+
+/// Returns `(key, env var)` pairs for config keys currently overridden by env.
+fn env_override_keys<F>(env_get: F) -> Vec<(&'static str, &'static str)>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    ENV_OVERRIDES
+        .iter()
+        .filter(|(var, _)| env_get(var).is_some())
+        .map(|(var, key)| (*key, *var))
+        .collect()
+}
+
+fn show_config<F>(cfg: &AppConfig, env_get: F, json_output: bool) -> anyhow::Result<()>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let overrides = env_override_keys(env_get);
+    if json_output {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                "ok",
+                "config",
+                "resolved configuration",
+                json!({
+                    "config": cfg,
+                    "env_overrides": overrides
+                        .iter()
+                        .map(|(key, var)| json!({"key": key, "env": var}))
+                        .collect::<Vec<_>>(),
+                }),
+            ))?
+        );
+        return Ok(());
+    }
+
+    for (key, var) in &overrides {
+        println!("# {key} overridden by env {var}");
+    }
+    print!("{}", toml::to_string_pretty(cfg)?);
+    Ok(())
+}
+
+fn show_config_paths(json_output: bool) -> anyhow::Result<()> {
+    let mut entries = vec![("local", Some(local_config_path()))];
+    entries.push(("home", home_config_path()));
+
+    if json_output {
+        let files: Vec<_> = entries
+            .iter()
+            .map(|(scope, path)| {
+                json!({
+                    "scope": scope,
+                    "path": path,
+                    "loaded": path.as_ref().is_some_and(|p| p.exists()),
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&JsonEnvelope::new(
+                "ok",
+                "config",
+                "config files in precedence order",
+                json!({ "files": files }),
+            ))?
+        );
+        return Ok(());
+    }
+
+    for (idx, (scope, path)) in entries.iter().enumerate() {
+        match path {
+            Some(path) if path.exists() => {
+                println!("{}. {} ({scope}, loaded)", idx + 1, path.display())
+            }
+            Some(path) => println!("{}. {} ({scope}, not found)", idx + 1, path.display()),
+            None => println!("{}. <unset HOME> ({scope}, skipped)", idx + 1),
+        }
+    }
+    Ok(())
+}
+
+/// Merges defaults, home, local, the selected profile, and env overrides in that order.
+///
+/// `profile` falls back to `BEENO_PROFILE`; naming a profile that no config file
+/// defines is an error.
+fn resolve_config<F>(
+    home: Option<Value>,
+    local: Option<Value>,
+    profile: Option<&str>,
+    env_get: F,
+) -> anyhow::Result<AppConfig>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut merged = Value::try_from(AppConfig::default())?;
+    if let Some(home_value) = home {
+        merge_toml(&mut merged, home_value);
+    }
+    if let Some(local_value) = local {
+        merge_toml(&mut merged, local_value);
+    }
+
+    let profiles = merged
+        .as_table_mut()
+        .and_then(|table| table.remove("profiles"));
+    let profile = profile
+        .map(str::to_string)
+        .or_else(|| env_get("BEENO_PROFILE"))
+        .filter(|name| !name.trim().is_empty());
+    if let Some(name) = profile {
+        let table = profiles.as_ref().and_then(Value::as_table);
+        match table.and_then(|t| t.get(&name)) {
+            Some(overlay) => merge_toml(&mut merged, overlay.clone()),
+            None => {
+                let available: Vec<&str> = table
+                    .map(|t| t.keys().map(String::as_str).collect())
+                    .unwrap_or_default();
+                anyhow::bail!(
+                    "unknown profile `{name}`; available profiles: {}",
+                    if available.is_empty() {
+                        "none defined".to_string()
+                    } else {
+                        available.join(", ")
+                    }
+                );
+            }
+        }
+    }
+
+    let mut cfg: AppConfig = merged.try_into()?;
+    apply_env_overrides(&mut cfg, env_get);
+    Ok(cfg)
+}
+
+/// Reads a config file into a TOML value for merging, choosing the format by
+/// extension: `.json`, `.yaml` (with the `config-yaml` feature), otherwise TOML.
+fn read_config_value(path: &Path) -> anyhow::Result<Option<Value>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let raw = fs::read_to_string(path)?;
+    let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => json_config_value(serde_json::from_str(&raw)?)?,
+        Some("yaml") => yaml_config_value(&raw)?,
+        _ => raw.parse::<Value>()?,
+    };
+    if !parsed.is_table() {
+        anyhow::bail!("{} must contain a table of settings", path.display());
+    }
+    Ok(Some(parsed))
+}
+
+/// Converts JSON (or YAML read as JSON) config to TOML; `null` entries are dropped,
+/// as if the key were absent, since TOML has no null.
+fn json_config_value(value: serde_json::Value) -> anyhow::Result<Value> {
+    fn drop_nulls(value: serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(map) => map
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, drop_nulls(value)))
+                .collect(),
+            serde_json::Value::Array(items) => items
+                .into_iter()
+                .filter(|value| !value.is_null())
+                .map(drop_nulls)
+                .collect(),
+            other => other,
+        }
+    }
+    Ok(Value::try_from(drop_nulls(value))?)
+}
+
+#[cfg(feature = "config-yaml")]
+fn yaml_config_value(raw: &str) -> anyhow::Result<Value> {
+    json_config_value(serde_yaml::from_str(raw)?)
+}
+
+#[cfg(not(feature = "config-yaml"))]
+fn yaml_config_value(_raw: &str) -> anyhow::Result<Value> {
+    anyhow::bail!("reading .beeno.yaml requires beeno built with the `config-yaml` feature")
+}
+
+fn merge_toml(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base_map), Value::Table(overlay_map)) => {
+            for (key, value) in overlay_map {
+                if let Some(base_value) = base_map.get_mut(&key) {
+                    merge_toml(base_value, value);
+                } else {
+                    base_map.insert(key, value);
+                }
+            }
+        }
+        (base_value, overlay_value) => {
+            *base_value = overlay_value;
+        }
+    }
+}
+
+fn apply_env_overrides<F>(cfg: &mut AppConfig, env_get: F)
+where
+    F: Fn(&str) -> Option<String>,
+{
+    if let Some(v) = env_get("BEENO_PROVIDER") {
+        cfg.llm.provider = v;
+    }
+    if let Some(v) = env_get("BEENO_MODEL") {
+        cfg.llm.model = v;
+    }
+    if let Some(v) = env_get("BEENO_ENDPOINT") {
+        cfg.llm.endpoint = Some(v);
+    }
+    if let Some(v) = env_get("BEENO_TEMPERATURE").and_then(|v| v.parse::<f32>().ok()) {
+        cfg.llm.temperature = v;
+    }
+    if let Some(v) = env_get("BEENO_MAX_TOKENS").and_then(|v| v.parse::<u32>().ok()) {
+        cfg.llm.max_tokens = v;
+    }
+    if let Some(v) = env_get("BEENO_MAX_TOKENS_WARN").and_then(|v| v.parse::<u32>().ok()) {
+        cfg.llm.max_tokens_warn = v;
+    }
+    if let Some(v) = env_get("BEENO_ENDPOINT_ENV_VAR") {
+        cfg.llm.endpoint_env_var = v;
+    }
+    if let Some(v) = env_get("BEENO_API_KEY_ENV_VAR") {
+        cfg.llm.api_key_env_var = v;
+    }
+    if let Some(v) = env_get("BEENO_JSON_MODE").and_then(|v| parse_bool(&v)) {
+        cfg.llm.json_mode = v;
+    }
+    if let Some(v) = env_get("BEENO_STREAM").and_then(|v| parse_bool(&v)) {
+        cfg.llm.stream = v;
+    }
+    if let Some(v) = env_get("BEENO_AUTO_CONTINUE").and_then(|v| parse_bool(&v)) {
+        cfg.llm.auto_continue = v;
+    }
+    if let Some(v) = env_get("BEENO_DEPLOYMENT") {
+        cfg.llm.deployment = Some(v);
+    }
+    if let Some(v) = env_get("BEENO_API_VERSION") {
+        cfg.llm.api_version = Some(v);
+    }
+    if let Some(v) = env_get("BEENO_REQUESTS_PER_MINUTE").and_then(|v| v.parse::<u32>().ok()) {
+        cfg.llm.requests_per_minute = v;
+    }
+    if let Some(v) = env_get("BEENO_TARGET_LANGUAGE").and_then(|v| TargetLanguage::parse(&v)) {
+        cfg.llm.target_language = v;
+    }
+
+    if let Some(v) = env_get("BEENO_OFFLINE").and_then(|v| parse_bool(&v)) {
+        cfg.offline = v;
+    }
+
+    if let Some(v) = env_get("BEENO_POLICY_PATH") {
+        cfg.policy.policy_path = Some(v);
+    }
+    if let Some(v) = env_get("BEENO_CONFIRM_RISKY").and_then(|v| parse_bool(&v)) {
+        cfg.policy.confirm_risky = v;
+        cfg.policy.confirm_mode = Some(if v {
+            ConfirmMode::Risky
+        } else {
+            ConfirmMode::Never
+        });
+    }
+    if let Some(v) = env_get("BEENO_CONFIRM_MODE").and_then(|v| ConfirmMode::parse(&v)) {
+        cfg.policy.confirm_mode = Some(v);
+    }
+    if let Some(v) = env_get("BEENO_MIN_CONFIDENCE").and_then(|v| v.parse::<f32>().ok()) {
+        cfg.policy.min_confidence = v;
+    }
+
+    if let Some(v) = env_get("BEENO_RESUME").and_then(|v| parse_bool(&v)) {
+        cfg.repl.resume = v;
+    }
+
+    if let Some(v) = env_get("BEENO_SELF_HEAL_ENABLED").and_then(|v| parse_bool(&v)) {
+        cfg.self_heal.enabled = v;
+    }
+    if let Some(v) = env_get("BEENO_SELF_HEAL_AUTO_ON_RUN_FAILURE").and_then(|v| parse_bool(&v)) {
+        cfg.self_heal.auto_on_run_failure = v;
+    }
+    if let Some(v) = env_get("BEENO_APPLY_FIXES_DEFAULT").and_then(|v| parse_bool(&v)) {
+        cfg.self_heal.apply_fixes_default = v;
+    }
+    if let Some(v) = env_get("BEENO_SELF_HEAL_MAX_ATTEMPTS").and_then(|v| v.parse::<u8>().ok()) {
+        cfg.self_heal.max_attempts = v;
+    }
+
+    if let Some(v) = env_get("BEENO_ARTIFACT_DIR") {
+        cfg.artifacts.dir = v;
+    }
+    if let Some(v) = env_get("BEENO_ARTIFACT_KEEP_LAST").and_then(|v| v.parse::<usize>().ok()) {
+        cfg.artifacts.keep_last = v;
+    }
+
+    if let Some(v) = env_get("BEENO_MAX_FILES").and_then(|v| v.parse::<usize>().ok()) {
+        cfg.limits.max_files = v;
+    }
+    if let Some(v) = env_get("BEENO_MAX_CHANGED_LINES").and_then(|v| v.parse::<usize>().ok()) {
+        cfg.limits.max_changed_lines = v;
+    }
+    if let Some(v) = env_get("BEENO_MAX_SOURCE_BYTES").and_then(|v| v.parse::<usize>().ok()) {
+        cfg.limits.max_source_bytes = v;
+    }
+
+    if let Some(v) = env_get("BEENO_PROTECT_DENY") {
+        cfg.protect.deny = v
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(ToString::to_string)
+            .collect();
+    }
+
+    if let Some(v) = env_get("BEENO_SERVER_GRACE_MS").and_then(|v| v.parse::<u64>().ok()) {
+        cfg.timeouts.server_grace_ms = v;
+    }
+    if let Some(v) = env_get("BEENO_TYPE_CHECK_BEFORE_RUN").and_then(|v| parse_bool(&v)) {
+        cfg.runtime.type_check_before_run = v;
+    }
+    if let Some(v) = env_get("BEENO_NO_REMOTE").and_then(|v| parse_bool(&v)) {
+        cfg.runtime.no_remote = v;
+    }
+    if let Some(v) = env_get("BEENO_CACHED_ONLY").and_then(|v| parse_bool(&v)) {
+        cfg.runtime.cached_only = v;
+    }
+}
+
+/// Applies `--env`, `--deno-config`, and `--import-map` on top of `[runtime]`.
+fn apply_runtime_flags(
+    cfg: &mut AppConfig,
+    env: Vec<(String, String)>,
+    deno_config: Option<String>,
+    import_map: Option<String>,
+) {
+    cfg.runtime.env.extend(env);
+    if deno_config.is_some() {
+        cfg.runtime.deno_config = deno_config;
+    }
+    if import_map.is_some() {
+        cfg.runtime.import_map = import_map;
+    }
+}
+
+/// Returns the `deno.json`/`deno.jsonc` next to `source`, if one exists.
+fn sibling_deno_config(source: &Path) -> Option<String> {
+    let dir = source.parent().unwrap_or(Path::new(""));
+    ["deno.json", "deno.jsonc"]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+/// Applies `--heal`/`--no-heal` and `--heal-attempts` to `[self_heal]`.
+fn apply_heal_flags(cfg: &mut AppConfig, heal: bool, no_heal: bool, attempts: Option<u8>) {
+    if heal {
+        cfg.self_heal.enabled = true;
+        cfg.self_heal.auto_on_run_failure = true;
+    }
+    if no_heal {
+        cfg.self_heal.enabled = false;
+    }
+    if let Some(attempts) = attempts {
+        cfg.self_heal.max_attempts = attempts;
+    }
+}
+
+/// Applies `--temperature` / `--max-tokens` / `--max-tokens-warn`, which take precedence
+/// over env and config.
+fn apply_llm_flags(
+    cfg: &mut AppConfig,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    max_tokens_warn: Option<u32>,
+) {
+    if let Some(temperature) = temperature {
+        cfg.llm.temperature = temperature;
+    }
+    if let Some(max_tokens) = max_tokens {
+        cfg.llm.max_tokens = max_tokens;
+    }
+    if let Some(max_tokens_warn) = max_tokens_warn {
+        cfg.llm.max_tokens_warn = max_tokens_warn;
+    }
+}
+
+/// Parses a `--temperature` argument in the range accepted by `llm.temperature`.
+fn parse_temperature(raw: &str) -> Result<f32, String> {
+    match raw.parse::<f32>() {
+        Ok(value) if (0.0..=2.0).contains(&value) => Ok(value),
+        Ok(value) => Err(format!("temperature must be between 0 and 2 (got {value})")),
+        Err(_) => Err(format!("expected a number, got `{raw}`")),
+    }
+}
+
+fn parse_bool(raw: &str) -> Option<bool> {
+    match raw.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "y" | "on" => Some(true),
+        "0" | "false" | "no" | "n" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn init_config_file(path: &Path, force: bool) -> anyhow::Result<()> {
+    if path.exists() && !force {
+        anyhow::bail!(
+            "{} already exists; re-run with --force to overwrite",
+            path.display()
+        );
+    }
+    fs::write(path, config_template()?)?;
+    Ok(())
+}
+
+/// Opening lines of the file written by `init-config`.
+const CONFIG_HEADER: &str = "# beeno configuration
+# precedence: CLI > env > local .beeno.toml > home ~/.beeno.toml > defaults
+";
+
+/// Comments placed above a key (`section.key`) or table header (`section`) in the
+/// generated config.
+const CONFIG_KEY_COMMENTS: &[(&str, &str)] = &[
+    (
+        "offline",
+        "# only allow local providers (mock, command, loopback ollama); skip remote policy fetches and browser opens",
+    ),
+    (
+        "llm.provider",
+        "# provider options: http, mock, ollama, chatgpt, openrouter, openai_compat, azure, command, fallback",
+    ),
+    (
+        "llm.fallback",
+        "# with provider = \"fallback\": ordered \"provider\" or \"provider:model\" entries to try
+# e.g. [\"chatgpt\", \"ollama:llama3.1\"]",
+    ),
+    (
+        "llm.max_tokens_warn",
+        "# ask before requests with max_tokens above this (non-interactive runs need --yes; 0 = off)",
+    ),
+    (
+        "llm.json_mode",
+        "# OpenAI-compatible providers: request a {\"code\", \"explanation\"} JSON object",
+    ),
+    (
+        "llm.stream",
+        "# ollama: stream the generation as newline-delimited JSON chunks",
+    ),
+    (
+        "llm.auto_continue",
+        "# ask the provider to finish code cut off by max_tokens or a dropped stream",
+    ),
+    (
+        "llm.requests_per_minute",
+        "# space provider requests to at most this many per minute (0 = unlimited)",
+    ),
+    (
+        "llm.target_language",
+        "# \"javascript\" asks for plain JS and transpiles any TypeScript in replies",
+    ),
+    (
+        "llm.command",
+        "# provider = \"command\": a local program that reads the prompt on stdin and prints code",
+    ),
+    (
+        "policy.allow_risky",
+        "# risky patterns containing these substrings print a warning instead of prompting",
+    ),
+    (
+        "policy.min_confidence",
+        "# translations reporting a lower confidence warn (and prompt unless confirm_mode is \"never\")",
+    ),
+    (
+        "repl.narrative_every",
+        "# ask the model for a short session summary every N REPL inputs (0 = off)",
+    ),
+    (
+        "repl.persist_session",
+        "# save context to ~/.beeno/session.json on exit (`repl --resume` also saves)",
+    ),
+    (
+        "repl.max_entry_chars",
+        "# cut long context entries, and drop the oldest ones past max_summary_chars (chars, 0 = no limit)",
+    ),
+    (
+        "timeouts.server_grace_ms",
+        "# time a managed server gets to finish in-flight requests after SIGTERM",
+    ),
+    (
+        "prompts.max_context_chars",
+        "# longest rendered {summary} sent to a provider, in chars (0 = no limit)",
+    ),
+    (
+        "permissions.eval",
+        "# granted by default per origin; --allow-* flags add to these, --no-default-perms ignores them",
+    ),
+    (
+        "runtime.cached_only",
+        "# refuse remote modules missing from Deno's cache (`--cached-only`)",
+    ),
+    (
+        "runtime.no_remote",
+        "# refuse non-local imports (`--no-remote`)",
+    ),
+    (
+        "runtime.type_check_before_run",
+        "# `deno check` the inlined `run` script first; type errors stop the run (also `--type-check`)",
+    ),
+];
+
+/// Commented-out examples appended to the end of a table, for options whose
+/// defaults are unset and therefore absent from the serialized config.
+const CONFIG_TABLE_NOTES: &[(&str, &str)] = &[
+    (
+        "llm",
+        "# optional explicit endpoint override (for custom URLs / OpenAI-compatible gateways)
+# endpoint = \"https://gateway.example.com/v1/chat/completions\"
+# provider = \"azure\": endpoint is the resource URL (https://<resource>.openai.azure.com)
+# deployment = \"gpt-4o\"
+# api_version = \"2024-10-21\"",
+    ),
+    (
+        "llm.command",
+        "# short model names; `model`, `--model`, `/model`, and fallback entries expand them
+# [llm.aliases]
+# fast = \"gpt-4.1-mini\"
+# default endpoint per provider when `endpoint` is unset (built-in defaults otherwise)
+# [llm.endpoints]
+# chatgpt = \"https://gateway.example.com/v1/chat/completions\"",
+    ),
+    (
+        "policy",
+        "# local path or http(s) URL of a policy file
+# policy_path = \"policy.json\"
+# when to prompt before executing: \"never\", \"risky\", \"always\", or \"net-or-write\"
+# (unset follows confirm_risky)
+# confirm_mode = \"risky\"",
+    ),
+    (
+        "prompts",
+        "# replaces each provider's default prompt; placeholders: {mode} {input} {summary} {file} {language}
+# template = \"Translate to Deno 2.x JavaScript/TypeScript only. Input mode: {mode}.\\nSession context:\\n{summary}\\nInput: {input}\"",
+    ),
+    (
+        "runtime",
+        "# deno.json passed as --config (defaults to one next to the file for `run`/`dev --file`)
+# deno_config = \"deno.json\"
+# import_map = \"import_map.json\"
+# code prepended to every eval/repl/dev program before the policy runs
+# prelude = \"globalThis.onunhandledrejection = (e) => console.error(e.reason);\"
+# variables set on executed code; `$NAME` expands from beeno's environment
+# [runtime.env]
+# API_BASE = \"https://api.example.com\"
+# API_TOKEN = \"$API_TOKEN\"",
+    ),
+];
+
+/// Renders `AppConfig::default()` as TOML with explanatory comments attached, so
+/// the generated file always matches the real defaults.
+fn config_template() -> anyhow::Result<String> {
+    fn push_comment(out: &mut String, comments: &[(&str, &str)], path: &str) {
+        if let Some((_, text)) = comments.iter().find(|(key, _)| *key == path) {
+            out.push_str(text);
+            out.push('\n');
+        }
+    }
+
+    let body = AppConfig::default().to_toml_string()?;
+    let mut out = String::from(CONFIG_HEADER);
+    let mut section = String::new();
+    out.push('\n');
+    for line in body.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(table) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            push_comment(&mut out, CONFIG_TABLE_NOTES, &section);
+            out.push('\n');
+            section = table.to_string();
+            push_comment(&mut out, CONFIG_KEY_COMMENTS, &section);
+        } else if let Some((key, _)) = line.split_once(" = ") {
+            let path = if section.is_empty() {
+                key.to_string()
+            } else {
+                format!("{section}.{key}")
+            };
+            push_comment(&mut out, CONFIG_KEY_COMMENTS, &path);
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    push_comment(&mut out, CONFIG_TABLE_NOTES, &section);
+    Ok(out)
+}
+
+/// Rejects paths matching any `protect.deny` pattern.
+fn ensure_not_protected(path: &Path, deny: &[String]) -> anyhow::Result<()> {
+    if let Some(pattern) = protected_pattern(path, deny) {
+        anyhow::bail!(
+            "{} is protected by protect.deny pattern `{pattern}`",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Checks a `--tee` path against `protect.deny` and empties it, so repeated runs
+/// and self-heal retries of one invocation append to a fresh file.
+fn start_tee(path: Option<PathBuf>, deny: &[String]) -> anyhow::Result<Option<PathBuf>> {
+    if let Some(path) = &path {
+        ensure_not_protected(path, deny)?;
+        fs::write(path, "")
+            .map_err(|e| anyhow::anyhow!("failed to create tee file {}: {e}", path.display()))?;
+    }
+    Ok(path)
+}
+
+/// Rejects `--allow-read` / `--allow-write` entries that name a `protect.deny` path
+/// or a directory holding one (including `.` and `*`), so permission flags cannot
+/// expose files the protect list guards.
+fn ensure_permissions_unprotected(
+    permissions: &DenoPermissions,
+    deny: &[String],
+) -> anyhow::Result<()> {
+    let entries = [
+        ("--allow-read", &permissions.allow_read),
+        ("--allow-write", &permissions.allow_write),
+    ];
+    for (flag, paths) in entries {
+        for path in paths {
+            let pattern =
+                protected_pattern(Path::new(path), deny).or_else(|| covered_pattern(path, deny));
+            if let Some(pattern) = pattern {
+                anyhow::bail!(
+                    "{flag}={path} conflicts with protect.deny pattern `{pattern}`; \
+                     grant a narrower path or remove the pattern from protect.deny"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the first deny pattern matching the path or its file name.
+fn protected_pattern<'a>(path: &Path, deny: &'a [String]) -> Option<&'a str> {
+    let full = path.to_string_lossy();
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    deny.iter()
+        .map(String::as_str)
+        .find(|pattern| glob_match(pattern, &name) || glob_match(pattern, &full))
+}
+
+/// Returns the first deny pattern, taken relative to the working directory, that
+/// lies inside the directory `grant` names; `*` grants everything.
+fn covered_pattern<'a>(grant: &str, deny: &'a [String]) -> Option<&'a str> {
+    if grant == "*" {
+        return deny.first().map(String::as_str);
+    }
+    let grant = lexical_absolute(Path::new(grant));
+    deny.iter().map(String::as_str).find(|pattern| {
+        let pattern = lexical_absolute(Path::new(pattern));
+        let mut pattern = pattern.components();
+        grant.components().all(|dir| match pattern.next() {
+            Some(Component::Normal(part)) => {
+                glob_match(&part.to_string_lossy(), &dir.as_os_str().to_string_lossy())
+            }
+            Some(other) => other == dir,
+            None => false,
+        })
+    })
+}
+
+/// `path` made absolute against the working directory, with `.` and `..`
+/// resolved lexically (symlinks are not followed).
+fn lexical_absolute(path: &Path) -> PathBuf {
+    let mut out = std::env::current_dir().unwrap_or_default();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Minimal glob matcher supporting `*` (any run) and `?` (any single char).
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star_pi, star_ti)) = star {
+            pi = star_pi + 1;
+            ti = star_ti + 1;
+            star = Some((star_pi, star_ti + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+fn render_engine_error(err: EngineError) -> anyhow::Error {
+    match err {
+        EngineError::Blocked(reasons) => {
+            anyhow::anyhow!(
+                "blocked by policy: {}; retry with safer instructions",
+                reasons.join(", ")
+            )
+        }
+        EngineError::Provider(ProviderError::RateLimited {
+            retry_after: Some(delay),
+        }) => anyhow::anyhow!(
+            "rate limited by provider, retry in {}s",
+            delay.as_secs().max(1)
+        ),
+        EngineError::Provider(ProviderError::RateLimited { retry_after: None }) => {
+            anyhow::anyhow!("rate limited by provider, retry shortly")
+        }
+        other => anyhow::anyhow!(other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use beeno_core::types::ProtectConfig;
+    use beeno_core::util::unique_temp_path;
+    use clap::Parser;
+    use std::collections::HashMap;
+
+    #[test]
+    fn config_precedence_cli_env_local_home_defaults() {
+        let home = Some(
+            r#"
+            [llm]
+            model = "home-model"
+
+            [artifacts]
+            keep_last = 99
+            "#
+            .parse::<Value>()
+            .expect("home parse"),
+        );
+
+        let local = Some(
+            r#"
+            [llm]
+            model = "local-model"
+
+            [policy]
+            confirm_risky = false
+            "#
+            .parse::<Value>()
+            .expect("local parse"),
+        );
+
+        let env = HashMap::from([
+            ("BEENO_MODEL".to_string(), "env-model".to_string()),
+            ("BEENO_PROVIDER".to_string(), "mock".to_string()),
+        ]);
+
+        let cfg =
+            resolve_config(home, local, None, |k| env.get(k).cloned()).expect("resolve config");
+
+        assert_eq!(cfg.llm.model, "env-model");
+        assert_eq!(cfg.llm.provider, "mock");
+        assert!(!cfg.policy.confirm_risky);
+        assert_eq!(cfg.artifacts.keep_last, 99);
+    }
+
+    #[test]
+    fn confirm_mode_env_overrides_legacy_toggle() {
+        let local = Some(
+            "[policy]\nconfirm_mode = \"always\"\n"
+                .parse::<Value>()
+                .expect("local parse"),
+        );
+        let env = HashMap::from([("BEENO_CONFIRM_RISKY".to_string(), "false".to_string())]);
+        let cfg = resolve_config(None, local.clone(), None, |k| env.get(k).cloned())
+            .expect("resolve config");
+        assert_eq!(cfg.policy.effective_confirm_mode(), ConfirmMode::Never);
+
+        let env = HashMap::from([
+            ("BEENO_CONFIRM_RISKY".to_string(), "false".to_string()),
+            ("BEENO_CONFIRM_MODE".to_string(), "net-or-write".to_string()),
+        ]);
+        let cfg =
+            resolve_config(None, local, None, |k| env.get(k).cloned()).expect("resolve config");
+        assert_eq!(cfg.policy.effective_confirm_mode(), ConfirmMode::NetOrWrite);
+    }
+
+    #[test]
+    fn profile_overrides_base_config_before_env() {
+        let local = Some(
+            r#"
+            [llm]
+            provider = "chatgpt"
+            model = "gpt-4.1-mini"
+
+            [profiles.local.llm]
+            provider = "ollama"
+            model = "llama3.1"
+
+            [profiles.prod.policy]
+            confirm_risky = false
+            "#
+            .parse::<Value>()
+            .expect("local parse"),
+        );
+
+        let cfg = resolve_config(None, local.clone(), Some("local"), |_| None).expect("profile");
+        assert_eq!(cfg.llm.provider, "ollama");
+        assert_eq!(cfg.llm.model, "llama3.1");
+
+        let env = HashMap::from([
+            ("BEENO_PROFILE".to_string(), "local".to_string()),
+            ("BEENO_MODEL".to_string(), "env-model".to_string()),
+        ]);
+        let cfg = resolve_config(None, local.clone(), None, |k| env.get(k).cloned())
+            .expect("env profile");
+        assert_eq!(cfg.llm.provider, "ollama");
+        assert_eq!(cfg.llm.model, "env-model");
+
+        let cfg = resolve_config(None, local.clone(), None, |_| None).expect("no profile");
+        assert_eq!(cfg.llm.provider, "chatgpt");
+
+        let err =
+            resolve_config(None, local, Some("staging"), |_| None).expect_err("unknown profile");
+        assert_eq!(
+            err.to_string(),
+            "unknown profile `staging`; available profiles: local, prod"
+        );
+    }
+
+    #[test]
+    fn env_override_keys_report_set_variables() {
+        let env = HashMap::from([
+            ("BEENO_MODEL".to_string(), "env-model".to_string()),
+            ("BEENO_PROTECT_DENY".to_string(), ".env".to_string()),
+        ]);
+        let keys = env_override_keys(|k| env.get(k).cloned());
+        assert_eq!(
+            keys,
+            vec![
+                ("llm.model", "BEENO_MODEL"),
+                ("protect.deny", "BEENO_PROTECT_DENY")
+            ]
+        );
+    }
+
+    #[test]
+    fn config_command_parses_actions() {
+        let cli = Cli::try_parse_from(["beeno", "config", "show"]).expect("cli parse");
+        assert!(matches!(
+            cli.cmd,
+            Commands::Config {
+                action: ConfigAction::Show
+            }
+        ));
+        let cli = Cli::try_parse_from(["beeno", "config", "path"]).expect("cli parse");
+        assert!(matches!(
+            cli.cmd,
+            Commands::Config {
+                action: ConfigAction::Path
+            }
+        ));
+    }
+
+    #[test]
+    fn init_config_requires_force_to_overwrite() {
+        let base = unique_temp_path("beeno-cli-test");
+        fs::create_dir_all(&base).expect("create temp dir");
+        let cfg_path = base.join(".beeno.toml");
+
+        init_config_file(&cfg_path, false).expect("must create first config");
+        let err = init_config_file(&cfg_path, false).expect_err("must reject overwrite");
+        assert!(err.to_string().contains("--force"));
+
+        init_config_file(&cfg_path, true).expect("force overwrite should succeed");
+        let content = fs::read_to_string(&cfg_path).expect("read config");
+        assert!(content.contains("[self_heal]"));
+
+        fs::remove_dir_all(&base).expect("cleanup temp dir");
+    }
+
+    #[test]
+    fn config_files_are_read_by_extension_and_searched_in_precedence_order() {
+        let dir = unique_temp_path("beeno-cli-formats");
+        fs::create_dir_all(&dir).expect("create temp dir");
+        let read = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            fs::write(&path, contents).expect("write config");
+            let value = read_config_value(&path).expect("parse").expect("present");
+            resolve_config(None, Some(value), None, |_| None).expect("resolve")
+        };
+
+        let toml = read(
+            ".beeno.toml",
+            "[llm]\nmodel = \"from-toml\"\ntemperature = 0.5\n",
+        );
+        assert_eq!(
+            (toml.llm.model.as_str(), toml.llm.temperature),
+            ("from-toml", 0.5)
+        );
+
+        let json = read(
+            ".beeno.json",
+            r#"{"llm": {"model": "from-json", "endpoint": null, "max_tokens": 512}}"#,
+        );
+        assert_eq!(json.llm.model, "from-json");
+        assert_eq!(json.llm.max_tokens, 512);
+        assert_eq!(json.llm.endpoint, None);
+
+        #[cfg(feature = "config-yaml")]
+        {
+            let yaml = read(
+                ".beeno.yaml",
+                "llm:\n  model: from-yaml\n  json_mode: true\n",
+            );
+            assert_eq!(yaml.llm.model, "from-yaml");
+            assert!(yaml.llm.json_mode);
+        }
+        #[cfg(not(feature = "config-yaml"))]
+        {
+            fs::write(dir.join(".beeno.yaml"), "llm: {}\n").expect("write config");
+            let err = read_config_value(&dir.join(".beeno.yaml")).expect_err("feature off");
+            assert!(err.to_string().contains("config-yaml"));
+        }
+
+        assert_eq!(config_file_in(&dir), dir.join(".beeno.toml"));
+        fs::remove_file(dir.join(".beeno.toml")).expect("remove toml");
+        assert_eq!(config_file_in(&dir), dir.join(".beeno.json"));
+        fs::remove_file(dir.join(".beeno.json")).expect("remove json");
+        assert_eq!(config_file_in(&dir), dir.join(".beeno.yaml"));
+        fs::remove_file(dir.join(".beeno.yaml")).expect("remove yaml");
+        assert_eq!(config_file_in(&dir), dir.join(".beeno.toml"));
+
+        fs::write(dir.join(".beeno.json"), "[1, 2]").expect("write config");
+        assert!(read_config_value(&dir.join(".beeno.json")).is_err());
+
+        fs::remove_dir_all(&dir).expect("cleanup temp dir");
+    }
+
+    #[test]
+    fn config_template_round_trips_to_defaults() {
+        let template = config_template().expect("render template");
+        assert!(template.starts_with("# beeno configuration"));
+        assert!(template.contains("# provider options:"));
+        assert!(template.contains("[repl]") && template.contains("[timeouts]"));
+
+        let local = template.parse::<Value>().expect("template must parse");
+        let cfg = resolve_config(None, Some(local), None, |_| None).expect("resolve");
+        assert_eq!(
+            cfg.to_toml_string().expect("serialize"),
+            AppConfig::default().to_toml_string().expect("serialize")
+        );
+    }
+
+    #[test]
+    fn provider_endpoint_prefers_config_then_env() {
+        let mut cfg = AppConfig::default();
+        cfg.llm.endpoint = Some("https://example.invalid/v1/chat/completions".to_string());
+        cfg.llm.endpoint_env_var = "CUSTOM_ENDPOINT".to_string();
+
+        let env = HashMap::from([(
+            "CUSTOM_ENDPOINT".to_string(),
+            "https://env.invalid/v1/chat/completions".to_string(),
+        )]);
+
+        let endpoint = resolve_provider_endpoint(&cfg, |k| env.get(k).cloned());
+        assert_eq!(
+            endpoint.as_deref(),
+            Some("https://example.invalid/v1/chat/completions")
+        );
+
+        cfg.llm.endpoint = Some("".to_string());
+        let endpoint = resolve_provider_endpoint(&cfg, |k| env.get(k).cloned());
+        assert_eq!(
+            endpoint.as_deref(),
+            Some("https://env.invalid/v1/chat/completions")
+        );
+    }
+
+    #[test]
+    fn endpoint_table_entry_wins_over_builtin_default() {
+        let mut cfg = AppConfig::default();
+        assert_eq!(
+            default_endpoint(&cfg, "chatgpt").as_deref(),
+            Some("https://api.openai.com/v1/chat/completions")
+        );
+
+        cfg.llm.endpoints = BTreeMap::from([
+            (
+                "ChatGPT".to_string(),
+                "https://gateway.example.com/v1/chat/completions".to_string(),
+            ),
+            ("ollama".to_string(), " ".to_string()),
+        ]);
+        assert_eq!(
+            default_endpoint(&cfg, "chatgpt").as_deref(),
+            Some("https://gateway.example.com/v1/chat/completions")
+        );
+        assert_eq!(
+            default_endpoint(&cfg, "ollama").as_deref(),
+            Some("http://127.0.0.1:11434/api/generate")
+        );
+        assert_eq!(default_endpoint(&cfg, "mock"), None);
+    }
+
+    #[tokio::test]
+    async fn empty_policy_path_uses_default_policy() {
+        let mut cfg = AppConfig::default();
+        cfg.policy.policy_path = Some("".to_string());
+        let result = policy_from_cfg(&cfg).await;
+        assert!(result.is_ok());
+    }
+
+    #[cfg(not(feature = "wasm-policy"))]
+    #[tokio::test]
+    async fn wasm_policy_requires_the_feature() {
+        let mut cfg = AppConfig::default();
+        cfg.policy.policy_path = Some("org-policy.WASM".to_string());
+        let err = policy_from_cfg(&cfg)
+            .await
+            .map(|_| ())
+            .expect_err("no runtime");
+        assert!(err.to_string().contains("`wasm-policy` feature"), "{err}");
+    }
+
+    #[test]
+    fn explain_policy_names_source_and_pattern_lists() {
+        let text = explain_policy(&DefaultRiskPolicy::default()).expect("explain");
+        assert!(text.starts_with("policy source: default\n"));
+        assert!(text.contains("blocked_patterns = ["));
+        assert!(text.contains("\"Deno.Command\""));
+    }
+
+    #[test]
+    fn run_defaults_deno_config_to_sibling_file() {
+        let dir = unique_temp_path("beeno-cli-deno-config");
+        fs::create_dir_all(&dir).expect("mkdir");
+        let script = dir.join("main.ts");
+        assert_eq!(sibling_deno_config(&script), None);
+        fs::write(dir.join("deno.json"), "{}").expect("write config");
+        assert_eq!(
+            sibling_deno_config(&script),
+            Some(dir.join("deno.json").to_string_lossy().to_string())
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn env_flag_parses_key_value_pairs() {
+        let cli = Cli::try_parse_from([
+            "beeno",
+            "eval",
+            "print hi",
+            "--env",
+            "API_BASE=https://api.example.com/?a=b",
+            "--env",
+            "EMPTY=",
+        ])
+        .expect("cli parse");
+        match cli.cmd {
+            Commands::Eval { env, .. } => assert_eq!(
+                env,
+                vec![
+                    (
+                        "API_BASE".to_string(),
+                        "https://api.example.com/?a=b".to_string()
+                    ),
+                    ("EMPTY".to_string(), String::new()),
+                ]
+            ),
+            _ => panic!("expected eval command"),
+        }
+        assert!(Cli::try_parse_from(["beeno", "eval", "x", "--env", "NOVALUE"]).is_err());
+    }
+
+    #[test]
+    fn offline_mode_refuses_remote_providers() {
+        let env = HashMap::from([("BEENO_OFFLINE".to_string(), "true".to_string())]);
+        let mut cfg = resolve_config(None, None, None, |k| env.get(k).cloned()).expect("resolve");
+        assert!(cfg.offline);
+
+        cfg.llm.provider = "openai_compat".to_string();
+        cfg.llm.endpoint = Some("https://api.openai.com/v1/chat/completions".to_string());
+        let err = build_provider(&cfg, |_| None)
+            .err()
+            .expect("remote provider refused");
+        assert!(err.to_string().contains("offline mode"), "{err}");
+
+        cfg.llm.provider = "ollama".to_string();
+        let err = build_provider(&cfg, |_| None)
+            .err()
+            .expect("remote endpoint refused");
+        assert!(err.to_string().contains("not a loopback address"), "{err}");
+
+        cfg.llm.endpoint = None;
+        assert!(build_provider(&cfg, |_| None).is_ok());
+        cfg.llm.endpoint = Some("http://localhost:11434/api/generate".to_string());
+        assert!(build_provider(&cfg, |_| None).is_ok());
+
+        cfg.llm.provider = "fallback".to_string();
+        cfg.llm.fallback = vec!["ollama".to_string(), "chatgpt".to_string()];
+        assert!(build_provider(&cfg, |_| None).is_err());
+    }
+
+    #[cfg(not(feature = "provider-azure"))]
+    #[test]
+    fn providers_left_out_of_the_build_are_refused() {
+        let mut cfg = AppConfig::default();
+        cfg.llm.provider = "azure".to_string();
+        let err = build_provider(&cfg, |_| None)
+            .err()
+            .expect("azure is not compiled in");
+        assert!(err.to_string().contains("not compiled in"), "{err}");
+    }
+
+    #[test]
+    fn max_tokens_above_the_soft_cap_needs_confirmation() {
+        let mut cfg = AppConfig::default();
+        assert_eq!(cfg.llm.max_tokens_warn, 4096);
+        assert_eq!(max_tokens_over_cap(&cfg), None);
+
+        cfg.llm.max_tokens = 100_000;
+        let warning = max_tokens_over_cap(&cfg).expect("over the cap");
+        assert!(
+            warning.contains("100000") && warning.contains("4096"),
+            "{warning}"
+        );
+
+        let cli = Cli::try_parse_from(["beeno", "repl", "--max-tokens-warn", "0"]).expect("parse");
+        let Commands::Repl {
+            max_tokens_warn, ..
+        } = cli.cmd
+        else {
+            panic!("expected repl command");
+        };
+        apply_llm_flags(&mut cfg, None, None, max_tokens_warn);
+        assert_eq!(max_tokens_over_cap(&cfg), None);
+    }
+
+    #[test]
+    fn max_tokens_confirmation_skips_providers_without_token_cost() {
+        let mut cfg = AppConfig::default();
+        cfg.llm.max_tokens = 100_000;
+        cfg.llm.provider = "fallback".to_string();
+        cfg.llm.fallback = vec!["command".to_string(), "Mock:any".to_string()];
+        let backends = configured_backends(&cfg);
+        assert_eq!(backends, vec!["command", "mock"]);
+        assert!(confirm_max_tokens(&cfg, &backends).is_ok());
+
+        cfg.llm.provider = "mock".to_string();
+        assert!(build_provider(&cfg, |_| None).is_ok());
+    }
+
+    #[test]
+    fn llm_flags_override_env_and_validate_ranges() {
+        let cli = Cli::try_parse_from([
+            "beeno",
+            "eval",
+            "print hi",
+            "--temperature",
+            "0.7",
+            "--max-tokens",
+            "64",
+        ])
+        .expect("cli parse");
+        let Commands::Eval {
+            temperature,
+            max_tokens,
+            max_tokens_warn,
+            ..
+        } = cli.cmd
+        else {
+            panic!("expected eval command");
+        };
+        let env = HashMap::from([
+            ("BEENO_TEMPERATURE".to_string(), "1.5".to_string()),
+            ("BEENO_MAX_TOKENS".to_string(), "2048".to_string()),
+        ]);
+        let mut cfg = resolve_config(None, None, None, |k| env.get(k).cloned()).expect("resolve");
+        assert_eq!(cfg.llm.max_tokens, 2048);
+        apply_llm_flags(&mut cfg, temperature, max_tokens, max_tokens_warn);
+        assert_eq!(cfg.llm.temperature, 0.7);
+        assert_eq!(cfg.llm.max_tokens, 64);
+
+        for bad in [
+            ["beeno", "repl", "--temperature", "2.5"],
+            ["beeno", "run", "--temperature", "warm"],
+            ["beeno", "eval", "--max-tokens", "0"],
+        ] {
+            assert!(Cli::try_parse_from(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_runs_stop_at_first_failure_unless_keep_going() {
+        let fail_second = |calls: &std::cell::Cell<u32>| {
+            calls.set(calls.get() + 1);
+            let n = calls.get();
+            async move {
+                if n == 2 {
+                    Err(EngineError::RuntimeFailure(
+                        beeno_core::types::ExecutionFailure {
+                            exit_code: Some(3),
+                            stdout: String::new(),
+                            stderr: String::new(),
+                        },
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+        };
+
+        let calls = std::cell::Cell::new(0);
+        let repeat = Repeat {
+            repeat: 4,
+            keep_going: false,
+        };
+        let (runs, error) = execute_repeated(repeat, true, || fail_second(&calls)).await;
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1].exit_code, Some(3));
+        assert!(error.is_some());
+
+        let calls = std::cell::Cell::new(0);
+        let repeat = Repeat {
+            repeat: 4,
+            keep_going: true,
+        };
+        let (runs, error) = execute_repeated(repeat, true, || fail_second(&calls)).await;
+        assert_eq!(
+            runs.iter().map(|r| r.ok).collect::<Vec<_>>(),
+            [true, false, true, true]
+        );
+        assert!(error.is_some());
+        assert!(summarize_runs(&runs).starts_with("4 runs: 3 ok, 1 failed; total "));
+
+        assert!(Cli::try_parse_from(["beeno", "eval", "x", "--repeat", "0"]).is_err());
+        assert!(Cli::try_parse_from(["beeno", "run", "a.ts", "--keep-going"]).is_err());
+    }
+
+    #[test]
+    fn failed_runs_forward_the_deno_exit_code() {
+        let failure = |exit_code| {
+            render_engine_error(EngineError::RuntimeFailure(
+                beeno_core::types::ExecutionFailure {
+                    exit_code,
+                    stdout: String::new(),
+                    stderr: String::new(),
+                },
+            ))
+        };
+        assert_eq!(child_exit_code(&failure(Some(42))), Some(42));
+        assert_eq!(child_exit_code(&failure(None)), None);
+        assert_eq!(
+            child_exit_code(&render_engine_error(EngineError::RuntimeMissing)),
+            Some(RUNTIME_MISSING_EXIT_CODE)
+        );
+        assert_eq!(
+            child_exit_code(&render_engine_error(EngineError::Blocked(vec![]))),
+            None
+        );
+        assert_eq!(
+            success_line("eval", Duration::from_millis(1234)),
+            "✓ executed (mode=eval, 1.2s)"
+        );
+        assert!(!show_success_line(false, true));
+        assert!(Cli::try_parse_from(["beeno", "eval", "x", "--quiet"]).is_ok());
+        assert!(Cli::try_parse_from(["beeno", "eval", "x", "--quiet", "--verbose"]).is_err());
     }
 
-    if let Some(v) = env_get("BEENO_POLICY_PATH") {
-        cfg.policy.policy_path = Some(v);
-    }
-    if let Some(v) = env_get("BEENO_CONFIRM_RISKY").and_then(|v| parse_bool(&v)) {
-        cfg.policy.confirm_risky = v;
+    #[test]
+    fn permission_report_shows_needed_granted_and_missing() {
+        let granted = DenoPermissions {
+            allow_read: vec!["./data".to_string()],
+            allow_env: true,
+            ..Default::default()
+        };
+        let report = analyze_permissions("await Deno.readTextFile('x'); fetch(u);", &granted);
+        assert_eq!(
+            format_permission_report(&report),
+            "permissions:\n  read  needed      granted (./data)\n  write not needed  not granted\n  \
+             net   needed      not granted\n  env   not needed  granted\n  run   not needed  \
+             not granted\nmissing: --allow-net"
+        );
+        assert!(Cli::try_parse_from(["beeno", "eval", "x", "--perms"]).is_ok());
     }
 
-    if let Some(v) = env_get("BEENO_SELF_HEAL_ENABLED").and_then(|v| parse_bool(&v)) {
-        cfg.self_heal.enabled = v;
-    }
-    if let Some(v) = env_get("BEENO_SELF_HEAL_AUTO_ON_RUN_FAILURE").and_then(|v| parse_bool(&v)) {
-        cfg.self_heal.auto_on_run_failure = v;
-    }
-    if let Some(v) = env_get("BEENO_APPLY_FIXES_DEFAULT").and_then(|v| parse_bool(&v)) {
-        cfg.self_heal.apply_fixes_default = v;
+    #[test]
+    fn granting_a_missing_permission_names_its_call_and_clears_it() {
+        let source = "const url = 'https://example.com';\nawait fetch(url);";
+        let mut permissions = DenoPermissions::default();
+        let report = analyze_permissions(source, &permissions);
+        let trigger = report.trigger("--allow-net").expect("net trigger");
+        assert_eq!(
+            describe_trigger(trigger),
+            "`fetch(` on line 2 needs --allow-net: await fetch(url);"
+        );
+        grant_permission(&mut permissions, "--allow-net").expect("grant");
+        assert_eq!(permission_args(&permissions), vec!["--allow-net"]);
+        assert!(analyze_permissions(source, &permissions).missing.is_empty());
     }
-    if let Some(v) = env_get("BEENO_SELF_HEAL_MAX_ATTEMPTS").and_then(|v| v.parse::<u8>().ok()) {
-        cfg.self_heal.max_attempts = v;
+
+    #[test]
+    fn file_grants_are_not_offered_while_paths_are_protected() {
+        let deny = ProtectConfig::default().deny;
+        assert!(can_offer_grant("--allow-net", &deny));
+        assert!(!can_offer_grant("--allow-read", &deny));
+        assert!(!can_offer_grant("--allow-write", &deny));
+        assert!(can_offer_grant("--allow-write", &[]));
     }
 
-    if let Some(v) = env_get("BEENO_ARTIFACT_DIR") {
-        cfg.artifacts.dir = v;
+    #[test]
+    fn preview_shows_code_and_risk_reasons() {
+        let risk = RiskReport {
+            level: RiskLevel::Risky,
+            reasons: vec!["network access".to_string()],
+            requires_confirmation: true,
+            sources: Vec::new(),
+        };
+        assert_eq!(
+            format_preview("await fetch(url);\n", &risk),
+            "--- generated code ---\nawait fetch(url);\n----------------------\nrisk: Risky\n- network access"
+        );
+        assert!(!show_preview(true, false, ConfirmMode::Always));
+        assert!(!show_preview(false, true, ConfirmMode::Always));
+        assert!(!show_preview(false, false, ConfirmMode::Never));
+        assert!(Cli::try_parse_from(["beeno", "run", "a.ts", "--no-preview"]).is_ok());
     }
-    if let Some(v) = env_get("BEENO_ARTIFACT_KEEP_LAST").and_then(|v| v.parse::<usize>().ok()) {
-        cfg.artifacts.keep_last = v;
+
+    #[test]
+    fn history_command_parses_list_and_replay() {
+        let cli = Cli::try_parse_from(["beeno", "history", "--limit", "5"]).expect("cli parse");
+        assert!(matches!(
+            cli.cmd,
+            Commands::History {
+                limit: Some(5),
+                action: None
+            }
+        ));
+        let cli = Cli::try_parse_from(["beeno", "history", "replay", "1700000000000"])
+            .expect("cli parse");
+        match cli.cmd {
+            Commands::History {
+                action: Some(HistoryAction::Replay { id, .. }),
+                ..
+            } => assert_eq!(id, "1700000000000"),
+            _ => panic!("expected history replay"),
+        }
     }
 
-    if let Some(v) = env_get("BEENO_MAX_FILES").and_then(|v| v.parse::<usize>().ok()) {
-        cfg.limits.max_files = v;
+    #[test]
+    fn format_utc_renders_calendar_dates() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+        assert_eq!(format_utc(1_709_210_096_000), "2024-02-29 12:34:56");
     }
-    if let Some(v) = env_get("BEENO_MAX_CHANGED_LINES").and_then(|v| v.parse::<usize>().ok()) {
-        cfg.limits.max_changed_lines = v;
+
+    #[test]
+    fn eval_reads_argument_input_file_or_stdin() {
+        let dir = unique_temp_path("beeno-cli-input-file");
+        fs::create_dir_all(&dir).expect("create temp dir");
+        let path = dir.join("prompt.txt");
+        fs::write(&path, "fetch the weather\n\nthen print it as a table\n").unwrap();
+        let mut stdin = io::Cursor::new("print hello");
+
+        assert_eq!(
+            read_eval_input(Some("1 + 1".to_string()), None, &mut stdin).unwrap(),
+            "1 + 1"
+        );
+        assert_eq!(
+            read_eval_input(None, Some(&path), &mut stdin).unwrap(),
+            "fetch the weather\n\nthen print it as a table\n"
+        );
+        assert_eq!(
+            read_eval_input(Some("-".to_string()), None, &mut stdin).unwrap(),
+            "print hello"
+        );
+        let both = read_eval_input(Some("1 + 1".to_string()), Some(&path), &mut stdin)
+            .unwrap_err()
+            .to_string();
+        assert!(both.contains("not both"), "{both}");
+        assert!(read_eval_input(None, None, &mut stdin).is_err());
+        assert!(read_eval_input(None, Some(&dir.join("missing.txt")), &mut stdin).is_err());
+        fs::remove_dir_all(&dir).expect("cleanup temp dir");
     }
 
-    if let Some(v) = env_get("BEENO_PROTECT_DENY") {
-        cfg.protect.deny = v
-            .split(',')
-            .map(str::trim)
-            .filter(|s| !s.is_empty())
-            .map(ToString::to_string)
-            .collect();
+    #[test]
+    fn analyze_reads_inline_stdin_or_file_source() {
+        let mut stdin = io::Cursor::new("Deno.exit(1);");
+        assert_eq!(
+            read_analyze_source(None, Some("1 + 1".to_string()), &mut stdin).unwrap(),
+            "1 + 1"
+        );
+        assert_eq!(
+            read_analyze_source(Some(Path::new("-")), None, &mut stdin).unwrap(),
+            "Deno.exit(1);"
+        );
+        assert!(read_analyze_source(None, None, &mut stdin).is_err());
+        assert!(Cli::try_parse_from(["beeno", "analyze", "a.ts", "--input", "1"]).is_err());
     }
-}
 
-fn parse_bool(raw: &str) -> Option<bool> {
-    match raw.to_ascii_lowercase().as_str() {
-        "1" | "true" | "yes" | "y" | "on" => Some(true),
-        "0" | "false" | "no" | "n" | "off" => Some(false),
-        _ => None,
+    #[test]
+    fn bench_command_parses_repeated_providers() {
+        let cli = Cli::try_parse_from([
+            "beeno",
+            "bench",
+            "prompts.txt",
+            "--provider",
+            "mock",
+            "--provider",
+            "ollama:llama3.1",
+        ])
+        .expect("cli parse");
+        match cli.cmd {
+            Commands::Bench { prompts, providers } => {
+                assert_eq!(prompts, PathBuf::from("prompts.txt"));
+                assert_eq!(providers, vec!["mock", "ollama:llama3.1"]);
+            }
+            _ => panic!("expected bench command"),
+        }
     }
-}
 
-fn init_config_file(path: &Path, force: bool) -> anyhow::Result<()> {
-    if path.exists() && !force {
-        anyhow::bail!(
-            "{} already exists; re-run with --force to overwrite",
-            path.display()
+    #[test]
+    fn heal_flags_override_self_heal_config() {
+        let heal_flags = |args: &[&str]| {
+            let cli = Cli::try_parse_from([&["beeno", "run", "main.ts"], args].concat())
+                .expect("cli parse");
+            let Commands::Run {
+                heal,
+                no_heal,
+                heal_attempts,
+                ..
+            } = cli.cmd
+            else {
+                panic!("expected run command");
+            };
+            let mut cfg = AppConfig::default();
+            cfg.self_heal.auto_on_run_failure = false;
+            apply_heal_flags(&mut cfg, heal, no_heal, heal_attempts);
+            HealOptions::from_config(&cfg)
+        };
+        assert_eq!(heal_flags(&[]), None);
+        let forced = heal_flags(&["--heal", "--heal-attempts", "5"]).expect("heal on");
+        assert_eq!(forced.max_attempts, 5);
+        assert!(!forced.apply_by_default);
+        assert_eq!(
+            forced.artifacts_dir,
+            Path::new(".beeno/suggestions").join(HEAL_DIR)
         );
-    }
-    fs::write(path, config_template())?;
-    Ok(())
-}
+        assert_eq!(heal_flags(&["--no-heal", "--heal-attempts", "2"]), None);
 
-fn config_template() -> &'static str {
-    r#"# beeno configuration
-# precedence: CLI > env > local .beeno.toml > home ~/.beeno.toml > defaults
+        assert!(Cli::try_parse_from(["beeno", "eval", "x", "--heal", "--no-heal"]).is_err());
+        assert!(Cli::try_parse_from(["beeno", "eval", "x", "--heal-attempts", "0"]).is_err());
+    }
 
-[llm]
-# provider options: http, mock, ollama, chatgpt, openrouter, openai_compat
-provider = "http"
-# optional explicit endpoint override (for custom URLs / OpenAI-compatible gateways)
-endpoint = ""
-model = "gpt-4.1-mini"
-temperature = 0.1
-max_tokens = 512
-endpoint_env_var = "DENO_NL_ENDPOINT"
-api_key_env_var = "DENO_NL_API_KEY"
+    #[test]
+    fn only_single_runtime_and_type_check_failures_are_healed() {
+        let failure = ExecutionFailure {
+            exit_code: Some(1),
+            stdout: String::new(),
+            stderr: "boom".to_string(),
+        };
+        let single = Repeat {
+            repeat: 1,
+            keep_going: false,
+        };
+        let error = Some(EngineError::RuntimeFailure(failure.clone()));
+        assert_eq!(
+            healable_failure(&error, single),
+            Some(HealTarget::Runtime(failure))
+        );
+        let type_errors = Some(EngineError::TypeCheck("TS2322".to_string()));
+        let target = healable_failure(&type_errors, single).expect("type errors are healed");
+        assert_eq!(target.failure().stderr, "TS2322");
+        assert!(matches!(target.into_error(), EngineError::TypeCheck(d) if d == "TS2322"));
+        let repeated = Repeat {
+            repeat: 3,
+            keep_going: false,
+        };
+        assert_eq!(healable_failure(&error, repeated), None);
+        let blocked = Some(EngineError::Blocked(vec!["nope".to_string()]));
+        assert_eq!(healable_failure(&blocked, single), None);
+        assert_eq!(healable_failure(&None, single), None);
+    }
 
-[policy]
-policy_path = ""
-confirm_risky = true
+    #[test]
+    fn models_command_parses_provider_override() {
+        let cli =
+            Cli::try_parse_from(["beeno", "models", "--provider", "ollama"]).expect("cli parse");
+        assert!(matches!(
+            cli.cmd,
+            Commands::Models { provider: Some(ref p) } if p == "ollama"
+        ));
+    }
 
-[self_heal]
-enabled = true
-auto_on_run_failure = true
-apply_fixes_default = false
-max_attempts = 3
+    #[tokio::test]
+    async fn bench_counts_parsed_translations() {
+        let prompts = vec!["print hello".to_string(), "print goodbye".to_string()];
+        let result = bench_provider(
+            "mock",
+            &MockProvider,
+            &DefaultRiskPolicy::default(),
+            &prompts,
+        )
+        .await;
+        assert_eq!(result.prompts, 2);
+        assert_eq!(result.parsed, 2);
+        assert_eq!(result.errors, 0);
+        assert!((result.success_rate - 1.0).abs() < f64::EPSILON);
+        // The policy reuses the parse from the validity check.
+        assert_eq!((result.parses, result.parse_cache_hits), (2, 2));
+    }
 
-[artifacts]
-dir = ".beeno/suggestions"
-keep_last = 20
+    #[test]
+    fn dev_commands_match_whole_words() {
+        assert_eq!(command_args("/health", "/health"), Some(""));
+        assert_eq!(command_args("/health /ready", "/health"), Some(" /ready"));
+        assert_eq!(command_args("/healthz", "/health"), None);
+        assert_eq!(command_args("/health-check", "/health"), None);
+        assert_eq!(command_args("/permsx", "/perms"), None);
+        assert_eq!(command_args("/perms net on", "/perms"), Some(" net on"));
+    }
 
-[limits]
-max_files = 10
-max_changed_lines = 500
+    #[test]
+    fn dev_ready_flags_select_the_readiness_check() {
+        let parse = |args: &[&str]| {
+            let cli = Cli::try_parse_from([&["beeno", "dev"], args].concat()).expect("cli parse");
+            let Commands::Dev { ready_path, ws, .. } = cli.cmd else {
+                panic!("expected dev command");
+            };
+            ready_check(ready_path, ws)
+        };
+        assert_eq!(parse(&[]), ReadyCheck::TcpConnect);
+        assert_eq!(
+            parse(&["--ready-path", "health"]),
+            ReadyCheck::HttpGet {
+                path: "/health".to_string()
+            }
+        );
+        assert_eq!(
+            parse(&["--ws"]),
+            ReadyCheck::WebSocket {
+                path: "/".to_string()
+            }
+        );
+        assert_eq!(
+            parse(&["--ws", "--ready-path", "/socket"]),
+            ReadyCheck::WebSocket {
+                path: "/socket".to_string()
+            }
+        );
+    }
 
-[protect]
-deny = [".env", ".env.*", "deno.lock", "Cargo.lock", "package-lock.json", "pnpm-lock.yaml", "yarn.lock"]
-"#
-}
+    #[test]
+    fn dev_command_parses_flags() {
+        let cli = Cli::try_parse_from([
+            "beeno", "dev", "--file", "app.ts", "--port", "3333", "--open",
+        ])
+        .expect("cli parse");
 
-fn render_engine_error(err: EngineError) -> anyhow::Error {
-    match err {
-        EngineError::Blocked(reasons) => {
-            anyhow::anyhow!(
-                "blocked by policy: {}; retry with safer instructions",
-                reasons.join(", ")
-            )
+        match cli.cmd {
+            Commands::Dev {
+                file, port, open, ..
+            } => {
+                assert_eq!(file, Some(PathBuf::from("app.ts")));
+                assert_eq!(port, 3333);
+                assert!(open);
+            }
+            _ => panic!("expected dev command"),
         }
-        other => anyhow::anyhow!(other),
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use clap::Parser;
-    use std::collections::HashMap;
-    use std::time::{SystemTime, UNIX_EPOCH};
 
     #[test]
-    fn config_precedence_cli_env_local_home_defaults() {
-        let home = Some(
-            r#"
-            [llm]
-            model = "home-model"
-
-            [artifacts]
-            keep_last = 99
-            "#
-            .parse::<Value>()
-            .expect("home parse"),
+    fn describe_translation_includes_confidence_and_explanation() {
+        let mut translated = TranslateResult::new("Deno.serve(() => new Response('hi'))");
+        translated.explanation = Some("creates an HTTP server".to_string());
+        translated.confidence = Some(0.92);
+        assert_eq!(
+            describe_translation(&translated),
+            "translated (confidence 0.92): creates an HTTP server"
         );
+    }
 
-        let local = Some(
-            r#"
-            [llm]
-            model = "local-model"
-
-            [policy]
-            confirm_risky = false
-            "#
-            .parse::<Value>()
-            .expect("local parse"),
-        );
+    #[test]
+    fn run_command_parses_out_and_no_exec() {
+        let cli = Cli::try_parse_from([
+            "beeno",
+            "run",
+            "app.ts",
+            "--out",
+            "dist/app.ts",
+            "--no-exec",
+        ])
+        .expect("cli parse");
 
-        let env = HashMap::from([
-            ("BEENO_MODEL".to_string(), "env-model".to_string()),
-            ("BEENO_PROVIDER".to_string(), "mock".to_string()),
-        ]);
+        match cli.cmd {
+            Commands::Run { out, no_exec, .. } => {
+                assert_eq!(out, Some(PathBuf::from("dist/app.ts")));
+                assert!(no_exec);
+            }
+            _ => panic!("expected run command"),
+        }
+    }
 
-        let cfg = resolve_config(home, local, |k| env.get(k).cloned()).expect("resolve config");
+    #[tokio::test]
+    async fn explain_only_prints_a_plan_without_running() {
+        let cli = Cli::try_parse_from(["beeno", "eval", "count the lines", "--explain-only"])
+            .expect("cli parse");
+        assert!(matches!(
+            cli.cmd,
+            Commands::Eval {
+                explain_only: true,
+                ..
+            }
+        ));
 
-        assert_eq!(cfg.llm.model, "env-model");
-        assert_eq!(cfg.llm.provider, "mock");
-        assert!(!cfg.policy.confirm_risky);
-        assert_eq!(cfg.artifacts.keep_last, 99);
+        let mut cfg = AppConfig::default();
+        cfg.llm.provider = "mock".to_string();
+        explain_with_provider(&cfg, "count the lines", false)
+            .await
+            .expect("explain");
+        assert!(explain_with_provider(&cfg, "  ", false).await.is_err());
     }
 
     #[test]
-    fn init_config_requires_force_to_overwrite() {
-        let base = std::env::temp_dir().join(format!(
-            "beeno-cli-test-{}-{}",
-            std::process::id(),
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("time")
-                .as_nanos()
+    fn scaffold_command_parses_and_summarizes() {
+        let cli = Cli::try_parse_from(["beeno", "scaffold", "a todo api", "--out", "app"])
+            .expect("cli parse");
+        assert!(matches!(
+            cli.cmd,
+            Commands::Scaffold { ref prompt, ref out, force: false }
+                if prompt == "a todo api" && out == Path::new("app")
         ));
-        fs::create_dir_all(&base).expect("create temp dir");
-        let cfg_path = base.join(".beeno.toml");
+        assert!(Cli::try_parse_from(["beeno", "scaffold", "x"]).is_err());
 
-        init_config_file(&cfg_path, false).expect("must create first config");
-        let err = init_config_file(&cfg_path, false).expect_err("must reject overwrite");
-        assert!(err.to_string().contains("--force"));
+        let files = vec![
+            beeno_core::scaffold::ScaffoldFile {
+                path: "main.ts".to_string(),
+                contents: "console.log(1);".to_string(),
+            },
+            beeno_core::scaffold::ScaffoldFile {
+                path: "deno.json".to_string(),
+                contents: "{}".to_string(),
+            },
+        ];
+        assert_eq!(
+            scaffold_summary(Path::new("app"), &files),
+            "wrote 2 files to app\n  main.ts (15 bytes)\n  deno.json (2 bytes)"
+        );
+    }
 
-        init_config_file(&cfg_path, true).expect("force overwrite should succeed");
-        let content = fs::read_to_string(&cfg_path).expect("read config");
-        assert!(content.contains("[self_heal]"));
+    #[test]
+    fn classify_command_parses_input() {
+        let cli = Cli::try_parse_from(["beeno", "classify", "const x = 1"]).expect("cli parse");
+        assert!(matches!(cli.cmd, Commands::Classify { ref input } if input == "const x = 1"));
+    }
 
-        fs::remove_dir_all(&base).expect("cleanup temp dir");
+    #[test]
+    fn events_flag_is_global() {
+        let cli = Cli::try_parse_from(["beeno", "eval", "1 + 1", "--events"]).expect("cli parse");
+        assert_eq!(cli.events, Some(None));
+        let cli = Cli::try_parse_from(["beeno", "--events", "run", "app.ts"]).expect("cli parse");
+        assert_eq!(cli.events, Some(None));
+        let cli = Cli::try_parse_from(["beeno", "--events=events.ndjson", "run", "app.ts"])
+            .expect("cli parse");
+        assert_eq!(cli.events, Some(Some(PathBuf::from("events.ndjson"))));
     }
 
     #[test]
-    fn provider_endpoint_prefers_config_then_env() {
-        let mut cfg = AppConfig::default();
-        cfg.llm.endpoint = Some("https://example.invalid/v1/chat/completions".to_string());
-        cfg.llm.endpoint_env_var = "CUSTOM_ENDPOINT".to_string();
+    fn events_file_gets_phases_and_a_terminal_error() {
+        let deny = ProtectConfig::default().deny;
+        assert!(EventSink::new(Some(Some(PathBuf::from(".env.local"))), &deny).is_err());
 
-        let env = HashMap::from([(
-            "CUSTOM_ENDPOINT".to_string(),
-            "https://env.invalid/v1/chat/completions".to_string(),
-        )]);
+        let path = unique_temp_path("beeno-events").with_extension("ndjson");
+        fs::write(&path, "previous invocation\n").expect("seed events file");
+        let events = EventSink::new(Some(Some(path.clone())), &deny)
+            .expect("events")
+            .with_trace("trace-1");
+        events
+            .emit("classify", "script classified", json!({}))
+            .expect("emit");
+        events.emit_error(&anyhow::anyhow!("translation failed"));
 
-        let endpoint = resolve_provider_endpoint(&cfg, |k| env.get(k).cloned());
+        let written = fs::read_to_string(&path).expect("read events");
+        let _ = fs::remove_file(&path);
+        let lines: Vec<JsonEnvelope> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("json event"))
+            .collect();
+        assert_eq!(lines.len(), 2, "{written}");
         assert_eq!(
-            endpoint.as_deref(),
-            Some("https://example.invalid/v1/chat/completions")
+            (lines[0].status.as_str(), lines[0].phase.as_str()),
+            ("ok", "classify")
         );
+        assert_eq!(
+            (lines[1].status.as_str(), lines[1].phase.as_str()),
+            ("error", "error")
+        );
+        assert_eq!(lines[1].message, "translation failed");
+        assert_eq!(lines[1].details["trace_id"], "trace-1");
+    }
 
-        cfg.llm.endpoint = Some("".to_string());
-        let endpoint = resolve_provider_endpoint(&cfg, |k| env.get(k).cloned());
+    #[test]
+    fn protected_paths_match_deny_globs() {
+        let deny = ProtectConfig::default().deny;
         assert_eq!(
-            endpoint.as_deref(),
-            Some("https://env.invalid/v1/chat/completions")
+            protected_pattern(Path::new("config/.env.local"), &deny),
+            Some(".env.*")
         );
+        assert_eq!(
+            protected_pattern(Path::new("deno.lock"), &deny),
+            Some("deno.lock")
+        );
+        assert!(protected_pattern(Path::new("dist/app.ts"), &deny).is_none());
+        assert!(ensure_not_protected(Path::new(".env"), &deny).is_err());
     }
 
     #[test]
-    fn empty_policy_path_uses_default_policy() {
-        let mut cfg = AppConfig::default();
-        cfg.policy.policy_path = Some("".to_string());
-        let result = policy_from_cfg(&cfg);
-        assert!(result.is_ok());
+    fn tee_path_is_protected_and_starts_empty() {
+        let deny = ProtectConfig::default().deny;
+        let err = start_tee(Some(PathBuf::from(".env.local")), &deny).expect_err("protected");
+        assert!(err.to_string().contains("protect.deny"), "{err}");
+
+        let path = unique_temp_path("beeno-tee").with_extension("log");
+        fs::write(&path, "previous invocation\n").expect("seed tee file");
+        let tee = start_tee(Some(path.clone()), &deny).expect("tee");
+        assert_eq!(tee.as_deref(), Some(path.as_path()));
+        assert_eq!(fs::read_to_string(&path).expect("read tee"), "");
+        let _ = fs::remove_file(&path);
+        assert_eq!(start_tee(None, &deny).expect("no tee"), None);
     }
 
     #[test]
-    fn dev_command_parses_flags() {
-        let cli = Cli::try_parse_from([
-            "beeno", "dev", "--file", "app.ts", "--port", "3333", "--open",
-        ])
-        .expect("cli parse");
+    fn permission_paths_cannot_bypass_protect_deny() {
+        let deny = ProtectConfig::default().deny;
+        let cli = Cli::try_parse_from(["beeno", "eval", "x", "--allow-write=.env.local"])
+            .expect("cli parse");
+        let Commands::Eval { allow_write, .. } = cli.cmd else {
+            panic!("expected eval command");
+        };
+        let write = DenoPermissions {
+            allow_write,
+            ..DenoPermissions::default()
+        };
+        let err = ensure_permissions_unprotected(&write, &deny).expect_err("protected");
+        assert!(
+            err.to_string().starts_with(
+                "--allow-write=.env.local conflicts with protect.deny pattern `.env.*`"
+            ),
+            "{err}"
+        );
 
-        match cli.cmd {
-            Commands::Dev { file, port, open } => {
-                assert_eq!(file, Some(PathBuf::from("app.ts")));
-                assert_eq!(port, 3333);
-                assert!(open);
-            }
-            _ => panic!("expected dev command"),
+        let read = DenoPermissions {
+            allow_read: vec!["src".to_string(), "config/deno.lock".to_string()],
+            ..DenoPermissions::default()
+        };
+        let err = ensure_permissions_unprotected(&read, &deny).expect_err("protected");
+        assert!(
+            err.to_string().contains("--allow-read=config/deno.lock"),
+            "{err}"
+        );
+
+        for grant in [".", "./", "*", ".."] {
+            let read = DenoPermissions {
+                allow_read: vec![grant.to_string()],
+                ..DenoPermissions::default()
+            };
+            let err = ensure_permissions_unprotected(&read, &deny).expect_err("covers .env");
+            assert!(
+                err.to_string().starts_with(&format!(
+                    "--allow-read={grant} conflicts with protect.deny pattern `.env`"
+                )),
+                "{err}"
+            );
         }
+        let nested = vec!["config/secrets/*".to_string()];
+        let read = DenoPermissions {
+            allow_read: vec!["./config".to_string()],
+            ..DenoPermissions::default()
+        };
+        assert!(ensure_permissions_unprotected(&read, &nested).is_err());
+
+        let safe = DenoPermissions {
+            allow_read: vec!["src".to_string(), "config/public".to_string()],
+            allow_write: vec!["dist".to_string()],
+            ..DenoPermissions::default()
+        };
+        assert!(ensure_permissions_unprotected(&safe, &deny).is_ok());
+        assert!(ensure_permissions_unprotected(&safe, &nested).is_ok());
+    }
+
+    #[test]
+    fn doctor_flags_missing_deno_and_api_key() {
+        let mut cfg = AppConfig::default();
+        cfg.llm.provider = "chatgpt".to_string();
+        let checks = doctor_checks(&Ok(cfg), |_| None, None);
+
+        let deno = checks
+            .iter()
+            .find(|c| c.name == "deno")
+            .expect("deno check");
+        assert!(!deno.ok && deno.critical);
+        let key = checks
+            .iter()
+            .find(|c| c.name == "api key")
+            .expect("key check");
+        assert!(!key.ok && key.critical);
+        assert!(key.detail.contains("DENO_NL_API_KEY"));
+    }
+
+    #[test]
+    fn doctor_never_prints_api_key_value() {
+        let env = HashMap::from([("DENO_NL_API_KEY".to_string(), "sk-secret".to_string())]);
+        let checks = doctor_checks(
+            &Ok(AppConfig::default()),
+            |k| env.get(k).cloned(),
+            Some("deno 2.0.0".to_string()),
+        );
+        assert!(checks.iter().all(|c| c.ok));
+        assert!(checks.iter().all(|c| !c.detail.contains("sk-secret")));
+    }
+
+    #[test]
+    fn doctor_reports_config_errors() {
+        let checks = doctor_checks(
+            &Err(anyhow::anyhow!("invalid TOML")),
+            |_| None,
+            Some("deno 2.0.0".to_string()),
+        );
+        let config = checks
+            .iter()
+            .find(|c| c.name == "config")
+            .expect("config check");
+        assert!(!config.ok);
+        assert_eq!(config.detail, "invalid TOML");
     }
 
     #[test]
@@ -0,0 +1,353 @@
+//! Suggestion records persisted under `artifacts.dir`.
+//!
+//! Every record lives in its own `<dir>/<unix-millis>/` session directory next to
+//! any heal attempts for the same session; the directory name is the record id.
+//! Only the newest `artifacts.keep_last` session directories are kept.
+//!
+//! [`BlockCache`] keeps tagged-block translations in `<dir>/block-cache/`, which
+//! pruning never touches.
+
+use crate::types::RiskLevel;
+use crate::util::{redact, stable_hash};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File holding a [`SuggestionRecord`] inside its session directory.
+pub const RECORD_FILE: &str = "record.json";
+
+/// A translated suggestion saved for `beeno history`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuggestionRecord {
+    /// Session directory name; assigned by [`save_suggestion`].
+    #[serde(default)]
+    pub id: String,
+    pub timestamp_ms: u64,
+    pub mode: String,
+    pub input: String,
+    pub risk: RiskLevel,
+    pub code: String,
+    #[serde(default)]
+    pub trace_id: Option<String>,
+}
+
+impl SuggestionRecord {
+    /// Creates an unsaved record stamped with the current time.
+    pub fn new(mode: &str, input: &str, risk: RiskLevel, code: &str) -> Self {
+        Self {
+            id: String::new(),
+            timestamp_ms: unix_millis() as u64,
+            mode: mode.to_string(),
+            input: input.to_string(),
+            risk,
+            code: code.to_string(),
+            trace_id: None,
+        }
+    }
+
+    /// First non-empty line of the original input.
+    pub fn first_line(&self) -> &str {
+        self.input
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or("")
+    }
+}
+
+/// Creates a fresh `<base>/<unix-millis>/` session directory.
+///
+/// Names always sort after existing sessions, even when several are created in
+/// the same millisecond, so pruning never removes the session just created.
+pub fn create_session_dir(base: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(base)?;
+    let newest = session_dirs(base)?
+        .last()
+        .and_then(|dir| dir.file_name()?.to_str()?.parse::<u128>().ok());
+    let mut stamp = unix_millis().max(newest.map_or(0, |n| n + 1));
+    loop {
+        let dir = base.join(stamp.to_string());
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => stamp += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Writes `record` to a new session directory, then prunes to `keep_last` sessions.
+///
+/// Secrets in the input and code are [redacted](redact) on disk.
+pub fn save_suggestion(
+    base: &Path,
+    mut record: SuggestionRecord,
+    keep_last: usize,
+) -> io::Result<SuggestionRecord> {
+    let dir = create_session_dir(base)?;
+    record.id = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let json = serde_json::to_string_pretty(&record).map_err(io::Error::other)?;
+    fs::write(dir.join(RECORD_FILE), redact(&json))?;
+    prune_sessions(base, keep_last)?;
+    Ok(record)
+}
+
+/// Lists saved suggestions, newest first. Sessions without a record are skipped.
+pub fn list_suggestions(base: &Path) -> io::Result<Vec<SuggestionRecord>> {
+    let mut records = Vec::new();
+    for dir in session_dirs(base)?.into_iter().rev() {
+        if let Ok(raw) = fs::read_to_string(dir.join(RECORD_FILE)) {
+            if let Ok(record) = serde_json::from_str(&raw) {
+                records.push(record);
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Loads the suggestion saved under `id`.
+pub fn load_suggestion(base: &Path, id: &str) -> io::Result<SuggestionRecord> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid history id `{id}`"),
+        ));
+    }
+    let raw = fs::read_to_string(base.join(id).join(RECORD_FILE))
+        .map_err(|e| io::Error::new(e.kind(), format!("no saved suggestion with id `{id}`")))?;
+    serde_json::from_str(&raw).map_err(io::Error::other)
+}
+
+/// Removes the oldest session directories beyond `keep_last`; returns how many were removed.
+pub fn prune_sessions(base: &Path, keep_last: usize) -> io::Result<usize> {
+    let dirs = session_dirs(base)?;
+    let excess = dirs.len().saturating_sub(keep_last);
+    for dir in &dirs[..excess] {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(excess)
+}
+
+/// Session directories under `base`, oldest first.
+fn session_dirs(base: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(base) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut dirs: Vec<(u128, PathBuf)> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let stamp = entry.file_name().to_str()?.parse().ok()?;
+            Some((stamp, entry.path()))
+        })
+        .collect();
+    dirs.sort();
+    Ok(dirs.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Subdirectory of `artifacts.dir` holding [`BlockCache`] entries.
+pub const BLOCK_CACHE_DIR: &str = "block-cache";
+
+/// On-disk cache of `/*nl ... */` block translations, keyed by a hash of the
+/// block's NL text, target language, and the translator that produced it (see
+/// [`Self::with_translator`]).
+///
+/// Entries store the NL text too, so a hash collision reads as a miss. With
+/// [`Self::with_refresh`] lookups always miss, while fresh translations are
+/// still written.
+#[derive(Debug, Clone)]
+pub struct BlockCache {
+    dir: PathBuf,
+    refresh: bool,
+    translator: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BlockCacheEntry {
+    nl: String,
+    language: String,
+    #[serde(default)]
+    translator: String,
+    code: String,
+}
+
+impl BlockCache {
+    /// Caches entries in `<artifacts_dir>/block-cache/`.
+    pub fn new(artifacts_dir: &Path) -> Self {
+        Self {
+            dir: artifacts_dir.join(BLOCK_CACHE_DIR),
+            refresh: false,
+            translator: String::new(),
+        }
+    }
+
+    /// Keys entries by the provider, resolved model, and prompt template too, so
+    /// switching any of them translates blocks again instead of reusing old code.
+    pub fn with_translator(mut self, provider: &str, model: &str, prompt_template: &str) -> Self {
+        self.translator = format!("{provider}\n{model}\n{}", stable_hash(prompt_template));
+        self
+    }
+
+    /// Ignores existing entries so every block is translated again.
+    pub fn with_refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// The cached translation of `nl` for `language`, if any.
+    pub fn get(&self, nl: &str, language: &str) -> Option<String> {
+        if self.refresh {
+            return None;
+        }
+        let raw = fs::read_to_string(self.entry_path(nl, language)).ok()?;
+        let entry: BlockCacheEntry = serde_json::from_str(&raw).ok()?;
+        (entry.nl == nl && entry.language == language && entry.translator == self.translator)
+            .then_some(entry.code)
+    }
+
+    /// Stores the translation of `nl`.
+    ///
+    /// Code that [`redact`] would change is not cached: it holds a secret that
+    /// must not reach disk, and a redacted copy would not run.
+    pub fn put(&self, nl: &str, language: &str, code: &str) -> io::Result<()> {
+        if redact(nl) != nl || redact(code) != code {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+        let entry = BlockCacheEntry {
+            nl: nl.to_string(),
+            language: language.to_string(),
+            translator: self.translator.clone(),
+            code: code.to_string(),
+        };
+        let json = serde_json::to_string_pretty(&entry).map_err(io::Error::other)?;
+        fs::write(self.entry_path(nl, language), json)
+    }
+
+    fn entry_path(&self, nl: &str, language: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{}.json",
+            stable_hash(&format!("{}\n{language}\n{nl}", self.translator))
+        ))
+    }
+}
+
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::unique_temp_path;
+
+    fn temp_base(name: &str) -> PathBuf {
+        unique_temp_path(&format!("beeno-artifacts-{name}"))
+    }
+
+    #[test]
+    fn saved_suggestions_list_newest_first_and_load_by_id() {
+        let base = temp_base("list");
+        let first = save_suggestion(
+            &base,
+            SuggestionRecord::new("eval", "print hello\nsecond line", RiskLevel::Safe, "a"),
+            10,
+        )
+        .expect("save");
+        let second = save_suggestion(
+            &base,
+            SuggestionRecord::new("run", "main.ts", RiskLevel::Risky, "b"),
+            10,
+        )
+        .expect("save");
+
+        let records = list_suggestions(&base).expect("list");
+        let ids: Vec<_> = records.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec![second.id.as_str(), first.id.as_str()]);
+        assert_eq!(records[1].first_line(), "print hello");
+
+        let loaded = load_suggestion(&base, &first.id).expect("load");
+        assert_eq!(loaded.code, "a");
+        assert!(load_suggestion(&base, "../etc").is_err());
+        let _ = fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn block_cache_round_trips_and_refresh_misses() {
+        let base = temp_base("block-cache");
+        let cache = BlockCache::new(&base);
+        assert_eq!(cache.get("print hi", "typescript"), None);
+        cache
+            .put("print hi", "typescript", "console.log('hi');")
+            .expect("put");
+        assert_eq!(
+            cache.get("print hi", "typescript").as_deref(),
+            Some("console.log('hi');")
+        );
+        assert_eq!(cache.get("print hi", "javascript"), None);
+        assert_eq!(
+            cache
+                .clone()
+                .with_refresh(true)
+                .get("print hi", "typescript"),
+            None
+        );
+
+        cache
+            .put(
+                "call api",
+                "typescript",
+                "const k = 'sk-abcdefghijklmnopqrstuvwx';",
+            )
+            .expect("put");
+        assert_eq!(cache.get("call api", "typescript"), None);
+        // The cache directory is not a session and survives pruning.
+        assert_eq!(prune_sessions(&base, 0).expect("prune"), 0);
+        let _ = fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn block_cache_misses_after_a_translator_change() {
+        let base = temp_base("block-cache-translator");
+        let cache = BlockCache::new(&base).with_translator("openai_compat", "gpt-4o", "template");
+        cache
+            .put("print hi", "typescript", "console.log('hi');")
+            .expect("put");
+        assert!(cache.get("print hi", "typescript").is_some());
+
+        for other in [
+            BlockCache::new(&base).with_translator("openai_compat", "gpt-4o-mini", "template"),
+            BlockCache::new(&base).with_translator("ollama", "gpt-4o", "template"),
+            BlockCache::new(&base).with_translator("openai_compat", "gpt-4o", "edited"),
+        ] {
+            assert_eq!(other.get("print hi", "typescript"), None);
+        }
+        let _ = fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn saving_prunes_to_keep_last() {
+        let base = temp_base("prune");
+        for i in 0..4 {
+            save_suggestion(
+                &base,
+                SuggestionRecord::new("eval", &format!("input {i}"), RiskLevel::Safe, "x"),
+                2,
+            )
+            .expect("save");
+        }
+        let records = list_suggestions(&base).expect("list");
+        let inputs: Vec<_> = records.iter().map(|r| r.input.as_str()).collect();
+        assert_eq!(inputs, vec!["input 3", "input 2"]);
+        let _ = fs::remove_dir_all(base);
+    }
+}
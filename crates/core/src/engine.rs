@@ -1,29 +1,79 @@
-use crate::providers::{ProviderError, TranslatorProvider};
+use crate::artifacts::BlockCache;
+use crate::heal::heal_input;
+use crate::parse_cache;
+use crate::providers::{
+    ProviderCapabilities, ProviderError, TranslatorProvider, CONTINUE_MODE, HEAL_MODE,
+};
+use crate::scaffold::{parse_manifest, ScaffoldError, ScaffoldFile, SCAFFOLD_INSTRUCTIONS};
+use crate::sourcemap::{SourceMap, StackMapping};
 use crate::types::{
-    ExecutionRequest, FileMetadata, RiskLevel, RiskReport, SessionSummary, TranslateRequest,
-    TranslateResult,
+    new_trace_id, ConfirmMode, ExecutionFailure, ExecutionOutput, ExecutionRequest, FileMetadata,
+    LimitsConfig, PolicySource, RiskLevel, RiskReport, SessionSummary, TranslateRequest,
+    TranslateResult, Warning, WarningKind,
 };
+use crate::util::{http_client, truncate_chars};
 use async_trait::async_trait;
-use deno_ast::{parse_module, MediaType, ParseParams};
+use deno_ast::swc::ast::{
+    CallExpr, Callee, ExportAll, Expr, ImportDecl, ImportSpecifier, Lit, ModuleExportName,
+    NamedExport,
+};
+use deno_ast::swc::ecma_visit::{Visit, VisitWith};
+pub use deno_ast::MediaType;
+use deno_ast::{
+    parse_module, EmitOptions, ParseParams, SourceMapOption, SourceRangedForSpanned,
+    TranspileModuleOptions, TranspileOptions,
+};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
 use tokio::process::Command;
+use tracing::Instrument;
 use url::Url;
 
 /// Heuristic classification of user input before translation/execution.
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum InputKind {
     Code,
     Pseudocode,
 }
 
+/// Classification result with the evidence that produced it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Classification {
+    pub kind: InputKind,
+    pub matched_indicators: Vec<String>,
+    pub word_count: usize,
+    pub reason: String,
+    /// Input without code indicators that is long but has no sentence markers,
+    /// or short but reads like prose (`then`/`and`, a sentence-ending period).
+    pub ambiguous: bool,
+}
+
+const CODE_INDICATORS: [&str; 11] = [
+    "let ",
+    "const ",
+    "function ",
+    "=>",
+    "import ",
+    "export ",
+    "class ",
+    "if (",
+    "for (",
+    "while (",
+    "console.",
+];
+
 /// Classifies text as probable JS/TS code or pseudocode.
 ///
+/// See [`classify_and_explain`] for the reasoning behind a result.
+///
 /// # Examples
 ///
 /// ```
@@ -33,36 +83,96 @@ pub enum InputKind {
 /// assert_eq!(classify_input("create a map and print all keys."), InputKind::Pseudocode);
 /// ```
 pub fn classify_input(input: &str) -> InputKind {
+    classify_and_explain(input).kind
+}
+
+/// Classifies text like [`classify_input`] and reports which heuristics fired.
+///
+/// # Examples
+///
+/// ```
+/// use beeno_core::engine::{classify_and_explain, InputKind};
+///
+/// let c = classify_and_explain("const x = 1");
+/// assert_eq!(c.kind, InputKind::Code);
+/// assert_eq!(c.matched_indicators, vec!["const "]);
+/// assert_eq!(c.reason, "classified as code because it contains `const `");
+/// ```
+pub fn classify_and_explain(input: &str) -> Classification {
     let trimmed = input.trim();
+    let word_count = trimmed.split_whitespace().count();
     if trimmed.is_empty() {
-        return InputKind::Code;
+        return Classification {
+            kind: InputKind::Code,
+            matched_indicators: Vec::new(),
+            word_count,
+            reason: "classified as code because the input is empty".to_string(),
+            ambiguous: false,
+        };
     }
 
-    let indicators = [
-        "let ",
-        "const ",
-        "function ",
-        "=>",
-        "import ",
-        "export ",
-        "class ",
-        "if (",
-        "for (",
-        "while (",
-        "console.",
-    ];
-
-    if indicators.iter().any(|i| trimmed.contains(i)) || trimmed.ends_with(';') {
-        return InputKind::Code;
+    let mut matched_indicators: Vec<String> = CODE_INDICATORS
+        .iter()
+        .filter(|i| trimmed.contains(*i))
+        .map(|i| i.to_string())
+        .collect();
+    if trimmed.ends_with(';') {
+        matched_indicators.push(";".to_string());
+    }
+    if !matched_indicators.is_empty() {
+        let listed = matched_indicators
+            .iter()
+            .map(|i| format!("`{i}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let reason = if matched_indicators == [";"] {
+            "classified as code because it ends with `;`".to_string()
+        } else {
+            format!("classified as code because it contains {listed}")
+        };
+        return Classification {
+            kind: InputKind::Code,
+            matched_indicators,
+            word_count,
+            reason,
+            ambiguous: false,
+        };
     }
 
-    let words = trimmed.split_whitespace().count();
     let has_sentence_markers =
         trimmed.contains('.') || trimmed.contains(" then ") || trimmed.contains(" and ");
-    if words > 5 && has_sentence_markers {
-        InputKind::Pseudocode
+    let reads_like_prose = trimmed.contains(" then ")
+        || trimmed.contains(" and ")
+        || trimmed.contains(". ")
+        || trimmed.ends_with('.');
+    let ambiguous = if word_count > 5 {
+        !has_sentence_markers
+    } else {
+        reads_like_prose
+    };
+    if word_count > 5 && has_sentence_markers {
+        Classification {
+            kind: InputKind::Pseudocode,
+            matched_indicators,
+            word_count,
+            reason: format!(
+                "classified as pseudocode because it has {word_count} words and sentence markers"
+            ),
+            ambiguous,
+        }
     } else {
-        InputKind::Code
+        let why = if word_count > 5 {
+            "no sentence markers"
+        } else {
+            "5 or fewer words"
+        };
+        Classification {
+            kind: InputKind::Code,
+            matched_indicators,
+            word_count,
+            reason: format!("classified as code by default: no code indicators and {why}"),
+            ambiguous,
+        }
     }
 }
 
@@ -71,6 +181,28 @@ pub fn classify_input(input: &str) -> InputKind {
 pub trait RiskPolicy: Send + Sync {
     /// Analyzes source and returns a risk report for execution gating.
     async fn analyze(&self, source: &str) -> RiskReport;
+
+    /// Like [`RiskPolicy::analyze`], for source of a known media type (e.g. JSX).
+    ///
+    /// Defaults to `analyze` for policies that do not parse source.
+    async fn analyze_with_media(&self, source: &str, media_type: MediaType) -> RiskReport {
+        let _ = media_type;
+        self.analyze(source).await
+    }
+}
+
+#[async_trait]
+impl<T> RiskPolicy for Box<T>
+where
+    T: RiskPolicy + ?Sized,
+{
+    async fn analyze(&self, source: &str) -> RiskReport {
+        (**self).analyze(source).await
+    }
+
+    async fn analyze_with_media(&self, source: &str, media_type: MediaType) -> RiskReport {
+        (**self).analyze_with_media(source, media_type).await
+    }
 }
 
 /// Configurable string-pattern policy inputs.
@@ -79,12 +211,26 @@ pub struct PolicyConfig {
     pub blocked_patterns: Vec<String>,
     pub risky_patterns: Vec<String>,
     pub trusted_import_prefixes: Vec<String>,
+    /// How imports outside `trusted_import_prefixes` are treated.
+    #[serde(default)]
+    pub untrusted_imports: UntrustedImports,
+}
+
+/// Risk level assigned to imports not covered by a trusted prefix.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UntrustedImports {
+    #[default]
+    Risky,
+    Blocked,
 }
 
 /// Default built-in policy implementation used by Beeno.
 #[derive(Debug, Clone)]
 pub struct DefaultRiskPolicy {
     cfg: PolicyConfig,
+    source: PolicySource,
+    allow_risky: Vec<String>,
 }
 
 impl Default for DefaultRiskPolicy {
@@ -104,32 +250,212 @@ impl Default for DefaultRiskPolicy {
                     "**/*".to_string(),
                 ],
                 trusted_import_prefixes: vec!["https://deno.land".to_string()],
+                untrusted_imports: UntrustedImports::Risky,
             },
+            source: PolicySource::Default,
+            allow_risky: Vec::new(),
         }
     }
 }
 
 impl DefaultRiskPolicy {
     /// Loads policy settings from TOML or JSON file.
+    ///
+    /// WebAssembly plugins are loaded with `wasm_policy::WasmPolicy` instead.
     pub fn from_path(path: &Path) -> anyhow::Result<Self> {
-        let content = fs::read_to_string(path)?;
-        let cfg = if path
+        let extension = path
             .extension()
             .and_then(|s| s.to_str())
-            .unwrap_or_default()
-            .eq_ignore_ascii_case("json")
-        {
-            serde_json::from_str(&content)?
-        } else {
-            toml::from_str(&content)?
+            .unwrap_or_default();
+        let content = fs::read_to_string(path)?;
+        let is_json = extension.eq_ignore_ascii_case("json");
+        Ok(Self::from_config(
+            parse_policy(&content, is_json)?,
+            PolicySource::File(path.display().to_string()),
+        ))
+    }
+
+    fn from_config(cfg: PolicyConfig, source: PolicySource) -> Self {
+        Self {
+            cfg,
+            source,
+            allow_risky: Vec::new(),
+        }
+    }
+
+    /// The resolved pattern lists this policy checks.
+    pub fn config(&self) -> &PolicyConfig {
+        &self.cfg
+    }
+
+    /// Where [`Self::config`] was loaded from.
+    pub fn source(&self) -> &PolicySource {
+        &self.source
+    }
+
+    /// Risky patterns containing any of `allow_risky` are still reported but no
+    /// longer require confirmation.
+    pub fn with_allow_risky(mut self, allow_risky: Vec<String>) -> Self {
+        self.allow_risky = allow_risky;
+        self
+    }
+
+    fn is_allowed_risky(&self, pattern: &str) -> bool {
+        self.allow_risky
+            .iter()
+            .any(|allowed| !allowed.is_empty() && pattern.contains(allowed.as_str()))
+    }
+
+    /// Fetches policy settings (TOML or JSON) from an `http(s)://` URL.
+    ///
+    /// A download that parses is cached under `cache_dir`; when the fetch fails or
+    /// returns an invalid policy, the cached copy is used instead.
+    pub async fn from_url(url: &str, cache_dir: &Path) -> anyhow::Result<Self> {
+        let cache_path = cache_dir.join(policy_cache_file_name(url));
+        let fetched = match fetch_policy(url).await {
+            Ok(content) => {
+                parse_policy(&content, looks_like_json(&content)).map(|cfg| (cfg, content))
+            }
+            Err(e) => Err(e),
         };
-        Ok(Self { cfg })
+        match fetched {
+            Ok((cfg, content)) => {
+                if let Err(e) =
+                    fs::create_dir_all(cache_dir).and_then(|_| fs::write(&cache_path, content))
+                {
+                    tracing::warn!(cache = %cache_path.display(), error = %e, "could not cache policy");
+                }
+                Ok(Self::from_config(cfg, PolicySource::Url(url.to_string())))
+            }
+            Err(fetch_err) => {
+                if !cache_path.exists() {
+                    return Err(fetch_err.context(format!(
+                        "could not load policy from {url} and no cached copy exists"
+                    )));
+                }
+                tracing::warn!(url, error = %fetch_err, "policy fetch failed, using cached copy");
+                Self::from_cached_url(url, cache_dir)
+            }
+        }
+    }
+
+    /// Loads the copy of a remote policy cached by [`Self::from_url`] without fetching it.
+    pub fn from_cached_url(url: &str, cache_dir: &Path) -> anyhow::Result<Self> {
+        let cache_path = cache_dir.join(policy_cache_file_name(url));
+        let cached = fs::read_to_string(&cache_path).map_err(|e| {
+            anyhow::anyhow!(
+                "no cached copy of policy {url} at {}: {e}",
+                cache_path.display()
+            )
+        })?;
+        Ok(Self::from_config(
+            parse_policy(&cached, looks_like_json(&cached))?,
+            PolicySource::Url(url.to_string()),
+        ))
+    }
+
+    /// Builds a report whose reasons all come from this policy's pattern lists.
+    fn report(
+        &self,
+        level: RiskLevel,
+        reasons: Vec<String>,
+        requires_confirmation: bool,
+    ) -> RiskReport {
+        RiskReport {
+            level,
+            sources: vec![self.source.clone(); reasons.len()],
+            reasons,
+            requires_confirmation,
+        }
+    }
+
+    /// Returns whether an import specifier is local or covered by a trusted prefix.
+    ///
+    /// `npm:` and `jsr:` specifiers are compared without the optional leading
+    /// slash Deno accepts, so `npm:/chalk` matches a `npm:chalk` prefix.
+    fn is_trusted_import(&self, specifier: &str) -> bool {
+        if specifier.starts_with("./")
+            || specifier.starts_with("../")
+            || specifier.starts_with('/')
+            || specifier.starts_with("file:")
+        {
+            return true;
+        }
+        let normalized = normalize_specifier(specifier);
+        self.cfg
+            .trusted_import_prefixes
+            .iter()
+            .any(|prefix| matches_import_prefix(&normalized, &normalize_specifier(prefix)))
+    }
+}
+
+/// Whether `specifier` falls under `prefix`, stopping at a path or version boundary so
+/// `https://deno.land` does not cover `https://deno.land.evil.test` and `npm:zod` does
+/// not cover `npm:zod-evil`.
+fn matches_import_prefix(specifier: &str, prefix: &str) -> bool {
+    let Some(rest) = specifier.strip_prefix(prefix) else {
+        return false;
+    };
+    rest.is_empty() || prefix.ends_with(['/', ':', '@']) || rest.starts_with(['/', '@'])
+}
+
+/// Maximum time spent fetching a remote policy before falling back to the cache.
+const POLICY_FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+async fn fetch_policy(url: &str) -> anyhow::Result<String> {
+    let response = http_client()
+        .get(url)
+        .timeout(POLICY_FETCH_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.text().await?)
+}
+
+fn parse_policy(content: &str, is_json: bool) -> anyhow::Result<PolicyConfig> {
+    Ok(if is_json {
+        serde_json::from_str(content)?
+    } else {
+        toml::from_str(content)?
+    })
+}
+
+fn looks_like_json(content: &str) -> bool {
+    content.trim_start().starts_with('{')
+}
+
+fn policy_cache_file_name(url: &str) -> String {
+    let slug: String = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{slug}.policy")
+}
+
+fn normalize_specifier(specifier: &str) -> String {
+    for scheme in ["npm:", "jsr:"] {
+        if let Some(rest) = specifier.strip_prefix(scheme) {
+            return format!("{scheme}{}", rest.trim_start_matches('/'));
+        }
     }
+    specifier.to_string()
 }
 
 #[async_trait]
 impl RiskPolicy for DefaultRiskPolicy {
     async fn analyze(&self, source: &str) -> RiskReport {
+        self.analyze_with_media(source, MediaType::TypeScript).await
+    }
+
+    async fn analyze_with_media(&self, source: &str, media_type: MediaType) -> RiskReport {
         let mut reasons = Vec::new();
         for pattern in &self.cfg.blocked_patterns {
             if source.contains(pattern) {
@@ -137,43 +463,55 @@ impl RiskPolicy for DefaultRiskPolicy {
             }
         }
 
-        if parse_js(source).is_err() {
+        let Ok(specifiers) = import_specifiers_with_media(source, media_type) else {
+            let mut sources = vec![self.source.clone(); reasons.len()];
             reasons.push("generated source does not parse as JS/TS".to_string());
+            sources.push(PolicySource::Parser);
             return RiskReport {
                 level: RiskLevel::Blocked,
                 reasons,
                 requires_confirmation: false,
+                sources,
             };
+        };
+
+        let mut risky_reasons = Vec::new();
+        for specifier in specifiers {
+            if self.is_trusted_import(&specifier) {
+                continue;
+            }
+            let reason = format!("untrusted import: {specifier}");
+            match self.cfg.untrusted_imports {
+                UntrustedImports::Risky => risky_reasons.push(reason),
+                UntrustedImports::Blocked => reasons.push(reason),
+            }
         }
 
         if !reasons.is_empty() {
-            return RiskReport {
-                level: RiskLevel::Blocked,
-                reasons,
-                requires_confirmation: false,
-            };
+            return self.report(RiskLevel::Blocked, reasons, false);
         }
 
-        let mut risky_reasons = Vec::new();
+        let mut allowed_reasons = Vec::new();
         for pattern in &self.cfg.risky_patterns {
-            if source.contains(pattern) {
+            if !source.contains(pattern) {
+                continue;
+            }
+            if self.is_allowed_risky(pattern) {
+                allowed_reasons.push(format!(
+                    "risky pattern detected: {pattern} (allowed by policy.allow_risky)"
+                ));
+            } else {
                 risky_reasons.push(format!("risky pattern detected: {pattern}"));
             }
         }
 
-        if !risky_reasons.is_empty() {
-            return RiskReport {
-                level: RiskLevel::Risky,
-                reasons: risky_reasons,
-                requires_confirmation: true,
-            };
+        if !risky_reasons.is_empty() || !allowed_reasons.is_empty() {
+            let requires_confirmation = !risky_reasons.is_empty();
+            risky_reasons.extend(allowed_reasons);
+            return self.report(RiskLevel::Risky, risky_reasons, requires_confirmation);
         }
 
-        RiskReport {
-            level: RiskLevel::Safe,
-            reasons: vec![],
-            requires_confirmation: false,
-        }
+        self.report(RiskLevel::Safe, Vec::new(), false)
     }
 }
 
@@ -184,66 +522,329 @@ pub trait ContextSummarizer: Send + Sync {
     async fn update(&mut self, event: &str) -> SessionSummary;
     /// Returns the current summary snapshot.
     fn current(&self) -> SessionSummary;
+    /// Replaces the accumulated state, e.g. when resuming a saved session.
+    ///
+    /// Summarizers that cannot restore keep the default, which ignores `summary`
+    /// so a resumed session starts from an empty context.
+    fn restore(&mut self, _summary: SessionSummary) {}
+    /// Tracks the imports and declarations of freshly generated `source` and
+    /// prefers evicting entries it no longer references.
+    ///
+    /// Summarizers that only prune by age keep the default, which changes nothing.
+    async fn update_with_source(&mut self, _source: &str) -> SessionSummary {
+        self.current()
+    }
 }
 
 /// Fixed-size rolling summary implementation for REPL-like workflows.
+///
+/// Besides the per-bucket count, entries are cut to `max_entry_chars` and
+/// [`ContextSummarizer::current`] drops the oldest entries once the summary
+/// exceeds `max_summary_chars`. After
+/// [`ContextSummarizer::update_with_source`], symbols and imports the
+/// generated code still uses outlive older unused ones.
 #[derive(Debug, Clone)]
 pub struct RollingContextSummarizer {
     max: usize,
+    max_entry_chars: usize,
+    max_summary_chars: usize,
     summary: SessionSummary,
 }
 
 impl RollingContextSummarizer {
     /// Creates a summarizer with a maximum retained item count per bucket.
     pub fn new(max: usize) -> Self {
+        let limits = crate::types::ReplConfig::default();
         Self {
             max,
+            max_entry_chars: limits.max_entry_chars,
+            max_summary_chars: limits.max_summary_chars,
             summary: SessionSummary::default(),
         }
     }
 
-    fn push_trimmed(vec: &mut Vec<String>, value: String, max: usize) {
-        vec.push(value);
+    /// Sets the per-entry and total character budgets; `0` disables either.
+    pub fn with_char_limits(mut self, max_entry_chars: usize, max_summary_chars: usize) -> Self {
+        self.max_entry_chars = max_entry_chars;
+        self.max_summary_chars = max_summary_chars;
+        self
+    }
+
+    /// Drops the oldest entry of the largest bucket until `summary` fits the total budget.
+    fn fit_to_budget(&self, summary: &mut SessionSummary) {
+        if let Some(narrative) = summary.narrative.as_mut() {
+            *narrative = truncate_chars(narrative, self.max_entry_chars);
+        }
+        if self.max_summary_chars == 0 {
+            return;
+        }
+        let chars = |items: &Vec<String>| items.iter().map(|i| i.chars().count()).sum::<usize>();
+        loop {
+            let narrative = summary
+                .narrative
+                .as_deref()
+                .map_or(0, |n| n.chars().count());
+            let buckets = [
+                &mut summary.symbols,
+                &mut summary.imports,
+                &mut summary.side_effects,
+                &mut summary.recent_intents,
+            ];
+            let total = narrative + buckets.iter().map(|b| chars(b)).sum::<usize>();
+            if total <= self.max_summary_chars {
+                return;
+            }
+            match buckets.into_iter().max_by_key(|b| chars(b)) {
+                Some(largest) if !largest.is_empty() => {
+                    largest.remove(0);
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn push_trimmed(vec: &mut Vec<String>, value: String, max: usize, max_chars: usize) {
+        vec.push(truncate_chars(&value, max_chars));
         if vec.len() > max {
             let overflow = vec.len() - max;
             vec.drain(0..overflow);
         }
     }
+
+    /// Like [`Self::push_trimmed`], but moves a repeated entry to the end and
+    /// evicts the oldest entry `relevant` rejects before falling back to age.
+    fn push_relevant(
+        vec: &mut Vec<String>,
+        value: String,
+        max: usize,
+        max_chars: usize,
+        relevant: impl Fn(&str) -> bool,
+    ) {
+        let value = truncate_chars(&value, max_chars);
+        vec.retain(|entry| *entry != value);
+        vec.push(value);
+        while vec.len() > max {
+            let stale = vec.iter().position(|entry| !relevant(entry)).unwrap_or(0);
+            vec.remove(stale);
+        }
+    }
+}
+
+/// The name declared by a `let`, `const`, or `function` line.
+fn declared_symbol(line: &str) -> Option<String> {
+    if !(line.starts_with("let ") || line.starts_with("const ") || line.starts_with("function ")) {
+        return None;
+    }
+    let symbol = line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or(line)
+        .trim_matches(|c: char| c == '{' || c == '(' || c == ';');
+    Some(symbol.to_string())
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// Whether `source` still uses the tracked `import` line: it names one of
+/// its bindings or repeats its specifier.
+fn import_referenced(import: &str, source: &str, identifiers: &HashSet<&str>) -> bool {
+    let specifier = import.split(['\'', '"']).nth(1);
+    if specifier.is_some_and(|specifier| source.contains(specifier)) {
+        return true;
+    }
+    let Some((clause, _)) = import.trim_start_matches("import ").split_once(" from ") else {
+        return false;
+    };
+    clause
+        .split(|c: char| !is_identifier_char(c))
+        .filter(|word| !word.is_empty() && !matches!(*word, "as" | "type"))
+        .any(|binding| identifiers.contains(binding))
 }
 
 #[async_trait]
 impl ContextSummarizer for RollingContextSummarizer {
     async fn update(&mut self, event: &str) -> SessionSummary {
         let event = event.trim();
+        let (max, max_chars) = (self.max, self.max_entry_chars);
         if event.starts_with("import ") {
-            Self::push_trimmed(&mut self.summary.imports, event.to_string(), self.max);
-        } else if event.starts_with("let ")
-            || event.starts_with("const ")
-            || event.starts_with("function ")
-        {
-            let symbol = event
-                .split_whitespace()
-                .nth(1)
-                .unwrap_or(event)
-                .trim_matches(|c: char| c == '{' || c == '(' || c == ';')
-                .to_string();
-            Self::push_trimmed(&mut self.summary.symbols, symbol, self.max);
+            Self::push_trimmed(&mut self.summary.imports, event.to_string(), max, max_chars);
+        } else if let Some(symbol) = declared_symbol(event) {
+            Self::push_trimmed(&mut self.summary.symbols, symbol, max, max_chars);
         } else {
-            Self::push_trimmed(&mut self.summary.side_effects, event.to_string(), self.max);
+            Self::push_trimmed(
+                &mut self.summary.side_effects,
+                event.to_string(),
+                max,
+                max_chars,
+            );
         }
         Self::push_trimmed(
             &mut self.summary.recent_intents,
             event.to_string(),
-            self.max,
+            max,
+            max_chars,
+        );
+        self.current()
+    }
+
+    fn current(&self) -> SessionSummary {
+        let mut summary = self.summary.clone();
+        self.fit_to_budget(&mut summary);
+        summary
+    }
+
+    fn restore(&mut self, summary: SessionSummary) {
+        self.summary = summary;
+    }
+
+    async fn update_with_source(&mut self, source: &str) -> SessionSummary {
+        let identifiers: HashSet<&str> = source
+            .split(|c: char| !is_identifier_char(c))
+            .filter(|word| !word.is_empty())
+            .collect();
+        let (max, max_chars) = (self.max, self.max_entry_chars);
+        for line in source.lines().map(str::trim) {
+            if line.starts_with("import ") {
+                Self::push_relevant(
+                    &mut self.summary.imports,
+                    line.to_string(),
+                    max,
+                    max_chars,
+                    |import| import_referenced(import, source, &identifiers),
+                );
+            } else if let Some(symbol) = declared_symbol(line) {
+                Self::push_relevant(&mut self.summary.symbols, symbol, max, max_chars, |s| {
+                    identifiers.contains(s)
+                });
+            }
+        }
+        self.current()
+    }
+}
+
+/// Summarizer that periodically asks the model to compress the session into
+/// [`SessionSummary::narrative`], on top of the rolling buckets.
+///
+/// Only every `every`-th update calls the provider; events in between are
+/// batched into the next request. A failed call keeps the previous narrative.
+pub struct LlmContextSummarizer<P: TranslatorProvider> {
+    provider: P,
+    rolling: RollingContextSummarizer,
+    every: usize,
+    updates: usize,
+    pending: Vec<String>,
+    narrative: Option<String>,
+}
+
+impl<P: TranslatorProvider> LlmContextSummarizer<P> {
+    /// Creates a summarizer keeping `max` items per bucket and summarizing every `every` updates.
+    pub fn new(provider: P, max: usize, every: usize) -> Self {
+        Self {
+            provider,
+            rolling: RollingContextSummarizer::new(max),
+            every: every.max(1),
+            updates: 0,
+            pending: Vec::new(),
+            narrative: None,
+        }
+    }
+
+    /// Sets the per-entry and total character budgets; `0` disables either.
+    pub fn with_char_limits(mut self, max_entry_chars: usize, max_summary_chars: usize) -> Self {
+        self.rolling = self
+            .rolling
+            .with_char_limits(max_entry_chars, max_summary_chars);
+        self
+    }
+
+    async fn summarize(&mut self) {
+        let mut input = String::from(
+            "Summarize this REPL session in at most three sentences so later code generation \
+             keeps its context. Reply with a single JavaScript line comment.\n",
         );
-        self.summary.clone()
+        if let Some(previous) = &self.narrative {
+            input.push_str(&format!("Previous summary: {previous}\n"));
+        }
+        input.push_str("New inputs:\n");
+        for event in &self.pending {
+            input.push_str(&format!("- {event}\n"));
+        }
+        let req = TranslateRequest {
+            input,
+            mode: "summarize".to_string(),
+            session_summary: self.current(),
+            file_metadata: None,
+            trace_id: None,
+        };
+        match self.provider.translate(req).await {
+            Ok(result) => {
+                if let Some(text) = comment_text(&result.code) {
+                    self.narrative = Some(text);
+                }
+                self.pending.clear();
+            }
+            Err(err) => tracing::warn!(error = %err, "session summarization failed"),
+        }
+    }
+}
+
+#[async_trait]
+impl<P: TranslatorProvider> ContextSummarizer for LlmContextSummarizer<P> {
+    async fn update(&mut self, event: &str) -> SessionSummary {
+        self.rolling.update(event).await;
+        self.pending.push(event.trim().to_string());
+        // Keep the batch bounded if the provider keeps failing.
+        let overflow = self.pending.len().saturating_sub(self.every * 2);
+        self.pending.drain(..overflow);
+        self.updates += 1;
+        if self.updates.is_multiple_of(self.every) {
+            self.summarize().await;
+        }
+        self.current()
     }
 
     fn current(&self) -> SessionSummary {
-        self.summary.clone()
+        let mut summary = SessionSummary {
+            narrative: self.narrative.clone(),
+            ..self.rolling.summary.clone()
+        };
+        self.rolling.fit_to_budget(&mut summary);
+        summary
+    }
+
+    fn restore(&mut self, summary: SessionSummary) {
+        self.narrative = summary.narrative.clone();
+        self.pending.clear();
+        self.rolling.restore(summary);
+    }
+
+    async fn update_with_source(&mut self, source: &str) -> SessionSummary {
+        self.rolling.update_with_source(source).await;
+        self.current()
     }
 }
 
+/// Extracts the text of a model reply that should be a comment, dropping comment markers.
+fn comment_text(code: &str) -> Option<String> {
+    let text = code
+        .trim()
+        .trim_start_matches("/*")
+        .trim_end_matches("*/")
+        .lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches("//")
+                .trim_start_matches('*')
+                .trim()
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
 /// Errors emitted by translation, policy, and runtime execution flows.
 #[derive(Debug, Error)]
 pub enum EngineError {
@@ -251,12 +852,61 @@ pub enum EngineError {
     Provider(#[from] ProviderError),
     #[error("source blocked by policy: {0:?}")]
     Blocked(Vec<String>),
+    /// The input cannot be classified well enough to run.
+    #[error(transparent)]
+    Classification(#[from] ClassificationError),
+    /// A `scaffold` manifest was malformed, too large, or named unsafe paths.
+    #[error(transparent)]
+    Scaffold(#[from] ScaffoldError),
+    /// Validation or spawn failures before the code ran.
     #[error("execution error: {0}")]
     Execution(String),
+    /// The `deno` binary is not installed or not on `PATH`.
+    #[error("deno is not installed or not on PATH; install it from {DENO_INSTALL_URL}")]
+    RuntimeMissing,
+    /// The code ran and Deno exited unsuccessfully.
+    #[error("{0}")]
+    RuntimeFailure(ExecutionFailure),
+    /// `deno check` reported type errors, so the code was not run.
+    #[error("type check failed:\n{0}")]
+    TypeCheck(String),
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
 }
 
+/// Why [`Engine::prepare_source`] refused to classify its input.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ClassificationError {
+    /// Empty or whitespace-only input, which would run as an empty program.
+    #[error("input is empty; nothing to run")]
+    Empty,
+    /// The heuristics could go either way; retry with `force_js` or `force_nl`.
+    #[error("input is ambiguous ({reason}); say whether it is code or pseudocode")]
+    Ambiguous { reason: String },
+}
+
+/// Output of [`Engine::process_tagged_script`].
+#[derive(Debug, Clone)]
+pub struct ProcessedScript {
+    /// The script with every nl block replaced by its translation, and the
+    /// translations' imports hoisted to the top.
+    pub source: String,
+    pub warnings: Vec<Warning>,
+    /// Maps lines of `source` back to the original script.
+    pub source_map: SourceMap,
+    /// Blocks whose translation came from the engine's [`BlockCache`].
+    pub cached_blocks: usize,
+}
+
+/// Output of [`Engine::scaffold`]: validated files that have not been written yet.
+#[derive(Debug, Clone)]
+pub struct ScaffoldPlan {
+    pub files: Vec<ScaffoldFile>,
+    /// Policy report for each entry of `files`; `None` for non-script files.
+    pub risks: Vec<Option<RiskReport>>,
+    pub translated: TranslateResult,
+}
+
 /// Main orchestration entry for classify/translate/validate flows.
 ///
 /// This type coordinates [`TranslatorProvider`] and [`RiskPolicy`] to
@@ -268,8 +918,24 @@ where
 {
     provider: P,
     policy: R,
+    limits: LimitsConfig,
+    block_cache: Option<BlockCache>,
+    executor: Arc<dyn Executor>,
+    auto_continue: bool,
+    source_transform: Option<SourceTransform>,
+    prelude: Option<String>,
 }
 
+/// Rewrites prepared source before the policy sees it; see [`Engine::with_source_transform`].
+pub type SourceTransform = Box<dyn Fn(&str) -> String + Send + Sync>;
+
+/// What [`Engine::prepare_source`] returns: the source, the provider's result when
+/// the input was translated, the risk report, and warnings to show.
+pub type PreparedSource = (String, Option<TranslateResult>, RiskReport, Vec<Warning>);
+
+/// Continuation requests sent for one cut-off response when auto-continue is on.
+pub const MAX_CONTINUATIONS: usize = 2;
+
 impl<P, R> Engine<P, R>
 where
     P: TranslatorProvider,
@@ -277,11 +943,136 @@ where
 {
     /// Constructs a new engine with a provider and policy implementation.
     pub fn new(provider: P, policy: R) -> Self {
-        Self { provider, policy }
+        Self {
+            provider,
+            policy,
+            limits: LimitsConfig::default(),
+            block_cache: None,
+            executor: Arc::new(DenoBinaryExecutor),
+            auto_continue: false,
+            source_transform: None,
+            prelude: None,
+        }
+    }
+
+    /// Replaces the size limits applied to generated source.
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Asks the provider to continue a response it cut off ([`ProviderError::Truncated`])
+    /// up to [`MAX_CONTINUATIONS`] times, joining the pieces (`llm.auto_continue`).
+    pub fn with_auto_continue(mut self, enabled: bool) -> Self {
+        self.auto_continue = enabled;
+        self
+    }
+
+    /// Rewrites every source [`Self::prepare_source`] returns, e.g. to add a
+    /// prelude or pin imports.
+    ///
+    /// The transform runs after translation and before the policy, so its
+    /// output is checked and executed like any other code. Tagged scripts from
+    /// [`Self::process_tagged_script`] are left alone to keep their source maps exact.
+    pub fn with_source_transform(
+        mut self,
+        transform: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.source_transform = Some(Box::new(transform));
+        self
+    }
+
+    /// Prepends `prelude` (`runtime.prelude`) to prepared source, wrapping any
+    /// transform already installed so that one still runs first; `None` or blank
+    /// leaves the current transform in place.
+    ///
+    /// [`Self::process_tagged_script`] also inserts it, below any shebang and
+    /// directive lines, shifting the source map to match.
+    pub fn with_prelude(mut self, prelude: Option<String>) -> Self {
+        let Some(prelude) = prelude.filter(|prelude| !prelude.trim().is_empty()) else {
+            return self;
+        };
+        self.prelude = Some(format!("{}\n", prelude.trim_end()));
+        let inner = self.source_transform.take();
+        self.with_source_transform(move |source| {
+            let source = match &inner {
+                Some(transform) => transform(source),
+                None => source.to_string(),
+            };
+            format!("{}\n{source}", prelude.trim_end())
+        })
+    }
+
+    /// Translates `req`, continuing a cut-off response when auto-continue is on.
+    ///
+    /// Each continuation carries the original input and the code so far; the
+    /// joined code must parse once the provider stops cutting it off, and is then
+    /// handed to [`TranslatorProvider::finish_continued`].
+    async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+        let mut partial = match self.provider.translate(req.clone()).await {
+            Err(ProviderError::Truncated(partial)) if self.auto_continue => partial,
+            other => return other,
+        };
+        for attempt in 1..=MAX_CONTINUATIONS {
+            tracing::info!(attempt, "response was cut off, requesting a continuation");
+            let next = TranslateRequest {
+                input: format!("Request: {}\nCode so far:\n{partial}", req.input),
+                mode: CONTINUE_MODE.to_string(),
+                ..req.clone()
+            };
+            match self.provider.translate(next).await {
+                Ok(mut rest) => {
+                    rest.code = format!("{partial}{}", rest.code);
+                    if parse_js(&rest.code).is_err() {
+                        return Err(ProviderError::InvalidResponse(
+                            "continued response is still not valid code".to_string(),
+                        ));
+                    }
+                    rest.raw_provider_meta
+                        .insert("continuations".to_string(), attempt.into());
+                    return self.provider.finish_continued(rest);
+                }
+                Err(ProviderError::Truncated(more)) => partial.push_str(&more),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(ProviderError::Truncated(partial))
+    }
+
+    /// Replaces the runtime backend used by [`Self::execute`]; defaults to
+    /// [`DenoBinaryExecutor`].
+    pub fn with_executor(mut self, executor: impl Executor + 'static) -> Self {
+        self.executor = Arc::new(executor);
+        self
+    }
+
+    /// Checks permission alignment and runs `req` on the engine's [`Executor`].
+    ///
+    /// A trace id is generated when the request has none.
+    pub async fn execute(&self, mut req: ExecutionRequest) -> Result<ExecutionOutput, EngineError> {
+        req.trace_id.get_or_insert_with(new_trace_id);
+        enforce_permission_alignment(&req.source, &req.deno_permissions)?;
+        self.executor.execute(&req).await
+    }
+
+    /// Reuses translations of unchanged `/*nl` blocks in
+    /// [`Self::process_tagged_script`]; `None` translates every block.
+    pub fn with_block_cache(mut self, cache: Option<BlockCache>) -> Self {
+        self.block_cache = cache;
+        self
     }
 
     /// Prepares executable source from raw input and returns risk metadata.
     ///
+    /// Alongside the source, the provider's result (when translated), and the
+    /// risk report, it returns warnings for the caller to show: the provider's
+    /// caveats and the reasons behind a risky verdict that needs no confirmation.
+    ///
+    /// `trace_id` is forwarded to the provider; a new one is generated when absent.
+    /// Mode `force_js` runs the input as-is and `force_nl` always translates. Other
+    /// modes fail with [`ClassificationError::Ambiguous`] when the classifier is
+    /// unsure, and every mode rejects empty input with [`ClassificationError::Empty`].
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -291,22 +1082,66 @@ where
     ///
     /// # async fn demo() -> Result<(), Box<dyn std::error::Error>> {
     /// let engine = Engine::new(MockProvider, DefaultRiskPolicy::default());
-    /// let (source, _, risk) = engine
-    ///     .prepare_source("print hello", "eval", SessionSummary::default(), None)
+    /// let (source, _, risk, _warnings) = engine
+    ///     .prepare_source("print hello", "eval", SessionSummary::default(), None, None)
     ///     .await?;
     /// assert!(source.contains("console.log"));
     /// assert!(!risk.requires_confirmation);
     /// # Ok(())
     /// # }
     /// ```
+    #[tracing::instrument(skip_all, fields(mode = %mode, trace_id))]
     pub async fn prepare_source(
         &self,
         input: &str,
         mode: &str,
         summary: SessionSummary,
         file_metadata: Option<FileMetadata>,
-    ) -> Result<(String, Option<TranslateResult>, RiskReport), EngineError> {
-        let (source, translated) = match classify_input(input) {
+        trace_id: Option<String>,
+    ) -> Result<PreparedSource, EngineError> {
+        let trace_id = trace_id.unwrap_or_else(new_trace_id);
+        tracing::Span::current().record("trace_id", trace_id.as_str());
+        let (prepared, stats) = parse_cache::scope(self.prepare_in_scope(
+            input,
+            mode,
+            summary,
+            file_metadata,
+            trace_id,
+        ))
+        .await;
+        tracing::debug!(parses = stats.parses, hits = stats.hits, "parse cache");
+        prepared
+    }
+
+    /// Body of [`Self::prepare_source`], run inside a [`parse_cache::scope`] so the
+    /// provider's validity check and the policy share one parse.
+    async fn prepare_in_scope(
+        &self,
+        input: &str,
+        mode: &str,
+        summary: SessionSummary,
+        file_metadata: Option<FileMetadata>,
+        trace_id: String,
+    ) -> Result<PreparedSource, EngineError> {
+        let media_type = media_type_for(
+            file_metadata
+                .as_ref()
+                .and_then(|metadata| metadata.path.as_deref()),
+        );
+        if input.trim().is_empty() {
+            return Err(ClassificationError::Empty.into());
+        }
+        let classification = classify_and_explain(input);
+        tracing::debug!(kind = ?classification.kind, reason = %classification.reason, "classified input");
+        let forced = mode == "force_js" || mode == "force_nl";
+        if classification.ambiguous && !forced {
+            return Err(ClassificationError::Ambiguous {
+                reason: classification.reason,
+            }
+            .into());
+        }
+        let (source, translated) = match classification.kind {
+            _ if mode == "force_js" => (input.to_string(), None),
             InputKind::Code if mode != "force_nl" => (input.to_string(), None),
             _ => {
                 let req = TranslateRequest {
@@ -314,63 +1149,610 @@ where
                     mode: mode.to_string(),
                     session_summary: summary,
                     file_metadata,
+                    trace_id: Some(trace_id),
                 };
-                let translated = self.provider.translate(req).await?;
+                let translated = self
+                    .translate(req)
+                    .instrument(tracing::info_span!("translate"))
+                    .await?;
+                self.check_generated_size(&translated.code)?;
                 (translated.code.clone(), Some(translated))
             }
         };
+        let source = match &self.source_transform {
+            Some(transform) => transform(&source),
+            None => source,
+        };
 
-        let risk = self.policy.analyze(&source).await;
+        let risk = self.analyze_as(&source, media_type).await;
         if risk.level == RiskLevel::Blocked {
             return Err(EngineError::Blocked(risk.reasons));
         }
 
-        Ok((source, translated, risk))
+        let mut warnings = translated
+            .as_ref()
+            .map(TranslateResult::caveats)
+            .unwrap_or_default();
+        if risk.level == RiskLevel::Risky && !risk.requires_confirmation {
+            warnings.extend(
+                risk.reasons
+                    .iter()
+                    .map(|reason| Warning::new(WarningKind::Risky, reason)),
+            );
+        }
+        Ok((source, translated, risk, warnings))
     }
 
-    /// Replaces tagged NL blocks in script content with translated JS/TS.
-    pub async fn process_tagged_script(
+    /// Sends `input` to the provider and returns its full result.
+    ///
+    /// Unlike [`Engine::prepare_source`], this always translates (even input that
+    /// classifies as code) and applies neither the risk policy nor size limits, so
+    /// callers can inspect output that would otherwise be rejected.
+    #[tracing::instrument(skip_all, fields(mode = %mode, trace_id))]
+    pub async fn translate_only(
         &self,
-        script: &str,
+        input: &str,
+        mode: &str,
         summary: SessionSummary,
-        file_path: Option<String>,
-    ) -> Result<(String, Vec<String>), EngineError> {
-        let mut out = String::new();
-        let mut warnings = Vec::new();
-        let mut cursor = 0;
+        file_metadata: Option<FileMetadata>,
+    ) -> Result<TranslateResult, EngineError> {
+        let trace_id = new_trace_id();
+        tracing::Span::current().record("trace_id", trace_id.as_str());
+        let req = TranslateRequest {
+            input: input.to_string(),
+            mode: mode.to_string(),
+            session_summary: summary,
+            file_metadata,
+            trace_id: Some(trace_id),
+        };
+        Ok(self
+            .provider
+            .translate(req)
+            .instrument(tracing::info_span!("translate"))
+            .await?)
+    }
 
-        while let Some(start) = script[cursor..].find("/*nl") {
-            let abs_start = cursor + start;
-            out.push_str(&script[cursor..abs_start]);
-            let after_tag = abs_start + 4;
-            let Some(end_rel) = script[after_tag..].find("*/") else {
-                warnings.push("unterminated nl block; leaving remainder unchanged".to_string());
-                out.push_str(&script[abs_start..]);
-                return Ok((out, warnings));
+    /// Asks the provider to fix `source` after it failed with `failure` (self-heal).
+    ///
+    /// The fix passes through the same size limits and policy as a translation;
+    /// a blocked fix is an [`EngineError::Blocked`] error.
+    #[tracing::instrument(skip_all, fields(trace_id))]
+    pub async fn propose_fix(
+        &self,
+        source: &str,
+        failure: &ExecutionFailure,
+        trace_id: Option<String>,
+    ) -> Result<(String, RiskReport), EngineError> {
+        let trace_id = trace_id.unwrap_or_else(new_trace_id);
+        tracing::Span::current().record("trace_id", trace_id.as_str());
+        let req = TranslateRequest {
+            input: heal_input(source, failure),
+            mode: HEAL_MODE.to_string(),
+            session_summary: SessionSummary::default(),
+            file_metadata: None,
+            trace_id: Some(trace_id),
+        };
+        let fixed = self
+            .translate(req)
+            .instrument(tracing::info_span!("translate"))
+            .await?
+            .code;
+        self.check_generated_size(&fixed)?;
+        let risk = self.analyze(&fixed).await;
+        if risk.level == RiskLevel::Blocked {
+            return Err(EngineError::Blocked(risk.reasons));
+        }
+        Ok((fixed, risk))
+    }
+
+    /// Asks the provider for a multi-file project and validates the manifest.
+    ///
+    /// The file count is capped by `limits.max_files` and every file by the size
+    /// limits; script files (by extension) go through the policy, and any blocked
+    /// file rejects the whole plan.
+    #[tracing::instrument(skip_all, fields(trace_id))]
+    pub async fn scaffold(
+        &self,
+        prompt: &str,
+        trace_id: Option<String>,
+    ) -> Result<ScaffoldPlan, EngineError> {
+        if prompt.trim().is_empty() {
+            return Err(ClassificationError::Empty.into());
+        }
+        let trace_id = trace_id.unwrap_or_else(new_trace_id);
+        tracing::Span::current().record("trace_id", trace_id.as_str());
+        let req = TranslateRequest {
+            input: format!("{SCAFFOLD_INSTRUCTIONS}{prompt}"),
+            mode: "scaffold".to_string(),
+            session_summary: SessionSummary::default(),
+            file_metadata: None,
+            trace_id: Some(trace_id),
+        };
+        let translated = self
+            .provider
+            .translate(req)
+            .instrument(tracing::info_span!("translate"))
+            .await?;
+        let files = parse_manifest(&translated.code)?;
+        if files.len() > self.limits.max_files {
+            return Err(ScaffoldError::TooManyFiles {
+                count: files.len(),
+                max: self.limits.max_files,
+            }
+            .into());
+        }
+
+        let mut risks = Vec::with_capacity(files.len());
+        for file in &files {
+            self.check_generated_size(&file.contents)
+                .map_err(|e| prefix_blocked(e, &file.path))?;
+            let media_type = MediaType::from_path(Path::new(&file.path));
+            if !is_script(media_type) {
+                risks.push(None);
+                continue;
+            }
+            let risk = self.analyze_as(&file.contents, media_type).await;
+            if risk.level == RiskLevel::Blocked {
+                return Err(prefix_blocked(
+                    EngineError::Blocked(risk.reasons),
+                    &file.path,
+                ));
+            }
+            risks.push(Some(risk));
+        }
+        Ok(ScaffoldPlan {
+            files,
+            risks,
+            translated,
+        })
+    }
+
+    /// Reports the active provider's capabilities.
+    pub fn capabilities(&self) -> ProviderCapabilities {
+        self.provider.capabilities()
+    }
+
+    /// Replaces the provider used for subsequent translations.
+    pub fn set_provider(&mut self, provider: P) {
+        self.provider = provider;
+    }
+
+    /// Explains how input would be classified before translation.
+    pub fn classify_and_explain(&self, input: &str) -> Classification {
+        classify_and_explain(input)
+    }
+
+    /// Runs the engine's policy over arbitrary source without translating it.
+    #[tracing::instrument(skip_all, fields(bytes = source.len()))]
+    pub async fn analyze(&self, source: &str) -> RiskReport {
+        let report = self.policy.analyze(source).await;
+        tracing::debug!(level = ?report.level, reasons = ?report.reasons, "policy decision");
+        report
+    }
+
+    /// Like [`Engine::analyze`], parsing `source` as `media_type`.
+    #[tracing::instrument(skip_all, fields(bytes = source.len(), media_type = %media_type))]
+    pub async fn analyze_as(&self, source: &str, media_type: MediaType) -> RiskReport {
+        let report = self.policy.analyze_with_media(source, media_type).await;
+        tracing::debug!(level = ?report.level, reasons = ?report.reasons, "policy decision");
+        report
+    }
+
+    /// Replaces tagged NL blocks in script content with translated JS/TS.
+    ///
+    /// Every block's translation shares `trace_id` (generated when absent). The
+    /// returned source map relates generated lines to the original script.
+    /// Generated code is checked as the media type of `file_path`'s extension.
+    ///
+    /// Imports in generated code are hoisted to the top of the script, dropping
+    /// those that bind a name the script or an earlier block already imports.
+    /// The [prelude](Self::with_prelude), when set, goes above them.
+    #[tracing::instrument(skip_all, fields(trace_id))]
+    pub async fn process_tagged_script(
+        &self,
+        script: &str,
+        summary: SessionSummary,
+        file_path: Option<String>,
+        trace_id: Option<String>,
+    ) -> Result<ProcessedScript, EngineError> {
+        let trace_id = trace_id.unwrap_or_else(new_trace_id);
+        tracing::Span::current().record("trace_id", trace_id.as_str());
+        let mut out = String::new();
+        let mut warnings = Vec::new();
+        let mut source_map = SourceMap::default();
+        let mut generated = String::new();
+        let mut cursor = 0;
+        let mut cached_blocks = 0;
+        let media_type = media_type_for(file_path.as_deref());
+        let language = language_hint(media_type);
+        let mut imports = HoistedImports::seeded(script, media_type);
+
+        while let Some(start) = script[cursor..].find("/*nl") {
+            let abs_start = cursor + start;
+            let verbatim = &script[cursor..abs_start];
+            source_map.push_verbatim(next_line(&out), next_line(&script[..cursor]), verbatim);
+            out.push_str(verbatim);
+            let after_tag = abs_start + 4;
+            let line = next_line(&script[..abs_start]);
+            let location = match &file_path {
+                Some(path) => format!("{path}:{line}"),
+                None => format!("line {line}"),
+            };
+            let Some(end_rel) = script[after_tag..].find("*/") else {
+                warnings.push(
+                    Warning::new(
+                        WarningKind::UnterminatedBlock,
+                        "unterminated nl block; leaving remainder unchanged",
+                    )
+                    .at(location),
+                );
+                let rest = &script[abs_start..];
+                source_map.push_verbatim(next_line(&out), line, rest);
+                out.push_str(rest);
+                warnings.extend(imports.warnings.drain(..));
+                let out = imports.prepend_to(out, &mut source_map);
+                return Ok(ProcessedScript {
+                    source: self.prepend_prelude(out, &mut source_map),
+                    warnings,
+                    source_map,
+                    cached_blocks,
+                });
             };
             let abs_end = after_tag + end_rel;
-            let nl_body = script[after_tag..abs_end].trim();
-            let req = TranslateRequest {
-                input: strip_fenced_nl(nl_body),
-                mode: "run".to_string(),
-                session_summary: summary.clone(),
-                file_metadata: Some(FileMetadata {
-                    path: file_path.clone(),
-                    language_hint: Some("typescript".to_string()),
-                }),
+            let nl = strip_fenced_nl(script[after_tag..abs_end].trim());
+            let cached = self
+                .block_cache
+                .as_ref()
+                .and_then(|cache| cache.get(&nl, language));
+            let code = match cached {
+                Some(code) => {
+                    tracing::debug!("reusing cached nl block translation");
+                    cached_blocks += 1;
+                    code
+                }
+                None => {
+                    let req = TranslateRequest {
+                        input: nl.clone(),
+                        mode: "run".to_string(),
+                        session_summary: summary.clone(),
+                        file_metadata: Some(FileMetadata {
+                            path: file_path.clone(),
+                            language_hint: Some(language.to_string()),
+                        }),
+                        trace_id: Some(trace_id.clone()),
+                    };
+                    let translated = self
+                        .translate(req)
+                        .instrument(tracing::info_span!("translate"))
+                        .await?;
+                    warnings.extend(
+                        translated
+                            .caveats()
+                            .into_iter()
+                            .map(|caveat| caveat.at(location.clone())),
+                    );
+                    translated.code
+                }
             };
-            let translated = self.provider.translate(req).await?;
-            let risk = self.policy.analyze(&translated.code).await;
+            generated.push_str(&code);
+            generated.push('\n');
+            self.check_generated_size(&generated)?;
+            // Cached code is re-checked too, since the policy may have changed.
+            let risk = self.analyze_as(&code, media_type).await;
             if risk.level == RiskLevel::Blocked {
                 return Err(EngineError::Blocked(risk.reasons));
             }
-            out.push_str(&translated.code);
+            if let Some(cache) = &self.block_cache {
+                if let Err(e) = cache.put(&nl, language, &code) {
+                    tracing::warn!(error = %e, "could not cache nl block translation");
+                }
+            }
+            let body = imports.hoist(&code, media_type, line, &location);
+            if !body.is_empty() {
+                source_map.push_block(next_line(&out), line, &body);
+                out.push_str(&body);
+            }
             cursor = abs_end + 2;
         }
 
-        out.push_str(&script[cursor..]);
-        Ok((out, warnings))
+        let rest = &script[cursor..];
+        source_map.push_verbatim(next_line(&out), next_line(&script[..cursor]), rest);
+        out.push_str(rest);
+        warnings.extend(imports.warnings.drain(..));
+        let out = imports.prepend_to(out, &mut source_map);
+        Ok(ProcessedScript {
+            source: self.prepend_prelude(out, &mut source_map),
+            warnings,
+            source_map,
+            cached_blocks,
+        })
+    }
+
+    /// Inserts the prelude into a processed script; its lines map to nothing.
+    fn prepend_prelude(&self, mut out: String, source_map: &mut SourceMap) -> String {
+        if let Some(prelude) = &self.prelude {
+            insert_after_preamble(&mut out, prelude, SourceMap::default(), source_map);
+        }
+        out
+    }
+
+    /// Rejects provider output beyond `max_changed_lines` or `max_source_bytes`.
+    ///
+    /// Only generated code counts; source the user wrote themselves runs as-is.
+    fn check_generated_size(&self, source: &str) -> Result<(), EngineError> {
+        let lines = source.lines().count();
+        if lines > self.limits.max_changed_lines {
+            return Err(EngineError::Blocked(vec![format!(
+                "source exceeds {} lines",
+                self.limits.max_changed_lines
+            )]));
+        }
+        if source.len() > self.limits.max_source_bytes {
+            return Err(EngineError::Blocked(vec![format!(
+                "source exceeds {} bytes",
+                self.limits.max_source_bytes
+            )]));
+        }
+        Ok(())
+    }
+}
+
+/// Media type for a script path, defaulting to TypeScript for unknown or missing extensions.
+fn media_type_for(path: Option<&str>) -> MediaType {
+    path.map_or(MediaType::TypeScript, |path| {
+        media_type_for_path(Path::new(path))
+    })
+}
+
+/// Provider language hint for generated code of `media_type`.
+fn language_hint(media_type: MediaType) -> &'static str {
+    match media_type {
+        MediaType::JavaScript | MediaType::Mjs | MediaType::Cjs => "javascript",
+        MediaType::Jsx => "jsx",
+        MediaType::Tsx => "tsx",
+        _ => "typescript",
+    }
+}
+
+/// 1-based line on which text appended after `text` starts.
+fn next_line(text: &str) -> usize {
+    text.matches('\n').count() + 1
+}
+
+/// Import statements collected from nl blocks for the top of the inlined script.
+#[derive(Default)]
+struct HoistedImports {
+    /// Hoisted statements with the `/*nl` line of the block they came from.
+    statements: Vec<(String, usize)>,
+    /// Local name to the specifier and export it is bound to.
+    bindings: HashMap<String, (String, String)>,
+    /// Specifiers of side-effect-only imports.
+    side_effects: HashSet<String>,
+    warnings: Vec<Warning>,
+}
+
+impl HoistedImports {
+    /// Starts from the imports the user wrote, which stay where they are but
+    /// make identical generated imports redundant. The `/*nl` blocks are
+    /// comments, so the original script parses as-is; if it does not, nothing is seeded.
+    fn seeded(script: &str, media_type: MediaType) -> Self {
+        let mut imports = Self::default();
+        if let Ok(parsed) = parse_source(script, media_type) {
+            let mut collector = ImportDeclCollector::default();
+            parsed.program_ref().visit_with(&mut collector);
+            for decl in &collector.decls {
+                let specifier = decl.src.value.to_string();
+                if decl.specifiers.is_empty() {
+                    imports.side_effects.insert(specifier);
+                }
+                for (local, export) in import_bindings(decl) {
+                    imports
+                        .bindings
+                        .entry(local)
+                        .or_insert((specifier.clone(), export));
+                }
+            }
+        }
+        imports
+    }
+
+    /// Removes the import statements from `code`, keeping those not yet
+    /// imported for the top of the script, and returns the rest of the block.
+    ///
+    /// Code that does not parse is returned unchanged.
+    fn hoist(&mut self, code: &str, media_type: MediaType, line: usize, location: &str) -> String {
+        let Ok(parsed) = parse_source(code, media_type) else {
+            return code.to_string();
+        };
+        let mut collector = ImportDeclCollector::default();
+        parsed.program_ref().visit_with(&mut collector);
+        if collector.decls.is_empty() {
+            return code.to_string();
+        }
+        let start = parsed.text_info_lazy().range().start;
+
+        let mut body = String::with_capacity(code.len());
+        let mut cursor = 0;
+        for decl in &collector.decls {
+            let range = decl.range().as_byte_range(start);
+            body.push_str(&code[cursor..range.start]);
+            // Drop the statement's line break with it so no blank line is left behind.
+            cursor = match code[range.end..].find('\n') {
+                Some(newline) if code[range.end..range.end + newline].trim().is_empty() => {
+                    range.end + newline + 1
+                }
+                _ => range.end,
+            };
+
+            let specifier = decl.src.value.to_string();
+            if decl.specifiers.is_empty() {
+                if self.side_effects.insert(specifier) {
+                    self.statements.push((code[range].trim().to_string(), line));
+                }
+                continue;
+            }
+            let mut kept = Vec::new();
+            for (binding, (local, export)) in decl.specifiers.iter().zip(import_bindings(decl)) {
+                match self.bindings.get(&local) {
+                    Some((bound, bound_export))
+                        if *bound == specifier && *bound_export == export => {}
+                    // Binding the name twice would not parse, so the first import wins.
+                    Some((other, _)) => {
+                        self.warnings.push(
+                            Warning::new(
+                                WarningKind::ImportConflict,
+                                format!(
+                                    "`{local}` is imported from both `{other}` and `{specifier}`; \
+                                     keeping the `{other}` import"
+                                ),
+                            )
+                            .at(location),
+                        );
+                    }
+                    None => {
+                        self.bindings.insert(local, (specifier.clone(), export));
+                        kept.push(binding);
+                    }
+                }
+            }
+            let statement = code[range].trim().to_string();
+            if kept.len() == decl.specifiers.len() {
+                self.statements.push((statement, line));
+            } else if !kept.is_empty() {
+                let statement = rebuild_import(decl, &kept, code, start).unwrap_or(statement);
+                self.statements.push((statement, line));
+            }
+        }
+        body.push_str(&code[cursor..]);
+        body
+    }
+
+    /// Puts the hoisted statements at the top of `out`, after a shebang and
+    /// leading directive comments, shifting `source_map` to match.
+    fn prepend_to(&self, mut out: String, source_map: &mut SourceMap) -> String {
+        if self.statements.is_empty() {
+            return out;
+        }
+        let mut header = String::new();
+        let mut header_map = SourceMap::default();
+        for (statement, line) in &self.statements {
+            header_map.push_block(next_line(&header), *line, statement);
+            header.push_str(statement);
+            header.push('\n');
+        }
+        insert_after_preamble(&mut out, &header, header_map, source_map);
+        out
+    }
+}
+
+/// Inserts the whole lines of `text` below `out`'s [preamble](preamble_len),
+/// mapping them with `header` and shifting the rest of `source_map`.
+fn insert_after_preamble(
+    out: &mut String,
+    text: &str,
+    header: SourceMap,
+    source_map: &mut SourceMap,
+) {
+    let mut at = preamble_len(out);
+    if at > 0 && !out[..at].ends_with('\n') {
+        // The preamble is the whole script and lacks a final newline.
+        out.push('\n');
+        at += 1;
+    }
+    source_map.insert(next_line(&out[..at]), header, text.matches('\n').count());
+    out.insert_str(at, text);
+}
+
+/// Bytes of `source`'s leading shebang and directive comment lines
+/// (`/// <reference ...>`, `// @ts-...`), which must stay first.
+fn preamble_len(source: &str) -> usize {
+    let mut len = 0;
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let directive = (len == 0 && line.starts_with("#!"))
+            || trimmed.starts_with("/// <reference")
+            || trimmed.starts_with("// @ts-");
+        if !directive {
+            break;
+        }
+        len += line.len();
+    }
+    len
+}
+
+/// Every `import` declaration in a module, in source order.
+#[derive(Default)]
+struct ImportDeclCollector {
+    decls: Vec<ImportDecl>,
+}
+
+impl Visit for ImportDeclCollector {
+    fn visit_import_decl(&mut self, node: &ImportDecl) {
+        self.decls.push(node.clone());
+    }
+}
+
+/// `(local name, export)` per specifier of `decl`; the export is `default`,
+/// `*`, or the imported name.
+fn import_bindings(decl: &ImportDecl) -> Vec<(String, String)> {
+    decl.specifiers
+        .iter()
+        .map(|specifier| match specifier {
+            ImportSpecifier::Default(default) => {
+                (default.local.sym.to_string(), "default".to_string())
+            }
+            ImportSpecifier::Namespace(namespace) => {
+                (namespace.local.sym.to_string(), "*".to_string())
+            }
+            ImportSpecifier::Named(named) => {
+                let export = match &named.imported {
+                    Some(ModuleExportName::Ident(ident)) => ident.sym.to_string(),
+                    Some(ModuleExportName::Str(name)) => name.value.to_string(),
+                    None => named.local.sym.to_string(),
+                };
+                (named.local.sym.to_string(), export)
+            }
+        })
+        .collect()
+}
+
+/// Rewrites `decl` with only the `kept` specifiers, or `None` when it has
+/// import attributes that a rewrite could lose.
+fn rebuild_import(
+    decl: &ImportDecl,
+    kept: &[&ImportSpecifier],
+    code: &str,
+    start: deno_ast::StartSourcePos,
+) -> Option<String> {
+    if decl.with.is_some() {
+        return None;
+    }
+    let mut parts = Vec::new();
+    let mut named = Vec::new();
+    for specifier in kept {
+        match specifier {
+            ImportSpecifier::Named(_) => named.push(node_text(code, start, *specifier)),
+            _ => parts.push(node_text(code, start, *specifier)),
+        }
+    }
+    if !named.is_empty() {
+        parts.push(format!("{{ {} }}", named.join(", ")));
     }
+    let type_only = if decl.type_only { "type " } else { "" };
+    Some(format!(
+        "import {type_only}{} from {};",
+        parts.join(", "),
+        node_text(code, start, &*decl.src)
+    ))
+}
+
+/// The text of `node` within `code`, the source it was parsed from.
+fn node_text<'a>(
+    code: &'a str,
+    start: deno_ast::StartSourcePos,
+    node: &impl SourceRangedForSpanned,
+) -> &'a str {
+    &code[node.range().as_byte_range(start)]
 }
 
 fn strip_fenced_nl(body: &str) -> String {
@@ -387,224 +1769,2194 @@ fn strip_fenced_nl(body: &str) -> String {
 }
 
 /// Validates permissions and executes source using the runtime backend.
-pub async fn execute_request(req: ExecutionRequest) -> Result<(), EngineError> {
+///
+/// A trace id is generated when the request has none; it names the temp module.
+#[tracing::instrument(skip_all, fields(origin = %req.origin, trace_id))]
+pub async fn execute_request(mut req: ExecutionRequest) -> Result<(), EngineError> {
+    let trace_id = req.trace_id.get_or_insert_with(new_trace_id);
+    tracing::Span::current().record("trace_id", trace_id.as_str());
     enforce_permission_alignment(&req.source, &req.deno_permissions)?;
-    execute_with_deno_binary(req).await
+    run_live(&req).await?.into_result()
 }
 
-fn enforce_permission_alignment(
-    source: &str,
-    perms: &crate::types::DenoPermissions,
-) -> Result<(), EngineError> {
-    let read_ops = ["Deno.readTextFile", "Deno.readFile", "Deno.open("];
-    let write_ops = ["Deno.writeTextFile", "Deno.writeFile", "Deno.mkdir("];
-    let net_ops = ["fetch(", "WebSocket(", "Deno.connect("];
-    let env_ops = ["Deno.env.get", "Deno.env.toObject", "Deno.env.set"];
-    let run_ops = ["Deno.Command", "Deno.run("];
-
-    if read_ops.iter().any(|op| source.contains(op)) && perms.allow_read.is_empty() {
-        return Err(EngineError::Execution(
-            "code requires --allow-read but none was provided".to_string(),
-        ));
-    }
-    if write_ops.iter().any(|op| source.contains(op)) && perms.allow_write.is_empty() {
-        return Err(EngineError::Execution(
-            "code requires --allow-write but none was provided".to_string(),
-        ));
-    }
-    if net_ops.iter().any(|op| source.contains(op)) && perms.allow_net.is_empty() {
-        return Err(EngineError::Execution(
-            "code requires --allow-net but none was provided".to_string(),
-        ));
-    }
-    if env_ops.iter().any(|op| source.contains(op)) && !perms.allow_env {
-        return Err(EngineError::Execution(
-            "code requires --allow-env but none was provided".to_string(),
-        ));
-    }
-    if run_ops.iter().any(|op| source.contains(op)) && !perms.allow_run {
-        return Err(EngineError::Execution(
-            "code requires --allow-run but none was provided".to_string(),
-        ));
-    }
-    Ok(())
+/// Like [`execute_request`], but captures stdout/stderr instead of inheriting them.
+///
+/// A non-zero exit is reported through [`ExecutionOutput::success`] rather than
+/// as an error; only validation and spawn failures return `Err`.
+#[tracing::instrument(skip_all, fields(origin = %req.origin, trace_id))]
+pub async fn execute_request_captured(
+    mut req: ExecutionRequest,
+) -> Result<ExecutionOutput, EngineError> {
+    let trace_id = req.trace_id.get_or_insert_with(new_trace_id);
+    tracing::Span::current().record("trace_id", trace_id.as_str());
+    enforce_permission_alignment(&req.source, &req.deno_permissions)?;
+    capture_with_deno_binary(req).await
 }
 
-/// Parses source as TypeScript/JavaScript to ensure syntactic validity.
-///
-/// # Examples
-///
-/// ```
-/// use beeno_core::engine::parse_js;
+/// Runtime backend that runs a validated [`ExecutionRequest`].
 ///
-/// assert!(parse_js("const x: number = 1;").is_ok());
-/// assert!(parse_js("const =").is_err());
-/// ```
-pub fn parse_js(source: &str) -> anyhow::Result<()> {
-    parse_module(ParseParams {
-        specifier: Url::parse("file:///inline.ts")?,
-        text: Arc::<str>::from(source),
-        media_type: MediaType::TypeScript,
-        capture_tokens: false,
-        maybe_syntax: None,
-        scope_analysis: false,
-    })?;
-    Ok(())
+/// [`Engine::execute`] checks permission alignment before delegating here, so
+/// implementations only need to run the source.
+#[async_trait]
+pub trait Executor: Send + Sync {
+    /// Runs `req` and returns its captured output.
+    async fn execute(&self, req: &ExecutionRequest) -> Result<ExecutionOutput, EngineError>;
 }
 
-async fn execute_with_deno_binary(req: ExecutionRequest) -> Result<(), EngineError> {
-    let temp_path = temp_module_path();
-    fs::write(&temp_path, req.source).map_err(EngineError::Io)?;
-
-    let mut cmd = Command::new("deno");
-    cmd.arg("run");
-    for arg in permission_args(&req.deno_permissions) {
-        cmd.arg(arg);
+impl ExecutionOutput {
+    /// `Ok` for a successful run, otherwise [`EngineError::RuntimeFailure`] carrying its output.
+    pub fn into_result(self) -> Result<(), EngineError> {
+        if self.success {
+            return Ok(());
+        }
+        Err(EngineError::RuntimeFailure(ExecutionFailure {
+            exit_code: self.exit_code,
+            stdout: self.stdout,
+            stderr: self.stderr,
+        }))
     }
-    cmd.arg(&temp_path);
-    cmd.stdout(Stdio::inherit());
-    cmd.stderr(Stdio::inherit());
-    cmd.stdin(Stdio::inherit());
-
-    let status = cmd
-        .status()
-        .await
-        .map_err(|e| EngineError::Execution(format!("failed to launch deno binary: {e}")))?;
+}
 
-    let _ = fs::remove_file(&temp_path);
+/// Default [`Executor`] that spawns the `deno` binary.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DenoBinaryExecutor;
 
-    if status.success() {
-        Ok(())
-    } else {
-        Err(EngineError::Execution(format!(
-            "deno run exited with status {status}"
-        )))
+#[async_trait]
+impl Executor for DenoBinaryExecutor {
+    /// Echoes output as it arrives for [`ExecutionRequest::live`] requests and
+    /// captures it otherwise.
+    async fn execute(&self, req: &ExecutionRequest) -> Result<ExecutionOutput, EngineError> {
+        if req.live {
+            run_live(req).await
+        } else {
+            execute_request_captured(req.clone()).await
+        }
     }
 }
 
-fn permission_args(perms: &crate::types::DenoPermissions) -> Vec<String> {
-    let mut args = Vec::new();
-    if !perms.allow_read.is_empty() {
-        args.push(format!("--allow-read={}", perms.allow_read.join(",")));
-    }
-    if !perms.allow_write.is_empty() {
-        args.push(format!("--allow-write={}", perms.allow_write.join(",")));
-    }
-    if !perms.allow_net.is_empty() {
-        args.push(format!("--allow-net={}", perms.allow_net.join(",")));
-    }
-    if perms.allow_env {
-        args.push("--allow-env".to_string());
-    }
-    if perms.allow_run {
-        args.push("--allow-run".to_string());
+/// [`Executor`] that runs nothing and reports success with empty output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopExecutor;
+
+#[async_trait]
+impl Executor for NoopExecutor {
+    async fn execute(&self, _req: &ExecutionRequest) -> Result<ExecutionOutput, EngineError> {
+        Ok(ExecutionOutput {
+            success: true,
+            exit_code: Some(0),
+            stdout: String::new(),
+            stderr: String::new(),
+        })
     }
-    args
 }
 
-fn temp_module_path() -> std::path::PathBuf {
-    let millis = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_millis())
-        .unwrap_or(0);
-    std::env::temp_dir().join(format!("beeno-{millis}-{}.ts", std::process::id()))
+/// [`Executor`] that records every request and returns a fixed output.
+///
+/// Intended for tests that exercise execution paths without a runtime.
+#[derive(Debug)]
+pub struct RecordingExecutor {
+    output: ExecutionOutput,
+    requests: std::sync::Mutex<Vec<ExecutionRequest>>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::providers::MockProvider;
-
-    #[test]
-    fn classifier_detects_basic_code() {
-        assert_eq!(classify_input("let x = 1;"), InputKind::Code);
+impl RecordingExecutor {
+    /// Creates a recorder that answers every request with `output`.
+    pub fn new(output: ExecutionOutput) -> Self {
+        Self {
+            output,
+            requests: std::sync::Mutex::new(Vec::new()),
+        }
     }
 
-    #[test]
-    fn classifier_detects_pseudocode() {
-        assert_eq!(
-            classify_input("create a map and then print every key and value."),
-            InputKind::Pseudocode
-        );
+    /// Returns the requests executed so far, oldest first.
+    pub fn requests(&self) -> Vec<ExecutionRequest> {
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
     }
+}
 
-    #[tokio::test]
-    async fn policy_blocks_command_spawn() {
-        let policy = DefaultRiskPolicy::default();
-        let report = policy.analyze("new Deno.Command('ls')").await;
-        assert_eq!(report.level, RiskLevel::Blocked);
+impl Default for RecordingExecutor {
+    fn default() -> Self {
+        Self::new(ExecutionOutput {
+            success: true,
+            exit_code: Some(0),
+            stdout: String::new(),
+            stderr: String::new(),
+        })
     }
+}
 
-    #[tokio::test]
-    async fn policy_marks_eval_as_risky() {
-        let policy = DefaultRiskPolicy::default();
-        let report = policy.analyze("eval('1 + 1')").await;
-        assert_eq!(report.level, RiskLevel::Risky);
+#[async_trait]
+impl Executor for RecordingExecutor {
+    async fn execute(&self, req: &ExecutionRequest) -> Result<ExecutionOutput, EngineError> {
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(req.clone());
+        Ok(self.output.clone())
     }
+}
 
-    #[test]
-    fn strip_fenced() {
-        let body = "```nl\nprint hello\n```";
-        assert_eq!(strip_fenced_nl(body), "print hello");
+#[async_trait]
+impl<E: Executor + ?Sized> Executor for Arc<E> {
+    async fn execute(&self, req: &ExecutionRequest) -> Result<ExecutionOutput, EngineError> {
+        (**self).execute(req).await
     }
+}
 
-    #[tokio::test]
-    async fn summary_rolls() {
-        let mut s = RollingContextSummarizer::new(2);
-        s.update("let a = 1;").await;
-        s.update("import x from 'y';").await;
-        s.update("console.log(a)").await;
-        let cur = s.current();
-        assert!(cur.recent_intents.len() <= 2);
+/// Runs `deno check` on `req.source` with its config and import map.
+///
+/// Returns `Ok(None)` when the source type-checks and the diagnostics otherwise.
+/// With a [`ExecutionRequest::stack_mapping`], temp-module locations in the
+/// diagnostics are rewritten to the original file as they are for a run.
+pub async fn type_check_request(req: &ExecutionRequest) -> Result<Option<String>, EngineError> {
+    let module = TempModule::for_request("beeno-check", req)?;
+    let mut cmd = Command::new("deno");
+    cmd.arg("check");
+    cmd.args(config_args(
+        req.deno_config.as_deref(),
+        req.import_map.as_deref(),
+    ));
+    cmd.args(remote_args(req.no_remote, req.cached_only));
+    cmd.arg(module.path());
+    cmd.envs(&req.env);
+    cmd.stdin(Stdio::null());
+    let output = cmd.output().await.map_err(launch_error)?;
+    if output.status.success() {
+        return Ok(None);
     }
+    let mut diagnostics = String::from_utf8_lossy(&output.stderr).to_string();
+    diagnostics.push_str(&String::from_utf8_lossy(&output.stdout));
+    Ok(Some(map_diagnostics(
+        &diagnostics,
+        &module.file_name(),
+        req.stack_mapping.as_ref(),
+    )))
+}
 
-    #[tokio::test]
-    async fn prepare_source_translates_pseudocode() {
-        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default());
-        let (source, translated, risk) = engine
-            .prepare_source(
-                "create an object and print it.",
-                "eval",
-                SessionSummary::default(),
-                None,
-            )
-            .await
-            .expect("translation should succeed");
-        assert!(translated.is_some());
-        assert!(source.contains("console.log"));
-        assert_eq!(risk.level, RiskLevel::Safe);
+/// Trims `deno check` output and points temp-module locations at the original file.
+fn map_diagnostics(diagnostics: &str, module_name: &str, mapping: Option<&StackMapping>) -> String {
+    let diagnostics = diagnostics.trim();
+    match mapping {
+        Some(mapping) => {
+            mapping
+                .source_map
+                .rewrite_locations(diagnostics, module_name, &mapping.original)
+        }
+        None => diagnostics.to_string(),
     }
+}
 
-    #[tokio::test]
-    async fn process_tagged_script_replaces_nl_block() {
-        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default());
-        let script = r#"
-const before = 1;
-/*nl
-print hello from nl
-*/
-const after = 2;
-"#;
-        let (processed, warnings) = engine
-            .process_tagged_script(script, SessionSummary::default(), None)
-            .await
-            .expect("processing should succeed");
-        assert!(warnings.is_empty());
-        assert!(processed.contains("console.log"));
-        assert!(processed.contains("const before = 1;"));
-        assert!(processed.contains("const after = 2;"));
-    }
+const WRITE_OPS: [&str; 3] = ["Deno.writeTextFile", "Deno.writeFile", "Deno.mkdir("];
+const NET_OPS: [&str; 3] = ["fetch(", "WebSocket(", "Deno.connect("];
+
+/// Whether `source` performs network or file-write operations.
+pub fn uses_net_or_write(source: &str) -> bool {
+    NET_OPS
+        .iter()
+        .chain(WRITE_OPS.iter())
+        .any(|op| source.contains(op))
+}
+
+/// Decides whether to prompt before executing `source` under `mode`.
+pub fn needs_confirmation(mode: ConfirmMode, risk: &RiskReport, source: &str) -> bool {
+    match mode {
+        ConfirmMode::Never => false,
+        ConfirmMode::Risky => risk.requires_confirmation,
+        ConfirmMode::Always => true,
+        ConfirmMode::NetOrWrite => uses_net_or_write(source),
+    }
+}
+
+const READ_OPS: [&str; 3] = ["Deno.readTextFile", "Deno.readFile", "Deno.open("];
+const ENV_OPS: [&str; 3] = ["Deno.env.get", "Deno.env.toObject", "Deno.env.set"];
+const RUN_OPS: [&str; 2] = ["Deno.Command", "Deno.run("];
+
+/// Deno permissions `source` appears to use, next to the ones granted for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PermissionReport {
+    pub needs_read: bool,
+    pub needs_write: bool,
+    pub needs_net: bool,
+    pub needs_env: bool,
+    pub needs_run: bool,
+    pub granted: crate::types::DenoPermissions,
+    /// Flags the source needs but `granted` lacks, e.g. `--allow-net`.
+    pub missing: Vec<String>,
+    /// The first call needing each permission, in flag order.
+    pub triggers: Vec<PermissionTrigger>,
+}
+
+impl PermissionReport {
+    /// The call that made `flag` (e.g. `--allow-net`) necessary, if any.
+    pub fn trigger(&self, flag: &str) -> Option<&PermissionTrigger> {
+        self.triggers.iter().find(|trigger| trigger.flag == flag)
+    }
+}
+
+/// A call in the source that needs a permission, e.g. `fetch(` on line 3.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PermissionTrigger {
+    /// Flag the call needs, e.g. `--allow-net`.
+    pub flag: String,
+    /// The matched API, e.g. `Deno.readTextFile`.
+    pub op: String,
+    /// 1-based line of the call.
+    pub line: usize,
+    /// The trimmed source line containing the call.
+    pub code: String,
+}
+
+/// Finds the first line of `source` that uses one of `ops`.
+fn first_use(source: &str, flag: &str, ops: &[&str]) -> Option<PermissionTrigger> {
+    source.lines().enumerate().find_map(|(index, line)| {
+        let op = ops.iter().find(|op| line.contains(*op))?;
+        Some(PermissionTrigger {
+            flag: flag.to_string(),
+            op: op.to_string(),
+            line: index + 1,
+            code: line.trim().to_string(),
+        })
+    })
+}
+
+/// Infers which permissions `source` needs from the Deno APIs it calls and
+/// compares them with `perms`.
+///
+/// # Examples
+///
+/// ```
+/// use beeno_core::engine::analyze_permissions;
+/// use beeno_core::types::DenoPermissions;
+///
+/// let report = analyze_permissions("await fetch(url);", &DenoPermissions::default());
+/// assert!(report.needs_net);
+/// assert_eq!(report.missing, vec!["--allow-net".to_string()]);
+/// ```
+pub fn analyze_permissions(
+    source: &str,
+    perms: &crate::types::DenoPermissions,
+) -> PermissionReport {
+    let triggers: Vec<PermissionTrigger> = [
+        ("--allow-read", &READ_OPS[..]),
+        ("--allow-write", &WRITE_OPS[..]),
+        ("--allow-net", &NET_OPS[..]),
+        ("--allow-env", &ENV_OPS[..]),
+        ("--allow-run", &RUN_OPS[..]),
+    ]
+    .into_iter()
+    .filter_map(|(flag, ops)| first_use(source, flag, ops))
+    .collect();
+    let uses = |flag: &str| triggers.iter().any(|trigger| trigger.flag == flag);
+    let (needs_read, needs_write) = (uses("--allow-read"), uses("--allow-write"));
+    let (needs_net, needs_env, needs_run) = (
+        uses("--allow-net"),
+        uses("--allow-env"),
+        uses("--allow-run"),
+    );
+    let missing = [
+        ("--allow-read", needs_read && perms.allow_read.is_empty()),
+        ("--allow-write", needs_write && perms.allow_write.is_empty()),
+        ("--allow-net", needs_net && perms.allow_net.is_empty()),
+        ("--allow-env", needs_env && !perms.allow_env),
+        ("--allow-run", needs_run && !perms.allow_run),
+    ]
+    .into_iter()
+    .filter(|(_, missing)| *missing)
+    .map(|(flag, _)| flag.to_string())
+    .collect();
+    PermissionReport {
+        needs_read,
+        needs_write,
+        needs_net,
+        needs_env,
+        needs_run,
+        granted: perms.clone(),
+        missing,
+        triggers,
+    }
+}
+
+fn enforce_permission_alignment(
+    source: &str,
+    perms: &crate::types::DenoPermissions,
+) -> Result<(), EngineError> {
+    match analyze_permissions(source, perms).missing.first() {
+        Some(flag) => Err(EngineError::Execution(format!(
+            "code requires {flag} but none was provided"
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Parses source as TypeScript/JavaScript to ensure syntactic validity.
+///
+/// # Examples
+///
+/// ```
+/// use beeno_core::engine::parse_js;
+///
+/// assert!(parse_js("const x: number = 1;").is_ok());
+/// assert!(parse_js("const =").is_err());
+/// ```
+pub fn parse_js(source: &str) -> anyhow::Result<()> {
+    parse_js_with_media(source, MediaType::TypeScript)
+}
+
+/// Parses source as `media_type`, e.g. [`MediaType::Tsx`] to accept JSX.
+///
+/// # Examples
+///
+/// ```
+/// use beeno_core::engine::{parse_js_with_media, MediaType};
+///
+/// assert!(parse_js_with_media("const el = <div />;", MediaType::Jsx).is_ok());
+/// assert!(parse_js_with_media("const el = <div />;", MediaType::TypeScript).is_err());
+/// ```
+pub fn parse_js_with_media(source: &str, media_type: MediaType) -> anyhow::Result<()> {
+    parse_source(source, media_type)?;
+    Ok(())
+}
+
+/// Strips TypeScript syntax from `source`, returning plain JavaScript.
+///
+/// # Examples
+///
+/// ```
+/// use beeno_core::engine::transpile_to_js;
+///
+/// let js = transpile_to_js("const n: number = 1;").expect("transpiles");
+/// assert_eq!(js.trim(), "const n = 1;");
+/// ```
+pub fn transpile_to_js(source: &str) -> anyhow::Result<String> {
+    // Transpiling consumes the parse, so it gets its own rather than a cached one.
+    let emitted = parse_uncached(source, MediaType::TypeScript)?
+        .transpile(
+            &TranspileOptions::default(),
+            &TranspileModuleOptions::default(),
+            &EmitOptions {
+                source_map: SourceMapOption::None,
+                ..EmitOptions::default()
+            },
+        )?
+        .into_source();
+    Ok(emitted.text)
+}
+
+/// Whether `media_type` is a JS/TS flavor the policy can parse.
+fn is_script(media_type: MediaType) -> bool {
+    matches!(
+        media_type,
+        MediaType::JavaScript
+            | MediaType::Jsx
+            | MediaType::Mjs
+            | MediaType::Cjs
+            | MediaType::TypeScript
+            | MediaType::Mts
+            | MediaType::Cts
+            | MediaType::Tsx
+    )
+}
+
+/// Names `path` in each reason of a [`EngineError::Blocked`] error.
+fn prefix_blocked(err: EngineError, path: &str) -> EngineError {
+    match err {
+        EngineError::Blocked(reasons) => EngineError::Blocked(
+            reasons
+                .into_iter()
+                .map(|reason| format!("{path}: {reason}"))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Media type implied by a script's extension (`.jsx`, `.tsx`, `.js`, ...).
+///
+/// Paths without a JS/TS extension are treated as TypeScript.
+pub fn media_type_for_path(path: &Path) -> MediaType {
+    match MediaType::from_path(path) {
+        media_type if is_script(media_type) => media_type,
+        _ => MediaType::TypeScript,
+    }
+}
+
+/// Extension a temp module of `path`'s source keeps ([`ExecutionRequest::module_extension`]):
+/// its own JS/TS extension, or `None` for anything else, which runs as `.ts`.
+pub fn module_extension_for_path(path: &Path) -> Option<String> {
+    is_script(MediaType::from_path(path))
+        .then(|| path.extension()?.to_str().map(str::to_ascii_lowercase))
+        .flatten()
+}
+
+/// Parses `source`, reusing an earlier parse within the current [`parse_cache::scope`].
+fn parse_source(source: &str, media_type: MediaType) -> anyhow::Result<deno_ast::ParsedSource> {
+    parse_cache::get_or_parse(source, media_type, || parse_uncached(source, media_type))
+}
+
+fn parse_uncached(source: &str, media_type: MediaType) -> anyhow::Result<deno_ast::ParsedSource> {
+    Ok(parse_module(ParseParams {
+        specifier: Url::parse("file:///inline.ts")?,
+        text: Arc::<str>::from(source),
+        media_type,
+        capture_tokens: false,
+        maybe_syntax: None,
+        scope_analysis: false,
+    })?)
+}
+
+/// Extracts static, re-export, and literal dynamic import specifiers from source.
+///
+/// # Examples
+///
+/// ```
+/// use beeno_core::engine::import_specifiers;
+///
+/// let specs = import_specifiers("import { z } from 'npm:zod'; await import('jsr:@std/path');")
+///     .expect("parses");
+/// assert_eq!(specs, vec!["npm:zod", "jsr:@std/path"]);
+/// ```
+pub fn import_specifiers(source: &str) -> anyhow::Result<Vec<String>> {
+    import_specifiers_with_media(source, MediaType::TypeScript)
+}
+
+/// Like [`import_specifiers`], parsing `source` as `media_type`.
+pub fn import_specifiers_with_media(
+    source: &str,
+    media_type: MediaType,
+) -> anyhow::Result<Vec<String>> {
+    let parsed = parse_source(source, media_type)?;
+    let mut collector = ImportCollector::default();
+    parsed.program_ref().visit_with(&mut collector);
+    Ok(collector.specifiers)
+}
+
+#[derive(Default)]
+struct ImportCollector {
+    specifiers: Vec<String>,
+}
+
+impl Visit for ImportCollector {
+    fn visit_import_decl(&mut self, node: &ImportDecl) {
+        self.specifiers.push(node.src.value.to_string());
+    }
+
+    fn visit_export_all(&mut self, node: &ExportAll) {
+        self.specifiers.push(node.src.value.to_string());
+    }
+
+    fn visit_named_export(&mut self, node: &NamedExport) {
+        if let Some(src) = &node.src {
+            self.specifiers.push(src.value.to_string());
+        }
+    }
+
+    fn visit_call_expr(&mut self, node: &CallExpr) {
+        if let Callee::Import(_) = node.callee {
+            if let Some(Expr::Lit(Lit::Str(src))) = node.args.first().map(|a| &*a.expr) {
+                self.specifiers.push(src.value.to_string());
+            }
+        }
+        node.visit_children_with(self);
+    }
+}
+
+/// Where [`EngineError::RuntimeMissing`] points users to install Deno.
+pub const DENO_INSTALL_URL: &str = "https://deno.land/#installation";
+
+/// Maps a failure to spawn `deno` to an [`EngineError`]; a missing binary becomes
+/// [`EngineError::RuntimeMissing`].
+pub fn launch_error(err: std::io::Error) -> EngineError {
+    if err.kind() == std::io::ErrorKind::NotFound {
+        EngineError::RuntimeMissing
+    } else {
+        EngineError::Execution(format!("failed to launch deno binary: {err}"))
+    }
+}
+
+fn deno_run_command(req: &ExecutionRequest, module_path: &Path) -> Command {
+    let mut cmd = Command::new("deno");
+    cmd.arg("run");
+    cmd.args(config_args(
+        req.deno_config.as_deref(),
+        req.import_map.as_deref(),
+    ));
+    cmd.args(remote_args(req.no_remote, req.cached_only));
+    for arg in permission_args(&req.deno_permissions) {
+        cmd.arg(arg);
+    }
+    cmd.arg(module_path);
+    cmd.envs(&req.env);
+    cmd
+}
+
+/// Runs `req` with output echoed as it arrives, rewriting stack locations with
+/// its [`StackMapping`] when it has one.
+async fn run_live(req: &ExecutionRequest) -> Result<ExecutionOutput, EngineError> {
+    let module = TempModule::for_request("beeno", req)?;
+    let module_name = module.file_name();
+    let rewrite = req.stack_mapping.as_ref().map(|mapping| {
+        move |line: &str| {
+            mapping
+                .source_map
+                .rewrite_locations(line, &module_name, &mapping.original)
+        }
+    });
+    run_teed(
+        deno_run_command(req, module.path()),
+        rewrite
+            .as_ref()
+            .map(|rewrite| rewrite as &(dyn Fn(&str) -> String + Sync)),
+        req.tee.as_deref(),
+        req.capture_output,
+    )
+    .await
+}
+
+/// Bytes of stdout/stderr kept for [`ExecutionFailure`]; earlier output is dropped.
+const FAILURE_OUTPUT_LIMIT: usize = 64 * 1024;
+
+/// Runs `cmd` with output echoed live, returning its exit status and the tail
+/// of that output.
+///
+/// Output is only piped through beeno when something needs it: `capture` (the
+/// failure tail), `tee` (both streams are also appended to that file), or
+/// `rewrite_stderr` (applied to each stderr line before it is echoed and kept).
+/// Streams that are not piped are inherited, so the program still sees a TTY,
+/// and their part of the failure tail is empty.
+async fn run_teed(
+    mut cmd: Command,
+    rewrite_stderr: Option<&(dyn Fn(&str) -> String + Sync)>,
+    tee: Option<&Path>,
+    capture: bool,
+) -> Result<ExecutionOutput, EngineError> {
+    let pipe_stdout = capture || tee.is_some();
+    let pipe_stderr = pipe_stdout || rewrite_stderr.is_some();
+    let tee = tee.map(|path| open_tee(path).map(Mutex::new)).transpose()?;
+    cmd.stdout(if pipe_stdout {
+        Stdio::piped()
+    } else {
+        Stdio::inherit()
+    });
+    cmd.stderr(if pipe_stderr {
+        Stdio::piped()
+    } else {
+        Stdio::inherit()
+    });
+    cmd.stdin(Stdio::inherit());
+    let mut child = cmd.spawn().map_err(launch_error)?;
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    let (stdout, stderr) = tokio::join!(
+        async {
+            match stdout {
+                Some(out) => tee_output(out, TeeWriter::new(std::io::stdout(), tee.as_ref())).await,
+                None => Ok(String::new()),
+            }
+        },
+        async {
+            match (stderr, rewrite_stderr) {
+                (Some(err), Some(rewrite)) => {
+                    let mut sink = TeeWriter::new(std::io::stderr(), tee.as_ref());
+                    let mut kept = String::new();
+                    let mut lines = BufReader::new(err).lines();
+                    while let Some(line) = lines.next_line().await? {
+                        let line = rewrite(&line);
+                        writeln!(sink, "{line}")?;
+                        kept.push_str(&line);
+                        kept.push('\n');
+                        truncate_front(&mut kept, FAILURE_OUTPUT_LIMIT);
+                    }
+                    Ok(kept)
+                }
+                (Some(err), None) => {
+                    tee_output(err, TeeWriter::new(std::io::stderr(), tee.as_ref())).await
+                }
+                (None, _) => Ok(String::new()),
+            }
+        }
+    );
+    let status = child.wait().await?;
+    Ok(ExecutionOutput {
+        success: status.success(),
+        exit_code: status.code(),
+        stdout: stdout?,
+        stderr: stderr?,
+    })
+}
+
+/// Copies `reader` to `sink` as it arrives, returning the last
+/// [`FAILURE_OUTPUT_LIMIT`] bytes of it.
+async fn tee_output<R, W>(mut reader: R, mut sink: W) -> std::io::Result<String>
+where
+    R: AsyncRead + Unpin,
+    W: std::io::Write,
+{
+    let mut tail = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        sink.write_all(&buf[..read])?;
+        sink.flush()?;
+        tail.extend_from_slice(&buf[..read]);
+        if tail.len() > FAILURE_OUTPUT_LIMIT {
+            tail.drain(..tail.len() - FAILURE_OUTPUT_LIMIT);
+        }
+    }
+    Ok(String::from_utf8_lossy(&tail).into_owned())
+}
+
+fn open_tee(path: &Path) -> std::io::Result<fs::File> {
+    fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Writes to the terminal `sink` and, when set, the `--tee` file shared by both streams.
+struct TeeWriter<'a, W> {
+    sink: W,
+    copy: Option<&'a Mutex<fs::File>>,
+}
+
+impl<'a, W: std::io::Write> TeeWriter<'a, W> {
+    fn new(sink: W, copy: Option<&'a Mutex<fs::File>>) -> Self {
+        Self { sink, copy }
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for TeeWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.sink.write_all(buf)?;
+        if let Some(copy) = self.copy {
+            copy.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.sink.flush()?;
+        if let Some(copy) = self.copy {
+            copy.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Drops whole characters from the front of `text` until it fits in `limit` bytes.
+fn truncate_front(text: &mut String, limit: usize) {
+    if text.len() > limit {
+        let mut cut = text.len() - limit;
+        while !text.is_char_boundary(cut) {
+            cut += 1;
+        }
+        text.drain(..cut);
+    }
+}
+
+async fn capture_with_deno_binary(req: ExecutionRequest) -> Result<ExecutionOutput, EngineError> {
+    let module = TempModule::for_request("beeno", &req)?;
+
+    let mut cmd = deno_run_command(&req, module.path());
+    cmd.stdin(Stdio::null());
+
+    let output = cmd.output().await.map_err(launch_error)?;
+    if let Some(path) = &req.tee {
+        let mut tee = open_tee(path)?;
+        tee.write_all(&output.stdout)?;
+        tee.write_all(&output.stderr)?;
+    }
+
+    Ok(ExecutionOutput {
+        success: output.status.success(),
+        exit_code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+/// Maps optional Deno config and import map paths to `deno run` flags.
+pub fn config_args(deno_config: Option<&str>, import_map: Option<&str>) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(path) = deno_config.filter(|p| !p.trim().is_empty()) {
+        args.push("--config".to_string());
+        args.push(path.to_string());
+    }
+    if let Some(path) = import_map.filter(|p| !p.trim().is_empty()) {
+        args.push("--import-map".to_string());
+        args.push(path.to_string());
+    }
+    args
+}
+
+/// Maps the remote-import restrictions to `--no-remote`/`--cached-only` flags.
+pub fn remote_args(no_remote: bool, cached_only: bool) -> Vec<String> {
+    let mut args = Vec::new();
+    if no_remote {
+        args.push("--no-remote".to_string());
+    }
+    if cached_only {
+        args.push("--cached-only".to_string());
+    }
+    args
+}
+
+/// Maps a permission set to `deno run` flags.
+///
+/// A `"*"` entry in a read/write/net list grants the capability unrestricted.
+pub fn permission_args(perms: &crate::types::DenoPermissions) -> Vec<String> {
+    let mut args = Vec::new();
+    for (flag, values) in [
+        ("allow-read", &perms.allow_read),
+        ("allow-write", &perms.allow_write),
+        ("allow-net", &perms.allow_net),
+    ] {
+        if values.iter().any(|v| v == "*") {
+            args.push(format!("--{flag}"));
+        } else if !values.is_empty() {
+            args.push(format!("--{flag}={}", values.join(",")));
+        }
+    }
+    if perms.allow_env {
+        args.push("--allow-env".to_string());
+    }
+    if perms.allow_run {
+        args.push("--allow-run".to_string());
+    }
+    args
+}
+
+/// A generated module in the temp dir that is deleted when the guard drops.
+///
+/// Each file gets a UUID name, so rapid or concurrent runs never share one, and
+/// dropping on an early return or panic still removes it.
+#[derive(Debug)]
+pub struct TempModule {
+    path: PathBuf,
+}
+
+impl TempModule {
+    /// Writes `source` to a new `<prefix>-<uuid>.ts` file in the temp dir.
+    pub fn create(prefix: &str, source: &str) -> std::io::Result<Self> {
+        Self::create_with_extension(prefix, "ts", source)
+    }
+
+    /// Like [`Self::create`], naming the file `<prefix>-<uuid>.<extension>`.
+    pub fn create_with_extension(
+        prefix: &str,
+        extension: &str,
+        source: &str,
+    ) -> std::io::Result<Self> {
+        let module = Self {
+            path: std::env::temp_dir()
+                .join(format!("{prefix}-{}.{extension}", uuid::Uuid::new_v4())),
+        };
+        fs::write(&module.path, source)?;
+        Ok(module)
+    }
+
+    /// Writes `req`'s source with its [`ExecutionRequest::module_extension`].
+    fn for_request(prefix: &str, req: &ExecutionRequest) -> Result<Self, EngineError> {
+        let extension = req.module_extension.as_deref().unwrap_or("ts");
+        Self::create_with_extension(prefix, extension, &req.source).map_err(EngineError::Io)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The module's file name, as it appears in Deno stack traces.
+    pub fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for TempModule {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::MockProvider;
+    use crate::util::unique_temp_path;
+
+    #[test]
+    fn classifier_detects_basic_code() {
+        assert_eq!(classify_input("let x = 1;"), InputKind::Code);
+    }
+
+    #[test]
+    fn classification_explains_indicators_and_defaults() {
+        let c = classify_and_explain("const total = items.length;");
+        assert_eq!(c.matched_indicators, vec!["const ", ";"]);
+        assert_eq!(
+            c.reason,
+            "classified as code because it contains `const `, `;`"
+        );
+
+        let c = classify_and_explain("print hello");
+        assert_eq!(c.kind, InputKind::Code);
+        assert_eq!(c.word_count, 2);
+        assert!(c.matched_indicators.is_empty());
+        assert!(c.reason.contains("5 or fewer words"));
+
+        let c = classify_and_explain("fetch the page and then count all the links.");
+        assert_eq!(c.kind, InputKind::Pseudocode);
+        assert_eq!(c.word_count, 9);
+    }
+
+    #[test]
+    fn confirm_modes_decide_when_to_prompt() {
+        let safe = RiskReport {
+            level: RiskLevel::Safe,
+            reasons: Vec::new(),
+            requires_confirmation: false,
+            sources: Vec::new(),
+        };
+        let risky = RiskReport {
+            requires_confirmation: true,
+            ..safe.clone()
+        };
+        let fetching = "await fetch('https://example.com');";
+        assert!(!needs_confirmation(ConfirmMode::Never, &risky, fetching));
+        assert!(needs_confirmation(ConfirmMode::Risky, &risky, "1"));
+        assert!(!needs_confirmation(ConfirmMode::Risky, &safe, fetching));
+        assert!(needs_confirmation(ConfirmMode::Always, &safe, "1"));
+        assert!(needs_confirmation(ConfirmMode::NetOrWrite, &safe, fetching));
+        assert!(needs_confirmation(
+            ConfirmMode::NetOrWrite,
+            &safe,
+            "await Deno.writeTextFile('a.txt', 'x');"
+        ));
+        assert!(!needs_confirmation(
+            ConfirmMode::NetOrWrite,
+            &risky,
+            "console.log(1)"
+        ));
+    }
+
+    #[tokio::test]
+    async fn llm_summarizer_only_calls_the_provider_every_n_updates() {
+        let mut summarizer = LlmContextSummarizer::new(MockProvider, 8, 2);
+        let summary = summarizer.update("const a = 1;").await;
+        assert!(summary.narrative.is_none());
+        assert_eq!(summary.symbols, vec!["a"]);
+
+        let summary = summarizer.update("print a").await;
+        let narrative = summary.narrative.expect("summarized on the second update");
+        assert!(narrative.contains("Summarize this REPL session"));
+        assert_eq!(summarizer.current().narrative.as_deref(), Some(&*narrative));
+    }
+
+    #[test]
+    fn comment_text_strips_comment_markers() {
+        assert_eq!(
+            comment_text("// defined a\n// fetched users").as_deref(),
+            Some("defined a fetched users")
+        );
+        assert_eq!(
+            comment_text("/* a block\n * summary */").as_deref(),
+            Some("a block summary")
+        );
+        assert_eq!(comment_text("//  "), None);
+    }
+
+    #[test]
+    fn classifier_detects_pseudocode() {
+        assert_eq!(
+            classify_input("create a map and then print every key and value."),
+            InputKind::Pseudocode
+        );
+    }
+
+    #[tokio::test]
+    async fn policy_trusts_deno_land_imports() {
+        let policy = DefaultRiskPolicy::default();
+        let report = policy
+            .analyze("import { join } from 'https://deno.land/std/path/mod.ts';\nconsole.log(join('a', 'b'));")
+            .await;
+        assert_eq!(report.level, RiskLevel::Safe);
+    }
+
+    #[tokio::test]
+    async fn policy_flags_untrusted_imports() {
+        let policy = DefaultRiskPolicy::default();
+        let report = policy
+            .analyze("import x from 'https://evil.test/mod.ts';\nconsole.log(x);")
+            .await;
+        assert_eq!(report.level, RiskLevel::Risky);
+        assert_eq!(
+            report.reasons,
+            vec!["untrusted import: https://evil.test/mod.ts"]
+        );
+
+        let blocking = DefaultRiskPolicy::from_config(
+            PolicyConfig {
+                untrusted_imports: UntrustedImports::Blocked,
+                ..PolicyConfig::default()
+            },
+            PolicySource::Default,
+        );
+        let report = blocking
+            .analyze("export * from 'https://evil.test/mod.ts';")
+            .await;
+        assert_eq!(report.level, RiskLevel::Blocked);
+    }
+
+    #[tokio::test]
+    async fn policy_matches_npm_and_jsr_prefixes() {
+        let policy = DefaultRiskPolicy::from_config(
+            PolicyConfig {
+                trusted_import_prefixes: vec!["npm:zod".to_string(), "jsr:@std/".to_string()],
+                ..PolicyConfig::default()
+            },
+            PolicySource::Default,
+        );
+        let report = policy
+            .analyze("import { z } from 'npm:/zod@3';\nconst p = await import('jsr:@std/path');\nimport './local.ts';")
+            .await;
+        assert_eq!(report.level, RiskLevel::Safe);
+
+        let report = policy.analyze("import chalk from 'npm:chalk';").await;
+        assert_eq!(report.reasons, vec!["untrusted import: npm:chalk"]);
+    }
+
+    #[tokio::test]
+    async fn trusted_prefixes_stop_at_a_boundary() {
+        let policy = DefaultRiskPolicy::from_config(
+            PolicyConfig {
+                trusted_import_prefixes: vec![
+                    "https://deno.land".to_string(),
+                    "npm:zod".to_string(),
+                ],
+                ..PolicyConfig::default()
+            },
+            PolicySource::Default,
+        );
+        for trusted in [
+            "https://deno.land/std/path/mod.ts",
+            "npm:zod",
+            "npm:zod@3",
+            "npm:zod/v4",
+        ] {
+            assert!(policy.is_trusted_import(trusted), "{trusted}");
+        }
+        for untrusted in ["https://deno.land.evil.test/x.ts", "npm:zod-evil"] {
+            assert!(!policy.is_trusted_import(untrusted), "{untrusted}");
+        }
+
+        let report = policy.analyze("import z from 'npm:zod-evil';").await;
+        assert_eq!(report.reasons, vec!["untrusted import: npm:zod-evil"]);
+    }
+
+    /// Serves `body` to every connection with the given HTTP status line.
+    async fn serve_once(status: &'static str, body: &'static str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        tokio::spawn(async move {
+            if let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{addr}/policy.json")
+    }
+
+    #[tokio::test]
+    async fn remote_policy_is_fetched_and_cached() {
+        let cache_dir = unique_temp_path("beeno-policy-test");
+        let url = serve_once(
+            "200 OK",
+            r#"{"blocked_patterns":["fetch("],"risky_patterns":[],"trusted_import_prefixes":[]}"#,
+        )
+        .await;
+        let policy = DefaultRiskPolicy::from_url(&url, &cache_dir)
+            .await
+            .expect("fetch policy");
+        assert_eq!(policy.cfg.blocked_patterns, vec!["fetch("]);
+
+        // The one-shot server is gone now, so this load must come from the cache.
+        let cached = DefaultRiskPolicy::from_url(&url, &cache_dir)
+            .await
+            .expect("cached policy");
+        assert_eq!(cached.cfg.blocked_patterns, vec!["fetch("]);
+        let offline = DefaultRiskPolicy::from_cached_url(&url, &cache_dir).expect("cache only");
+        assert_eq!(offline.cfg.blocked_patterns, vec!["fetch("]);
+        let _ = fs::remove_dir_all(&cache_dir);
+    }
+
+    #[tokio::test]
+    async fn invalid_remote_policy_without_cache_fails() {
+        let cache_dir = unique_temp_path("beeno-policy-test");
+        let url = serve_once("200 OK", r#"{"blocked_patterns":"nope"}"#).await;
+        assert!(DefaultRiskPolicy::from_url(&url, &cache_dir).await.is_err());
+        assert!(!cache_dir.exists());
+    }
+
+    #[test]
+    fn temp_module_is_removed_when_the_guard_drops() {
+        let module = TempModule::create("beeno-test", "console.log(1);").expect("create");
+        let other = TempModule::create("beeno-test", "console.log(2);").expect("create");
+        let path = module.path().to_path_buf();
+        assert_ne!(path, other.path());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "console.log(1);");
+        assert!(module.file_name().starts_with("beeno-test-"));
+
+        drop(module);
+        assert!(!path.exists());
+
+        let other_path = other.path().to_path_buf();
+        let panicked = std::panic::catch_unwind(move || {
+            let _guard = other;
+            panic!("run failed");
+        });
+        assert!(panicked.is_err());
+        assert!(!other_path.exists());
+    }
+
+    #[test]
+    fn temp_modules_keep_a_jsx_scripts_extension() {
+        assert_eq!(
+            module_extension_for_path(Path::new("app.TSX")).as_deref(),
+            Some("tsx")
+        );
+        assert_eq!(
+            module_extension_for_path(Path::new("view.jsx")).as_deref(),
+            Some("jsx")
+        );
+        assert_eq!(module_extension_for_path(Path::new("notes.md")), None);
+
+        let module =
+            TempModule::create_with_extension("beeno-test", "tsx", "<div />;").expect("create");
+        assert!(
+            module.file_name().ends_with(".tsx"),
+            "{}",
+            module.file_name()
+        );
+    }
+
+    #[test]
+    fn transpile_strips_type_syntax() {
+        let js = transpile_to_js(
+            "interface Point { x: number }\nconst p: Point = { x: 1 };\nfunction id<T>(v: T): T { return v; }\nconsole.log(id(p) as Point);",
+        )
+        .expect("transpiles");
+        assert!(!js.contains("interface"));
+        assert!(js.contains("const p = {"));
+        assert!(js.contains("function id(v) {"));
+        assert!(js.contains("console.log(id(p));"));
+        assert!(parse_js_with_media(&js, MediaType::JavaScript).is_ok());
+        assert!(transpile_to_js("const =").is_err());
+    }
+
+    #[tokio::test]
+    async fn media_type_follows_the_file_extension() {
+        let snippet = "const App = () => <main>{'hi'}</main>;";
+        assert!(parse_js_with_media(snippet, MediaType::Tsx).is_ok());
+        assert!(parse_js_with_media(snippet, MediaType::TypeScript).is_err());
+        assert!(parse_js(snippet).is_err());
+
+        assert_eq!(media_type_for_path(Path::new("app.tsx")), MediaType::Tsx);
+        assert_eq!(media_type_for_path(Path::new("app.jsx")), MediaType::Jsx);
+        assert_eq!(
+            media_type_for_path(Path::new("app.js")),
+            MediaType::JavaScript
+        );
+        assert_eq!(
+            media_type_for_path(Path::new("notes.nl")),
+            MediaType::TypeScript
+        );
+
+        let policy = DefaultRiskPolicy::default();
+        let report = policy.analyze_with_media(snippet, MediaType::Tsx).await;
+        assert_eq!(report.level, RiskLevel::Safe);
+        let report = policy.analyze(snippet).await;
+        assert_eq!(report.level, RiskLevel::Blocked);
+    }
+
+    #[tokio::test]
+    async fn allow_listed_risky_patterns_only_warn() {
+        let policy = DefaultRiskPolicy::default().with_allow_risky(vec!["eval(".to_string()]);
+
+        let report = policy.analyze("eval('1 + 1')").await;
+        assert_eq!(report.level, RiskLevel::Risky);
+        assert!(!report.requires_confirmation);
+        assert_eq!(
+            report.reasons,
+            vec!["risky pattern detected: eval( (allowed by policy.allow_risky)"]
+        );
+
+        let report = policy
+            .analyze("eval('1'); await Deno.permissions.request({ name: 'net' });")
+            .await;
+        assert!(report.requires_confirmation);
+        assert_eq!(
+            report.reasons[0],
+            "risky pattern detected: Deno.permissions.request"
+        );
+        assert_eq!(report.reasons.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn prepare_source_returns_unconfirmed_risks_as_warnings() {
+        let engine = Engine::new(
+            MockProvider,
+            DefaultRiskPolicy::default().with_allow_risky(vec!["eval(".to_string()]),
+        );
+        let (_, _, _, warnings) = engine
+            .prepare_source(
+                "eval('1 + 1')",
+                "force_js",
+                SessionSummary::default(),
+                None,
+                None,
+            )
+            .await
+            .expect("allowed");
+        assert_eq!(
+            warnings,
+            vec![Warning::new(
+                WarningKind::Risky,
+                "risky pattern detected: eval( (allowed by policy.allow_risky)"
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn policy_blocks_command_spawn() {
+        let policy = DefaultRiskPolicy::default();
+        let report = policy.analyze("new Deno.Command('ls')").await;
+        assert_eq!(report.level, RiskLevel::Blocked);
+    }
+
+    #[tokio::test]
+    async fn policy_marks_eval_as_risky() {
+        let policy = DefaultRiskPolicy::default();
+        let report = policy.analyze("eval('1 + 1')").await;
+        assert_eq!(report.level, RiskLevel::Risky);
+    }
+
+    #[tokio::test]
+    async fn policy_reasons_carry_their_source() {
+        let report = DefaultRiskPolicy::default()
+            .analyze("new Deno.Command('ls')")
+            .await;
+        assert_eq!(report.sources, vec![PolicySource::Default]);
+
+        let path = unique_temp_path("beeno-policy").with_extension("toml");
+        fs::write(
+            &path,
+            "blocked_patterns = [\"Deno.Command\"]\nrisky_patterns = []\ntrusted_import_prefixes = []\n",
+        )
+        .expect("write policy");
+        let policy = DefaultRiskPolicy::from_path(&path).expect("load policy");
+        let _ = fs::remove_file(&path);
+        let file = PolicySource::File(path.display().to_string());
+        assert_eq!(policy.source(), &file);
+        let report = policy.analyze("new Deno.Command('ls'); let x = ;").await;
+        assert_eq!(report.reasons.len(), 2);
+        assert_eq!(report.sources, vec![file, PolicySource::Parser]);
+    }
+
+    #[test]
+    fn strip_fenced() {
+        let body = "```nl\nprint hello\n```";
+        assert_eq!(strip_fenced_nl(body), "print hello");
+    }
+
+    #[tokio::test]
+    async fn summary_truncates_entries_and_stays_within_budget() {
+        let mut s = RollingContextSummarizer::new(8).with_char_limits(100, 300);
+        let giant = format!("console.log({})", "x".repeat(10_000));
+        let cur = s.update(&giant).await;
+        assert_eq!(cur.side_effects[0].chars().count(), 100);
+        assert!(cur.side_effects[0].ends_with('…'));
+
+        for i in 0..8 {
+            s.update(&format!("console.log({i}, {})", "y".repeat(60)))
+                .await;
+        }
+        let cur = s.current();
+        let total: usize = [
+            &cur.symbols,
+            &cur.imports,
+            &cur.side_effects,
+            &cur.recent_intents,
+        ]
+        .iter()
+        .flat_map(|bucket| bucket.iter())
+        .map(|entry| entry.chars().count())
+        .sum();
+        assert!(total <= 300, "summary is {total} chars");
+        assert!(cur
+            .recent_intents
+            .last()
+            .expect("newest kept")
+            .contains("7, "));
+    }
+
+    #[tokio::test]
+    async fn update_with_source_keeps_referenced_imports_past_the_window() {
+        let mut s = RollingContextSummarizer::new(2);
+        s.update("import { z } from 'npm:zod';").await;
+        s.update("import chalk from 'npm:chalk';").await;
+        let cur = s
+            .update_with_source(
+                "import dayjs from 'npm:dayjs';\nconst schema = z.string();\nconsole.log(dayjs());",
+            )
+            .await;
+        assert_eq!(
+            cur.imports,
+            vec![
+                "import { z } from 'npm:zod';",
+                "import dayjs from 'npm:dayjs';"
+            ]
+        );
+        assert_eq!(cur.symbols, vec!["schema"]);
+
+        // Plain updates still roll by age.
+        let cur = s.update("import ms from 'npm:ms';").await;
+        assert_eq!(
+            cur.imports,
+            vec!["import dayjs from 'npm:dayjs';", "import ms from 'npm:ms';"]
+        );
+    }
+
+    #[tokio::test]
+    async fn summary_rolls() {
+        let mut s = RollingContextSummarizer::new(2);
+        s.update("let a = 1;").await;
+        s.update("import x from 'y';").await;
+        s.update("console.log(a)").await;
+        let cur = s.current();
+        assert!(cur.recent_intents.len() <= 2);
+    }
+
+    #[tokio::test]
+    async fn prepare_source_translates_pseudocode() {
+        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default());
+        let (source, translated, risk, _) = engine
+            .prepare_source(
+                "create an object and print it.",
+                "eval",
+                SessionSummary::default(),
+                None,
+                None,
+            )
+            .await
+            .expect("translation should succeed");
+        assert!(translated.is_some());
+        assert!(source.contains("console.log"));
+        assert_eq!(risk.level, RiskLevel::Safe);
+    }
+
+    #[tokio::test]
+    async fn translate_only_skips_classification_and_limits() {
+        let engine =
+            Engine::new(MockProvider, DefaultRiskPolicy::default()).with_limits(LimitsConfig {
+                max_source_bytes: 8,
+                ..LimitsConfig::default()
+            });
+        let result = engine
+            .translate_only("let x = 1;", "eval", SessionSummary::default(), None)
+            .await
+            .expect("translate");
+        assert_eq!(result.code, "console.log(\"let x = 1;\");");
+        assert_eq!(result.explanation.as_deref(), Some("mock translation"));
+    }
+
+    #[tokio::test]
+    async fn type_check_diagnostics_point_at_the_original_script() {
+        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default());
+        let script =
+            "const before: number = 1;\n/*nl\nprint hello\n*/\nconst after: string = before;\n";
+        let processed = engine
+            .process_tagged_script(script, SessionSummary::default(), None, None)
+            .await
+            .expect("process");
+        let diagnostics = "\nTS2322 [ERROR]: Type 'number' is not assignable to type 'string'.\n    at file:///tmp/beeno-check-1.ts:3:7\n";
+        let mapping = StackMapping {
+            source_map: processed.source_map,
+            original: "main.ts".to_string(),
+        };
+        let mapped = map_diagnostics(diagnostics, "beeno-check-1.ts", Some(&mapping));
+        assert!(mapped.starts_with("TS2322"));
+        assert!(mapped.ends_with("at main.ts:5:7"), "{mapped}");
+        assert_eq!(
+            map_diagnostics(diagnostics, "beeno-check-1.ts", None),
+            diagnostics.trim()
+        );
+    }
+
+    #[tokio::test]
+    async fn process_tagged_script_replaces_nl_block() {
+        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default());
+        let script = r#"
+const before = 1;
+/*nl
+print hello from nl
+*/
+const after = 2;
+"#;
+        let processed = engine
+            .process_tagged_script(script, SessionSummary::default(), None, None)
+            .await
+            .expect("processing should succeed");
+        assert!(processed.warnings.is_empty());
+        assert!(processed.source.contains("console.log"));
+        assert!(processed.source.contains("const before = 1;"));
+        assert!(processed.source.contains("const after = 2;"));
+
+        let after_line = processed
+            .source
+            .lines()
+            .position(|l| l == "const after = 2;")
+            .expect("after line")
+            + 1;
+        let block_line = processed
+            .source
+            .lines()
+            .position(|l| l.contains("console.log"))
+            .expect("block line")
+            + 1;
+        let original = |line| processed.source_map.lookup(line).expect("mapped");
+        assert_eq!(original(after_line).line, 6);
+        assert!(!original(after_line).nl_block);
+        assert_eq!(original(block_line).line, 3);
+        assert!(original(block_line).nl_block);
+    }
+
+    #[tokio::test]
+    async fn unterminated_nl_block_warns_with_its_location() {
+        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default());
+        let script = "const a = 1;\n/*nl print hello\n";
+        let processed = engine
+            .process_tagged_script(script, SessionSummary::default(), Some("a.ts".into()), None)
+            .await
+            .expect("processing should succeed");
+        assert_eq!(processed.source, script);
+        assert_eq!(processed.warnings.len(), 1);
+        let warning = &processed.warnings[0];
+        assert_eq!(warning.kind, WarningKind::UnterminatedBlock);
+        assert_eq!(warning.location.as_deref(), Some("a.ts:2"));
+        assert_eq!(
+            serde_json::to_value(warning).expect("serialize")["kind"],
+            "unterminated-block"
+        );
+    }
+
+    #[tokio::test]
+    async fn block_imports_are_deduplicated_and_hoisted() {
+        let engine = Engine::new(
+            ScriptedProvider::new(vec![
+                Ok("import { assert } from \"jsr:@std/assert\";\nassert(before === 1);"),
+                Ok("import { assert } from \"jsr:@std/assert\";\nassert(true);"),
+            ]),
+            DefaultRiskPolicy::default(),
+        );
+        let script =
+            "const before = 1;\n/*nl check before */\n/*nl check again */\nconsole.log('done');\n";
+        let processed = engine
+            .process_tagged_script(script, SessionSummary::default(), None, None)
+            .await
+            .expect("processing should succeed");
+        assert!(processed.warnings.is_empty());
+        assert_eq!(
+            processed.source,
+            "import { assert } from \"jsr:@std/assert\";\nconst before = 1;\nassert(before === 1);\nassert(true);\nconsole.log('done');\n"
+        );
+        let original = |line| processed.source_map.lookup(line).expect("mapped");
+        assert_eq!(original(1).line, 2);
+        assert!(original(1).nl_block);
+        assert_eq!(original(2).line, 1);
+        assert_eq!(original(4).line, 3);
+        assert!(original(4).nl_block);
+        assert_eq!(original(5).line, 4);
+        assert!(!original(5).nl_block);
+    }
 
     #[tokio::test]
-    async fn execution_blocks_without_allow_net() {
+    async fn conflicting_block_imports_warn() {
+        let engine = Engine::new(
+            ScriptedProvider::new(vec![
+                Ok("import { assert } from \"jsr:@std/assert\";\nassert(true);"),
+                Ok("import { assert } from \"node:assert\";\nassert(true);"),
+            ]),
+            DefaultRiskPolicy::default(),
+        );
+        let script = "/*nl check once */\n/*nl check twice */\n";
+        let processed = engine
+            .process_tagged_script(script, SessionSummary::default(), Some("a.ts".into()), None)
+            .await
+            .expect("processing should succeed");
+        assert_eq!(processed.warnings.len(), 1);
+        let warning = &processed.warnings[0];
+        assert_eq!(warning.kind, WarningKind::ImportConflict);
+        assert_eq!(warning.location.as_deref(), Some("a.ts:2"));
+        assert_eq!(
+            warning.message,
+            "`assert` is imported from both `jsr:@std/assert` and `node:assert`; \
+             keeping the `jsr:@std/assert` import"
+        );
+        assert_eq!(
+            processed.source,
+            "import { assert } from \"jsr:@std/assert\";\nassert(true);\nassert(true);\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn block_imports_are_hoisted_below_a_shebang_and_directives() {
+        let engine = Engine::new(
+            ScriptedProvider::new(vec![Ok(
+                "import { assert } from \"jsr:@std/assert\";\nassert(true);",
+            )]),
+            DefaultRiskPolicy::default(),
+        );
+        let script = "#!/usr/bin/env -S deno run\n// @ts-nocheck\nconst a = 1;\n/*nl check a */\nconsole.log(a);\n";
+        let processed = engine
+            .process_tagged_script(script, SessionSummary::default(), None, None)
+            .await
+            .expect("processing should succeed");
+        assert_eq!(
+            processed.source,
+            "#!/usr/bin/env -S deno run\n// @ts-nocheck\nimport { assert } from \"jsr:@std/assert\";\nconst a = 1;\nassert(true);\nconsole.log(a);\n"
+        );
+        let original = |line| processed.source_map.lookup(line).expect("mapped");
+        assert_eq!(original(2).line, 2);
+        assert!(!original(2).nl_block);
+        assert_eq!(original(3).line, 4);
+        assert!(original(3).nl_block);
+        assert_eq!(original(4).line, 3);
+        assert!(!original(4).nl_block);
+        assert_eq!(original(6).line, 5);
+    }
+
+    #[tokio::test]
+    async fn prelude_is_inserted_into_tagged_scripts_below_the_shebang() {
+        let engine = Engine::new(
+            ScriptedProvider::new(vec![Ok("import { z } from \"npm:zod\";\nz.string();")]),
+            DefaultRiskPolicy::default(),
+        )
+        .with_prelude(Some("globalThis.started = Date.now();\n".to_string()));
+        let script =
+            "#!/usr/bin/env -S deno run\nconst a = 1;\n/*nl validate */\nthrow new Error();\n";
+        let processed = engine
+            .process_tagged_script(script, SessionSummary::default(), None, None)
+            .await
+            .expect("processing should succeed");
+        assert_eq!(
+            processed.source,
+            "#!/usr/bin/env -S deno run\nglobalThis.started = Date.now();\nimport { z } from \"npm:zod\";\nconst a = 1;\nz.string();\nthrow new Error();\n"
+        );
+        assert_eq!(processed.source_map.lookup(2), None);
+        let original = |line| processed.source_map.lookup(line).expect("mapped");
+        assert_eq!(original(3).line, 3);
+        assert!(original(3).nl_block);
+        assert_eq!(original(4).line, 2);
+        assert_eq!(original(6).line, 4);
+        assert!(!original(6).nl_block);
+    }
+
+    #[test]
+    fn borderline_phrases_are_ambiguous() {
+        // Six words but no sentence markers: code by default, yet plausibly prose.
+        let c = classify_and_explain("print the sum of two numbers");
+        assert_eq!(c.kind, InputKind::Code);
+        assert!(c.ambiguous);
+        assert!(classify_and_explain("fetch it then print").ambiguous);
+        assert!(!classify_and_explain("fetch the page and then count all the links.").ambiguous);
+        assert!(!classify_and_explain("Deno.version").ambiguous);
+        assert!(!classify_and_explain("const a = 1 and then more words here").ambiguous);
+    }
+
+    #[tokio::test]
+    async fn prelude_is_prepended_and_checked_by_the_policy() {
+        let prelude = "globalThis.onunhandledrejection = (e) => console.error(e.reason);\n";
+        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default())
+            .with_prelude(Some(prelude.to_string()));
+        let (source, _, risk, _) = engine
+            .prepare_source("print hello", "eval", SessionSummary::default(), None, None)
+            .await
+            .expect("prelude passes policy");
+        assert!(source.starts_with(prelude), "{source}");
+        assert!(source.contains("console.log"));
+        assert_eq!(risk.level, RiskLevel::Safe);
+
+        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default())
+            .with_source_transform(|source| format!("new Deno.Command('ls');\n{source}"));
+        let err = engine
+            .prepare_source("print hello", "eval", SessionSummary::default(), None, None)
+            .await
+            .expect_err("transformed source is analyzed");
+        assert!(matches!(err, EngineError::Blocked(_)));
+    }
+
+    #[tokio::test]
+    async fn prelude_wraps_an_existing_source_transform() {
+        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default())
+            .with_source_transform(|source| format!("{source}\nconsole.log('pinned');"))
+            .with_prelude(Some("const started = Date.now();".to_string()));
+        let (source, _, _, _) = engine
+            .prepare_source("print hello", "eval", SessionSummary::default(), None, None)
+            .await
+            .expect("prepared");
+        assert!(
+            source.starts_with("const started = Date.now();\n"),
+            "{source}"
+        );
+        assert!(source.ends_with("\nconsole.log('pinned');"), "{source}");
+    }
+
+    #[tokio::test]
+    async fn prepare_source_rejects_empty_and_ambiguous_input() {
+        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default());
+        for (input, mode) in [("", "eval"), ("  \n\t", "force_nl")] {
+            let err = engine
+                .prepare_source(input, mode, SessionSummary::default(), None, None)
+                .await
+                .expect_err("empty input");
+            assert!(matches!(
+                err,
+                EngineError::Classification(ClassificationError::Empty)
+            ));
+        }
+
+        let borderline = "print the sum of two numbers";
+        let err = engine
+            .prepare_source(borderline, "eval", SessionSummary::default(), None, None)
+            .await
+            .expect_err("ambiguous input");
+        assert!(matches!(
+            err,
+            EngineError::Classification(ClassificationError::Ambiguous { .. })
+        ));
+
+        // Forced to code it skips translation, so the prose fails the parse check.
+        let err = engine
+            .prepare_source(
+                borderline,
+                "force_js",
+                SessionSummary::default(),
+                None,
+                None,
+            )
+            .await
+            .expect_err("prose is not JS");
+        assert!(matches!(err, EngineError::Blocked(_)));
+        let (source, _, _, _) = engine
+            .prepare_source(
+                borderline,
+                "force_nl",
+                SessionSummary::default(),
+                None,
+                None,
+            )
+            .await
+            .expect("forced pseudocode");
+        assert!(source.contains("console.log"));
+    }
+
+    /// Counts translations while delegating to [`MockProvider`].
+    struct CountingProvider(std::sync::atomic::AtomicUsize);
+
+    #[async_trait]
+    impl TranslatorProvider for CountingProvider {
+        async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            MockProvider.translate(req).await
+        }
+    }
+
+    /// Replies with each of `replies` in turn; `Err` entries are cut off.
+    struct ScriptedProvider {
+        replies: std::sync::Mutex<Vec<Result<&'static str, &'static str>>>,
+        requests: Arc<std::sync::Mutex<Vec<TranslateRequest>>>,
+    }
+
+    impl ScriptedProvider {
+        fn new(mut replies: Vec<Result<&'static str, &'static str>>) -> Self {
+            replies.reverse();
+            Self {
+                replies: std::sync::Mutex::new(replies),
+                requests: Default::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl TranslatorProvider for ScriptedProvider {
+        async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+            self.requests.lock().unwrap().push(req);
+            match self
+                .replies
+                .lock()
+                .unwrap()
+                .pop()
+                .expect("unexpected request")
+            {
+                Ok(code) => Ok(TranslateResult::new(code)),
+                Err(partial) => Err(ProviderError::Truncated(partial.to_string())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn auto_continue_stitches_a_length_truncated_response() {
+        let provider = ScriptedProvider::new(vec![
+            Err("const items = [1, 2, 3];\nfor (const item of items) {\n  console"),
+            Ok(".log(item);\n}"),
+        ]);
+        let requests = provider.requests.clone();
+        let engine = Engine::new(provider, DefaultRiskPolicy::default()).with_auto_continue(true);
+        let (source, translated, _, _) = engine
+            .prepare_source(
+                "print each item",
+                "force_nl",
+                SessionSummary::default(),
+                None,
+                None,
+            )
+            .await
+            .expect("continued");
+        assert_eq!(
+            source,
+            "const items = [1, 2, 3];\nfor (const item of items) {\n  console.log(item);\n}"
+        );
+        assert_eq!(
+            translated.expect("translated").raw_provider_meta["continuations"],
+            1
+        );
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[1].mode, CONTINUE_MODE);
+        assert!(requests[1]
+            .input
+            .starts_with("Request: print each item\nCode so far:\n"));
+        assert!(requests[1].input.ends_with("  console"));
+    }
+
+    #[tokio::test]
+    async fn javascript_target_transpiles_a_continued_reply_once_stitched() {
+        let provider = ScriptedProvider::new(vec![
+            Err("const total: number = [1, 2]"),
+            Ok(".length as number;\nconsole.log(total);"),
+        ]);
+        let provider = crate::providers::JavaScriptTargetProvider::new(Box::new(provider));
+        let engine = Engine::new(provider, DefaultRiskPolicy::default()).with_auto_continue(true);
+        let (source, translated, _, _) = engine
+            .prepare_source(
+                "print the count",
+                "force_nl",
+                SessionSummary::default(),
+                None,
+                None,
+            )
+            .await
+            .expect("continued");
+        assert_eq!(
+            source.trim(),
+            "const total = [1, 2].length;\nconsole.log(total);"
+        );
+        let translated = translated.expect("translated");
+        assert_eq!(translated.raw_provider_meta["continuations"], 1);
+        assert_eq!(translated.raw_provider_meta["transpiled"], true);
+    }
+
+    #[tokio::test]
+    async fn truncated_response_fails_without_auto_continue_or_after_the_limit() {
+        let engine = Engine::new(
+            ScriptedProvider::new(vec![Err("console")]),
+            DefaultRiskPolicy::default(),
+        );
+        let err = engine
+            .prepare_source(
+                "print hi",
+                "force_nl",
+                SessionSummary::default(),
+                None,
+                None,
+            )
+            .await
+            .expect_err("cut off");
+        assert!(matches!(
+            err,
+            EngineError::Provider(ProviderError::Truncated(_))
+        ));
+
+        let engine = Engine::new(
+            ScriptedProvider::new(vec![Err("console"), Err(".log("), Err("'hi'")]),
+            DefaultRiskPolicy::default(),
+        )
+        .with_auto_continue(true);
+        let err = engine
+            .prepare_source(
+                "print hi",
+                "force_nl",
+                SessionSummary::default(),
+                None,
+                None,
+            )
+            .await
+            .expect_err("still cut off");
+        assert!(matches!(
+            err,
+            EngineError::Provider(ProviderError::Truncated(partial)) if partial == "console.log('hi'"
+        ));
+    }
+
+    struct ManifestProvider(&'static str);
+
+    #[async_trait]
+    impl TranslatorProvider for ManifestProvider {
+        async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+            assert_eq!(req.mode, "scaffold");
+            assert!(req.input.starts_with(SCAFFOLD_INSTRUCTIONS));
+            Ok(TranslateResult::new(self.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn scaffold_validates_manifest_against_policy_and_limits() {
+        let engine =
+            |manifest| Engine::new(ManifestProvider(manifest), DefaultRiskPolicy::default());
+        let plan = engine(
+            r#"[{"path":"main.ts","contents":"console.log(1);"},{"path":"README.md","contents":"Hello app"}]"#,
+        )
+        .scaffold("a hello app", None)
+        .await
+        .expect("plan");
+        assert_eq!(plan.files.len(), 2);
+        assert_eq!(
+            plan.risks[0].as_ref().map(|r| r.level),
+            Some(RiskLevel::Safe)
+        );
+        assert!(plan.risks[1].is_none());
+
+        let err = engine(r#"[{"path":"main.ts","contents":"new Deno.Command('rm');"}]"#)
+            .scaffold("x", None)
+            .await
+            .expect_err("blocked file");
+        assert!(
+            matches!(&err, EngineError::Blocked(reasons) if reasons[0].starts_with("main.ts: "))
+        );
+
+        let err = engine(r#"[{"path":"../escape.ts","contents":""}]"#)
+            .scaffold("x", None)
+            .await
+            .expect_err("traversal");
+        assert!(matches!(
+            err,
+            EngineError::Scaffold(ScaffoldError::UnsafePath(_))
+        ));
+
+        let err = engine(r#"[{"path":"a.ts","contents":""},{"path":"b.ts","contents":""}]"#)
+            .with_limits(LimitsConfig {
+                max_files: 1,
+                ..LimitsConfig::default()
+            })
+            .scaffold("x", None)
+            .await
+            .expect_err("too many files");
+        assert!(matches!(
+            err,
+            EngineError::Scaffold(ScaffoldError::TooManyFiles { count: 2, max: 1 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn block_cache_reuses_unchanged_blocks() {
+        let dir = unique_temp_path("beeno-block-cache");
+        let engine = |refresh| {
+            Engine::new(
+                CountingProvider(Default::default()),
+                DefaultRiskPolicy::default(),
+            )
+            .with_block_cache(Some(BlockCache::new(&dir).with_refresh(refresh)))
+        };
+        async fn process(
+            engine: &Engine<CountingProvider, DefaultRiskPolicy>,
+            script: &str,
+        ) -> ProcessedScript {
+            engine
+                .process_tagged_script(script, SessionSummary::default(), None, None)
+                .await
+                .expect("process")
+        }
+
+        let first = engine(false);
+        let processed = process(&first, "/*nl print one */\n/*nl print two */\n").await;
+        assert_eq!(processed.cached_blocks, 0);
+        assert_eq!(
+            first.provider.0.load(std::sync::atomic::Ordering::SeqCst),
+            2
+        );
+
+        let second = engine(false);
+        let edited = process(&second, "/*nl print one */\n/*nl print three */\n").await;
+        assert_eq!(edited.cached_blocks, 1);
+        assert_eq!(
+            second.provider.0.load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+        assert!(edited.source.contains("print three"));
+
+        let refreshed = engine(true);
+        let processed = process(&refreshed, "/*nl print one */\n/*nl print three */\n").await;
+        assert_eq!(processed.cached_blocks, 0);
+        assert_eq!(
+            refreshed
+                .provider
+                .0
+                .load(std::sync::atomic::Ordering::SeqCst),
+            2
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn oversized_generated_source_is_blocked() {
+        let engine =
+            Engine::new(MockProvider, DefaultRiskPolicy::default()).with_limits(LimitsConfig {
+                max_changed_lines: 1,
+                ..LimitsConfig::default()
+            });
+        engine
+            .prepare_source(
+                "print hello",
+                "force_nl",
+                SessionSummary::default(),
+                None,
+                None,
+            )
+            .await
+            .expect("one generated line fits");
+
+        let script = "/*nl print one */\n/*nl print two */\n";
+        let err = engine
+            .process_tagged_script(script, SessionSummary::default(), None, None)
+            .await
+            .expect_err("cumulative output exceeds the limit");
+        assert!(
+            matches!(err, EngineError::Blocked(ref reasons) if reasons == &["source exceeds 1 lines"])
+        );
+
+        let engine =
+            Engine::new(MockProvider, DefaultRiskPolicy::default()).with_limits(LimitsConfig {
+                max_source_bytes: 8,
+                ..LimitsConfig::default()
+            });
+        let err = engine
+            .prepare_source(
+                "print hello",
+                "force_nl",
+                SessionSummary::default(),
+                None,
+                None,
+            )
+            .await
+            .expect_err("byte limit applies");
+        assert!(err.to_string().contains("source exceeds 8 bytes"));
+    }
+
+    #[test]
+    fn permission_args_expand_wildcards() {
+        let perms = crate::types::DenoPermissions {
+            allow_net: vec!["*".to_string()],
+            allow_read: vec!["./data".to_string()],
+            allow_env: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            permission_args(&perms),
+            vec!["--allow-read=./data", "--allow-net", "--allow-env"]
+        );
+    }
+
+    #[test]
+    fn deno_command_sets_runtime_env() {
+        let req = ExecutionRequest {
+            env: [(
+                "API_BASE".to_string(),
+                "https://api.example.com".to_string(),
+            )]
+            .into(),
+            ..ExecutionRequest::new(
+                "console.log(Deno.env.get('API_BASE'))",
+                crate::types::DenoPermissions::default(),
+                "eval",
+            )
+        };
+        let cmd = deno_run_command(&req, Path::new("module.ts"));
+        assert!(!cmd.as_std().get_args().any(|a| a == "--config"));
+        let envs: Vec<_> = cmd.as_std().get_envs().collect();
+        assert_eq!(
+            envs,
+            vec![(
+                std::ffi::OsStr::new("API_BASE"),
+                Some(std::ffi::OsStr::new("https://api.example.com"))
+            )]
+        );
+    }
+
+    #[test]
+    fn deno_command_passes_config_and_import_map() {
+        let req = ExecutionRequest {
+            deno_config: Some("app/deno.json".to_string()),
+            import_map: Some("import_map.json".to_string()),
+            ..ExecutionRequest::new(
+                "import { z } from 'zod';",
+                crate::types::DenoPermissions::default(),
+                "run",
+            )
+        };
+        let cmd = deno_run_command(&req, Path::new("module.ts"));
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            args,
+            vec![
+                "run",
+                "--config",
+                "app/deno.json",
+                "--import-map",
+                "import_map.json",
+                "module.ts"
+            ]
+        );
+    }
+
+    #[test]
+    fn deno_command_restricts_remote_imports() {
         let req = ExecutionRequest {
-            source: "await fetch('https://example.com')".to_string(),
-            deno_permissions: crate::types::DenoPermissions::default(),
-            origin: "eval".to_string(),
+            no_remote: true,
+            cached_only: true,
+            ..ExecutionRequest::new(
+                "import { z } from 'npm:zod';",
+                crate::types::DenoPermissions::default(),
+                "run",
+            )
+        };
+        let cmd = deno_run_command(&req, Path::new("module.ts"));
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            args,
+            vec!["run", "--no-remote", "--cached-only", "module.ts"]
+        );
+        assert!(remote_args(false, false).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn tee_file_receives_the_program_output() {
+        let path = unique_temp_path("beeno-tee").with_extension("log");
+        fs::write(&path, "earlier run\n").unwrap();
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo 'hello from deno'; echo warned >&2"]);
+        run_teed(cmd, None, Some(&path), false).await.expect("run");
+        let teed = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(teed.contains("hello from deno\n"), "{teed}");
+        assert!(teed.contains("warned\n"), "{teed}");
+        assert!(teed.starts_with("earlier run\n"), "{teed}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn output_is_only_captured_when_asked() {
+        let failing = || {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", "echo broke >&2; exit 3"]);
+            cmd
+        };
+        let inherited = run_teed(failing(), None, None, false).await.expect("run");
+        assert!(!inherited.success);
+        assert_eq!(inherited.exit_code, Some(3));
+        assert!(inherited.stderr.is_empty(), "{}", inherited.stderr);
+
+        let captured = run_teed(failing(), None, None, true).await.expect("run");
+        let Err(EngineError::RuntimeFailure(failure)) = captured.into_result() else {
+            panic!("expected a runtime failure");
         };
+        assert_eq!(failure.stderr, "broke\n");
+    }
+
+    #[tokio::test]
+    async fn runtime_failure_keeps_the_tail_of_output() {
+        let mut echoed = Vec::new();
+        let kept = tee_output(
+            &b"error: Uncaught ReferenceError: x is not defined\n"[..],
+            &mut echoed,
+        )
+        .await
+        .expect("tee");
+        assert_eq!(kept.as_bytes(), echoed.as_slice());
+
+        let long = "a".repeat(FAILURE_OUTPUT_LIMIT) + "tail";
+        let kept = tee_output(long.as_bytes(), std::io::sink())
+            .await
+            .expect("tee");
+        assert_eq!(kept.len(), FAILURE_OUTPUT_LIMIT);
+        assert!(kept.ends_with("tail"));
+
+        let mut text = "ééé".to_string();
+        truncate_front(&mut text, 3);
+        assert_eq!(text, "é");
+
+        let err = EngineError::RuntimeFailure(ExecutionFailure {
+            exit_code: Some(1),
+            stdout: String::new(),
+            stderr: kept,
+        });
+        assert_eq!(err.to_string(), "deno run exited with code 1");
+    }
+
+    #[tokio::test]
+    async fn execution_blocks_without_allow_net() {
+        let req = ExecutionRequest::new(
+            "await fetch('https://example.com')",
+            crate::types::DenoPermissions::default(),
+            "eval",
+        );
         let err = execute_request(req)
             .await
             .expect_err("must block without allow-net");
         assert!(err.to_string().contains("--allow-net"));
     }
+
+    #[test]
+    fn permission_report_lists_needed_and_missing_flags() {
+        let source = "const t = await Deno.readTextFile('a');\nawait fetch(t);\nDeno.env.get('X');";
+        let granted = crate::types::DenoPermissions {
+            allow_read: vec!["a".to_string()],
+            ..Default::default()
+        };
+        let report = analyze_permissions(source, &granted);
+        assert!(report.needs_read && report.needs_net && report.needs_env);
+        assert!(!report.needs_write && !report.needs_run);
+        assert_eq!(report.granted, granted);
+        assert_eq!(report.missing, vec!["--allow-net", "--allow-env"]);
+        assert_eq!(
+            enforce_permission_alignment(source, &granted)
+                .map_err(|e| e.to_string())
+                .unwrap_err(),
+            "execution error: code requires --allow-net but none was provided"
+        );
+        assert!(enforce_permission_alignment("console.log(1);", &granted).is_ok());
+    }
+
+    #[test]
+    fn permission_triggers_point_at_the_first_offending_call() {
+        let source = "const url = 'https://example.com';\n  const res = await fetch(url);\nawait fetch(url);";
+        let report = analyze_permissions(source, &Default::default());
+        assert_eq!(report.triggers.len(), 1);
+        assert_eq!(
+            report.trigger("--allow-net"),
+            Some(&PermissionTrigger {
+                flag: "--allow-net".to_string(),
+                op: "fetch(".to_string(),
+                line: 2,
+                code: "const res = await fetch(url);".to_string(),
+            })
+        );
+        assert_eq!(report.trigger("--allow-read"), None);
+    }
+
+    #[test]
+    fn missing_deno_binary_is_a_typed_error() {
+        let err = launch_error(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert!(matches!(err, EngineError::RuntimeMissing));
+        assert!(err.to_string().contains(DENO_INSTALL_URL));
+        let err = launch_error(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert!(err
+            .to_string()
+            .starts_with("execution error: failed to launch deno binary"));
+    }
+
+    #[tokio::test]
+    async fn engine_executes_through_pluggable_executor() {
+        let recorder = Arc::new(RecordingExecutor::new(ExecutionOutput {
+            success: true,
+            exit_code: Some(0),
+            stdout: "hi\n".to_string(),
+            stderr: String::new(),
+        }));
+        let engine =
+            Engine::new(MockProvider, DefaultRiskPolicy::default()).with_executor(recorder.clone());
+        let req = |source: &str| {
+            ExecutionRequest::new(source, crate::types::DenoPermissions::default(), "test")
+        };
+
+        let output = engine
+            .execute(req("console.log('hi')"))
+            .await
+            .expect("execute");
+        assert_eq!(output.stdout, "hi\n");
+
+        let err = engine
+            .execute(req("await fetch('https://example.com')"))
+            .await
+            .expect_err("must block without allow-net");
+        assert!(err.to_string().contains("--allow-net"));
+
+        let requests = recorder.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].source, "console.log('hi')");
+        assert!(requests[0].trace_id.is_some());
+    }
+
+    #[tokio::test]
+    async fn failed_executions_convert_to_runtime_failures() {
+        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default()).with_executor(
+            RecordingExecutor::new(ExecutionOutput {
+                success: false,
+                exit_code: Some(2),
+                stdout: String::new(),
+                stderr: "boom\n".to_string(),
+            }),
+        );
+        let output = engine
+            .execute(ExecutionRequest {
+                capture_output: true,
+                live: true,
+                ..ExecutionRequest::new(
+                    "throw new Error('boom');",
+                    crate::types::DenoPermissions::default(),
+                    "run",
+                )
+            })
+            .await
+            .expect("execute");
+        let Err(EngineError::RuntimeFailure(failure)) = output.into_result() else {
+            panic!("expected a runtime failure");
+        };
+        assert_eq!(failure.exit_code, Some(2));
+        assert_eq!(failure.stderr, "boom\n");
+    }
 }
@@ -0,0 +1,261 @@
+//! Review helpers for self-heal fixes.
+//!
+//! The heal loop proposes a rewritten source after a failed run. Before a fix is
+//! applied (unless `self_heal.apply_fixes_default` is set) the user sees a unified
+//! diff against the failing source and chooses to apply, skip, or edit it. Every
+//! attempted fix is written to the artifact directory for later review.
+
+pub use crate::artifacts::create_session_dir;
+use crate::types::ExecutionFailure;
+use crate::util::redact;
+use serde::Serialize;
+use similar::TextDiff;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Subdirectory of `artifacts.dir` holding self-heal sessions.
+pub const HEAL_DIR: &str = "heal";
+
+/// What to do with a proposed fix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixDecision {
+    Apply,
+    Skip,
+    Edit,
+}
+
+impl FixDecision {
+    /// Parses a prompt answer; anything unrecognized (including empty) is `None`.
+    pub fn parse(answer: &str) -> Option<Self> {
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "a" | "apply" | "y" | "yes" => Some(Self::Apply),
+            "s" | "skip" | "n" | "no" => Some(Self::Skip),
+            "e" | "edit" => Some(Self::Edit),
+            _ => None,
+        }
+    }
+}
+
+/// A fix that was applied and re-run during self-heal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AppliedFix {
+    /// 1-based attempt that proposed the fix.
+    pub attempt: u8,
+    /// Unified diff from the failing source to the source that ran next.
+    pub diff: String,
+    /// Where the attempt's `.patch` artifact was saved, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch: Option<PathBuf>,
+}
+
+/// What one self-heal run did.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HealReport {
+    /// Fixes requested from the provider.
+    pub attempts: u8,
+    pub applied_fixes: Vec<AppliedFix>,
+    /// Whether the last applied fix ran successfully.
+    pub fixed: bool,
+}
+
+impl HealReport {
+    /// One-line outcome, e.g. `fixed after 2 attempts`.
+    pub fn summary(&self) -> String {
+        let attempts = match self.attempts {
+            1 => "1 attempt".to_string(),
+            n => format!("{n} attempts"),
+        };
+        if self.fixed {
+            format!("fixed after {attempts}")
+        } else {
+            format!("still failing after {attempts}")
+        }
+    }
+}
+
+/// Builds the [`HEAL_MODE`](crate::providers::HEAL_MODE) request input: the error
+/// output (stderr, or stdout when stderr is empty) followed by the failing program.
+pub fn heal_input(source: &str, failure: &ExecutionFailure) -> String {
+    let output = if failure.stderr.trim().is_empty() {
+        &failure.stdout
+    } else {
+        &failure.stderr
+    };
+    format!("Error ({failure}):\n{}\nProgram:\n{source}", output.trim())
+}
+
+/// Renders a unified diff between the failing source and the proposed fix.
+pub fn unified_diff(original: &str, proposed: &str, label: &str) -> String {
+    TextDiff::from_lines(original, proposed)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{label}"), &format!("b/{label}"))
+        .to_string()
+}
+
+/// Prints `diff` and asks whether to apply, skip, or edit. Defaults to skip.
+pub fn prompt_fix_decision(
+    diff: &str,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<FixDecision> {
+    writeln!(output, "{diff}")?;
+    loop {
+        write!(
+            output,
+            "apply this fix? [a]pply/[s]kip/[e]dit (default: skip): "
+        )?;
+        output.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 || answer.trim().is_empty() {
+            return Ok(FixDecision::Skip);
+        }
+        match FixDecision::parse(&answer) {
+            Some(decision) => return Ok(decision),
+            None => writeln!(output, "please answer a, s, or e")?,
+        }
+    }
+}
+
+/// Opens the proposed fix in `$VISUAL`/`$EDITOR` (default `vi`) and returns the saved text.
+pub fn edit_fix(proposed: &str) -> anyhow::Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let path = std::env::temp_dir().join(format!("beeno-fix-{}.ts", unix_millis()));
+    fs::write(&path, proposed)?;
+    let status = Command::new(&editor).arg(&path).status();
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    let status = status.map_err(|e| anyhow::anyhow!("failed to launch editor `{editor}`: {e}"))?;
+    if !status.success() {
+        anyhow::bail!("editor `{editor}` exited with status {status}");
+    }
+    Ok(edited?)
+}
+
+/// Shows the diff for a proposed fix and returns the source to run next, or
+/// `None` if the user skipped it. With `apply_by_default` the fix is accepted
+/// without prompting.
+pub fn review_fix(
+    original: &str,
+    proposed: &str,
+    label: &str,
+    apply_by_default: bool,
+) -> anyhow::Result<Option<String>> {
+    if apply_by_default {
+        return Ok(Some(proposed.to_string()));
+    }
+    let diff = unified_diff(original, proposed, label);
+    let stdin = io::stdin();
+    let decision = prompt_fix_decision(&diff, &mut stdin.lock(), &mut io::stdout())?;
+    match decision {
+        FixDecision::Apply => Ok(Some(proposed.to_string())),
+        FixDecision::Skip => Ok(None),
+        FixDecision::Edit => edit_fix(proposed).map(Some),
+    }
+}
+
+/// Stores an attempted fix as `attempt-<n>.ts` plus `attempt-<n>.patch` in `session_dir`,
+/// with secrets [redacted](crate::util::redact).
+pub fn save_fix_artifact(
+    session_dir: &Path,
+    attempt: u8,
+    proposed: &str,
+    diff: &str,
+) -> io::Result<PathBuf> {
+    fs::create_dir_all(session_dir)?;
+    fs::write(
+        session_dir.join(format!("attempt-{attempt}.ts")),
+        redact(proposed),
+    )?;
+    let patch = session_dir.join(format!("attempt-{attempt}.patch"));
+    fs::write(&patch, redact(diff))?;
+    Ok(patch)
+}
+
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::unique_temp_path;
+
+    #[test]
+    fn diff_shows_only_changed_lines() {
+        let original = "const a = 1;\nconst b = 2;\nconsole.log(a + c);\n";
+        let proposed = "const a = 1;\nconst b = 2;\nconsole.log(a + b);\n";
+        let diff = unified_diff(original, proposed, "main.ts");
+        assert!(diff.contains("--- a/main.ts"));
+        assert!(diff.contains("-console.log(a + c);"));
+        assert!(diff.contains("+console.log(a + b);"));
+        assert!(!diff.contains("-const a = 1;"));
+    }
+
+    #[test]
+    fn heal_input_carries_the_error_and_program() {
+        let failure = ExecutionFailure {
+            exit_code: Some(1),
+            stdout: "partial output".to_string(),
+            stderr: "error: Uncaught ReferenceError: c is not defined\n".to_string(),
+        };
+        assert_eq!(
+            heal_input("console.log(a + c);", &failure),
+            "Error (deno run exited with code 1):\nerror: Uncaught ReferenceError: c is not defined\n\
+             Program:\nconsole.log(a + c);"
+        );
+    }
+
+    #[test]
+    fn heal_summary_counts_attempts() {
+        let mut report = HealReport {
+            attempts: 2,
+            fixed: true,
+            ..HealReport::default()
+        };
+        assert_eq!(report.summary(), "fixed after 2 attempts");
+        report.attempts = 1;
+        report.fixed = false;
+        assert_eq!(report.summary(), "still failing after 1 attempt");
+    }
+
+    #[test]
+    fn prompt_reprompts_on_unknown_answer_and_defaults_to_skip() {
+        let mut out = Vec::new();
+        let decision =
+            prompt_fix_decision("diff", &mut io::Cursor::new("x\ne\n"), &mut out).expect("prompt");
+        assert_eq!(decision, FixDecision::Edit);
+        assert!(String::from_utf8_lossy(&out).contains("please answer"));
+
+        let decision = prompt_fix_decision("diff", &mut io::Cursor::new("\n"), &mut Vec::new())
+            .expect("prompt");
+        assert_eq!(decision, FixDecision::Skip);
+    }
+
+    #[test]
+    fn apply_by_default_skips_the_prompt() {
+        let fixed = review_fix("a\n", "b\n", "main.ts", true).expect("review");
+        assert_eq!(fixed.as_deref(), Some("b\n"));
+    }
+
+    #[test]
+    fn fix_artifacts_are_written_per_attempt() {
+        let base = unique_temp_path("beeno-heal-test");
+        let session = create_session_dir(&base).expect("session dir");
+        let patch = save_fix_artifact(&session, 2, "b\n", "-a\n+b\n").expect("artifact");
+        assert_eq!(patch.file_name().unwrap(), "attempt-2.patch");
+        assert_eq!(
+            fs::read_to_string(session.join("attempt-2.ts")).unwrap(),
+            "b\n"
+        );
+        let _ = fs::remove_dir_all(base);
+    }
+}
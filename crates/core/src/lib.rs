@@ -5,10 +5,20 @@
 //!
 //! `beeno_core` provides:
 //! - translation orchestration via [`engine`]
+//! - persisted suggestion records via [`artifacts`]
+//! - self-heal fix review and artifacts via [`heal`]
+//! - per-request reuse of parsed JS/TS via [`parse_cache`]
+//! - an elapsed-time spinner for slow provider calls via [`progress`]
+//! - yes/no confirmation prompts honoring `--yes` via [`prompt`]
 //! - provider adapters via [`providers`]
 //! - interactive shell flows via [`repl`]
+//! - newline-delimited JSON editor integration via [`rpc`]
+//! - multi-file project manifests for `beeno scaffold` via [`scaffold`]
 //! - background server management via [`server`]
+//! - stack trace mapping for inlined tagged scripts via [`sourcemap`]
 //! - shared configuration and request/response types via [`types`]
+//! - secret redaction for logs and artifacts, and warning output, via [`util`]
+//! - WebAssembly policy plugins via `wasm_policy` (with the `wasm-policy` feature)
 //!
 //! # Quick Start
 //!
@@ -19,12 +29,13 @@
 //!
 //! # async fn demo() -> Result<(), Box<dyn std::error::Error>> {
 //! let engine = Engine::new(MockProvider, DefaultRiskPolicy::default());
-//! let (source, _translated, _risk) = engine
+//! let (source, _translated, _risk, _warnings) = engine
 //!     .prepare_source(
 //!         "print hello from beeno",
 //!         "eval",
 //!         SessionSummary::default(),
 //!         None,
+//!         None,
 //!     )
 //!     .await?;
 //! assert!(source.contains("console.log"));
@@ -32,8 +43,20 @@
 //! # }
 //! ```
 
+pub mod artifacts;
 pub mod engine;
+pub mod heal;
+pub mod parse_cache;
+pub mod progress;
+pub mod prompt;
 pub mod providers;
 pub mod repl;
+pub mod rpc;
+pub mod scaffold;
 pub mod server;
+pub mod sourcemap;
 pub mod types;
+pub mod util;
+#[cfg(feature = "wasm-policy")]
+#[cfg_attr(docsrs, doc(cfg(feature = "wasm-policy")))]
+pub mod wasm_policy;
@@ -0,0 +1,130 @@
+//! Short-lived cache of parsed JS/TS modules.
+//!
+//! One translation parses the same code several times: the provider checks
+//! that its reply parses, a continuation re-checks the joined code, and the
+//! policy parses it again to collect imports. Inside [`scope`] those parses
+//! share one result per `(source, media type)`; outside a scope every call
+//! parses afresh, so nothing is retained between requests.
+
+use deno_ast::{MediaType, ParsedSource};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+
+tokio::task_local! {
+    static CACHE: RefCell<ParseCache>;
+}
+
+/// Parse counts recorded inside one [`scope`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ParseStats {
+    /// Sources actually handed to the parser.
+    pub parses: usize,
+    /// Lookups answered from the cache instead.
+    pub hits: usize,
+}
+
+/// Hash and length of the source, plus the media type it was parsed as.
+type Key = (u64, usize, MediaType);
+
+#[derive(Default)]
+struct ParseCache {
+    entries: HashMap<Key, Result<ParsedSource, String>>,
+    stats: ParseStats,
+}
+
+/// Runs `fut` with a fresh cache and returns its output with the parse counts.
+///
+/// A nested scope starts its own cache; the outer one resumes afterwards.
+pub async fn scope<F: Future>(fut: F) -> (F::Output, ParseStats) {
+    CACHE
+        .scope(RefCell::new(ParseCache::default()), async {
+            let output = fut.await;
+            (output, CACHE.with(|cache| cache.borrow().stats))
+        })
+        .await
+}
+
+/// Returns the cached parse of `source` as `media_type`, calling `parse` on a miss.
+///
+/// Failures are cached too, as their message, so invalid code is rejected
+/// with the same error each time.
+pub(crate) fn get_or_parse(
+    source: &str,
+    media_type: MediaType,
+    parse: impl FnOnce() -> anyhow::Result<ParsedSource>,
+) -> anyhow::Result<ParsedSource> {
+    let key = key(source, media_type);
+    let cached = CACHE.try_with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let hit = cache.entries.get(&key).cloned();
+        if hit.is_some() {
+            cache.stats.hits += 1;
+        }
+        hit
+    });
+    match cached {
+        Ok(Some(entry)) => return entry.map_err(anyhow::Error::msg),
+        // No scope: parse without recording anything.
+        Err(_) => return parse(),
+        Ok(None) => {}
+    }
+
+    let parsed = parse();
+    let entry = match &parsed {
+        Ok(parsed) => Ok(parsed.clone()),
+        Err(err) => Err(err.to_string()),
+    };
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.stats.parses += 1;
+        cache.entries.insert(key, entry);
+    });
+    parsed
+}
+
+fn key(source: &str, media_type: MediaType) -> Key {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    (hasher.finish(), source.len(), media_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{import_specifiers, parse_js, parse_js_with_media};
+
+    #[tokio::test]
+    async fn repeated_parses_in_a_scope_hit_the_cache() {
+        let source = "import x from 'npm:left-pad'; const n: number = 1;";
+        let ((), stats) = scope(async {
+            assert!(parse_js(source).is_ok());
+            assert!(parse_js(source).is_ok());
+            assert_eq!(import_specifiers(source).unwrap(), vec!["npm:left-pad"]);
+            assert!(parse_js("const =").is_err());
+            assert!(parse_js("const =").is_err());
+        })
+        .await;
+        assert_eq!(stats, ParseStats { parses: 2, hits: 3 });
+    }
+
+    #[tokio::test]
+    async fn media_type_is_part_of_the_key() {
+        let snippet = "const el = <div />;";
+        let ((), stats) = scope(async {
+            assert!(parse_js_with_media(snippet, MediaType::Tsx).is_ok());
+            assert!(parse_js_with_media(snippet, MediaType::TypeScript).is_err());
+        })
+        .await;
+        assert_eq!(stats, ParseStats { parses: 2, hits: 0 });
+    }
+
+    #[test]
+    fn parses_outside_a_scope_are_not_cached() {
+        assert!(parse_js("const n = 1;").is_ok());
+        assert!(CACHE.try_with(|_| ()).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Elapsed-time spinner shown on stderr while waiting for a provider.
+//!
+//! The spinner only draws when both stdout and stderr are terminals, so piped
+//! output and logs never see it. Nothing is drawn until the first tick, which
+//! keeps instant results (native code, cached blocks) from flashing a frame.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Time between redraws; also the delay before the first frame.
+const TICK: Duration = Duration::from_millis(100);
+
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// A running spinner; dropping it stops the ticker and erases the line.
+pub struct Spinner {
+    ticker: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl Spinner {
+    /// Starts drawing `label` with elapsed seconds when `enabled` and both
+    /// stdout and stderr are terminals; otherwise returns an inert spinner.
+    pub fn start(label: &str, enabled: bool) -> Self {
+        if !(enabled && io::stdout().is_terminal() && io::stderr().is_terminal()) {
+            return Self { ticker: None };
+        }
+        let label = label.to_string();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let started = Instant::now();
+            let mut drawn = false;
+            for frame in FRAMES.iter().cycle() {
+                if stopped.recv_timeout(TICK) != Err(RecvTimeoutError::Timeout) {
+                    break;
+                }
+                let mut stderr = io::stderr().lock();
+                let _ = write!(
+                    stderr,
+                    "\r{}",
+                    frame_line(*frame, &label, started.elapsed())
+                );
+                let _ = stderr.flush();
+                drawn = true;
+            }
+            if drawn {
+                let mut stderr = io::stderr().lock();
+                let _ = write!(stderr, "\r\x1b[2K");
+                let _ = stderr.flush();
+            }
+        });
+        Self {
+            ticker: Some((stop, handle)),
+        }
+    }
+
+    /// Stops the spinner and erases its line; same as dropping it.
+    pub fn finish(self) {}
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some((stop, handle)) = self.ticker.take() {
+            drop(stop);
+            let _ = handle.join();
+        }
+    }
+}
+
+/// One spinner frame, e.g. `⠋ translating… 3.2s`.
+fn frame_line(frame: char, label: &str, elapsed: Duration) -> String {
+    format!("{frame} {label}… {:.1}s", elapsed.as_secs_f64())
+}
+
+/// The `--verbose` summary of where time went, e.g. `timing: translate 1.2s, execute 0.3s`.
+pub fn timing_line(translate: Duration, execute: Duration) -> String {
+    format!(
+        "timing: translate {:.1}s, execute {:.1}s",
+        translate.as_secs_f64(),
+        execute.as_secs_f64()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_shows_label_and_elapsed_seconds() {
+        assert_eq!(
+            frame_line('⠋', "translating", Duration::from_millis(3_240)),
+            "⠋ translating… 3.2s"
+        );
+    }
+
+    #[test]
+    fn timing_line_reports_both_phases() {
+        assert_eq!(
+            timing_line(Duration::from_millis(1_240), Duration::from_millis(300)),
+            "timing: translate 1.2s, execute 0.3s"
+        );
+    }
+
+    #[test]
+    fn disabled_spinner_is_inert() {
+        let spinner = Spinner::start("translating", false);
+        assert!(spinner.ticker.is_none());
+        spinner.finish();
+    }
+}
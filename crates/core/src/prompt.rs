@@ -0,0 +1,114 @@
+//! Yes/no confirmation prompts shared by the CLI, REPL, and dev shell.
+//!
+//! `--yes` (or `BEENO_ASSUME_YES`) accepts every prompt without reading stdin.
+//! Without it, prompts answer "no" when stdin is not a terminal so scripted runs
+//! never block waiting for input.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+/// Makes every subsequent [`confirm`] return `true` without prompting.
+pub fn set_assume_yes(enabled: bool) {
+    ASSUME_YES.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether prompts are auto-accepted.
+pub fn assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
+}
+
+/// Whether stdin is attached to a terminal.
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal()
+}
+
+/// Asks `prompt` on stdout and reads a `y`/`yes` answer from stdin (default: no).
+pub fn confirm(prompt: &str) -> io::Result<bool> {
+    let stdin = io::stdin();
+    confirm_with(
+        prompt,
+        assume_yes(),
+        is_interactive(),
+        &mut stdin.lock(),
+        &mut io::stdout(),
+    )
+}
+
+/// Asks `prompt` like [`confirm`], for questions `--yes` must not answer (it
+/// only accepts risks). Returns `None` without prompting or printing when stdin
+/// is not a terminal, so the caller can fall back to its own default.
+pub fn ask(prompt: &str) -> io::Result<Option<bool>> {
+    if !is_interactive() {
+        return Ok(None);
+    }
+    let stdin = io::stdin();
+    confirm_with(prompt, false, true, &mut stdin.lock(), &mut io::stdout()).map(Some)
+}
+
+fn confirm_with(
+    prompt: &str,
+    assume_yes: bool,
+    interactive: bool,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<bool> {
+    if assume_yes {
+        writeln!(output, "{prompt} [y/N]: y (--yes)")?;
+        return Ok(true);
+    }
+    if !interactive {
+        writeln!(output, "{prompt} [y/N]: n (stdin is not a terminal)")?;
+        return Ok(false);
+    }
+    write!(output, "{prompt} [y/N]: ")?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "YES"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assume_yes_skips_reading_input() {
+        let mut out = Vec::new();
+        let accepted =
+            confirm_with("run?", true, false, &mut io::Cursor::new("n\n"), &mut out).unwrap();
+        assert!(accepted);
+        assert!(String::from_utf8_lossy(&out).contains("(--yes)"));
+    }
+
+    #[test]
+    fn non_interactive_stdin_defaults_to_no() {
+        let accepted = confirm_with(
+            "run?",
+            false,
+            false,
+            &mut io::Cursor::new("y\n"),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert!(!accepted);
+    }
+
+    #[test]
+    fn interactive_prompt_reads_the_answer() {
+        let confirm = |answer: &str| {
+            confirm_with(
+                "run?",
+                false,
+                true,
+                &mut io::Cursor::new(answer),
+                &mut Vec::new(),
+            )
+            .unwrap()
+        };
+        assert!(confirm("yes\n"));
+        assert!(!confirm("\n"));
+        assert!(!confirm("maybe\n"));
+    }
+}
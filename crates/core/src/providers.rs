@@ -1,4 +1,6 @@
-use crate::types::{TranslateRequest, TranslateResult};
+use crate::engine::{parse_js, transpile_to_js};
+use crate::types::{FileMetadata, SessionSummary, TranslateRequest, TranslateResult};
+use crate::util::{http_client, redact, truncate_chars};
 use async_trait::async_trait;
 #[cfg(any(
     feature = "provider-http",
@@ -9,6 +11,8 @@ use reqwest::{Client, RequestBuilder};
 use serde::Serialize;
 use serde_json::{json, Value};
 use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 /// Errors returned by provider adapters.
@@ -16,8 +20,348 @@ use thiserror::Error;
 pub enum ProviderError {
     #[error("provider request failed: {0}")]
     Request(String),
+    #[error("provider rate limited the request{}", retry_hint(.retry_after))]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("provider rejected credentials; check the configured API key")]
+    Unauthorized,
+    #[error("provider server error: http status {0}")]
+    ServerError(u16),
+    #[error("provider network error: {0}")]
+    Network(String),
     #[error("provider response invalid: {0}")]
     InvalidResponse(String),
+    /// The reply was an API error object (some gateways send one with a success status).
+    #[error("provider returned an error: {0}")]
+    Api(String),
+    #[error("provider did not respond within {}ms", .0.as_millis())]
+    Timeout(Duration),
+    /// The reply hit the token limit or the connection dropped before the code
+    /// was complete; carries the partial code received so far.
+    #[error("provider response was cut off before the code was complete; raise llm.max_tokens or set llm.auto_continue")]
+    Truncated(String),
+}
+
+fn retry_hint(retry_after: &Option<Duration>) -> String {
+    match retry_after {
+        Some(delay) => format!(", retry in {}s", delay.as_secs().max(1)),
+        None => String::new(),
+    }
+}
+
+/// Features a provider/model supports, so callers gate on them instead of guessing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct ProviderCapabilities {
+    /// Whether Beeno can stream partial output from this provider.
+    pub streaming: bool,
+    /// Model context window in tokens, when known.
+    pub max_context_tokens: Option<u32>,
+    /// Whether instructions can be sent as a separate system prompt.
+    pub supports_system_prompt: bool,
+}
+
+impl ProviderCapabilities {
+    /// Returns a warning when `max_tokens` exceeds the known context window.
+    pub fn max_tokens_warning(&self, max_tokens: u32) -> Option<String> {
+        let context = self.max_context_tokens?;
+        (max_tokens > context).then(|| {
+            format!("llm.max_tokens ({max_tokens}) exceeds the model's context window ({context} tokens)")
+        })
+    }
+}
+
+/// Context windows for common models, matched by name prefix (most specific first).
+const KNOWN_CONTEXT_WINDOWS: &[(&str, u32)] = &[
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+    ("llama3.1", 131_072),
+    ("llama3.2", 131_072),
+    ("llama3", 8_192),
+    ("qwen2.5-coder", 32_768),
+    ("codellama", 16_384),
+];
+
+/// Looks up the context window for `model`, ignoring any `vendor/` prefix.
+pub fn known_context_window(model: &str) -> Option<u32> {
+    let name = model
+        .rsplit('/')
+        .next()
+        .unwrap_or(model)
+        .to_ascii_lowercase();
+    KNOWN_CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, tokens)| *tokens)
+}
+
+/// Whether `endpoint` is a URL whose host is this machine (`localhost` or a loopback IP).
+///
+/// Used by offline mode to refuse providers that would leave the machine.
+pub fn is_loopback_endpoint(endpoint: &str) -> bool {
+    match url::Url::parse(endpoint.trim())
+        .ok()
+        .and_then(|u| u.host().map(|h| h.to_owned()))
+    {
+        Some(url::Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
+/// Spaces provider requests evenly to stay under `llm.requests_per_minute`.
+///
+/// Callers wait for their slot instead of failing; only a wait longer than
+/// `max_wait` returns [`ProviderError::RateLimited`].
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    max_wait: Duration,
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    /// Allows `requests_per_minute` requests; `None` when the limit is `0` (disabled).
+    pub fn per_minute(requests_per_minute: u32, max_wait: Duration) -> Option<Self> {
+        (requests_per_minute > 0).then(|| Self {
+            interval: Duration::from_secs(60) / requests_per_minute,
+            max_wait,
+            next_slot: Mutex::new(None),
+        })
+    }
+
+    /// Waits until the next request may be sent.
+    pub async fn acquire(&self) -> Result<(), ProviderError> {
+        let wait = {
+            let mut next_slot = self.next_slot.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let slot = next_slot.map_or(now, |slot| slot.max(now));
+            let wait = slot - now;
+            if wait > self.max_wait {
+                return Err(ProviderError::RateLimited {
+                    retry_after: Some(wait),
+                });
+            }
+            *next_slot = Some(slot + self.interval);
+            wait
+        };
+        if !wait.is_zero() {
+            tracing::debug!(
+                wait_ms = wait.as_millis() as u64,
+                "throttling provider request"
+            );
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+}
+
+/// Placeholders substituted by [`PromptTemplate::render`].
+pub const PROMPT_PLACEHOLDERS: [&str; 5] = ["mode", "input", "summary", "file", "language"];
+
+/// Default legacy HTTP prompt.
+pub const HTTP_PROMPT_TEMPLATE: &str =
+    "Translate to executable {language} only. Input mode: {mode}.\nSession context:\n{summary}\nInput: {input}";
+
+/// Default user message for OpenAI-compatible providers (instructions go in the system prompt).
+pub const OPENAI_PROMPT_TEMPLATE: &str =
+    "Target language: {language}\nInput mode: {mode}\nSession context:\n{summary}\nInput: {input}";
+
+/// Default Ollama prompt.
+pub const OLLAMA_PROMPT_TEMPLATE: &str = "Translate to executable {language} only. Return code only.\nInput mode: {mode}\nSession context:\n{summary}\nInput: {input}";
+
+/// Default prompt written to a [`LocalBinaryProvider`] command's stdin.
+pub const COMMAND_PROMPT_TEMPLATE: &str = OLLAMA_PROMPT_TEMPLATE;
+
+/// Input mode asking for a plain-language plan instead of code (`eval --explain-only`).
+///
+/// Providers return the plan as [`TranslateResult::explanation`] with empty `code`.
+pub const EXPLAIN_MODE: &str = "explain";
+
+/// Prompt used for [`EXPLAIN_MODE`] requests in place of the provider's code template.
+pub const EXPLAIN_PROMPT_TEMPLATE: &str = "Explain how you would write a Deno {language} program for the input below. Reply with a short numbered plan in plain language and no code.\nSession context:\n{summary}\nInput: {input}";
+
+/// Input mode asking the provider to continue code that an earlier reply cut off.
+///
+/// The input holds the original request and the code so far; replies are code
+/// fragments, returned without parsing so the caller can join them.
+pub const CONTINUE_MODE: &str = "continue";
+
+/// Prompt used for [`CONTINUE_MODE`] requests in place of the provider's code template.
+pub const CONTINUE_PROMPT_TEMPLATE: &str = "The Deno {language} program below was cut off. Reply with only the code that continues it exactly where it stops, without repeating any of it and without prose.\n{input}";
+
+/// Input mode asking the provider to fix code that failed at runtime (self-heal).
+///
+/// The input holds the error output and the failing program; replies are the
+/// whole corrected program.
+pub const HEAL_MODE: &str = "heal";
+
+/// Prompt used for [`HEAL_MODE`] requests in place of the provider's code template.
+pub const HEAL_PROMPT_TEMPLATE: &str = "The Deno {language} program below failed with the error shown. Reply with only the complete corrected program, without prose.\n{input}";
+
+/// Renders a session summary as a bullet list for prompts, or `none` when it is empty.
+///
+/// # Examples
+///
+/// ```
+/// use beeno_core::providers::format_context;
+/// use beeno_core::types::SessionSummary;
+///
+/// let summary = SessionSummary {
+///     symbols: vec!["total".to_string()],
+///     recent_intents: vec!["sum the list".to_string()],
+///     ..SessionSummary::default()
+/// };
+/// assert_eq!(
+///     format_context(&summary),
+///     "- Defined symbols: total\n- Recent inputs:\n  - sum the list"
+/// );
+/// assert_eq!(format_context(&SessionSummary::default()), "none");
+/// ```
+pub fn format_context(summary: &SessionSummary) -> String {
+    let mut lines = Vec::new();
+    if let Some(narrative) = summary.narrative.as_deref().map(str::trim) {
+        if !narrative.is_empty() {
+            lines.push(format!("- Summary: {narrative}"));
+        }
+    }
+    if !summary.symbols.is_empty() {
+        lines.push(format!("- Defined symbols: {}", summary.symbols.join(", ")));
+    }
+    for (label, items) in [
+        ("Imports", &summary.imports),
+        ("Recent inputs", &summary.recent_intents),
+        ("Side effects", &summary.side_effects),
+    ] {
+        if items.is_empty() {
+            continue;
+        }
+        lines.push(format!("- {label}:"));
+        lines.extend(items.iter().map(|item| format!("  - {}", item.trim())));
+    }
+    if let Some(server) = summary.server.as_ref().filter(|s| s.running) {
+        let url = server.url.as_deref().unwrap_or("unknown url");
+        lines.push(format!("- Server: running at {url} ({} mode)", server.mode));
+    }
+    if lines.is_empty() {
+        return "none".to_string();
+    }
+    lines.join("\n")
+}
+
+/// Language hint that asks the provider for plain JavaScript.
+pub const JAVASCRIPT_LANGUAGE_HINT: &str = "javascript";
+
+static UNKNOWN_PLACEHOLDER_WARNING: std::sync::Once = std::sync::Once::new();
+
+/// Prompt text with `{mode}`, `{input}`, `{summary}`, `{file}`, and `{language}` placeholders.
+///
+/// Unknown `{name}` placeholders are left as written; the first template
+/// containing any logs a warning once per process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    template: String,
+    max_context_chars: usize,
+}
+
+impl PromptTemplate {
+    pub fn new(template: impl Into<String>) -> Self {
+        let template = Self {
+            template: template.into(),
+            max_context_chars: 0,
+        };
+        let unknown = template.unknown_placeholders();
+        if !unknown.is_empty() {
+            UNKNOWN_PLACEHOLDER_WARNING.call_once(|| {
+                tracing::warn!(
+                    placeholders = ?unknown,
+                    "prompt template has unknown placeholders; they are sent literally"
+                );
+            });
+        }
+        template
+    }
+
+    /// Cuts the rendered `{summary}` to `max` chars (`0`, the default, keeps all of it).
+    pub fn with_max_context_chars(mut self, max: usize) -> Self {
+        self.max_context_chars = max;
+        self
+    }
+
+    /// Names of `{name}` placeholders that are not in [`PROMPT_PLACEHOLDERS`].
+    pub fn unknown_placeholders(&self) -> Vec<String> {
+        placeholders(&self.template)
+            .filter(|(_, name)| !PROMPT_PLACEHOLDERS.contains(name))
+            .map(|(_, name)| name.to_string())
+            .collect()
+    }
+
+    /// Substitutes request fields; `{summary}` is [`format_context`] of the session summary
+    /// (cut to the [`Self::with_max_context_chars`] budget),
+    /// `{file}` the file path (empty when there is none), and `{language}` names the
+    /// language to generate (plain JavaScript for a `javascript` language hint).
+    ///
+    /// [`EXPLAIN_MODE`], [`CONTINUE_MODE`], and [`HEAL_MODE`] requests render
+    /// [`EXPLAIN_PROMPT_TEMPLATE`], [`CONTINUE_PROMPT_TEMPLATE`], and
+    /// [`HEAL_PROMPT_TEMPLATE`] instead of this template.
+    pub fn render(&self, req: &TranslateRequest) -> String {
+        let template = match req.mode.as_str() {
+            EXPLAIN_MODE => EXPLAIN_PROMPT_TEMPLATE,
+            CONTINUE_MODE => CONTINUE_PROMPT_TEMPLATE,
+            HEAL_MODE => HEAL_PROMPT_TEMPLATE,
+            _ => self.template.as_str(),
+        };
+        let mut out = String::with_capacity(template.len() + req.input.len());
+        let mut last = 0;
+        for (start, name) in placeholders(template) {
+            let value = match name {
+                "mode" => req.mode.clone(),
+                "input" => req.input.clone(),
+                "summary" => truncate_chars(
+                    &format_context(&req.session_summary),
+                    self.max_context_chars,
+                ),
+                "file" => req
+                    .file_metadata
+                    .as_ref()
+                    .and_then(|meta| meta.path.clone())
+                    .unwrap_or_default(),
+                "language" => {
+                    let hint = req
+                        .file_metadata
+                        .as_ref()
+                        .and_then(|meta| meta.language_hint.as_deref());
+                    if hint == Some(JAVASCRIPT_LANGUAGE_HINT) {
+                        "JavaScript (no TypeScript syntax)".to_string()
+                    } else {
+                        "JavaScript/TypeScript".to_string()
+                    }
+                }
+                _ => continue,
+            };
+            out.push_str(&template[last..start]);
+            out.push_str(&value);
+            last = start + name.len() + 2;
+        }
+        out.push_str(&template[last..]);
+        out
+    }
+}
+
+/// Yields `(byte offset of '{', name)` for each `{identifier}` in `template`.
+fn placeholders(template: &str) -> impl Iterator<Item = (usize, &str)> {
+    template.match_indices('{').filter_map(move |(start, _)| {
+        let rest = &template[start + 1..];
+        let end = rest.find('}')?;
+        let name = &rest[..end];
+        (!name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+            .then_some((start, name))
+    })
 }
 
 /// Provider abstraction that translates NL/pseudocode into executable code.
@@ -25,6 +369,25 @@ pub enum ProviderError {
 pub trait TranslatorProvider: Send + Sync {
     /// Translates a request into JS/TS source.
     async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError>;
+
+    /// Reports what this provider supports; the default claims nothing optional.
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::default()
+    }
+
+    /// Lists the model IDs the provider's endpoint offers; the default reports
+    /// that listing is unsupported.
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        Err(ProviderError::Request(
+            "this provider cannot list models".to_string(),
+        ))
+    }
+
+    /// Post-processes a reply stitched together from [`CONTINUE_MODE`] pieces,
+    /// which the provider only saw one at a time; the default keeps it as is.
+    fn finish_continued(&self, result: TranslateResult) -> Result<TranslateResult, ProviderError> {
+        Ok(result)
+    }
 }
 
 #[async_trait]
@@ -35,6 +398,162 @@ where
     async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
         (**self).translate(req).await
     }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        (**self).capabilities()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        (**self).list_models().await
+    }
+
+    fn finish_continued(&self, result: TranslateResult) -> Result<TranslateResult, ProviderError> {
+        (**self).finish_continued(result)
+    }
+}
+
+/// Tries a chain of providers in order until one succeeds.
+///
+/// Transient failures ([`ProviderError::Network`], [`ProviderError::RateLimited`],
+/// [`ProviderError::ServerError`]) move on to the next provider; any other error
+/// is returned immediately. The successful provider's position is recorded as
+/// `fallback_index` in [`TranslateResult::raw_provider_meta`].
+pub struct FallbackProvider {
+    pub providers: Vec<Box<dyn TranslatorProvider>>,
+}
+
+impl FallbackProvider {
+    /// Creates a fallback chain; earlier providers are preferred.
+    pub fn new(providers: Vec<Box<dyn TranslatorProvider>>) -> Self {
+        Self { providers }
+    }
+}
+
+#[async_trait]
+impl TranslatorProvider for FallbackProvider {
+    async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+        let mut failures = Vec::new();
+        let mut last_error = None;
+        for (index, provider) in self.providers.iter().enumerate() {
+            match provider.translate(req.clone()).await {
+                Ok(mut result) => {
+                    result
+                        .raw_provider_meta
+                        .insert("fallback_index".to_string(), json!(index));
+                    if !failures.is_empty() {
+                        result
+                            .raw_provider_meta
+                            .insert("fallback_failures".to_string(), json!(failures));
+                    }
+                    return Ok(result);
+                }
+                Err(
+                    e @ (ProviderError::Network(_)
+                    | ProviderError::RateLimited { .. }
+                    | ProviderError::ServerError(_)),
+                ) => {
+                    tracing::warn!(index, error = %e, "provider failed, trying next");
+                    failures.push(e.to_string());
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            ProviderError::Request("no providers configured for fallback".to_string())
+        }))
+    }
+
+    /// Only what every provider in the chain supports, with the smallest context window.
+    fn capabilities(&self) -> ProviderCapabilities {
+        let all: Vec<_> = self.providers.iter().map(|p| p.capabilities()).collect();
+        ProviderCapabilities {
+            streaming: !all.is_empty() && all.iter().all(|c| c.streaming),
+            max_context_tokens: all.iter().filter_map(|c| c.max_context_tokens).min(),
+            supports_system_prompt: !all.is_empty() && all.iter().all(|c| c.supports_system_prompt),
+        }
+    }
+
+    /// Models from every provider in the chain that can list them, without duplicates.
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        let mut models = Vec::new();
+        let mut last_error = None;
+        for provider in &self.providers {
+            match provider.list_models().await {
+                Ok(listed) => {
+                    for model in listed {
+                        if !models.contains(&model) {
+                            models.push(model);
+                        }
+                    }
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) if models.is_empty() => Err(e),
+            _ => Ok(models),
+        }
+    }
+}
+
+/// Asks the wrapped provider for plain JavaScript and transpiles away any
+/// TypeScript syntax left in its reply.
+///
+/// Used when `llm.target_language = "javascript"`, for runtimes without TS support.
+/// Replies that needed transpiling carry `transpiled: true` in
+/// [`TranslateResult::raw_provider_meta`].
+pub struct JavaScriptTargetProvider {
+    pub inner: Box<dyn TranslatorProvider>,
+}
+
+impl JavaScriptTargetProvider {
+    pub fn new(inner: Box<dyn TranslatorProvider>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl TranslatorProvider for JavaScriptTargetProvider {
+    async fn translate(&self, mut req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+        let metadata = req.file_metadata.get_or_insert(FileMetadata {
+            path: None,
+            language_hint: None,
+        });
+        metadata.language_hint = Some(JAVASCRIPT_LANGUAGE_HINT.to_string());
+        // A continuation is only a fragment; the engine transpiles the stitched
+        // reply once through `finish_continued`.
+        let raw = req.mode == EXPLAIN_MODE || req.mode == CONTINUE_MODE;
+        let result = self.inner.translate(req).await?;
+        if raw {
+            return Ok(result);
+        }
+        self.finish_continued(result)
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.list_models().await
+    }
+
+    fn finish_continued(
+        &self,
+        mut result: TranslateResult,
+    ) -> Result<TranslateResult, ProviderError> {
+        let js = transpile_to_js(&result.code).map_err(|e| {
+            ProviderError::InvalidResponse(format!("could not transpile reply to JavaScript: {e}"))
+        })?;
+        if js.trim() != result.code.trim() {
+            result
+                .raw_provider_meta
+                .insert("transpiled".to_string(), json!(true));
+            result.code = js;
+        }
+        Ok(result)
+    }
 }
 
 /// Generic JSON HTTP provider using Beeno's legacy `{ code: ... }` contract.
@@ -47,6 +566,8 @@ pub struct HttpProvider {
     pub model: String,
     pub temperature: f32,
     pub max_tokens: u32,
+    pub prompt: PromptTemplate,
+    limiter: Option<Arc<RateLimiter>>,
     client: Client,
 }
 
@@ -66,9 +587,23 @@ impl HttpProvider {
             model,
             temperature,
             max_tokens,
-            client: Client::new(),
+            prompt: PromptTemplate::new(HTTP_PROMPT_TEMPLATE),
+            limiter: None,
+            client: http_client().clone(),
         }
     }
+
+    /// Replaces the default prompt template.
+    pub fn with_prompt_template(mut self, prompt: PromptTemplate) -> Self {
+        self.prompt = prompt;
+        self
+    }
+
+    /// Spaces requests through `limiter` (which may be shared); `None` disables throttling.
+    pub fn with_rate_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
 }
 
 #[cfg(feature = "provider-http")]
@@ -85,16 +620,15 @@ struct LegacyRequestBody {
 #[async_trait]
 impl TranslatorProvider for HttpProvider {
     async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+        let trace_id = req.trace_id.clone();
         let payload = LegacyRequestBody {
             model: self.model.clone(),
-            input: format!(
-                "Translate to executable JS/TS only. Input mode: {}.\\nSession summary: {:?}\\nInput: {}",
-                req.mode, req.session_summary, req.input
-            ),
+            input: self.prompt.render(&req),
             temperature: self.temperature,
             max_tokens: self.max_tokens,
             metadata: json!({
                 "file_metadata": req.file_metadata,
+                "trace_id": req.trace_id,
             }),
         };
 
@@ -103,24 +637,36 @@ impl TranslatorProvider for HttpProvider {
             request = request.bearer_auth(key);
         }
 
-        let value = send_json(request).await?;
-        let code = value
-            .get("code")
+        let value = send_json(
+            request,
+            &self.endpoint,
+            &self.model,
+            trace_id.as_deref(),
+            self.limiter.as_deref(),
+        )
+        .await?;
+        let explanation = value
+            .get("explanation")
             .and_then(Value::as_str)
-            .ok_or_else(|| {
-                ProviderError::InvalidResponse("missing string field `code`".to_string())
-            })?
-            .to_string();
+            .map(ToString::to_string);
+        let (code, explanation) = if req.mode == EXPLAIN_MODE {
+            // A plan may come back in either field of the legacy contract.
+            let plan = explanation
+                .as_deref()
+                .or_else(|| value.get("code").and_then(Value::as_str))
+                .unwrap_or_default();
+            (String::new(), Some(explain_reply(plan)?))
+        } else {
+            let code = reply_str(&value, "code", "HTTP provider")?.to_string();
+            (code, explanation)
+        };
 
         let mut meta = BTreeMap::new();
         meta.insert("raw".to_string(), value.clone());
 
         Ok(TranslateResult {
             code,
-            explanation: value
-                .get("explanation")
-                .and_then(Value::as_str)
-                .map(ToString::to_string),
+            explanation,
             confidence: value
                 .get("confidence")
                 .and_then(Value::as_f64)
@@ -130,6 +676,7 @@ impl TranslatorProvider for HttpProvider {
                 .and_then(Value::as_u64)
                 .map(|v| v as u32),
             raw_provider_meta: meta,
+            warnings: Vec::new(),
         })
     }
 }
@@ -144,6 +691,11 @@ pub struct OpenAICompatProvider {
     pub model: String,
     pub temperature: f32,
     pub max_tokens: u32,
+    /// Request `response_format: json_object` and parse `code`/`explanation` fields.
+    pub json_mode: bool,
+    /// User message template; the system prompt stays fixed.
+    pub prompt: PromptTemplate,
+    limiter: Option<Arc<RateLimiter>>,
     client: Client,
 }
 
@@ -163,11 +715,52 @@ impl OpenAICompatProvider {
             model,
             temperature,
             max_tokens,
-            client: Client::new(),
+            json_mode: false,
+            prompt: PromptTemplate::new(OPENAI_PROMPT_TEMPLATE),
+            limiter: None,
+            client: http_client().clone(),
         }
     }
+
+    /// Replaces the default user message template.
+    pub fn with_prompt_template(mut self, prompt: PromptTemplate) -> Self {
+        self.prompt = prompt;
+        self
+    }
+
+    /// Spaces requests through `limiter` (which may be shared); `None` disables throttling.
+    pub fn with_rate_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Enables JSON mode; not every OpenAI-compatible backend supports it.
+    pub fn with_json_mode(mut self, json_mode: bool) -> Self {
+        self.json_mode = json_mode;
+        self
+    }
+
+    /// The `/models` URL next to the configured chat completions endpoint.
+    pub fn models_url(&self) -> String {
+        let base = self.endpoint.trim_end_matches('/');
+        let base = base.strip_suffix("/chat/completions").unwrap_or(base);
+        format!("{base}/models")
+    }
 }
 
+#[cfg(feature = "provider-openai-compat")]
+const OPENAI_CODE_ONLY_PROMPT: &str =
+    "Translate user input to executable JavaScript/TypeScript only. Return code only.";
+
+#[cfg(feature = "provider-openai-compat")]
+const OPENAI_EXPLAIN_PROMPT: &str =
+    "Explain how you would solve the user's task in JavaScript/TypeScript. Return a plain-language plan, no code.";
+
+#[cfg(feature = "provider-openai-compat")]
+const OPENAI_JSON_PROMPT: &str = "Translate user input to executable JavaScript/TypeScript. \
+Respond with a JSON object {\"code\": \"<source>\", \"explanation\": \"<one sentence>\", \
+\"warnings\": [\"<caveat, e.g. needs network access>\"]} and nothing else; leave warnings empty when there are none.";
+
 #[cfg(feature = "provider-openai-compat")]
 #[derive(Debug, Serialize)]
 struct OpenAICompatMessage {
@@ -182,61 +775,274 @@ struct OpenAICompatRequest {
     messages: Vec<OpenAICompatMessage>,
     temperature: f32,
     max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<Value>,
 }
 
 #[cfg(feature = "provider-openai-compat")]
-#[async_trait]
-impl TranslatorProvider for OpenAICompatProvider {
-    async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
-        let payload = OpenAICompatRequest {
-            model: self.model.clone(),
+impl OpenAICompatRequest {
+    /// Builds a chat completion request with Beeno's system prompt.
+    ///
+    /// `explain` asks for a plan instead of code and turns JSON mode off.
+    fn new(
+        model: String,
+        user: String,
+        temperature: f32,
+        max_tokens: u32,
+        json_mode: bool,
+        explain: bool,
+    ) -> Self {
+        let json_mode = json_mode && !explain;
+        Self {
+            model,
             messages: vec![
                 OpenAICompatMessage {
                     role: "system".to_string(),
-                    content: "Translate user input to executable JavaScript/TypeScript only. Return code only.".to_string(),
+                    content: if explain {
+                        OPENAI_EXPLAIN_PROMPT
+                    } else if json_mode {
+                        OPENAI_JSON_PROMPT
+                    } else {
+                        OPENAI_CODE_ONLY_PROMPT
+                    }
+                    .to_string(),
                 },
                 OpenAICompatMessage {
                     role: "user".to_string(),
-                    content: format!(
-                        "Input mode: {}\\nSession summary: {:?}\\nInput: {}",
-                        req.mode, req.session_summary, req.input
-                    ),
+                    content: user,
                 },
             ],
-            temperature: self.temperature,
-            max_tokens: self.max_tokens,
-        };
+            temperature,
+            max_tokens,
+            response_format: json_mode.then(|| json!({ "type": "json_object" })),
+        }
+    }
+}
+
+/// Reads the translation from a chat completion response.
+///
+/// A `finish_reason` of `length` marks the reply as cut off (see [`code_or_plan`]);
+/// JSON-mode replies are never continued.
+#[cfg(feature = "provider-openai-compat")]
+fn chat_completion_result(
+    value: Value,
+    json_mode: bool,
+    mode: &str,
+) -> Result<TranslateResult, ProviderError> {
+    let content = reply_str(&value, "choices[0].message.content", "OpenAI-compatible")?;
+    let truncated = value
+        .get("choices")
+        .and_then(|choices| choices.get(0))
+        .and_then(|first| first.get("finish_reason"))
+        .and_then(Value::as_str)
+        == Some("length");
+
+    let json_mode = json_mode && mode != CONTINUE_MODE;
+    let mut warnings = Vec::new();
+    let (code, explanation) = match json_mode.then(|| parse_json_translation(content)) {
+        _ if mode == EXPLAIN_MODE => (String::new(), Some(explain_reply(content)?)),
+        Some(Some(reply)) => {
+            warnings = reply.warnings;
+            (extract_valid_code(&reply.code)?, reply.explanation)
+        }
+        Some(None) => (extract_valid_code(content)?, None),
+        None => code_or_plan(mode, content, truncated)?,
+    };
+    let mut meta = BTreeMap::new();
+    meta.insert("raw".to_string(), value);
+
+    Ok(TranslateResult {
+        code,
+        explanation,
+        confidence: None,
+        tokens: None,
+        raw_provider_meta: meta,
+        warnings,
+    })
+}
+
+#[cfg(feature = "provider-openai-compat")]
+#[async_trait]
+impl TranslatorProvider for OpenAICompatProvider {
+    async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+        let trace_id = req.trace_id.clone();
+        let explain = req.mode == EXPLAIN_MODE;
+        let payload = OpenAICompatRequest::new(
+            self.model.clone(),
+            self.prompt.render(&req),
+            self.temperature,
+            self.max_tokens,
+            self.json_mode && req.mode != CONTINUE_MODE,
+            explain,
+        );
 
         let mut request = self.client.post(&self.endpoint).json(&payload);
         if let Some(key) = &self.api_key {
             request = request.bearer_auth(key);
         }
 
-        let value = send_json(request).await?;
-        let content = value
-            .get("choices")
-            .and_then(Value::as_array)
-            .and_then(|choices| choices.first())
-            .and_then(|first| first.get("message"))
-            .and_then(|message| message.get("content"))
-            .and_then(Value::as_str)
-            .ok_or_else(|| {
-                ProviderError::InvalidResponse(
-                    "missing choices[0].message.content in OpenAI-compatible response".to_string(),
-                )
-            })?;
+        let value = send_json(
+            request,
+            &self.endpoint,
+            &self.model,
+            trace_id.as_deref(),
+            self.limiter.as_deref(),
+        )
+        .await?;
+        chat_completion_result(value, self.json_mode, &req.mode)
+    }
 
-        let code = strip_code_fences(content);
-        let mut meta = BTreeMap::new();
-        meta.insert("raw".to_string(), value);
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            streaming: false,
+            max_context_tokens: known_context_window(&self.model),
+            supports_system_prompt: true,
+        }
+    }
 
-        Ok(TranslateResult {
-            code,
-            explanation: None,
-            confidence: None,
-            tokens: None,
-            raw_provider_meta: meta,
-        })
+    /// Reads `data[].id` from `GET /v1/models`.
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        let url = self.models_url();
+        let mut request = self.client.get(&url);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let value = send_json(request, &url, &self.model, None, self.limiter.as_deref()).await?;
+        model_names(&value, "data", "id")
+    }
+}
+
+/// Azure OpenAI chat completions provider routed by deployment name.
+///
+/// Requests go to `{endpoint}/openai/deployments/{deployment}/chat/completions`
+/// with an `api-key` header instead of bearer auth.
+#[cfg(feature = "provider-azure")]
+#[cfg_attr(docsrs, doc(cfg(feature = "provider-azure")))]
+#[derive(Debug, Clone)]
+pub struct AzureOpenAIProvider {
+    /// Resource URL such as `https://my-resource.openai.azure.com`.
+    pub endpoint: String,
+    pub deployment: String,
+    pub api_version: String,
+    pub api_key: Option<String>,
+    pub temperature: f32,
+    pub max_tokens: u32,
+    /// Request `response_format: json_object` and parse `code`/`explanation` fields.
+    pub json_mode: bool,
+    /// User message template; the system prompt stays fixed.
+    pub prompt: PromptTemplate,
+    limiter: Option<Arc<RateLimiter>>,
+    client: Client,
+}
+
+/// `api-version` used when `llm.api_version` is not set.
+#[cfg(feature = "provider-azure")]
+pub const AZURE_DEFAULT_API_VERSION: &str = "2024-10-21";
+
+#[cfg(feature = "provider-azure")]
+impl AzureOpenAIProvider {
+    /// Creates a new Azure OpenAI provider client.
+    pub fn new(
+        endpoint: String,
+        deployment: String,
+        api_version: String,
+        api_key: Option<String>,
+        temperature: f32,
+        max_tokens: u32,
+    ) -> Self {
+        Self {
+            endpoint,
+            deployment,
+            api_version,
+            api_key,
+            temperature,
+            max_tokens,
+            json_mode: false,
+            prompt: PromptTemplate::new(OPENAI_PROMPT_TEMPLATE),
+            limiter: None,
+            client: http_client().clone(),
+        }
+    }
+
+    /// Enables JSON mode.
+    pub fn with_json_mode(mut self, json_mode: bool) -> Self {
+        self.json_mode = json_mode;
+        self
+    }
+
+    /// Replaces the default user message template.
+    pub fn with_prompt_template(mut self, prompt: PromptTemplate) -> Self {
+        self.prompt = prompt;
+        self
+    }
+
+    /// Spaces requests through `limiter` (which may be shared); `None` disables throttling.
+    pub fn with_rate_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// The chat completions URL for the configured deployment.
+    pub fn completions_url(&self) -> Result<String, ProviderError> {
+        if self.deployment.trim().is_empty() {
+            return Err(ProviderError::Request(
+                "azure provider requires llm.deployment (the Azure OpenAI deployment name)"
+                    .to_string(),
+            ));
+        }
+        if self.endpoint.trim().is_empty() {
+            return Err(ProviderError::Request(
+                "azure provider requires llm.endpoint (https://<resource>.openai.azure.com)"
+                    .to_string(),
+            ));
+        }
+        Ok(format!(
+            "{}/openai/deployments/{}/chat/completions?api-version={}",
+            self.endpoint.trim_end_matches('/'),
+            self.deployment.trim(),
+            self.api_version
+        ))
+    }
+}
+
+#[cfg(feature = "provider-azure")]
+#[async_trait]
+impl TranslatorProvider for AzureOpenAIProvider {
+    async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+        let url = self.completions_url()?;
+        let trace_id = req.trace_id.clone();
+        let explain = req.mode == EXPLAIN_MODE;
+        let payload = OpenAICompatRequest::new(
+            self.deployment.clone(),
+            self.prompt.render(&req),
+            self.temperature,
+            self.max_tokens,
+            self.json_mode && req.mode != CONTINUE_MODE,
+            explain,
+        );
+
+        let mut request = self.client.post(&url).json(&payload);
+        if let Some(key) = &self.api_key {
+            request = request.header("api-key", key);
+        }
+
+        let value = send_json(
+            request,
+            &url,
+            &self.deployment,
+            trace_id.as_deref(),
+            self.limiter.as_deref(),
+        )
+        .await?;
+        chat_completion_result(value, self.json_mode, &req.mode)
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            streaming: false,
+            max_context_tokens: known_context_window(&self.deployment),
+            supports_system_prompt: true,
+        }
     }
 }
 
@@ -249,6 +1055,10 @@ pub struct OllamaProvider {
     pub model: String,
     pub temperature: f32,
     pub max_tokens: u32,
+    pub prompt: PromptTemplate,
+    /// Request newline-delimited JSON chunks instead of one response object.
+    pub stream: bool,
+    limiter: Option<Arc<RateLimiter>>,
     client: Client,
 }
 
@@ -261,9 +1071,37 @@ impl OllamaProvider {
             model,
             temperature,
             max_tokens,
-            client: Client::new(),
+            prompt: PromptTemplate::new(OLLAMA_PROMPT_TEMPLATE),
+            stream: false,
+            limiter: None,
+            client: http_client().clone(),
         }
     }
+
+    /// Streams the generation as newline-delimited JSON and joins the chunks.
+    pub fn with_streaming(mut self, stream: bool) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    /// The `/api/tags` URL on the same server as the configured generate endpoint.
+    pub fn tags_url(&self) -> String {
+        let base = self.endpoint.trim_end_matches('/');
+        let base = base.strip_suffix("/api/generate").unwrap_or(base);
+        format!("{base}/api/tags")
+    }
+
+    /// Replaces the default prompt template.
+    pub fn with_prompt_template(mut self, prompt: PromptTemplate) -> Self {
+        self.prompt = prompt;
+        self
+    }
+
+    /// Spaces requests through `limiter` (which may be shared); `None` disables throttling.
+    pub fn with_rate_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
 }
 
 #[cfg(feature = "provider-ollama")]
@@ -279,13 +1117,11 @@ struct OllamaRequest {
 #[async_trait]
 impl TranslatorProvider for OllamaProvider {
     async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+        let trace_id = req.trace_id.clone();
         let payload = OllamaRequest {
             model: self.model.clone(),
-            prompt: format!(
-                "Translate to executable JavaScript/TypeScript only. Return code only.\\nInput mode: {}\\nSession summary: {:?}\\nInput: {}",
-                req.mode, req.session_summary, req.input
-            ),
-            stream: false,
+            prompt: self.prompt.render(&req),
+            stream: self.stream,
             options: json!({
                 "temperature": self.temperature,
                 "num_predict": self.max_tokens,
@@ -293,27 +1129,242 @@ impl TranslatorProvider for OllamaProvider {
         };
 
         let request = self.client.post(&self.endpoint).json(&payload);
-        let value = send_json(request).await?;
+        if self.stream {
+            let response = send_request(
+                request,
+                &self.endpoint,
+                &self.model,
+                trace_id.as_deref(),
+                self.limiter.as_deref(),
+            )
+            .await?;
+            let (text, last) = read_ollama_stream(response).await?;
+            let (code, explanation) = code_or_plan(&req.mode, &text, ollama_truncated(&last))?;
+            let tokens = last
+                .get("eval_count")
+                .and_then(Value::as_u64)
+                .map(|n| n as u32);
+            let mut meta = BTreeMap::new();
+            meta.insert("raw".to_string(), last);
+            return Ok(TranslateResult {
+                code,
+                explanation,
+                confidence: None,
+                tokens,
+                raw_provider_meta: meta,
+                warnings: Vec::new(),
+            });
+        }
 
-        let response = value
-            .get("response")
-            .and_then(Value::as_str)
-            .ok_or_else(|| {
-                ProviderError::InvalidResponse(
-                    "missing string field `response` in Ollama response".to_string(),
-                )
-            })?;
+        let value = send_json(
+            request,
+            &self.endpoint,
+            &self.model,
+            trace_id.as_deref(),
+            self.limiter.as_deref(),
+        )
+        .await?;
 
-        let code = strip_code_fences(response);
+        let response = reply_str(&value, "response", "Ollama")?;
+
+        let (code, explanation) = code_or_plan(&req.mode, response, ollama_truncated(&value))?;
         let mut meta = BTreeMap::new();
         meta.insert("raw".to_string(), value);
 
+        Ok(TranslateResult {
+            code,
+            explanation,
+            confidence: None,
+            tokens: None,
+            raw_provider_meta: meta,
+            warnings: Vec::new(),
+        })
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            streaming: self.stream,
+            max_context_tokens: known_context_window(&self.model),
+            supports_system_prompt: false,
+        }
+    }
+
+    /// Reads `models[].name` from `GET /api/tags`.
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        let url = self.tags_url();
+        let request = self.client.get(&url);
+        let value = send_json(request, &url, &self.model, None, self.limiter.as_deref()).await?;
+        model_names(&value, "models", "name")
+    }
+}
+
+/// Whether Ollama stopped generating because it hit `num_predict`.
+#[cfg(feature = "provider-ollama")]
+fn ollama_truncated(last: &Value) -> bool {
+    last.get("done_reason").and_then(Value::as_str) == Some("length")
+}
+
+/// Reads Ollama's newline-delimited JSON stream until an object with `done: true`.
+///
+/// Returns the concatenated `response` fields and that final object. Lines may
+/// be split across network reads, so bytes are buffered until a newline. A
+/// stream that drops after some text is [`ProviderError::Truncated`].
+#[cfg(feature = "provider-ollama")]
+async fn read_ollama_stream(
+    mut response: reqwest::Response,
+) -> Result<(String, Value), ProviderError> {
+    let mut buffer = Vec::new();
+    let mut text = String::new();
+    let cut_off = |text: &str, error: ProviderError| {
+        if text.trim().is_empty() {
+            error
+        } else {
+            ProviderError::Truncated(strip_code_fences(text))
+        }
+    };
+    loop {
+        let chunk = match response.chunk().await {
+            Ok(chunk) => chunk,
+            Err(e) => return Err(cut_off(&text, ProviderError::Network(e.to_string()))),
+        };
+        let at_end = chunk.is_none();
+        if let Some(bytes) = chunk {
+            buffer.extend_from_slice(&bytes);
+        } else if !buffer.is_empty() {
+            // A final line without a trailing newline.
+            buffer.push(b'\n');
+        }
+        while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value: Value = serde_json::from_str(line.trim())
+                .map_err(|e| ProviderError::InvalidResponse(format!("Ollama stream: {e}")))?;
+            if let Some(message) = api_error(&value) {
+                return Err(ProviderError::Api(message));
+            }
+            let piece = value.get("response").and_then(Value::as_str).unwrap_or("");
+            tracing::trace!(chunk = piece, "ollama stream chunk");
+            text.push_str(piece);
+            if value.get("done").and_then(Value::as_bool) == Some(true) {
+                return Ok((text, value));
+            }
+        }
+        if at_end {
+            break;
+        }
+    }
+    Err(cut_off(
+        &text,
+        ProviderError::InvalidResponse(
+            "Ollama stream ended before an object with `done: true`".to_string(),
+        ),
+    ))
+}
+
+/// Runs a local command per request: the rendered prompt goes to its stdin and
+/// its stdout (minus any code fences) is the generated code.
+///
+/// A non-zero exit is a [`ProviderError::Request`] carrying the command's stderr.
+#[derive(Debug, Clone)]
+pub struct LocalBinaryProvider {
+    pub command: String,
+    pub args: Vec<String>,
+    pub prompt: PromptTemplate,
+}
+
+impl LocalBinaryProvider {
+    /// Creates a provider that spawns `command` with `args` for each translation.
+    pub fn new(command: String, args: Vec<String>) -> Self {
+        Self {
+            command,
+            args,
+            prompt: PromptTemplate::new(COMMAND_PROMPT_TEMPLATE),
+        }
+    }
+
+    /// Replaces the default prompt template.
+    pub fn with_prompt_template(mut self, prompt: PromptTemplate) -> Self {
+        self.prompt = prompt;
+        self
+    }
+}
+
+#[async_trait]
+impl TranslatorProvider for LocalBinaryProvider {
+    async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+        use tokio::io::AsyncWriteExt;
+
+        if self.command.trim().is_empty() {
+            return Err(ProviderError::Request(
+                "command provider requires llm.command.command".to_string(),
+            ));
+        }
+        tracing::debug!(command = %self.command, trace_id = ?req.trace_id, "running provider command");
+        let started = Instant::now();
+        let mut child = tokio::process::Command::new(&self.command)
+            .args(&self.args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                ProviderError::Request(format!("failed to run `{}`: {e}", self.command))
+            })?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A command that exits without reading its input reports through its exit status.
+            let _ = stdin.write_all(self.prompt.render(&req).as_bytes()).await;
+        }
+        let output = child.wait_with_output().await.map_err(|e| {
+            ProviderError::Request(format!("failed to run `{}`: {e}", self.command))
+        })?;
+        tracing::info!(
+            command = %self.command,
+            status = %output.status,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "provider command finished"
+        );
+        if !output.status.success() {
+            return Err(ProviderError::Request(format!(
+                "`{}` {}: {}",
+                self.command,
+                output.status,
+                redact(String::from_utf8_lossy(&output.stderr).trim())
+            )));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if req.mode == EXPLAIN_MODE {
+            let mut meta = BTreeMap::new();
+            meta.insert("command".to_string(), json!(self.command));
+            return Ok(TranslateResult {
+                code: String::new(),
+                explanation: Some(explain_reply(&stdout)?),
+                confidence: None,
+                tokens: None,
+                raw_provider_meta: meta,
+                warnings: Vec::new(),
+            });
+        }
+        let code = strip_code_fences(&stdout);
+        if code.is_empty() {
+            return Err(ProviderError::InvalidResponse(format!(
+                "`{}` produced no code on stdout",
+                self.command
+            )));
+        }
+        let mut meta = BTreeMap::new();
+        meta.insert("command".to_string(), json!(self.command));
         Ok(TranslateResult {
             code,
             explanation: None,
             confidence: None,
             tokens: None,
             raw_provider_meta: meta,
+            warnings: Vec::new(),
         })
     }
 }
@@ -328,14 +1379,164 @@ impl TranslatorProvider for MockProvider {
         let mut meta = BTreeMap::new();
         meta.insert("provider".to_string(), json!("mock"));
 
+        if req.mode == EXPLAIN_MODE {
+            return Ok(TranslateResult {
+                code: String::new(),
+                explanation: Some(format!("1. Print {:?} with console.log.", req.input)),
+                confidence: Some(0.99),
+                tokens: Some(8),
+                raw_provider_meta: meta,
+                warnings: Vec::new(),
+            });
+        }
         Ok(TranslateResult {
             code: format!("console.log({:?});", req.input),
             explanation: Some("mock translation".to_string()),
             confidence: Some(0.99),
             tokens: Some(8),
             raw_provider_meta: meta,
+            warnings: Vec::new(),
         })
     }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        Ok(vec!["mock".to_string(), "mock-large".to_string()])
+    }
+}
+
+/// Collects the string `field` of each object in the `list` array of a model listing.
+#[cfg(any(feature = "provider-openai-compat", feature = "provider-ollama"))]
+fn model_names(value: &Value, list: &str, field: &str) -> Result<Vec<String>, ProviderError> {
+    if let Some(message) = api_error(value) {
+        return Err(ProviderError::Api(message));
+    }
+    let entries = value.get(list).and_then(Value::as_array).ok_or_else(|| {
+        ProviderError::InvalidResponse(format!("missing array field `{list}` in model list"))
+    })?;
+    Ok(entries
+        .iter()
+        .filter_map(|entry| entry.get(field).and_then(Value::as_str))
+        .map(ToString::to_string)
+        .collect())
+}
+
+/// Reads the string at `path` (e.g. `choices[0].message.content`) of a `what` reply.
+///
+/// An `error` object in the reply becomes [`ProviderError::Api`]; any other
+/// mismatch names the deepest part of `path` that was found and what it held,
+/// so a nonconforming gateway is quick to diagnose.
+#[cfg(any(
+    feature = "provider-http",
+    feature = "provider-openai-compat",
+    feature = "provider-ollama"
+))]
+fn reply_str<'a>(value: &'a Value, path: &str, what: &str) -> Result<&'a str, ProviderError> {
+    if let Some(message) = api_error(value) {
+        return Err(ProviderError::Api(message));
+    }
+    let invalid =
+        |detail: String| ProviderError::InvalidResponse(format!("{what} response {detail}"));
+    let mut current = value;
+    let mut found = String::new();
+    for segment in path.split('.') {
+        let (key, index) = match segment.split_once('[') {
+            Some((key, index)) => (key, index.trim_end_matches(']').parse::<usize>().ok()),
+            None => (segment, None),
+        };
+        current = match current.get(key) {
+            Some(next) => next,
+            None if found.is_empty() => {
+                return Err(invalid(format!(
+                    "has no `{key}` field{}",
+                    key_list(current)
+                )))
+            }
+            None if current.is_object() => {
+                return Err(invalid(format!(
+                    "has `{found}` but no `{key}` in it{}",
+                    key_list(current)
+                )))
+            }
+            None => {
+                return Err(invalid(format!(
+                    "has `{found}` as {}, not an object",
+                    json_kind(current)
+                )))
+            }
+        };
+        found = if found.is_empty() {
+            key.to_string()
+        } else {
+            format!("{found}.{key}")
+        };
+        if let Some(index) = index {
+            current = match current.as_array() {
+                Some(items) => items.get(index).ok_or_else(|| {
+                    invalid(format!(
+                        "has `{found}` with {} items, no [{index}]",
+                        items.len()
+                    ))
+                })?,
+                None => {
+                    return Err(invalid(format!(
+                        "has `{found}` as {}, not an array",
+                        json_kind(current)
+                    )))
+                }
+            };
+            found.push_str(&format!("[{index}]"));
+        }
+    }
+    current.as_str().ok_or_else(|| {
+        invalid(format!(
+            "has `{found}` as {}, not a string",
+            json_kind(current)
+        ))
+    })
+}
+
+/// The message of an API error reply: `{"error": "..."}` or
+/// `{"error": {"message": "...", "type": "..."}}`.
+#[cfg(any(
+    feature = "provider-http",
+    feature = "provider-openai-compat",
+    feature = "provider-ollama"
+))]
+fn api_error(value: &Value) -> Option<String> {
+    let error = value.get("error").filter(|error| !error.is_null())?;
+    let message = match error {
+        Value::String(message) => message.clone(),
+        Value::Object(fields) => match fields.get("message").and_then(Value::as_str) {
+            Some(message) => match ["type", "code"]
+                .iter()
+                .find_map(|key| fields.get(*key).filter(|v| !v.is_null()))
+            {
+                Some(Value::String(kind)) => format!("{message} ({kind})"),
+                Some(kind) => format!("{message} ({kind})"),
+                None => message.to_string(),
+            },
+            None => error.to_string(),
+        },
+        other => other.to_string(),
+    };
+    Some(message)
+}
+
+/// ` (keys: a, b)` for an object with fields, for naming what a reply held instead.
+#[cfg(any(
+    feature = "provider-http",
+    feature = "provider-openai-compat",
+    feature = "provider-ollama"
+))]
+fn key_list(value: &Value) -> String {
+    match value.as_object() {
+        Some(fields) if fields.is_empty() => " (it is an empty object)".to_string(),
+        Some(fields) => {
+            let keys: Vec<&str> = fields.keys().map(String::as_str).take(8).collect();
+            format!(" (keys: {})", keys.join(", "))
+        }
+        None => format!(" (it is {})", json_kind(value)),
+    }
 }
 
 #[cfg(any(
@@ -343,46 +1544,863 @@ impl TranslatorProvider for MockProvider {
     feature = "provider-openai-compat",
     feature = "provider-ollama"
 ))]
-async fn send_json(request: RequestBuilder) -> Result<Value, ProviderError> {
+fn json_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(any(
+    feature = "provider-http",
+    feature = "provider-openai-compat",
+    feature = "provider-ollama"
+))]
+async fn send_json(
+    request: RequestBuilder,
+    endpoint: &str,
+    model: &str,
+    trace_id: Option<&str>,
+    limiter: Option<&RateLimiter>,
+) -> Result<Value, ProviderError> {
+    send_request(request, endpoint, model, trace_id, limiter)
+        .await?
+        .json()
+        .await
+        .map_err(|e| ProviderError::InvalidResponse(e.to_string()))
+}
+
+/// Sends `request` and maps non-success statuses to [`ProviderError`]s.
+#[cfg(any(
+    feature = "provider-http",
+    feature = "provider-openai-compat",
+    feature = "provider-ollama"
+))]
+async fn send_request(
+    mut request: RequestBuilder,
+    endpoint: &str,
+    model: &str,
+    trace_id: Option<&str>,
+    limiter: Option<&RateLimiter>,
+) -> Result<reqwest::Response, ProviderError> {
+    if let Some(limiter) = limiter {
+        limiter.acquire().await?;
+    }
+    tracing::debug!(endpoint, model, trace_id, "sending provider request");
+    if let Some(trace_id) = trace_id {
+        request = request.header("x-beeno-trace-id", trace_id);
+    }
+    let started = std::time::Instant::now();
     let response = request
         .send()
         .await
-        .map_err(|e| ProviderError::Request(e.to_string()))?;
+        .map_err(|e| ProviderError::Network(e.to_string()))?;
     let status = response.status();
+    tracing::info!(
+        endpoint,
+        status = status.as_u16(),
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "provider responded"
+    );
     if !status.is_success() {
-        return Err(ProviderError::Request(format!(
-            "http status {} from provider",
-            status
-        )));
+        return Err(match status.as_u16() {
+            429 => ProviderError::RateLimited {
+                retry_after: response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(parse_retry_after),
+            },
+            401 | 403 => ProviderError::Unauthorized,
+            code if status.is_server_error() => ProviderError::ServerError(code),
+            _ => {
+                // Gateways usually explain a rejected request in an error object.
+                let body = response.json::<Value>().await.ok();
+                match body.as_ref().and_then(api_error) {
+                    Some(message) => ProviderError::Request(format!(
+                        "http status {status} from provider: {message}"
+                    )),
+                    None => ProviderError::Request(format!("http status {} from provider", status)),
+                }
+            }
+        });
     }
-    response
-        .json()
-        .await
-        .map_err(|e| ProviderError::InvalidResponse(e.to_string()))
+    Ok(response)
+}
+
+/// Parses a `Retry-After` header given in delay-seconds form.
+#[cfg(any(
+    feature = "provider-http",
+    feature = "provider-openai-compat",
+    feature = "provider-ollama"
+))]
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// Fields of a JSON-mode reply.
+#[cfg(feature = "provider-openai-compat")]
+#[derive(Debug, PartialEq)]
+struct JsonTranslation {
+    code: String,
+    explanation: Option<String>,
+    warnings: Vec<String>,
 }
 
+/// Reads `{"code", "explanation", "warnings"}` from a JSON-mode reply; `None` if
+/// it is not such an object.
+///
+/// `warnings` is optional, and entries that are not non-empty strings are skipped.
+#[cfg(feature = "provider-openai-compat")]
+fn parse_json_translation(content: &str) -> Option<JsonTranslation> {
+    let value: Value = serde_json::from_str(strip_code_fences(content).as_str()).ok()?;
+    let code = value.get("code")?.as_str()?.to_string();
+    let explanation = value
+        .get("explanation")
+        .and_then(Value::as_str)
+        .filter(|e| !e.trim().is_empty())
+        .map(ToString::to_string);
+    let warnings = value
+        .get("warnings")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(str::trim)
+        .filter(|w| !w.is_empty())
+        .map(ToString::to_string)
+        .collect();
+    Some(JsonTranslation {
+        code,
+        explanation,
+        warnings,
+    })
+}
+
+/// Returns the first fenced code block in `content`, or the trimmed text when there is none.
 fn strip_code_fences(content: &str) -> String {
-    let trimmed = content.trim();
-    if trimmed.starts_with("```") {
-        let mut lines = trimmed.lines();
-        let _ = lines.next();
-        let mut body: Vec<&str> = lines.collect();
-        if matches!(body.last(), Some(last) if last.trim() == "```") {
-            body.pop();
+    fenced_blocks(content)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| content.trim().to_string())
+}
+
+/// Returns the fence character and run length when `line` opens or closes a fence.
+fn fence_marker(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start();
+    let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = trimmed.chars().take_while(|c| *c == marker).count();
+    (len >= 3).then_some((marker, len))
+}
+
+/// Collects the bodies of all ```` ``` ```` / `~~~` fenced blocks in order.
+///
+/// The info string after an opening fence (`ts`, `javascript`, ...) is ignored, a
+/// block closes only on a matching fence at least as long as the opener, a
+/// single-line ```` ```code``` ```` counts as a block, and an unterminated block
+/// runs to the end of the text. Empty blocks are skipped.
+fn fenced_blocks(content: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(char, usize, Vec<&str>)> = None;
+    for line in content.lines() {
+        match (open.as_mut(), fence_marker(line)) {
+            (Some((marker, len, body)), Some((m, l))) if m == *marker && l >= *len => {
+                if line.trim().chars().all(|c| c == m) {
+                    blocks.push(body.join("\n").trim().to_string());
+                    open = None;
+                } else {
+                    body.push(line);
+                }
+            }
+            (Some((_, _, body)), _) => body.push(line),
+            (None, Some((marker, len))) => {
+                let rest = &line.trim_start()[len * marker.len_utf8()..];
+                let closing: String = std::iter::repeat_n(marker, len).collect();
+                match rest.trim_end().strip_suffix(closing.as_str()) {
+                    Some(inline) if !rest.trim().chars().all(|c| c == marker) => {
+                        blocks.push(inline.trim().to_string());
+                    }
+                    _ => open = Some((marker, len, Vec::new())),
+                }
+            }
+            (None, None) => {}
         }
-        body.join("\n").trim().to_string()
-    } else {
-        trimmed.to_string()
     }
+    if let Some((_, _, body)) = open {
+        blocks.push(body.join("\n").trim().to_string());
+    }
+    blocks.retain(|b| !b.is_empty());
+    blocks
+}
+
+/// Number of leading lines the cleanup pass will drop looking for parseable code.
+const MAX_LEADING_PROSE_LINES: usize = 20;
+
+/// Returns the trimmed plan from an [`EXPLAIN_MODE`] reply, rejecting empty ones.
+fn explain_reply(reply: &str) -> Result<String, ProviderError> {
+    let plan = reply.trim();
+    if plan.is_empty() {
+        return Err(ProviderError::InvalidResponse(
+            "provider returned an empty explanation".to_string(),
+        ));
+    }
+    Ok(plan.to_string())
+}
+
+/// Splits a free-text reply into `(code, explanation)` according to `mode`.
+///
+/// When the provider reports the reply as cut off (`truncated`) and its code does
+/// not parse, the partial code comes back as [`ProviderError::Truncated`].
+/// [`CONTINUE_MODE`] replies are fragments and are not parsed.
+#[cfg(any(feature = "provider-openai-compat", feature = "provider-ollama"))]
+fn code_or_plan(
+    mode: &str,
+    reply: &str,
+    truncated: bool,
+) -> Result<(String, Option<String>), ProviderError> {
+    match mode {
+        EXPLAIN_MODE => Ok((String::new(), Some(explain_reply(reply)?))),
+        CONTINUE_MODE if truncated => Err(ProviderError::Truncated(strip_code_fences(reply))),
+        CONTINUE_MODE => Ok((strip_code_fences(reply), None)),
+        _ => match extract_valid_code(reply) {
+            Err(_) if truncated => Err(ProviderError::Truncated(strip_code_fences(reply))),
+            code => Ok((code?, None)),
+        },
+    }
+}
+
+/// Strips fences from model output and checks that the result parses as JS/TS.
+///
+/// When the first attempt does not parse, a single cleanup pass tries the largest
+/// fenced block and then the text with leading prose lines removed.
+fn extract_valid_code(content: &str) -> Result<String, ProviderError> {
+    let code = strip_code_fences(content);
+    if parse_js(&code).is_ok() {
+        return Ok(code);
+    }
+    if let Some(block) = largest_fenced_block(content) {
+        if parse_js(&block).is_ok() {
+            return Ok(block);
+        }
+    }
+    let lines: Vec<&str> = code.lines().collect();
+    for skip in 1..lines.len().min(MAX_LEADING_PROSE_LINES + 1) {
+        let candidate = lines[skip..].join("\n").trim().to_string();
+        if !candidate.is_empty() && parse_js(&candidate).is_ok() {
+            return Ok(candidate);
+        }
+    }
+    Err(ProviderError::InvalidResponse(
+        "model did not return valid code".to_string(),
+    ))
+}
+
+/// Returns the body of the longest fenced block in `content`, if any.
+fn largest_fenced_block(content: &str) -> Option<String> {
+    fenced_blocks(content).into_iter().reduce(|best, block| {
+        if block.len() > best.len() {
+            block
+        } else {
+            best
+        }
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use super::strip_code_fences;
+    use super::*;
+    use crate::types::FileMetadata;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn local_binary_provider_pipes_the_prompt_through_a_command() {
+        let req = TranslateRequest {
+            input: "print hi".to_string(),
+            mode: "eval".to_string(),
+            session_summary: SessionSummary::default(),
+            file_metadata: None,
+            trace_id: None,
+        };
+        let echo = LocalBinaryProvider::new(
+            "sh".to_string(),
+            vec![
+                "-c".to_string(),
+                "read -r line; printf '```ts\\n// %s\\nconsole.log(1);\\n```\\n' \"$line\""
+                    .to_string(),
+            ],
+        )
+        .with_prompt_template(PromptTemplate::new("{input}"));
+        let result = echo.translate(req.clone()).await.expect("translate");
+        assert_eq!(result.code, "// print hi\nconsole.log(1);");
+        assert_eq!(result.raw_provider_meta["command"], json!("sh"));
+
+        let failing = LocalBinaryProvider::new(
+            "sh".to_string(),
+            vec![
+                "-c".to_string(),
+                "echo model missing >&2; exit 3".to_string(),
+            ],
+        );
+        match failing.translate(req).await {
+            Err(ProviderError::Request(message)) => {
+                assert!(message.contains("model missing"), "{message}");
+                assert!(message.contains('3'), "{message}");
+            }
+            other => panic!("expected request error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn loopback_endpoints_are_detected_from_the_parsed_host() {
+        for local in [
+            "http://127.0.0.1:11434/api/generate",
+            "http://localhost:8080/translate",
+            "http://[::1]:11434",
+            "http://127.0.0.2/",
+        ] {
+            assert!(is_loopback_endpoint(local), "{local}");
+        }
+        for remote in [
+            "https://api.openai.com/v1/chat/completions",
+            "http://localhost.example.com/",
+            "http://10.0.0.5:11434",
+            "not a url",
+        ] {
+            assert!(!is_loopback_endpoint(remote), "{remote}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_spaces_rapid_requests() {
+        let limiter =
+            RateLimiter::per_minute(1_200, Duration::from_secs(1)).expect("limit enabled");
+        let started = Instant::now();
+        limiter.acquire().await.expect("first");
+        limiter.acquire().await.expect("second");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let strict = RateLimiter::per_minute(1, Duration::from_millis(10)).expect("limit enabled");
+        strict.acquire().await.expect("first is immediate");
+        assert!(matches!(
+            strict.acquire().await,
+            Err(ProviderError::RateLimited {
+                retry_after: Some(_)
+            })
+        ));
+        assert!(RateLimiter::per_minute(0, Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn custom_prompt_template_renders_placeholders() {
+        let template =
+            PromptTemplate::new("Target Deno 2.x. [{mode}] {file}: {input} {unknown} {summary}");
+        let req = TranslateRequest {
+            input: "print {mode}".to_string(),
+            mode: "eval".to_string(),
+            session_summary: SessionSummary::default(),
+            file_metadata: Some(FileMetadata {
+                path: Some("main.ts".to_string()),
+                language_hint: None,
+            }),
+            trace_id: None,
+        };
+        let rendered = template.render(&req);
+        assert!(rendered.starts_with("Target Deno 2.x. [eval] main.ts: print {mode} {unknown} "));
+        assert!(rendered.ends_with(" none"));
+        assert_eq!(template.unknown_placeholders(), vec!["unknown"]);
+        assert!(PromptTemplate::new(OLLAMA_PROMPT_TEMPLATE)
+            .unknown_placeholders()
+            .is_empty());
+    }
+
+    #[test]
+    fn prompts_render_session_context_without_debug_noise() {
+        let req = TranslateRequest {
+            input: "double it".to_string(),
+            mode: "repl".to_string(),
+            session_summary: SessionSummary {
+                symbols: vec!["total".to_string()],
+                imports: vec!["import { z } from 'npm:zod';".to_string()],
+                recent_intents: vec!["const total = 1;".to_string(), "print total".to_string()],
+                ..SessionSummary::default()
+            },
+            file_metadata: None,
+            trace_id: None,
+        };
+        let rendered = PromptTemplate::new(OPENAI_PROMPT_TEMPLATE).render(&req);
+        assert!(!rendered.contains("SessionSummary {"));
+        assert!(rendered.contains(
+            "Session context:\n- Defined symbols: total\n- Imports:\n  - import { z } from 'npm:zod';\n- Recent inputs:\n  - const total = 1;\n  - print total\nInput: double it"
+        ));
+
+        let rendered = PromptTemplate::new("{summary}|{input}")
+            .with_max_context_chars(20)
+            .render(&req);
+        assert_eq!(rendered, "- Defined symbols: …|double it");
+    }
+
+    #[test]
+    fn extracts_fenced_code_surrounded_by_prose() {
+        let response = "Here is the code:\n\n```ts\nconst total = [1, 2, 3].reduce((a, b) => a + b, 0);\nconsole.log(total);\n```\n\nThis sums the list and prints it.";
+        assert_eq!(
+            extract_valid_code(response).expect("valid code"),
+            "const total = [1, 2, 3].reduce((a, b) => a + b, 0);\nconsole.log(total);"
+        );
+    }
+
+    #[test]
+    fn drops_leading_prose_without_fences() {
+        let response = "Sure! Here is the code:\nconsole.log(new Date().toISOString());";
+        assert_eq!(
+            extract_valid_code(response).expect("valid code"),
+            "console.log(new Date().toISOString());"
+        );
+        let err = extract_valid_code("I cannot help with that request.").expect_err("prose only");
+        assert_eq!(
+            err.to_string(),
+            "provider response invalid: model did not return valid code"
+        );
+    }
+
+    #[cfg(any(
+        feature = "provider-http",
+        feature = "provider-openai-compat",
+        feature = "provider-ollama"
+    ))]
+    #[test]
+    fn retry_after_parses_seconds() {
+        assert_eq!(parse_retry_after(" 12 "), Some(Duration::from_secs(12)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        let err = ProviderError::RateLimited {
+            retry_after: Some(Duration::from_secs(5)),
+        };
+        assert_eq!(
+            err.to_string(),
+            "provider rate limited the request, retry in 5s"
+        );
+    }
+
+    struct FailingProvider(fn() -> ProviderError);
+
+    #[async_trait]
+    impl TranslatorProvider for FailingProvider {
+        async fn translate(
+            &self,
+            _req: TranslateRequest,
+        ) -> Result<TranslateResult, ProviderError> {
+            Err((self.0)())
+        }
+    }
+
+    /// Replies with typed code, echoing the rendered prompt as the explanation.
+    struct TypedReplyProvider;
+
+    #[async_trait]
+    impl TranslatorProvider for TypedReplyProvider {
+        async fn translate(&self, req: TranslateRequest) -> Result<TranslateResult, ProviderError> {
+            let mut result =
+                TranslateResult::new("const n: number = 1;\nconsole.log(n as number);");
+            result.explanation = Some(PromptTemplate::new(OLLAMA_PROMPT_TEMPLATE).render(&req));
+            Ok(result)
+        }
+    }
+
+    #[tokio::test]
+    async fn javascript_target_transpiles_typed_replies() {
+        let provider = JavaScriptTargetProvider::new(Box::new(TypedReplyProvider));
+        let result = provider.translate(request()).await.expect("translates");
+        assert!(result
+            .explanation
+            .as_deref()
+            .unwrap()
+            .starts_with("Translate to executable JavaScript (no TypeScript syntax) only."));
+        assert_eq!(result.code.trim(), "const n = 1;\nconsole.log(n);");
+        assert_eq!(
+            result.raw_provider_meta.get("transpiled"),
+            Some(&json!(true))
+        );
+
+        let plain = JavaScriptTargetProvider::new(Box::new(MockProvider))
+            .translate(request())
+            .await
+            .expect("translates");
+        assert!(!plain.raw_provider_meta.contains_key("transpiled"));
+    }
+
+    fn request() -> TranslateRequest {
+        TranslateRequest {
+            input: "print hello".to_string(),
+            mode: "eval".to_string(),
+            session_summary: Default::default(),
+            file_metadata: None,
+            trace_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn fallback_moves_past_transient_failures() {
+        let chain = FallbackProvider::new(vec![
+            Box::new(FailingProvider(|| {
+                ProviderError::Network("connection refused".to_string())
+            })),
+            Box::new(MockProvider),
+        ]);
+        let result = chain.translate(request()).await.expect("second succeeds");
+        assert!(result.code.contains("console.log"));
+        assert_eq!(result.raw_provider_meta["fallback_index"], json!(1));
+        assert_eq!(result.raw_provider_meta["provider"], json!("mock"));
+    }
+
+    #[tokio::test]
+    async fn fallback_stops_on_invalid_response() {
+        let chain = FallbackProvider::new(vec![
+            Box::new(FailingProvider(|| {
+                ProviderError::InvalidResponse("missing code".to_string())
+            })),
+            Box::new(MockProvider),
+        ]);
+        let err = chain
+            .translate(request())
+            .await
+            .expect_err("must not fall back");
+        assert!(matches!(err, ProviderError::InvalidResponse(_)));
+
+        let empty = FallbackProvider::new(Vec::new());
+        assert!(empty.translate(request()).await.is_err());
+    }
+
+    #[test]
+    fn capabilities_warn_when_max_tokens_exceeds_context() {
+        assert_eq!(known_context_window("gpt-4o-mini"), Some(128_000));
+        assert_eq!(known_context_window("openai/gpt-4.1-mini"), Some(1_047_576));
+        assert_eq!(known_context_window("llama3:8b"), Some(8_192));
+        assert_eq!(known_context_window("mystery-model"), None);
+
+        let caps = ProviderCapabilities {
+            max_context_tokens: Some(8_192),
+            ..ProviderCapabilities::default()
+        };
+        assert!(caps.max_tokens_warning(16_000).is_some());
+        assert!(caps.max_tokens_warning(1_024).is_none());
+        assert!(MockProvider
+            .capabilities()
+            .max_tokens_warning(u32::MAX)
+            .is_none());
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "provider-ollama")]
+    async fn ollama_streaming_joins_chunks_split_across_reads() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.expect("accept");
+            let mut request = vec![0u8; 8192];
+            let _ = socket.read(&mut request).await;
+            let head = "HTTP/1.1 200 OK\r\ncontent-type: application/x-ndjson\r\nconnection: close\r\n\r\n";
+            let _ = socket.write_all(head.as_bytes()).await;
+            // The second object is cut mid-line to exercise buffering.
+            for part in [
+                "{\"response\":\"console.log(\",\"done\":false}\n{\"respon",
+                "se\":\"1);\",\"done\":false}\n",
+                "{\"response\":\"\",\"done\":true,\"eval_count\":7}\n",
+            ] {
+                let _ = socket.write_all(part.as_bytes()).await;
+                let _ = socket.flush().await;
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        let provider = OllamaProvider::new(
+            format!("http://{addr}/api/generate"),
+            "llama3".to_string(),
+            0.1,
+            512,
+        )
+        .with_streaming(true);
+        assert!(provider.capabilities().streaming);
+        let result = provider.translate(request()).await.expect("translate");
+        assert_eq!(result.code, "console.log(1);");
+        assert_eq!(result.tokens, Some(7));
+    }
+
+    #[tokio::test]
+    async fn fallback_lists_models_from_providers_that_can() {
+        let chain = FallbackProvider::new(vec![
+            Box::new(FailingProvider(|| {
+                ProviderError::Network("unused".to_string())
+            })),
+            Box::new(MockProvider),
+            Box::new(MockProvider),
+        ]);
+        assert_eq!(
+            chain.list_models().await.expect("mock lists"),
+            vec!["mock", "mock-large"]
+        );
+
+        let unsupported = FallbackProvider::new(vec![Box::new(FailingProvider(|| {
+            ProviderError::Network("unused".to_string())
+        }))]);
+        assert!(unsupported.list_models().await.is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "provider-openai-compat", feature = "provider-ollama"))]
+    fn model_list_urls_sit_next_to_the_generation_endpoint() {
+        let openai = |endpoint: &str| {
+            OpenAICompatProvider::new(endpoint.to_string(), None, "gpt-4o".to_string(), 0.1, 512)
+                .models_url()
+        };
+        assert_eq!(
+            openai("https://api.openai.com/v1/chat/completions"),
+            "https://api.openai.com/v1/models"
+        );
+        assert_eq!(
+            openai("http://localhost:1234/v1/"),
+            "http://localhost:1234/v1/models"
+        );
+
+        let ollama = OllamaProvider::new(
+            "http://127.0.0.1:11434/api/generate".to_string(),
+            "llama3".to_string(),
+            0.1,
+            512,
+        );
+        assert_eq!(ollama.tags_url(), "http://127.0.0.1:11434/api/tags");
+
+        let listing = json!({ "models": [{ "name": "llama3:8b" }, { "name": "qwen2.5-coder" }] });
+        assert_eq!(
+            model_names(&listing, "models", "name").expect("names"),
+            vec!["llama3:8b", "qwen2.5-coder"]
+        );
+        assert!(model_names(&json!({}), "data", "id").is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "provider-openai-compat", feature = "provider-ollama"))]
+    fn fallback_capabilities_are_the_common_subset() {
+        let chain = FallbackProvider::new(vec![
+            Box::new(OpenAICompatProvider::new(
+                "http://127.0.0.1:1".to_string(),
+                None,
+                "gpt-4o".to_string(),
+                0.1,
+                512,
+            )),
+            Box::new(OllamaProvider::new(
+                "http://127.0.0.1:1".to_string(),
+                "llama3".to_string(),
+                0.1,
+                512,
+            )),
+        ]);
+        let caps = chain.capabilities();
+        assert_eq!(caps.max_context_tokens, Some(8_192));
+        assert!(!caps.supports_system_prompt);
+        assert!(!caps.streaming);
+    }
+
+    #[test]
+    #[cfg(feature = "provider-azure")]
+    fn azure_routes_by_deployment_and_requires_one() {
+        let provider = AzureOpenAIProvider::new(
+            "https://res.openai.azure.com/".to_string(),
+            "gpt4o-prod".to_string(),
+            AZURE_DEFAULT_API_VERSION.to_string(),
+            Some("key".to_string()),
+            0.1,
+            512,
+        );
+        assert_eq!(
+            provider.completions_url().expect("url"),
+            "https://res.openai.azure.com/openai/deployments/gpt4o-prod/chat/completions?api-version=2024-10-21"
+        );
+
+        let missing = AzureOpenAIProvider {
+            deployment: String::new(),
+            ..provider
+        };
+        let err = missing.completions_url().expect_err("deployment required");
+        assert!(err.to_string().contains("llm.deployment"));
+    }
+
+    #[test]
+    #[cfg(feature = "provider-openai-compat")]
+    fn json_mode_reply_yields_code_and_explanation() {
+        let reply = r#"{"code": "console.log(1 + 1);", "explanation": "Prints two."}"#;
+        assert_eq!(
+            parse_json_translation(reply),
+            Some(JsonTranslation {
+                code: "console.log(1 + 1);".to_string(),
+                explanation: Some("Prints two.".to_string()),
+                warnings: Vec::new(),
+            })
+        );
+        let fenced = "```json\n{\"code\": \"const a = 1;\"}\n```";
+        assert_eq!(
+            parse_json_translation(fenced),
+            Some(JsonTranslation {
+                code: "const a = 1;".to_string(),
+                explanation: None,
+                warnings: Vec::new(),
+            })
+        );
+        assert_eq!(parse_json_translation("console.log(1);"), None);
+        assert_eq!(parse_json_translation(r#"{"source": "x"}"#), None);
+    }
+
+    #[test]
+    #[cfg(feature = "provider-openai-compat")]
+    fn json_mode_reply_carries_model_warnings() {
+        let content = r#"{"code": "await fetch('https://example.com');", "explanation": "Fetches a page.", "warnings": ["requires network access", " ", 3, "untested"]}"#;
+        let reply =
+            json!({"choices": [{"message": {"content": content}, "finish_reason": "stop"}]});
+        let translated = chat_completion_result(reply, true, "eval").expect("translates");
+        assert_eq!(translated.code, "await fetch('https://example.com');");
+        assert_eq!(
+            translated.warnings,
+            vec!["requires network access", "untested"]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "provider-openai-compat")]
+    fn error_object_replies_are_reported_as_api_errors() {
+        let reply = json!({"error": {"message": "model `gpt-x` does not exist", "type": "invalid_request_error"}});
+        let err = chat_completion_result(reply, false, "eval").expect_err("api error");
+        assert!(
+            matches!(&err, ProviderError::Api(message) if message == "model `gpt-x` does not exist (invalid_request_error)"),
+            "{err}"
+        );
+        let err = reply_str(&json!({"error": "model not found"}), "response", "Ollama")
+            .expect_err("api error");
+        assert_eq!(
+            err.to_string(),
+            "provider returned an error: model not found"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "provider-openai-compat")]
+    fn shape_mismatches_name_what_the_reply_held() {
+        let describe = |reply: Value| {
+            chat_completion_result(reply, false, "eval")
+                .expect_err("shape mismatch")
+                .to_string()
+        };
+        assert_eq!(
+            describe(json!({"choices": [{"text": "1 + 1", "index": 0}]})),
+            "provider response invalid: OpenAI-compatible response has `choices[0]` but no `message` in it (keys: index, text)"
+        );
+        assert_eq!(
+            describe(json!({"choices": []})),
+            "provider response invalid: OpenAI-compatible response has `choices` with 0 items, no [0]"
+        );
+        assert_eq!(
+            describe(json!({"output": "1 + 1"})),
+            "provider response invalid: OpenAI-compatible response has no `choices` field (keys: output)"
+        );
+        assert_eq!(
+            describe(json!({"choices": [{"message": {"content": null}}]})),
+            "provider response invalid: OpenAI-compatible response has `choices[0].message.content` as null, not a string"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "provider-openai-compat")]
+    fn length_truncated_reply_returns_partial_code() {
+        let reply = |content: &str, finish: &str| json!({"choices": [{"message": {"content": content}, "finish_reason": finish}]});
+        let cut = chat_completion_result(reply("```ts\nconst a = [1,", "length"), false, "eval")
+            .expect_err("cut off");
+        assert!(matches!(cut, ProviderError::Truncated(partial) if partial == "const a = [1,"));
+        assert!(matches!(
+            chat_completion_result(reply("const a = [1,", "stop"), false, "eval"),
+            Err(ProviderError::InvalidResponse(_))
+        ));
+        let whole = chat_completion_result(reply("const a = 1;", "length"), false, "eval");
+        assert_eq!(whole.expect("parses").code, "const a = 1;");
+        let rest = chat_completion_result(reply(" 2];", "stop"), false, CONTINUE_MODE);
+        assert_eq!(rest.expect("fragment").code, "2];");
+    }
+
+    #[test]
+    #[cfg(feature = "provider-openai-compat")]
+    fn explain_mode_returns_a_plan_instead_of_code() {
+        let chat = OpenAICompatRequest::new("m".into(), "u".into(), 0.0, 64, true, true);
+        assert_eq!(chat.messages[0].content, OPENAI_EXPLAIN_PROMPT);
+        assert!(chat.response_format.is_none());
+
+        let reply =
+            json!({"choices": [{"message": {"content": "1. Read the file.\n2. Count lines."}}]});
+        let result = chat_completion_result(reply, true, EXPLAIN_MODE).expect("plan");
+        assert_eq!(result.code, "");
+        assert_eq!(
+            result.explanation.as_deref(),
+            Some("1. Read the file.\n2. Count lines.")
+        );
+        let empty = json!({"choices": [{"message": {"content": "  "}}]});
+        assert!(chat_completion_result(empty, false, EXPLAIN_MODE).is_err());
+
+        let req = TranslateRequest {
+            mode: EXPLAIN_MODE.to_string(),
+            input: "count lines".to_string(),
+            ..request()
+        };
+        let rendered = PromptTemplate::new("{input}").render(&req);
+        assert!(rendered.starts_with("Explain how you would write"));
+        assert!(rendered.ends_with("Input: count lines"));
+    }
 
     #[test]
     fn strips_markdown_fence() {
         let src = "```ts\nconsole.log('x');\n```";
         assert_eq!(strip_code_fences(src), "console.log('x');");
     }
+
+    #[test]
+    fn strips_first_fence_after_leading_prose() {
+        let src = "Sure! Here you go:\n```javascript\nconsole.log(1);\n```\nLet me know.";
+        assert_eq!(strip_code_fences(src), "console.log(1);");
+    }
+
+    #[test]
+    fn strips_tilde_fences() {
+        let src = "~~~ts\nconst a: number = 1;\n~~~";
+        assert_eq!(strip_code_fences(src), "const a: number = 1;");
+    }
+
+    #[test]
+    fn returns_first_of_multiple_blocks() {
+        let src =
+            "First:\n```ts\nconst a = 1;\n```\nThen:\n```ts\nconst b = 2;\nconsole.log(b);\n```";
+        assert_eq!(strip_code_fences(src), "const a = 1;");
+        assert_eq!(
+            largest_fenced_block(src).as_deref(),
+            Some("const b = 2;\nconsole.log(b);")
+        );
+    }
+
+    #[test]
+    fn handles_inline_unterminated_and_nested_fences() {
+        assert_eq!(strip_code_fences("```console.log(2)```"), "console.log(2)");
+        assert_eq!(
+            strip_code_fences("```ts\nconsole.log(3);"),
+            "console.log(3);"
+        );
+        let nested = "~~~md\n```ts\nx\n```\n~~~";
+        assert_eq!(strip_code_fences(nested), "```ts\nx\n```");
+    }
+
+    #[test]
+    fn returns_raw_text_without_fences() {
+        assert_eq!(strip_code_fences("  console.log(4);\n"), "console.log(4);");
+    }
 }
@@ -1,36 +1,216 @@
 use crate::engine::{
-    execute_request, ContextSummarizer, DefaultRiskPolicy, Engine, EngineError,
+    classify_input, needs_confirmation, permission_args, Classification, ClassificationError,
+    ContextSummarizer, DefaultRiskPolicy, Engine, EngineError, InputKind, LlmContextSummarizer,
     RollingContextSummarizer,
 };
-use crate::providers::TranslatorProvider;
-use crate::server::ServerManager;
-use crate::types::{DenoPermissions, ExecutionRequest, ServerContext, SessionSummary};
-use std::io::{self, Write};
+use crate::progress::{timing_line, Spinner};
+use crate::providers::{ProviderError, TranslatorProvider};
+use crate::server::{
+    offer_orphan_cleanup, server_pid_dir, server_pid_path, server_source_warning, ServerKillSwitch,
+    ServerManager, DEFAULT_MAX_RESTARTS, DEFAULT_RESTART_WINDOW,
+};
+use crate::types::{
+    new_trace_id, parse_env_assignment, AppConfig, ConfirmMode, DenoPermissions, ExecutionOutput,
+    ExecutionRequest, ServerContext, SessionSummary, Verbosity,
+};
+use crate::util::{print_warnings, redact};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub async fn run_repl<P: TranslatorProvider>(
-    provider: P,
-    confirm_risky: bool,
-) -> anyhow::Result<()> {
-    let policy = DefaultRiskPolicy::default();
-    let engine = Engine::new(provider, policy);
-    let mut summarizer = RollingContextSummarizer::new(8);
-    let mut last_generated: Option<String> = None;
-    let mut last_nl_input: Option<String> = None;
-    let mut server_manager = ServerManager::default();
-    let mut server_port: u16 = 8080;
+/// REPL state carried between sessions by `repl.persist_session` / `--resume`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub summary: SessionSummary,
+    pub last_generated: Option<String>,
+    pub last_nl_input: Option<String>,
+}
 
-    println!("Beeno REPL");
-    println!("Type /help for commands. Use /exit to quit.");
-    println!("Slash command layout is primary; ':' aliases still work.");
-    loop {
-        print!("beeno> ");
+/// Location of the saved session, `~/.beeno/session.json`.
+pub fn session_path() -> Option<PathBuf> {
+    std::env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".beeno").join("session.json"))
+}
+
+/// Reads a saved session; a missing file is `None` and a corrupt one warns and is ignored.
+pub fn load_session(path: &Path) -> Option<SavedSession> {
+    let raw = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&raw) {
+        Ok(session) => Some(session),
+        Err(e) => {
+            eprintln!(
+                "warning: ignoring corrupt session file {}: {e}",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+/// Writes `session` to `path`, dropping server state that cannot be resumed and
+/// [redacting](crate::util::redact) secrets.
+pub fn save_session(path: &Path, session: &SavedSession) -> io::Result<()> {
+    let mut session = session.clone();
+    session.summary.server = None;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, redact(&serde_json::to_string_pretty(&session)?))
+}
+
+/// Where the REPL reads its lines from: stdin, or a `--script` file replayed first.
+#[derive(Debug, Default)]
+pub struct ReplInput {
+    script: Option<io::Cursor<String>>,
+    then_interactive: bool,
+}
+
+impl ReplInput {
+    /// Replays `script` as if typed, skipping blank and `#` comment lines; when it
+    /// runs out the REPL exits, or keeps reading stdin if `then_interactive`.
+    pub fn script(script: impl Into<String>, then_interactive: bool) -> Self {
+        Self {
+            script: Some(io::Cursor::new(script.into())),
+            then_interactive,
+        }
+    }
+
+    /// Prints `prompt` and returns the next line, or `None` at the end of input.
+    fn next_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        if let Some(script) = &mut self.script {
+            let mut line = String::new();
+            while script.read_line(&mut line)? > 0 {
+                let command = line.trim();
+                if !command.is_empty() && !command.starts_with('#') {
+                    println!("{prompt}{command}");
+                    return Ok(Some(command.to_string()));
+                }
+                line.clear();
+            }
+            self.script = None;
+            if !self.then_interactive {
+                return Ok(None);
+            }
+        }
+        print!("{prompt}");
         io::stdout().flush()?;
         let mut line = String::new();
         if io::stdin().read_line(&mut line)? == 0 {
-            break;
+            return Ok(None);
+        }
+        Ok(Some(line.trim().to_string()))
+    }
+
+    /// Reads a `/paste` block from the script while it lasts, otherwise from stdin.
+    fn paste_block(&mut self) -> io::Result<String> {
+        match &mut self.script {
+            Some(script) => {
+                let block = read_paste_block(script)?;
+                if !block.is_empty() {
+                    println!("{block}");
+                }
+                Ok(block)
+            }
+            None => read_paste_block(&mut io::stdin().lock()),
+        }
+    }
+}
+
+/// Provider/model pair the REPL is currently translating with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderSelection {
+    pub provider: String,
+    pub model: String,
+}
+
+/// Runs the interactive shell.
+///
+/// `cfg` supplies risk confirmation, size limits, and server timeouts.
+/// `build_provider` is called to rebuild the provider when `/provider` or
+/// `/model` switches the active selection; the session summary is kept.
+/// Lines come from `input`, so a script drives the same commands as typing.
+pub async fn run_repl<P, F>(
+    provider: P,
+    cfg: &AppConfig,
+    selection: ProviderSelection,
+    build_provider: F,
+    mut input: ReplInput,
+) -> anyhow::Result<()>
+where
+    P: TranslatorProvider,
+    F: Fn(&ProviderSelection) -> anyhow::Result<P>,
+{
+    let policy = DefaultRiskPolicy::default().with_allow_risky(cfg.policy.allow_risky.clone());
+    let mut engine = Engine::new(provider, policy)
+        .with_limits(cfg.limits.clone())
+        .with_auto_continue(cfg.llm.auto_continue)
+        .with_prelude(cfg.runtime.prelude.clone());
+    let settings = InputSettings {
+        confirm_mode: cfg.policy.effective_confirm_mode(),
+        translate_timeout: Duration::from_millis(cfg.timeouts.translate_ms),
+        env: cfg.runtime.resolved_env(|k| std::env::var(k).ok()),
+        deno_config: cfg.runtime.deno_config.clone(),
+        import_map: cfg.runtime.import_map.clone(),
+        no_remote: cfg.runtime.no_remote,
+        cached_only: cfg.runtime.cached_only,
+        permissions: cfg.permissions.for_origin("repl"),
+        verbosity: cfg.verbosity,
+    };
+    let mut selection = selection;
+    let mut summarizer: Box<dyn ContextSummarizer + '_> = if cfg.repl.narrative_every > 0 {
+        Box::new(
+            LlmContextSummarizer::new(build_provider(&selection)?, 8, cfg.repl.narrative_every)
+                .with_char_limits(cfg.repl.max_entry_chars, cfg.repl.max_summary_chars),
+        )
+    } else {
+        Box::new(
+            RollingContextSummarizer::new(8)
+                .with_char_limits(cfg.repl.max_entry_chars, cfg.repl.max_summary_chars),
+        )
+    };
+    let mut last_generated: Option<String> = None;
+    let mut last_nl_input: Option<String> = None;
+    let persist = cfg.repl.persist_session || cfg.repl.resume;
+    let saved_path = session_path();
+    if cfg.repl.resume {
+        if let Some(saved) = saved_path.as_deref().and_then(load_session) {
+            summarizer.restore(saved.summary);
+            last_generated = saved.last_generated;
+            last_nl_input = saved.last_nl_input;
+            if cfg.verbosity.chatty() {
+                println!("resumed previous session");
+            }
         }
-        let line = line.trim();
+    }
+    let mut last_classification: Option<Classification> = None;
+    if let Some(dir) = server_pid_dir() {
+        offer_orphan_cleanup(&dir)?;
+    }
+    let mut server_manager = ServerManager::default()
+        .with_stop_grace(Duration::from_millis(cfg.timeouts.server_grace_ms))
+        .with_env(settings.env.clone())
+        .with_deno_config(settings.deno_config.clone(), settings.import_map.clone())
+        .with_remote_imports(settings.no_remote, settings.cached_only)
+        .with_pid_file(server_pid_path());
+    let mut server_port: u16 = 8080;
+
+    if cfg.verbosity.chatty() {
+        println!("Beeno REPL");
+        println!("Type /help for commands. Use /exit to quit.");
+        println!("Slash command layout is primary; ':' aliases still work.");
+    }
+    let at_prompt = Arc::new(AtomicBool::new(false));
+    let interrupt_watcher =
+        exit_on_prompt_interrupt(Arc::clone(&at_prompt), server_manager.kill_switch());
+    while let Some(line) = prompt_line(&mut input, &at_prompt)? {
+        let line = line.as_str();
         if line.is_empty() {
             continue;
         }
@@ -41,6 +221,22 @@ pub async fn run_repl<P: TranslatorProvider>(
         if line == "/exit" || line == "/quit" || line == ":exit" || line == ":quit" {
             break;
         }
+        if line == "/reset" || line == ":reset" {
+            summarizer.restore(SessionSummary::default());
+            last_generated = None;
+            last_nl_input = None;
+            if let Some(path) = &saved_path {
+                match fs::remove_file(path) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => println!("warning: could not remove {}: {e}", path.display()),
+                }
+            }
+            if cfg.verbosity.chatty() {
+                println!("session context cleared");
+            }
+            continue;
+        }
         if line == "/clear" || line == ":clear" {
             print!("\x1B[2J\x1B[1;1H");
             io::stdout().flush()?;
@@ -57,23 +253,152 @@ pub async fn run_repl<P: TranslatorProvider>(
         }
 
         if line == "/context" || line == ":context" {
-            let ctx = current_summary_with_server(&mut summarizer, &mut server_manager);
+            let ctx = current_summary_with_server(summarizer.as_mut(), &mut server_manager);
+            println!(
+                "provider: {} (model: {})",
+                selection.provider, selection.model
+            );
+            println!("capabilities: {:?}", engine.capabilities());
+            if let Some(classification) = &last_classification {
+                println!("last input: {}", classification.reason);
+            }
             println!("session summary: {ctx:?}");
             continue;
         }
 
+        if let Some(arg) = line
+            .strip_prefix("/provider")
+            .or_else(|| line.strip_prefix(":provider"))
+        {
+            let name = arg.trim();
+            if name.is_empty() {
+                println!("provider: {}", selection.provider);
+                continue;
+            }
+            let next = ProviderSelection {
+                provider: name.to_string(),
+                model: selection.model.clone(),
+            };
+            switch_provider(&mut engine, &mut selection, next, &build_provider);
+            continue;
+        }
+
+        if let Some(arg) = line
+            .strip_prefix("/model")
+            .or_else(|| line.strip_prefix(":model"))
+        {
+            let name = arg.trim();
+            if name.is_empty() {
+                println!("model: {}", selection.model);
+                continue;
+            }
+            let next = ProviderSelection {
+                provider: selection.provider.clone(),
+                model: cfg.llm.resolve_model(name).to_string(),
+            };
+            switch_provider(&mut engine, &mut selection, next, &build_provider);
+            continue;
+        }
+
         if line == "/serve-status" || line == ":serve-status" {
             if let Some(status) = server_manager.status() {
                 println!("server running on {} (mode: {})", status.url, status.mode);
+            } else if let Some(err) = server_manager.last_error() {
+                println!("server not running (last error: {err})");
             } else {
                 println!("server not running");
             }
             continue;
         }
 
+        if line == "/serve-logs" || line == ":serve-logs" {
+            print_server_logs(&server_manager);
+            continue;
+        }
+
         if line == "/serve-stop" || line == ":serve-stop" {
             server_manager.stop().await?;
-            println!("server stopped");
+            if cfg.verbosity.chatty() {
+                println!("server stopped");
+            }
+            continue;
+        }
+
+        if matches!(
+            line,
+            "/serve-undo" | ":serve-undo" | "/serve-redo" | ":serve-redo"
+        ) {
+            let undo = line.ends_with("undo");
+            let result = if undo {
+                server_manager.undo().await
+            } else {
+                server_manager.redo().await
+            };
+            match result {
+                Ok(Some(status)) => {
+                    let (undo_left, redo_left) = server_manager.history_depth();
+                    println!(
+                        "server restarted with {} source: {} ({undo_left} undo, {redo_left} redo left)",
+                        if undo { "previous" } else { "next" },
+                        status.url
+                    );
+                }
+                Ok(None) => println!("nothing to {}", if undo { "undo" } else { "redo" }),
+                Err(e) => println!("error: {e}"),
+            }
+            continue;
+        }
+
+        if line == "/serve-env-clear" || line == ":serve-env-clear" {
+            server_manager.clear_extra_env();
+            if cfg.verbosity.chatty() {
+                println!("server env cleared; applied on next start or hotfix");
+            }
+            continue;
+        }
+
+        if let Some(args) = line
+            .strip_prefix("/serve-env")
+            .or_else(|| line.strip_prefix(":serve-env"))
+        {
+            update_server_env(&mut server_manager, args.trim());
+            continue;
+        }
+
+        if let Some(args) = line
+            .strip_prefix("/serve-perms")
+            .or_else(|| line.strip_prefix(":serve-perms"))
+        {
+            update_server_permissions(&mut server_manager, args.trim());
+            continue;
+        }
+
+        if let Some(arg) = line
+            .strip_prefix("/serve-supervise")
+            .or_else(|| line.strip_prefix(":serve-supervise"))
+        {
+            match arg.trim() {
+                "on" => {
+                    server_manager.set_supervision(DEFAULT_MAX_RESTARTS, DEFAULT_RESTART_WINDOW);
+                    println!(
+                        "server supervision on: restart on crash up to {DEFAULT_MAX_RESTARTS} times per {}s",
+                        DEFAULT_RESTART_WINDOW.as_secs()
+                    );
+                }
+                "off" => {
+                    server_manager.set_supervision(0, DEFAULT_RESTART_WINDOW);
+                    println!("server supervision off");
+                }
+                "" => match server_manager.supervision() {
+                    Some(s) => println!(
+                        "server supervision on: up to {} restarts per {}s",
+                        s.max_restarts,
+                        s.window.as_secs()
+                    ),
+                    None => println!("server supervision off"),
+                },
+                _ => println!("usage: /serve-supervise [on|off]"),
+            }
             continue;
         }
 
@@ -83,11 +408,15 @@ pub async fn run_repl<P: TranslatorProvider>(
         {
             let raw = value.trim();
             match raw.parse::<u16>() {
-                Ok(port) if port > 0 => {
+                Ok(0) => {
+                    server_port = 0;
+                    println!("server port set to 0; a free port is picked on each start");
+                }
+                Ok(port) => {
                     server_port = port;
                     println!("server port set to {server_port}");
                 }
-                _ => println!("invalid port; usage: /serve-port <1-65535>"),
+                _ => println!("invalid port; usage: /serve-port <0-65535>"),
             }
             continue;
         }
@@ -103,12 +432,13 @@ pub async fn run_repl<P: TranslatorProvider>(
             }
             match start_server_from_input(
                 &engine,
-                &mut summarizer,
+                summarizer.as_mut(),
                 &mut server_manager,
                 src,
                 "force_js",
                 server_port,
                 "js",
+                settings.verbosity,
             )
             .await
             {
@@ -121,6 +451,36 @@ pub async fn run_repl<P: TranslatorProvider>(
             continue;
         }
 
+        if let Some(hint) = line
+            .strip_prefix("/serve-restart-nl")
+            .or_else(|| line.strip_prefix(":serve-restart-nl"))
+        {
+            let Some(previous) = &last_nl_input else {
+                println!("no previous pseudocode input to regenerate the server from");
+                continue;
+            };
+            let src = refine_prompt(previous, hint.trim());
+            match start_server_from_input(
+                &engine,
+                summarizer.as_mut(),
+                &mut server_manager,
+                &src,
+                "force_nl",
+                server_port,
+                "nl-restart",
+                settings.verbosity,
+            )
+            .await
+            {
+                Ok(url) => {
+                    last_nl_input = Some(src);
+                    println!("server regenerated: {url}");
+                }
+                Err(e) => print_repl_error(e),
+            }
+            continue;
+        }
+
         if let Some(text) = line
             .strip_prefix("/serve-nl")
             .or_else(|| line.strip_prefix(":serve-nl"))
@@ -132,12 +492,13 @@ pub async fn run_repl<P: TranslatorProvider>(
             }
             match start_server_from_input(
                 &engine,
-                &mut summarizer,
+                summarizer.as_mut(),
                 &mut server_manager,
                 src,
                 "force_nl",
                 server_port,
                 "nl",
+                settings.verbosity,
             )
             .await
             {
@@ -162,11 +523,12 @@ pub async fn run_repl<P: TranslatorProvider>(
             }
             match hotfix_server_from_input(
                 &engine,
-                &mut summarizer,
+                summarizer.as_mut(),
                 &mut server_manager,
                 src,
                 "force_js",
                 "js-hotfix",
+                settings.verbosity,
             )
             .await
             {
@@ -187,11 +549,12 @@ pub async fn run_repl<P: TranslatorProvider>(
             }
             match hotfix_server_from_input(
                 &engine,
-                &mut summarizer,
+                summarizer.as_mut(),
                 &mut server_manager,
                 src,
                 "force_nl",
                 "nl-hotfix",
+                settings.verbosity,
             )
             .await
             {
@@ -211,18 +574,39 @@ pub async fn run_repl<P: TranslatorProvider>(
                 println!("no previous pseudocode input to retry");
                 continue;
             };
-            let retry_input = if hint.is_empty() {
-                previous.clone()
-            } else {
-                format!("{previous}\nRefine with: {hint}")
-            };
+            let retry_input = refine_prompt(previous, hint);
             match handle_input(
                 &engine,
-                &mut summarizer,
+                summarizer.as_mut(),
                 &mut server_manager,
                 &retry_input,
                 "force_nl",
-                confirm_risky,
+                &settings,
+                &mut last_generated,
+                &mut last_nl_input,
+            )
+            .await
+            {
+                Ok(()) => {}
+                Err(e) => print_repl_error(e),
+            }
+            continue;
+        }
+
+        if line == "/paste" || line == ":paste" {
+            println!("paste mode: end with a line containing only `.` or `/end`");
+            let buffer = input.paste_block()?;
+            if buffer.trim().is_empty() {
+                println!("nothing pasted");
+                continue;
+            }
+            match handle_input(
+                &engine,
+                summarizer.as_mut(),
+                &mut server_manager,
+                &buffer,
+                "force_js",
+                &settings,
                 &mut last_generated,
                 &mut last_nl_input,
             )
@@ -241,11 +625,11 @@ pub async fn run_repl<P: TranslatorProvider>(
             let src = code.trim();
             match handle_input(
                 &engine,
-                &mut summarizer,
+                summarizer.as_mut(),
                 &mut server_manager,
                 src,
                 "force_js",
-                confirm_risky,
+                &settings,
                 &mut last_generated,
                 &mut last_nl_input,
             )
@@ -264,11 +648,11 @@ pub async fn run_repl<P: TranslatorProvider>(
             let src = text.trim();
             match handle_input(
                 &engine,
-                &mut summarizer,
+                summarizer.as_mut(),
                 &mut server_manager,
                 src,
                 "force_nl",
-                confirm_risky,
+                &settings,
                 &mut last_generated,
                 &mut last_nl_input,
             )
@@ -280,13 +664,14 @@ pub async fn run_repl<P: TranslatorProvider>(
             continue;
         }
 
+        last_classification = Some(engine.classify_and_explain(line));
         match handle_input(
             &engine,
-            &mut summarizer,
+            summarizer.as_mut(),
             &mut server_manager,
             line,
             "repl",
-            confirm_risky,
+            &settings,
             &mut last_generated,
             &mut last_nl_input,
         )
@@ -297,86 +682,265 @@ pub async fn run_repl<P: TranslatorProvider>(
         }
     }
 
+    interrupt_watcher.abort();
     server_manager.stop().await?;
+    if persist {
+        if let Some(path) = &saved_path {
+            let session = SavedSession {
+                summary: summarizer.current(),
+                last_generated,
+                last_nl_input,
+            };
+            if let Err(e) = save_session(path, &session) {
+                eprintln!("warning: could not save session to {}: {e}", path.display());
+            }
+        }
+    }
     Ok(())
 }
 
+/// Reads the next REPL line with `at_prompt` set for [`exit_on_prompt_interrupt`].
+fn prompt_line(input: &mut ReplInput, at_prompt: &AtomicBool) -> io::Result<Option<String>> {
+    at_prompt.store(true, Ordering::SeqCst);
+    let line = input.next_line("beeno> ");
+    at_prompt.store(false, Ordering::SeqCst);
+    line
+}
+
+/// Exits with status 130 on Ctrl-C at the prompt, killing the server first.
+///
+/// The prompt blocks on stdin, so the REPL cannot notice Ctrl-C there and run its
+/// own cleanup; without this the server, which lives in its own process group,
+/// would outlive beeno. Elsewhere Ctrl-C is left to [`cancellable`].
+fn exit_on_prompt_interrupt(
+    at_prompt: Arc<AtomicBool>,
+    server: ServerKillSwitch,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if at_prompt.load(Ordering::SeqCst) {
+                server.kill();
+                println!();
+                std::process::exit(130);
+            }
+        }
+    })
+}
+
+fn switch_provider<P, F>(
+    engine: &mut Engine<P, DefaultRiskPolicy>,
+    selection: &mut ProviderSelection,
+    next: ProviderSelection,
+    build_provider: &F,
+) where
+    P: TranslatorProvider,
+    F: Fn(&ProviderSelection) -> anyhow::Result<P>,
+{
+    match build_provider(&next) {
+        Ok(provider) => {
+            engine.set_provider(provider);
+            println!(
+                "switched to provider {} (model: {})",
+                next.provider, next.model
+            );
+            *selection = next;
+        }
+        Err(e) => println!("error: could not switch provider: {e}"),
+    }
+}
+
+/// Per-session settings applied to every evaluated input.
+struct InputSettings {
+    confirm_mode: ConfirmMode,
+    translate_timeout: Duration,
+    env: BTreeMap<String, String>,
+    deno_config: Option<String>,
+    import_map: Option<String>,
+    no_remote: bool,
+    cached_only: bool,
+    permissions: DenoPermissions,
+    verbosity: Verbosity,
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn handle_input<P: TranslatorProvider>(
     engine: &Engine<P, DefaultRiskPolicy>,
-    summarizer: &mut RollingContextSummarizer,
+    summarizer: &mut dyn ContextSummarizer,
     server_manager: &mut ServerManager,
     input: &str,
     mode: &str,
-    confirm_risky: bool,
+    settings: &InputSettings,
     last_generated: &mut Option<String>,
     last_nl_input: &mut Option<String>,
 ) -> Result<(), EngineError> {
     let summary = current_summary_with_server(summarizer, server_manager);
-    let (source, _translated, risk) = engine.prepare_source(input, mode, summary, None).await?;
+    let trace_id = new_trace_id();
+    let mut mode = mode;
+    let translate_started = Instant::now();
+    let prepare = engine.prepare_source(input, mode, summary.clone(), None, Some(trace_id.clone()));
+    let spinner = Spinner::start("translating", true);
+    let prepared = cancellable(prepare, settings.translate_timeout).await;
+    spinner.finish();
+    let prepared = match prepared {
+        Err(EngineError::Classification(ClassificationError::Ambiguous { reason })) => {
+            mode = ambiguous_input_mode(input, &reason)
+                .map_err(|e| EngineError::Execution(e.to_string()))?;
+            let prepare = engine.prepare_source(input, mode, summary, None, Some(trace_id.clone()));
+            let spinner = Spinner::start("translating", true);
+            let prepared = cancellable(prepare, settings.translate_timeout).await;
+            spinner.finish();
+            prepared
+        }
+        other => other,
+    };
+    let Some((source, _, risk, warnings)) = prepared? else {
+        println!("translation cancelled");
+        return Ok(());
+    };
+    let translate_elapsed = translate_started.elapsed();
+    let chatty = settings.verbosity.chatty();
     *last_generated = Some(source.clone());
     if mode == "force_nl" || mode == "repl" {
         *last_nl_input = Some(input.to_string());
     }
 
-    if risk.requires_confirmation
-        && confirm_risky
-        && !prompt_confirm("risky output detected, execute?")
-            .map_err(|e| EngineError::Execution(e.to_string()))?
+    print_warnings(&warnings, !chatty);
+    if needs_confirmation(settings.confirm_mode, &risk, &source)
+        && !prompt_confirm(if risk.requires_confirmation {
+            "risky output detected, execute?"
+        } else {
+            "execute generated code?"
+        })
+        .map_err(|e| EngineError::Execution(e.to_string()))?
     {
         println!("execution skipped by user");
         return Ok(());
     }
 
-    execute_request(ExecutionRequest {
-        source,
-        deno_permissions: DenoPermissions::default(),
-        origin: "repl".to_string(),
-    })
-    .await?;
+    let started = Instant::now();
+    let executed = engine
+        .execute(ExecutionRequest {
+            trace_id: Some(trace_id),
+            env: settings.env.clone(),
+            deno_config: settings.deno_config.clone(),
+            import_map: settings.import_map.clone(),
+            no_remote: settings.no_remote,
+            cached_only: settings.cached_only,
+            live: true,
+            ..ExecutionRequest::new(source, settings.permissions.clone(), "repl")
+        })
+        .await
+        .and_then(ExecutionOutput::into_result);
+    if settings.verbosity.is_verbose() {
+        println!("{}", timing_line(translate_elapsed, started.elapsed()));
+    }
+    executed?;
 
-    summarizer.update(input).await;
+    for line in input.lines().filter(|l| !l.trim().is_empty()) {
+        summarizer.update(line).await;
+    }
+    if let Some(source) = last_generated.as_deref() {
+        summarizer.update_with_source(source).await;
+    }
     Ok(())
 }
 
+/// Runs `fut` until it finishes, `timeout` elapses, or Ctrl-C is pressed.
+///
+/// Returns `Ok(None)` when cancelled so the REPL can go back to the prompt.
+async fn cancellable<T>(
+    fut: impl std::future::Future<Output = Result<T, EngineError>>,
+    timeout: Duration,
+) -> Result<Option<T>, EngineError> {
+    tokio::select! {
+        res = tokio::time::timeout(timeout, fut) => match res {
+            Ok(out) => out.map(Some),
+            Err(_) => Err(EngineError::Provider(ProviderError::Timeout(timeout))),
+        },
+        _ = tokio::signal::ctrl_c() => Ok(None),
+    }
+}
+
+/// Reads lines until a lone `.` or `/end` (or EOF) and returns them joined.
+fn read_paste_block<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if matches!(line.trim(), "." | "/end") {
+            break;
+        }
+        lines.push(line.to_string());
+    }
+    Ok(lines.join("\n"))
+}
+
+/// The prompt for regenerating `previous`, with an optional refinement `hint` appended.
+pub fn refine_prompt(previous: &str, hint: &str) -> String {
+    if hint.is_empty() {
+        previous.to_string()
+    } else {
+        format!("{previous}\nRefine with: {hint}")
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn start_server_from_input<P: TranslatorProvider>(
     engine: &Engine<P, DefaultRiskPolicy>,
-    summarizer: &mut RollingContextSummarizer,
+    summarizer: &mut dyn ContextSummarizer,
     server_manager: &mut ServerManager,
     input: &str,
     mode: &str,
     port: u16,
     source_mode: &str,
+    verbosity: Verbosity,
 ) -> Result<String, EngineError> {
     let summary = current_summary_with_server(summarizer, server_manager);
-    let (source, _, _risk) = engine.prepare_source(input, mode, summary, None).await?;
+    let (source, _, _risk, mut warnings) = engine
+        .prepare_source(input, mode, summary, None, None)
+        .await?;
+    warnings.extend(server_source_warning(&source));
+    print_warnings(&warnings, !verbosity.chatty());
+    let generated = source.clone();
     let status = server_manager
         .start_with_code(source, port, source_mode)
         .await
         .map_err(|e| EngineError::Execution(e.to_string()))?;
     summarizer.update(input).await;
+    summarizer.update_with_source(&generated).await;
     Ok(status.url)
 }
 
 async fn hotfix_server_from_input<P: TranslatorProvider>(
     engine: &Engine<P, DefaultRiskPolicy>,
-    summarizer: &mut RollingContextSummarizer,
+    summarizer: &mut dyn ContextSummarizer,
     server_manager: &mut ServerManager,
     input: &str,
     mode: &str,
     source_mode: &str,
+    verbosity: Verbosity,
 ) -> Result<String, EngineError> {
     let summary = current_summary_with_server(summarizer, server_manager);
-    let (source, _, _risk) = engine.prepare_source(input, mode, summary, None).await?;
+    let (source, _, _risk, mut warnings) = engine
+        .prepare_source(input, mode, summary, None, None)
+        .await?;
+    warnings.extend(server_source_warning(&source));
+    print_warnings(&warnings, !verbosity.chatty());
+    let generated = source.clone();
     let status = server_manager
         .hotfix_with_code(source, source_mode)
         .await
         .map_err(|e| EngineError::Execution(e.to_string()))?;
     summarizer.update(input).await;
+    summarizer.update_with_source(&generated).await;
     Ok(status.url)
 }
 
 fn current_summary_with_server(
-    summarizer: &mut RollingContextSummarizer,
+    summarizer: &mut dyn ContextSummarizer,
     server_manager: &mut ServerManager,
 ) -> SessionSummary {
     let mut summary = summarizer.current();
@@ -389,12 +953,28 @@ fn current_summary_with_server(
     summary
 }
 
+/// Asks how to treat input the classifier found ambiguous; returns `force_nl` or `force_js`.
+///
+/// Only a terminal is asked, and `--yes` does not answer. Otherwise the
+/// classifier's best guess for `input` is used without printing anything, so
+/// piped output stays clean.
+pub fn ambiguous_input_mode(input: &str, reason: &str) -> io::Result<&'static str> {
+    let answer = crate::prompt::ask(&format!(
+        "input is ambiguous ({reason}); treat it as pseudocode?"
+    ))?;
+    Ok(mode_for_answer(answer, classify_input(input)))
+}
+
+fn mode_for_answer(answer: Option<bool>, guess: InputKind) -> &'static str {
+    if answer.unwrap_or(guess == InputKind::Pseudocode) {
+        "force_nl"
+    } else {
+        "force_js"
+    }
+}
+
 fn prompt_confirm(prompt: &str) -> anyhow::Result<bool> {
-    print!("{prompt} [y/N]: ");
-    io::stdout().flush()?;
-    let mut answer = String::new();
-    io::stdin().read_line(&mut answer)?;
-    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "YES"))
+    Ok(crate::prompt::confirm(prompt)?)
 }
 
 fn maybe_prompt_open_browser(url: &str) -> anyhow::Result<()> {
@@ -430,6 +1010,60 @@ fn maybe_prompt_open_browser(url: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn update_server_permissions(server_manager: &mut ServerManager, args: &str) {
+    if !args.is_empty() {
+        let (grant, value) = args.split_once(' ').unwrap_or((args, ""));
+        let mut perms = server_manager.permissions().clone();
+        if let Err(e) = perms.set_grant(grant, value) {
+            println!("{e}");
+            println!("usage: /serve-perms <read|write|net|env|run> <on|off|list>");
+            return;
+        }
+        server_manager.set_permissions(perms);
+        println!("server permissions updated; applied on next start or hotfix");
+    }
+    let args = permission_args(server_manager.permissions());
+    if args.is_empty() {
+        println!("server permissions: none");
+    } else {
+        println!("server permissions: {}", args.join(" "));
+    }
+}
+
+/// Handles `/serve-env [KEY=VALUE]`: sets a server variable, then lists the set ones.
+pub fn update_server_env(server_manager: &mut ServerManager, args: &str) {
+    if !args.is_empty() {
+        match parse_env_assignment(args) {
+            Ok((key, value)) => {
+                server_manager.set_extra_env(key, value);
+                println!("server env updated; applied on next start or hotfix");
+            }
+            Err(e) => {
+                println!("{e}");
+                println!("usage: /serve-env KEY=VALUE");
+                return;
+            }
+        }
+    }
+    if server_manager.extra_env().is_empty() {
+        println!("server env: none set");
+    }
+    for (key, value) in server_manager.extra_env() {
+        println!("{key}={}", redact(value));
+    }
+}
+
+fn print_server_logs(server_manager: &ServerManager) {
+    let logs = server_manager.logs();
+    if logs.is_empty() {
+        println!("no server output captured yet");
+        return;
+    }
+    for line in logs {
+        println!("{line}");
+    }
+}
+
 fn print_repl_error(err: EngineError) {
     match err {
         EngineError::Blocked(reasons) => {
@@ -448,16 +1082,134 @@ fn print_help() {
     println!("  /help                         show this help");
     println!("  /exit | /quit                 exit repl");
     println!("  /clear                        clear terminal");
+    println!("  /reset                        forget session context (and the saved session)");
     println!("  /js <code>                    force native JS/TS execution");
     println!("  /nl <prompt>                  force LLM translation before execution");
+    println!("  /paste                        run multi-line JS/TS; end with `.` or /end");
     println!("  /retry [hint]                 retry last NL prompt");
     println!("  /show                         show last generated code");
     println!("  /context                      show current session summary");
-    println!("  /serve-port <port>            set background server port");
+    println!("  /provider [name]              show or switch the LLM provider");
+    println!("  /model [name]                 show or switch the LLM model (aliases expand)");
+    println!("  /serve-port <port>            set background server port (0 = any free port)");
+    println!("  /serve-perms [grant value]    show or set server permissions (e.g. net on)");
+    println!("  /serve-env [KEY=VALUE]        show or set a server environment variable");
+    println!("  /serve-env-clear              drop variables set with /serve-env");
     println!("  /serve-js <code>              start/restart background server from JS/TS");
     println!("  /serve-nl <prompt>            start/restart background server from pseudocode");
     println!("  /serve-hotfix-js <code>       hotfix running server with JS/TS");
     println!("  /serve-hotfix-nl <prompt>     hotfix running server with pseudocode");
+    println!("  /serve-restart-nl [hint]      regenerate server from last NL prompt and restart");
     println!("  /serve-status                 show running server state");
+    println!("  /serve-logs                   show recent server output");
     println!("  /serve-stop                   stop running server");
+    println!("  /serve-undo | /serve-redo     step back/forward through served sources");
+    println!("  /serve-supervise [on|off]     restart the server when it crashes");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::unique_temp_path;
+
+    #[test]
+    fn refine_prompt_appends_an_optional_hint() {
+        assert_eq!(refine_prompt("serve hello", ""), "serve hello");
+        assert_eq!(
+            refine_prompt("serve hello", "use port 3000"),
+            "serve hello\nRefine with: use port 3000"
+        );
+    }
+
+    #[test]
+    fn unanswered_ambiguity_falls_back_to_the_classifier() {
+        assert_eq!(mode_for_answer(None, InputKind::Code), "force_js");
+        assert_eq!(mode_for_answer(None, InputKind::Pseudocode), "force_nl");
+        assert_eq!(mode_for_answer(Some(true), InputKind::Code), "force_nl");
+        assert_eq!(
+            mode_for_answer(Some(false), InputKind::Pseudocode),
+            "force_js"
+        );
+    }
+
+    #[test]
+    fn saved_session_round_trips_without_server_state() {
+        let path = unique_temp_path("beeno-session-test").join("session.json");
+        let mut summary = SessionSummary {
+            symbols: vec!["total".to_string()],
+            ..SessionSummary::default()
+        };
+        summary.server = Some(ServerContext {
+            running: true,
+            url: None,
+            port: Some(8080),
+            mode: "js".to_string(),
+        });
+        let session = SavedSession {
+            summary,
+            last_generated: Some("console.log(total);".to_string()),
+            last_nl_input: Some("print total".to_string()),
+        };
+        save_session(&path, &session).expect("save");
+        let loaded = load_session(&path).expect("load");
+        assert_eq!(loaded.summary.symbols, vec!["total"]);
+        assert!(loaded.summary.server.is_none());
+        assert_eq!(loaded.last_nl_input.as_deref(), Some("print total"));
+
+        fs::write(&path, "{not json").expect("corrupt");
+        assert!(load_session(&path).is_none());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn paste_block_stops_at_terminator() {
+        let mut input =
+            "function add(a, b) {\n  return a + b;\n}\n.\nconsole.log('after');\n".as_bytes();
+        let block = read_paste_block(&mut input).expect("read");
+        assert_eq!(block, "function add(a, b) {\n  return a + b;\n}");
+
+        let mut input = "const x = 1;\r\n/end\n".as_bytes();
+        assert_eq!(read_paste_block(&mut input).expect("read"), "const x = 1;");
+
+        let mut input = "const y = 2;".as_bytes();
+        assert_eq!(read_paste_block(&mut input).expect("read"), "const y = 2;");
+    }
+
+    #[test]
+    fn script_input_skips_comments_and_ends_without_stdin() {
+        let mut input = ReplInput::script(
+            "# demo\n/js const a = 1;\n\n  /paste\nconst b = 2;\n.\n/exit\n",
+            false,
+        );
+        assert_eq!(
+            input.next_line("> ").expect("line").as_deref(),
+            Some("/js const a = 1;")
+        );
+        assert_eq!(
+            input.next_line("> ").expect("line").as_deref(),
+            Some("/paste")
+        );
+        assert_eq!(input.paste_block().expect("paste"), "const b = 2;");
+        assert_eq!(
+            input.next_line("> ").expect("line").as_deref(),
+            Some("/exit")
+        );
+        assert_eq!(input.next_line("> ").expect("line"), None);
+    }
+
+    #[tokio::test]
+    async fn hung_translation_times_out() {
+        let hung = std::future::pending::<Result<(), EngineError>>();
+        let err = cancellable(hung, Duration::from_millis(20))
+            .await
+            .expect_err("timeout");
+        assert!(matches!(
+            err,
+            EngineError::Provider(ProviderError::Timeout(_))
+        ));
+
+        let done = async { Ok::<_, EngineError>(7) };
+        let out = cancellable(done, Duration::from_secs(1)).await.expect("ok");
+        assert_eq!(out, Some(7));
+    }
 }
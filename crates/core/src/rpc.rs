@@ -0,0 +1,291 @@
+use crate::engine::{Engine, EngineError, RiskPolicy};
+use crate::providers::TranslatorProvider;
+use crate::types::{new_trace_id, DenoPermissions, ExecutionRequest, RiskLevel, SessionSummary};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+/// One newline-delimited request read from the RPC input stream.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcRequest {
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// One newline-delimited response written to the RPC output stream.
+#[derive(Debug, Clone, Serialize)]
+pub struct RpcResponse {
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+/// Error object returned in place of a result.
+#[derive(Debug, Clone, Serialize)]
+pub struct RpcError {
+    pub code: String,
+    pub message: String,
+}
+
+impl RpcResponse {
+    fn ok(id: Value, result: Value) -> Self {
+        Self {
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    fn err(id: Value, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            id,
+            result: None,
+            error: Some(RpcError {
+                code: code.to_string(),
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct InputParams {
+    input: String,
+    #[serde(default = "default_rpc_mode")]
+    mode: String,
+    #[serde(default)]
+    session_summary: SessionSummary,
+    #[serde(default)]
+    trace_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SourceParams {
+    source: String,
+    #[serde(default)]
+    permissions: DenoPermissions,
+    #[serde(default)]
+    confirmed: bool,
+    #[serde(default)]
+    trace_id: Option<String>,
+}
+
+fn default_rpc_mode() -> String {
+    "force_nl".to_string()
+}
+
+/// Serves newline-delimited JSON requests until the input stream closes.
+///
+/// Supported methods are `translate`, `classify`, `analyze`, and `execute`.
+/// Every request produces exactly one single-line response echoing its `id`.
+/// Executed programs run with captured output so they cannot corrupt the
+/// protocol stream.
+pub async fn serve_rpc<P, R, I, O>(
+    engine: &Engine<P, R>,
+    input: I,
+    mut output: O,
+) -> anyhow::Result<()>
+where
+    P: TranslatorProvider,
+    R: RiskPolicy,
+    I: AsyncBufRead + Unpin,
+    O: AsyncWrite + Unpin,
+{
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<RpcRequest>(&line) {
+            Ok(req) => dispatch(engine, req).await,
+            Err(e) => RpcResponse::err(Value::Null, "parse_error", e.to_string()),
+        };
+        let mut encoded = serde_json::to_string(&response)?;
+        encoded.push('\n');
+        output.write_all(encoded.as_bytes()).await?;
+        output.flush().await?;
+    }
+    Ok(())
+}
+
+async fn dispatch<P, R>(engine: &Engine<P, R>, req: RpcRequest) -> RpcResponse
+where
+    P: TranslatorProvider,
+    R: RiskPolicy,
+{
+    let id = req.id;
+    match req.method.as_str() {
+        "classify" => match parse_params::<InputParams>(req.params) {
+            Ok(params) => RpcResponse::ok(id, json!(engine.classify_and_explain(&params.input))),
+            Err(e) => RpcResponse::err(id, "invalid_params", e),
+        },
+        "translate" => match parse_params::<InputParams>(req.params) {
+            Ok(params) => {
+                let trace_id = params.trace_id.unwrap_or_else(new_trace_id);
+                match engine
+                    .prepare_source(
+                        &params.input,
+                        &params.mode,
+                        params.session_summary,
+                        None,
+                        Some(trace_id.clone()),
+                    )
+                    .await
+                {
+                    Ok((source, translated, risk, warnings)) => RpcResponse::ok(
+                        id,
+                        json!({
+                            "source": source,
+                            "translation": translated,
+                            "risk": risk,
+                            "warnings": warnings,
+                            "trace_id": trace_id,
+                        }),
+                    ),
+                    Err(e) => engine_error(id, e),
+                }
+            }
+            Err(e) => RpcResponse::err(id, "invalid_params", e),
+        },
+        "analyze" => match parse_params::<SourceParams>(req.params) {
+            Ok(params) => {
+                let report = engine.analyze(&params.source).await;
+                RpcResponse::ok(id, json!(report))
+            }
+            Err(e) => RpcResponse::err(id, "invalid_params", e),
+        },
+        "execute" => match parse_params::<SourceParams>(req.params) {
+            Ok(params) => {
+                let risk = engine.analyze(&params.source).await;
+                if risk.level == RiskLevel::Blocked {
+                    return engine_error(id, EngineError::Blocked(risk.reasons));
+                }
+                if risk.requires_confirmation && !params.confirmed {
+                    return RpcResponse::err(
+                        id,
+                        "confirmation_required",
+                        format!(
+                            "risky source requires `confirmed: true`: {}",
+                            risk.reasons.join(", ")
+                        ),
+                    );
+                }
+                match engine
+                    .execute(ExecutionRequest {
+                        trace_id: params.trace_id,
+                        ..ExecutionRequest::new(params.source, params.permissions, "rpc")
+                    })
+                    .await
+                {
+                    Ok(output) => RpcResponse::ok(id, json!(output)),
+                    Err(e) => engine_error(id, e),
+                }
+            }
+            Err(e) => RpcResponse::err(id, "invalid_params", e),
+        },
+        other => RpcResponse::err(id, "unknown_method", format!("unknown method `{other}`")),
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, String> {
+    serde_json::from_value(params).map_err(|e| e.to_string())
+}
+
+fn engine_error(id: Value, err: EngineError) -> RpcResponse {
+    let code = match &err {
+        EngineError::Provider(_) => "provider_error",
+        EngineError::Blocked(_) => "blocked",
+        _ => "execution_error",
+    };
+    RpcResponse::err(id, code, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::DefaultRiskPolicy;
+    use crate::providers::MockProvider;
+
+    async fn roundtrip(input: &str) -> Vec<Value> {
+        let engine = Engine::new(MockProvider, DefaultRiskPolicy::default());
+        let mut out = Vec::new();
+        serve_rpc(&engine, input.as_bytes(), &mut out)
+            .await
+            .expect("serve rpc");
+        String::from_utf8(out)
+            .expect("utf8")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("json line"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rpc_translates_and_classifies_with_id_echo() {
+        let responses = roundtrip(concat!(
+            r#"{"id":1,"method":"translate","params":{"input":"print hello"}}"#,
+            "\n",
+            r#"{"id":"b","method":"classify","params":{"input":"let x = 1;"}}"#,
+            "\n",
+        ))
+        .await;
+
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["id"], 1);
+        assert!(responses[0]["result"]["source"]
+            .as_str()
+            .expect("source")
+            .contains("console.log"));
+        assert_eq!(
+            responses[0]["result"]["trace_id"].as_str().map(str::len),
+            Some(36)
+        );
+        assert_eq!(responses[1]["id"], "b");
+        assert_eq!(responses[1]["result"]["kind"], "code");
+        assert_eq!(responses[1]["result"]["matched_indicators"][0], "let ");
+    }
+
+    #[tokio::test]
+    async fn rpc_reports_errors_without_stopping() {
+        let responses = roundtrip(concat!(
+            "not json\n",
+            r#"{"id":2,"method":"explode"}"#,
+            "\n",
+            r#"{"id":3,"method":"analyze","params":{"source":"new Deno.Command('ls')"}}"#,
+            "\n",
+        ))
+        .await;
+
+        assert_eq!(responses[0]["error"]["code"], "parse_error");
+        assert_eq!(responses[1]["error"]["code"], "unknown_method");
+        assert_eq!(responses[2]["result"]["level"], "Blocked");
+    }
+
+    #[tokio::test]
+    async fn rpc_execute_runs_on_engine_executor() {
+        use crate::engine::RecordingExecutor;
+        use std::sync::Arc;
+
+        let recorder = Arc::new(RecordingExecutor::default());
+        let engine =
+            Engine::new(MockProvider, DefaultRiskPolicy::default()).with_executor(recorder.clone());
+        let input = concat!(
+            r#"{"id":1,"method":"execute","params":{"source":"console.log(1)"}}"#,
+            "\n",
+        );
+        let mut out = Vec::new();
+        serve_rpc(&engine, input.as_bytes(), &mut out)
+            .await
+            .expect("serve rpc");
+        let response: Value = serde_json::from_slice(&out).expect("json");
+
+        assert_eq!(response["result"]["success"], true);
+        let requests = recorder.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].origin, "rpc");
+    }
+}
@@ -0,0 +1,175 @@
+//! Multi-file project output for `beeno scaffold`.
+//!
+//! The provider is asked for a JSON manifest of `{ "path", "contents" }` entries.
+//! [`parse_manifest`] accepts that array (or an object with a `files` array),
+//! optionally wrapped in a code fence, and rejects paths that are absolute or
+//! climb out of the target directory. Policy and size checks happen in
+//! [`crate::engine::Engine::scaffold`]; [`write_files`] puts the result on disk.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+/// Instructions prepended to the user's prompt so the provider answers with a manifest.
+pub const SCAFFOLD_INSTRUCTIONS: &str =
+    "Generate a small Deno project. Respond with only a JSON array of \
+objects with string fields \"path\" (relative, using /) and \"contents\" (the full file text). \
+Do not wrap it in prose.\nProject: ";
+
+/// One file of a generated project.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScaffoldFile {
+    /// Path relative to the output directory, with `/` separators.
+    pub path: String,
+    pub contents: String,
+}
+
+/// Why a provider manifest was rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ScaffoldError {
+    #[error("invalid scaffold manifest: {0}")]
+    InvalidManifest(String),
+    #[error("scaffold has {count} files; limits.max_files allows {max}")]
+    TooManyFiles { count: usize, max: usize },
+    /// Absolute, empty, or `..` paths that would escape the output directory.
+    #[error("scaffold path `{0}` must be relative and stay inside the output directory")]
+    UnsafePath(String),
+    #[error("scaffold lists `{0}` more than once")]
+    DuplicatePath(String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Manifest {
+    Files(Vec<ScaffoldFile>),
+    Wrapped { files: Vec<ScaffoldFile> },
+}
+
+/// Parses a provider response into files, validating every path.
+pub fn parse_manifest(text: &str) -> Result<Vec<ScaffoldFile>, ScaffoldError> {
+    let body = strip_fence(text.trim());
+    let files = match serde_json::from_str::<Manifest>(body)
+        .map_err(|e| ScaffoldError::InvalidManifest(e.to_string()))?
+    {
+        Manifest::Files(files) | Manifest::Wrapped { files } => files,
+    };
+    if files.is_empty() {
+        return Err(ScaffoldError::InvalidManifest("no files".to_string()));
+    }
+    let mut seen = BTreeSet::new();
+    files
+        .into_iter()
+        .map(|file| {
+            let path = normalize_path(&file.path)?;
+            if !seen.insert(path.clone()) {
+                return Err(ScaffoldError::DuplicatePath(path));
+            }
+            Ok(ScaffoldFile {
+                path,
+                contents: file.contents,
+            })
+        })
+        .collect()
+}
+
+/// Normalizes separators to `/` and rejects paths that could leave the output directory.
+pub fn normalize_path(path: &str) -> Result<String, ScaffoldError> {
+    let unsafe_path = || ScaffoldError::UnsafePath(path.to_string());
+    let normalized = path.trim().replace('\\', "/");
+    let mut parts = Vec::new();
+    for component in Path::new(&normalized).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(unsafe_path())
+            }
+        }
+    }
+    // A drive letter (`C:`) is only a `Prefix` component on Windows.
+    if parts.is_empty() || parts[0].contains(':') {
+        return Err(unsafe_path());
+    }
+    Ok(parts.join("/"))
+}
+
+/// Writes `files` under `dir`, creating parent directories, and returns the written paths.
+pub fn write_files(dir: &Path, files: &[ScaffoldFile]) -> std::io::Result<Vec<PathBuf>> {
+    let mut written = Vec::with_capacity(files.len());
+    for file in files {
+        let relative = normalize_path(&file.path)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let path = dir.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, &file.contents)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn strip_fence(text: &str) -> &str {
+    let Some(rest) = text.strip_prefix("```") else {
+        return text;
+    };
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::unique_temp_path;
+
+    #[test]
+    fn parses_array_and_wrapped_manifests() {
+        let files = parse_manifest(
+            "```json\n[{\"path\":\"./src/main.ts\",\"contents\":\"console.log(1);\"}]\n```",
+        )
+        .expect("manifest");
+        assert_eq!(files[0].path, "src/main.ts");
+
+        let files =
+            parse_manifest(r#"{"files":[{"path":"deno.json","contents":"{}"}]}"#).expect("wrapped");
+        assert_eq!(files[0].contents, "{}");
+
+        assert!(matches!(
+            parse_manifest("[]"),
+            Err(ScaffoldError::InvalidManifest(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_output_directory() {
+        for path in ["../evil.ts", "src/../../x", "/etc/passwd", "", "C:/x.ts"] {
+            assert_eq!(
+                normalize_path(path),
+                Err(ScaffoldError::UnsafePath(path.to_string())),
+                "{path}"
+            );
+        }
+        assert_eq!(normalize_path("a\\b.ts").as_deref(), Ok("a/b.ts"));
+        assert_eq!(
+            parse_manifest(r#"[{"path":"a.ts","contents":""},{"path":"./a.ts","contents":""}]"#),
+            Err(ScaffoldError::DuplicatePath("a.ts".to_string()))
+        );
+    }
+
+    #[test]
+    fn writes_nested_files() {
+        let dir = unique_temp_path("beeno-scaffold");
+        let files = vec![ScaffoldFile {
+            path: "src/lib/util.ts".to_string(),
+            contents: "export const x = 1;\n".to_string(),
+        }];
+        let written = write_files(&dir, &files).expect("write");
+        assert_eq!(
+            fs::read_to_string(&written[0]).expect("read"),
+            "export const x = 1;\n"
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+}
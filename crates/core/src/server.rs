@@ -1,9 +1,78 @@
+use crate::engine::{config_args, launch_error, permission_args, remote_args, TempModule};
+use crate::types::{DenoPermissions, Warning, WarningKind};
+use crate::util::http_client;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 use std::fs;
-use std::path::PathBuf;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{Child, Command};
 
+/// Number of server output lines retained for `/serve-logs`.
+pub const LOG_CAPACITY: usize = 200;
+
+/// Number of previous server sources retained for `/undo`.
+pub const HISTORY_CAPACITY: usize = 20;
+
+/// Grace period [`ServerManager::stop`] allows before force-killing the server.
+pub const DEFAULT_STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// How long [`ServerManager::start_with_code`] watches source that does not look like a
+/// server before reporting that it exited.
+pub const QUICK_EXIT_WINDOW: Duration = Duration::from_millis(300);
+
+/// Launch attempts for port `0` before giving up on finding a free port.
+///
+/// [`pick_free_port`] releases the port before Deno binds it, so another process
+/// can take it in between; such a launch is retried on a freshly picked port.
+const EPHEMERAL_PORT_ATTEMPTS: usize = 3;
+
+/// Crash restarts `/serve-supervise on` allows within [`DEFAULT_RESTART_WINDOW`].
+pub const DEFAULT_MAX_RESTARTS: u32 = 3;
+
+/// Window over which supervisor restarts are counted by default.
+pub const DEFAULT_RESTART_WINDOW: Duration = Duration::from_secs(60);
+
+/// How often a supervised server is checked for an unexpected exit.
+const SUPERVISE_POLL: Duration = Duration::from_millis(250);
+
+/// How long [`ServerManager::start_with_code`] waits for a server to pass its [`ReadyCheck`].
+pub const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause between readiness probes.
+const READY_POLL: Duration = Duration::from_millis(100);
+
+/// Longest a single readiness probe may take before it counts as not ready.
+const READY_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `Sec-WebSocket-Key` sent by WebSocket readiness probes (the RFC 6455 sample nonce).
+const WS_PROBE_KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+/// `Sec-WebSocket-Accept` a server must answer [`WS_PROBE_KEY`] with.
+const WS_PROBE_ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+/// Source patterns that usually mean the code starts a listening server.
+const SERVER_MARKERS: &[&str] = &["Deno.serve", "serve(", "listen("];
+
+/// Returns a warning when `code` does not reference a known server entry point.
+///
+/// This is a heuristic: servers built on other APIs still start normally.
+pub fn server_source_warning(code: &str) -> Option<Warning> {
+    if SERVER_MARKERS.iter().any(|marker| code.contains(marker)) {
+        None
+    } else {
+        Some(Warning::new(
+            WarningKind::NoServerEntryPoint,
+            "this code doesn't appear to start a server (no Deno.serve, serve(, or listen( call)",
+        ))
+    }
+}
+
 /// Observable runtime status for the background dev server process.
 #[derive(Debug, Clone)]
 pub struct ServerStatus {
@@ -13,55 +82,559 @@ pub struct ServerStatus {
     pub mode: String,
 }
 
+/// Bounded buffer of the most recent server output lines.
+#[derive(Debug, Clone, Default)]
+struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+impl LogBuffer {
+    fn push(&self, line: String) {
+        if let Ok(mut lines) = self.lines.lock() {
+            push_bounded(&mut lines, line, LOG_CAPACITY);
+        }
+    }
+
+    fn push_error(&self, line: String) {
+        if let Ok(mut last) = self.last_error.lock() {
+            *last = Some(line.clone());
+        }
+        self.push(format!("[stderr] {line}"));
+    }
+
+    fn snapshot(&self) -> Vec<String> {
+        self.lines
+            .lock()
+            .map(|lines| lines.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn last_error(&self) -> Option<String> {
+        self.last_error.lock().ok().and_then(|last| last.clone())
+    }
+}
+
+/// Permissions granted to managed servers unless overridden: network and env only.
+pub fn default_server_permissions() -> DenoPermissions {
+    DenoPermissions {
+        allow_net: vec!["*".to_string()],
+        allow_env: true,
+        ..DenoPermissions::default()
+    }
+}
+
+/// How [`ServerManager`] decides a freshly started server is accepting requests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ReadyCheck {
+    /// A TCP connection to the port succeeds.
+    #[default]
+    TcpConnect,
+    /// `GET path` gets any HTTP response.
+    HttpGet { path: String },
+    /// `GET path` with WebSocket upgrade headers completes the opening handshake.
+    WebSocket { path: String },
+}
+
+impl ReadyCheck {
+    /// Probes `127.0.0.1:port` once, returning whether the server looks ready.
+    pub async fn probe(&self, port: u16) -> bool {
+        tokio::time::timeout(READY_PROBE_TIMEOUT, self.probe_inner(port))
+            .await
+            .unwrap_or(false)
+    }
+
+    async fn probe_inner(&self, port: u16) -> bool {
+        let Ok(mut stream) = tokio::net::TcpStream::connect(("127.0.0.1", port)).await else {
+            return false;
+        };
+        let (path, upgrade) = match self {
+            Self::TcpConnect => return true,
+            Self::HttpGet { path } => (path, String::new()),
+            Self::WebSocket { path } => (
+                path,
+                format!(
+                    "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {WS_PROBE_KEY}\r\nSec-WebSocket-Version: 13\r\n"
+                ),
+            ),
+        };
+        let connection = if upgrade.is_empty() {
+            "Connection: close\r\n"
+        } else {
+            ""
+        };
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: 127.0.0.1:{port}\r\n{connection}{upgrade}\r\n",
+            normalize_ready_path(path)
+        );
+        if stream.write_all(request.as_bytes()).await.is_err() {
+            return false;
+        }
+        let Some(head) = read_response_head(&mut stream).await else {
+            return false;
+        };
+        let mut lines = head.lines();
+        let status_ok = lines.next().is_some_and(|status| match self {
+            Self::WebSocket { .. } => status.split_whitespace().nth(1) == Some("101"),
+            _ => status.starts_with("HTTP/1."),
+        });
+        status_ok
+            && (!matches!(self, Self::WebSocket { .. })
+                || lines.any(|line| {
+                    line.split_once(':').is_some_and(|(name, value)| {
+                        name.trim().eq_ignore_ascii_case("sec-websocket-accept")
+                            && value.trim() == WS_PROBE_ACCEPT
+                    })
+                }))
+    }
+}
+
+impl std::fmt::Display for ReadyCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TcpConnect => write!(f, "tcp connect"),
+            Self::HttpGet { path } => write!(f, "http GET {}", normalize_ready_path(path)),
+            Self::WebSocket { path } => write!(f, "websocket {}", normalize_ready_path(path)),
+        }
+    }
+}
+
+fn normalize_ready_path(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{path}")
+    }
+}
+
+/// Reads an HTTP response up to the blank line ending its headers.
+async fn read_response_head(stream: &mut tokio::net::TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 512];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+        if head.len() > 16 * 1024 {
+            break;
+        }
+    }
+    (!head.is_empty()).then(|| String::from_utf8_lossy(&head).into_owned())
+}
+
+/// How long [`check_health`] waits for a response.
+pub const HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Body bytes [`check_health`] keeps for display.
+const HEALTH_BODY_PREVIEW: usize = 256;
+
+/// Response to a [`check_health`] request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    pub url: String,
+    pub status: u16,
+    /// The start of the body, lossily decoded.
+    pub body: String,
+    /// Whether the body continued past [`HealthReport::body`].
+    pub truncated: bool,
+    pub elapsed: Duration,
+}
+
+impl std::fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = reqwest::StatusCode::from_u16(self.status)
+            .ok()
+            .and_then(|status| status.canonical_reason());
+        write!(f, "GET {} -> {}", self.url, self.status)?;
+        if let Some(reason) = reason {
+            write!(f, " {reason}")?;
+        }
+        write!(f, " ({} ms)", self.elapsed.as_millis())?;
+        if !self.body.is_empty() {
+            write!(f, "\n{}", self.body)?;
+            if self.truncated {
+                write!(f, "…")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// GETs `path` (default `/`) on the server at `base_url` and keeps the start of the body.
+///
+/// A refused connection and a missed [`HEALTH_TIMEOUT`] are reported as such
+/// rather than as generic request errors.
+pub async fn check_health(base_url: &str, path: &str) -> anyhow::Result<HealthReport> {
+    let path = match path.trim() {
+        "" => "/".to_string(),
+        path => normalize_ready_path(path),
+    };
+    let url = format!("{}{path}", base_url.trim_end_matches('/'));
+    let started = Instant::now();
+    let mut response = http_client()
+        .get(&url)
+        .timeout(HEALTH_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| health_error(&url, e))?;
+    let status = response.status().as_u16();
+    let mut body = Vec::new();
+    let mut truncated = false;
+    while let Some(chunk) = response.chunk().await.map_err(|e| health_error(&url, e))? {
+        body.extend_from_slice(&chunk);
+        if body.len() > HEALTH_BODY_PREVIEW {
+            body.truncate(HEALTH_BODY_PREVIEW);
+            truncated = true;
+            break;
+        }
+    }
+    Ok(HealthReport {
+        url,
+        status,
+        body: String::from_utf8_lossy(&body).trim_end().to_string(),
+        truncated,
+        elapsed: started.elapsed(),
+    })
+}
+
+fn health_error(url: &str, err: reqwest::Error) -> anyhow::Error {
+    if err.is_connect() {
+        anyhow::anyhow!("connection refused at {url}; the server may have exited (see /logs)")
+    } else if err.is_timeout() {
+        anyhow::anyhow!(
+            "no response from {url} within {}s",
+            HEALTH_TIMEOUT.as_secs()
+        )
+    } else {
+        anyhow::anyhow!("GET {url} failed: {err}")
+    }
+}
+
+/// Crash-restart limits for a supervised server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Supervision {
+    /// Restarts allowed within `window` before the supervisor gives up.
+    pub max_restarts: u32,
+    pub window: Duration,
+}
+
+/// The managed child process, shared with the supervisor task.
+type SharedChild = Arc<Mutex<Option<Child>>>;
+
 /// Background Deno server lifecycle manager used by REPL and `beeno dev`.
 pub struct ServerManager {
-    child: Option<Child>,
-    source_path: Option<PathBuf>,
+    child: SharedChild,
     source_code: Option<String>,
     port: Option<u16>,
     mode: Option<String>,
+    logs: LogBuffer,
+    permissions: DenoPermissions,
+    stop_grace: Duration,
+    history: SourceHistory,
+    env: BTreeMap<String, String>,
+    /// Set with `/serve-env`; overrides `env` and survives restarts and hotfixes.
+    extra_env: BTreeMap<String, String>,
+    deno_config: Option<String>,
+    import_map: Option<String>,
+    no_remote: bool,
+    cached_only: bool,
+    supervision: Option<Supervision>,
+    ready_check: ReadyCheck,
+    last_launch: Option<LaunchSpec>,
+    /// Bumped on every stop, launch, or supervision change to retire running supervisors.
+    epoch: Arc<AtomicU64>,
+    /// Records the running server so a later session can find it if this one dies.
+    pid_file: Option<PathBuf>,
 }
 
 impl Default for ServerManager {
     fn default() -> Self {
         Self {
-            child: None,
-            source_path: None,
+            child: Arc::default(),
             source_code: None,
             port: None,
             mode: None,
+            logs: LogBuffer::default(),
+            permissions: default_server_permissions(),
+            stop_grace: DEFAULT_STOP_GRACE,
+            history: SourceHistory::default(),
+            env: BTreeMap::new(),
+            extra_env: BTreeMap::new(),
+            deno_config: None,
+            import_map: None,
+            no_remote: false,
+            cached_only: false,
+            supervision: None,
+            ready_check: ReadyCheck::default(),
+            last_launch: None,
+            epoch: Arc::default(),
+            pid_file: None,
+        }
+    }
+}
+
+impl Drop for ServerManager {
+    /// Kills a still-running server so a panic or early return does not leave
+    /// it holding the port. Best effort: the process is not waited for.
+    fn drop(&mut self) {
+        self.kill_switch().kill();
+    }
+}
+
+/// Kills a [`ServerManager`]'s server from outside the task that owns the manager,
+/// e.g. a Ctrl-C handler that is about to exit the process without running `Drop`.
+#[derive(Debug, Clone)]
+pub struct ServerKillSwitch {
+    child: SharedChild,
+    epoch: Arc<AtomicU64>,
+    pid_file: Option<PathBuf>,
+}
+
+impl ServerKillSwitch {
+    /// Retires any supervisor and kills the server's whole process group without waiting.
+    pub fn kill(&self) {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        let Some(mut child) = lock_child(&self.child).take() else {
+            return;
+        };
+        kill_process_group(&child);
+        let _ = child.start_kill();
+        if let (Some(path), Some(pid)) = (&self.pid_file, child.id()) {
+            clear_pid_file(path, pid);
         }
     }
 }
 
 impl ServerManager {
+    /// Sets extra environment variables for every launched server; `PORT` still wins.
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Passes a `deno.json` (`--config`) and/or import map to every launched server.
+    pub fn with_deno_config(
+        mut self,
+        deno_config: Option<String>,
+        import_map: Option<String>,
+    ) -> Self {
+        self.deno_config = deno_config;
+        self.import_map = import_map;
+        self
+    }
+
+    /// Launches every server with `--no-remote` and/or `--cached-only`.
+    pub fn with_remote_imports(mut self, no_remote: bool, cached_only: bool) -> Self {
+        self.no_remote = no_remote;
+        self.cached_only = cached_only;
+        self
+    }
+
+    /// Sets how [`ServerManager::start_with_code`] verifies that a server is accepting requests.
+    pub fn with_ready_check(mut self, ready_check: ReadyCheck) -> Self {
+        self.ready_check = ready_check;
+        self
+    }
+
+    /// Sets the grace period used by [`ServerManager::stop`].
+    pub fn with_stop_grace(mut self, grace: Duration) -> Self {
+        self.stop_grace = grace;
+        self
+    }
+
+    /// Records each launched server in `pid_file` (see [`server_pid_path`]) so
+    /// [`OrphanedServer::find`] can spot it if this process dies without stopping it.
+    pub fn with_pid_file(mut self, pid_file: Option<PathBuf>) -> Self {
+        self.pid_file = pid_file;
+        self
+    }
+
+    /// A handle that kills the running (or any later) server from another task.
+    pub fn kill_switch(&self) -> ServerKillSwitch {
+        ServerKillSwitch {
+            child: Arc::clone(&self.child),
+            epoch: Arc::clone(&self.epoch),
+            pid_file: self.pid_file.clone(),
+        }
+    }
+
     /// Starts (or restarts) the managed server process with provided source code.
+    ///
+    /// Server stdout/stderr is captured into a bounded log buffer instead of being
+    /// written to the terminal; use [`ServerManager::logs`] to inspect it.
+    /// Replacing the source with different code records the previous source for
+    /// [`ServerManager::undo`] and clears the redo history.
+    ///
+    /// Source that looks like a server is polled with the configured [`ReadyCheck`]
+    /// for up to [`READY_TIMEOUT`]; source flagged by [`server_source_warning`] is
+    /// only watched for [`QUICK_EXIT_WINDOW`]. If the process exits meanwhile this
+    /// returns an error carrying the last stderr line instead of a stopped status.
     pub async fn start_with_code(
         &mut self,
         code: String,
         port: u16,
         mode: &str,
+    ) -> anyhow::Result<ServerStatus> {
+        let previous = self.source_code.clone();
+        let watch_exit = server_source_warning(&code).is_some();
+        let status = self.launch(code, port, mode).await?;
+        if watch_exit {
+            tokio::time::sleep(QUICK_EXIT_WINDOW).await;
+            if self.status().is_none() {
+                return Err(self.exited_immediately());
+            }
+        } else {
+            self.wait_until_ready(status.port).await?;
+        }
+        if let Some(current) = &self.source_code {
+            self.history.record(previous, current);
+        }
+        Ok(status)
+    }
+
+    /// Polls the [`ReadyCheck`] until it passes, the server exits, or [`READY_TIMEOUT`] runs out.
+    async fn wait_until_ready(&mut self, port: u16) -> anyhow::Result<()> {
+        let deadline = Instant::now() + READY_TIMEOUT;
+        loop {
+            if self.ready_check.probe(port).await {
+                return Ok(());
+            }
+            if self.status().is_none() {
+                return Err(self.exited_immediately());
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!(
+                    "server is running but did not pass the {} readiness check within {}s; check /serve-logs",
+                    self.ready_check,
+                    READY_TIMEOUT.as_secs()
+                );
+            }
+            tokio::time::sleep(READY_POLL).await;
+        }
+    }
+
+    fn exited_immediately(&self) -> anyhow::Error {
+        anyhow::anyhow!(
+            "server exited immediately: {}",
+            self.last_error().unwrap_or_else(|| {
+                "no error output; the code may have finished without listening".to_string()
+            })
+        )
+    }
+
+    /// Restarts the server with the source that preceded the current one.
+    ///
+    /// Returns `Ok(None)` when there is nothing to undo.
+    pub async fn undo(&mut self) -> anyhow::Result<Option<ServerStatus>> {
+        let current = self.source_code.clone();
+        let Some(target) = self.history.step_back(current) else {
+            return Ok(None);
+        };
+        self.relaunch_step(target, "undo", true).await
+    }
+
+    /// Re-applies the source most recently reverted by [`ServerManager::undo`].
+    ///
+    /// Returns `Ok(None)` when there is nothing to redo.
+    pub async fn redo(&mut self) -> anyhow::Result<Option<ServerStatus>> {
+        let current = self.source_code.clone();
+        let Some(target) = self.history.step_forward(current) else {
+            return Ok(None);
+        };
+        self.relaunch_step(target, "redo", false).await
+    }
+
+    /// Returns how many steps `/undo` and `/redo` can currently take.
+    pub fn history_depth(&self) -> (usize, usize) {
+        (self.history.undo.len(), self.history.redo.len())
+    }
+
+    async fn relaunch_step(
+        &mut self,
+        target: String,
+        mode: &str,
+        backwards: bool,
+    ) -> anyhow::Result<Option<ServerStatus>> {
+        let port = self.port.unwrap_or(8080);
+        match self.launch(target.clone(), port, mode).await {
+            Ok(status) => Ok(Some(status)),
+            Err(e) => {
+                // Put the history back where it was; the stepped-to source never ran.
+                if backwards {
+                    self.history.step_forward(Some(target));
+                } else {
+                    self.history.step_back(Some(target));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Launches `code` on `port`, or on a free port picked by the OS when `port` is `0`.
+    async fn launch(
+        &mut self,
+        code: String,
+        port: u16,
+        mode: &str,
+    ) -> anyhow::Result<ServerStatus> {
+        if port != 0 {
+            return self.spawn_server(code, port, mode).await;
+        }
+        for attempt in 1..=EPHEMERAL_PORT_ATTEMPTS {
+            let port = pick_free_port()?;
+            let status = self.spawn_server(code.clone(), port, mode).await?;
+            // The port was released before Deno bound it; retry if it was taken meanwhile.
+            tokio::time::sleep(QUICK_EXIT_WINDOW).await;
+            let lost_race =
+                self.status().is_none() && self.last_error().is_some_and(|e| is_addr_in_use(&e));
+            if !lost_race {
+                return Ok(status);
+            }
+            self.logs.push(format!(
+                "--- port {port} was taken before the server bound it (attempt {attempt}) ---"
+            ));
+        }
+        anyhow::bail!("could not bind a free port after {EPHEMERAL_PORT_ATTEMPTS} attempts")
+    }
+
+    async fn spawn_server(
+        &mut self,
+        code: String,
+        port: u16,
+        mode: &str,
     ) -> anyhow::Result<ServerStatus> {
         self.stop().await?;
 
-        let source_path = temp_server_module_path();
-        fs::write(&source_path, &code)?;
+        if !port_available(port) {
+            anyhow::bail!(
+                "port {port} is already in use; choose another with --port <port> or /serve-port <port>"
+            );
+        }
 
-        let mut cmd = Command::new("deno");
-        cmd.arg("run")
-            .arg("--allow-net")
-            .arg("--allow-read")
-            .arg("--allow-env")
-            .arg("--allow-write")
-            .arg(&source_path)
-            .env("PORT", format!("{port}"))
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .stdin(Stdio::null());
+        let spec = LaunchSpec {
+            module: Arc::new(TempModule::create("beeno-server", &code)?),
+            port,
+            permissions: self.permissions.clone(),
+            env: self.launch_env(),
+            deno_config: self.deno_config.clone(),
+            import_map: self.import_map.clone(),
+            no_remote: self.no_remote,
+            cached_only: self.cached_only,
+        };
+        // A missing `deno` surfaces as `EngineError::RuntimeMissing`.
+        let child = spec.spawn(&self.logs).map_err(launch_error)?;
+        self.logs.push(format!(
+            "--- server started on port {port} (mode: {mode}) ---"
+        ));
 
-        let child = cmd.spawn()?;
-        self.child = Some(child);
-        self.source_path = Some(source_path);
+        if let Some(path) = &self.pid_file {
+            write_pid_file(path, &child, port, spec.module.path());
+        }
+        *lock_child(&self.child) = Some(child);
+        self.last_launch = Some(spec);
+        self.spawn_supervisor();
         self.source_code = Some(code);
         self.port = Some(port);
         self.mode = Some(mode.to_string());
@@ -85,21 +658,51 @@ impl ServerManager {
     }
 
     /// Stops the managed server process if it is currently running.
+    ///
+    /// Uses the configured grace period; see [`ServerManager::stop_graceful`].
     pub async fn stop(&mut self) -> anyhow::Result<()> {
-        if let Some(child) = &mut self.child {
-            let _ = child.start_kill();
-            let _ = child.wait().await;
+        self.stop_graceful(self.stop_grace).await
+    }
+
+    /// Asks the server to exit and force-kills it if it is still running after `grace`.
+    ///
+    /// On unix the process first receives SIGTERM so in-flight requests can finish;
+    /// elsewhere it is killed immediately.
+    pub async fn stop_graceful(&mut self, grace: Duration) -> anyhow::Result<()> {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        let Some(mut child) = lock_child(&self.child).take() else {
+            return Ok(());
+        };
+        if let (Some(path), Some(pid)) = (&self.pid_file, child.id()) {
+            clear_pid_file(path, pid);
+        }
+        if request_terminate(&child)
+            && matches!(tokio::time::timeout(grace, child.wait()).await, Ok(Ok(_)))
+        {
+            return Ok(());
         }
-        self.child = None;
+        let _ = child.start_kill();
+        let _ = child.wait().await;
         Ok(())
     }
 
     /// Returns current server status, or `None` if stopped/exited.
+    ///
+    /// When the process has exited on its own, [`ServerManager::last_error`]
+    /// usually explains why.
     pub fn status(&mut self) -> Option<ServerStatus> {
-        let child = self.child.as_mut()?;
-        if let Ok(Some(_status)) = child.try_wait() {
-            self.child = None;
-            return None;
+        {
+            let mut slot = lock_child(&self.child);
+            let child = slot.as_mut()?;
+            if let Ok(Some(status)) = child.try_wait() {
+                // A supervisor logs the exit itself and needs the child to restart it.
+                if self.supervision.is_none() {
+                    self.logs
+                        .push(format!("--- server exited with {status} ---"));
+                    *slot = None;
+                }
+                return None;
+            }
         }
 
         let port = self.port.unwrap_or(8080);
@@ -111,16 +714,934 @@ impl ServerManager {
         })
     }
 
+    /// Runs [`check_health`] against the running server.
+    ///
+    /// Fails without a request when the server has stopped, naming its last error.
+    pub async fn health(&mut self, path: &str) -> anyhow::Result<HealthReport> {
+        let Some(status) = self.status() else {
+            return Err(match self.last_error() {
+                Some(err) => anyhow::anyhow!("server is stopped (last error: {err})"),
+                None => anyhow::anyhow!("server is stopped"),
+            });
+        };
+        check_health(&status.url, path).await
+    }
+
+    /// Restarts the server from its last source when it exits unexpectedly, at most
+    /// `max_restarts` times within `window`; `max_restarts = 0` turns supervision off.
+    ///
+    /// A running server is supervised right away (when called inside a Tokio runtime);
+    /// otherwise supervision starts with the next launch. Each restart is logged.
+    pub fn set_supervision(&mut self, max_restarts: u32, window: Duration) {
+        self.supervision = (max_restarts > 0).then_some(Supervision {
+            max_restarts,
+            window,
+        });
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        if lock_child(&self.child).is_some() {
+            self.spawn_supervisor();
+        }
+    }
+
+    /// Returns the active crash-restart limits, if supervision is on.
+    pub fn supervision(&self) -> Option<Supervision> {
+        self.supervision
+    }
+
+    /// Watches the current child in a background task when supervision is on.
+    fn spawn_supervisor(&self) {
+        let (Some(supervision), Some(spec)) = (self.supervision, self.last_launch.clone()) else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let epoch = Arc::clone(&self.epoch);
+        let generation = epoch.fetch_add(1, Ordering::SeqCst) + 1;
+        let child = Arc::clone(&self.child);
+        let logs = self.logs.clone();
+        let pid_file = self.pid_file.clone();
+        runtime.spawn(async move {
+            let mut budget = RestartBudget::new(supervision);
+            loop {
+                tokio::time::sleep(SUPERVISE_POLL).await;
+                let mut slot = lock_child(&child);
+                // Stops and relaunches bump the epoch before taking the child under this
+                // lock, so checking here cannot race with them.
+                if epoch.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+                let exit = match slot.as_mut().map(Child::try_wait) {
+                    Some(Ok(None)) => continue,
+                    Some(Ok(Some(status))) => status.to_string(),
+                    Some(Err(e)) => e.to_string(),
+                    // Someone else took the server; it is not ours to bring back.
+                    None => return,
+                };
+                *slot = None;
+                if !budget.allow(Instant::now()) {
+                    logs.push(format!(
+                        "--- server exited with {exit}; {} restarts within {}s, giving up ---",
+                        supervision.max_restarts,
+                        supervision.window.as_secs()
+                    ));
+                    return;
+                }
+                logs.push(format!(
+                    "--- server exited with {exit}; restarting ({}/{} within {}s) ---",
+                    budget.used(),
+                    supervision.max_restarts,
+                    supervision.window.as_secs()
+                ));
+                tracing::warn!(
+                    exit,
+                    port = spec.port,
+                    "supervised server exited, restarting"
+                );
+                match spec.spawn(&logs) {
+                    Ok(restarted) => {
+                        if let Some(path) = &pid_file {
+                            write_pid_file(path, &restarted, spec.port, spec.module.path());
+                        }
+                        *slot = Some(restarted);
+                    }
+                    Err(e) => {
+                        logs.push(format!(
+                            "--- server restart failed: {} ---",
+                            launch_error(e)
+                        ));
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Returns the permissions applied on the next start, restart, or hotfix.
+    pub fn permissions(&self) -> &DenoPermissions {
+        &self.permissions
+    }
+
+    /// Replaces the permissions applied on the next start, restart, or hotfix.
+    pub fn set_permissions(&mut self, permissions: DenoPermissions) {
+        self.permissions = permissions;
+    }
+
+    /// Returns the variables added with [`ServerManager::set_extra_env`].
+    pub fn extra_env(&self) -> &BTreeMap<String, String> {
+        &self.extra_env
+    }
+
+    /// Sets a variable applied on the next start, restart, or hotfix, on top of the
+    /// configured env; `PORT` still wins.
+    pub fn set_extra_env(&mut self, key: String, value: String) {
+        self.extra_env.insert(key, value);
+    }
+
+    /// Drops every variable added with [`ServerManager::set_extra_env`].
+    pub fn clear_extra_env(&mut self) {
+        self.extra_env.clear();
+    }
+
+    /// The configured env with `/serve-env` variables layered on top.
+    fn launch_env(&self) -> BTreeMap<String, String> {
+        let mut env = self.env.clone();
+        env.extend(self.extra_env.clone());
+        env
+    }
+
     /// Returns the last source code used to start the server.
     pub fn last_source(&self) -> Option<String> {
         self.source_code.clone()
     }
+
+    /// Returns the most recent captured server output lines, oldest first.
+    pub fn logs(&self) -> Vec<String> {
+        self.logs.snapshot()
+    }
+
+    /// Returns the last line the server wrote to stderr, if any.
+    pub fn last_error(&self) -> Option<String> {
+        self.logs.last_error()
+    }
+}
+
+/// Everything needed to spawn (or respawn) the server for one source file.
+///
+/// The module file lives until the last spec (including a supervisor's copy) is dropped.
+#[derive(Debug, Clone)]
+struct LaunchSpec {
+    module: Arc<TempModule>,
+    port: u16,
+    permissions: DenoPermissions,
+    env: BTreeMap<String, String>,
+    deno_config: Option<String>,
+    import_map: Option<String>,
+    no_remote: bool,
+    cached_only: bool,
+}
+
+impl LaunchSpec {
+    /// Starts `deno run` with output captured into `logs`.
+    fn spawn(&self, logs: &LogBuffer) -> std::io::Result<Child> {
+        let mut cmd = Command::new("deno");
+        cmd.arg("run")
+            .args(config_args(
+                self.deno_config.as_deref(),
+                self.import_map.as_deref(),
+            ))
+            .args(remote_args(self.no_remote, self.cached_only))
+            .args(permission_args(&self.permissions))
+            .arg(self.module.path())
+            .envs(&self.env)
+            .env("PORT", format!("{}", self.port))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdin(Stdio::null());
+        // Keep the server out of the terminal's process group so Ctrl-C in the
+        // REPL (which cancels a pending translation) does not reach it.
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = cmd.spawn()?;
+        if let Some(stdout) = child.stdout.take() {
+            spawn_log_reader(stdout, logs.clone(), false);
+        }
+        if let Some(stderr) = child.stderr.take() {
+            spawn_log_reader(stderr, logs.clone(), true);
+        }
+        Ok(child)
+    }
+}
+
+/// Sliding-window count of supervisor restarts.
+#[derive(Debug)]
+struct RestartBudget {
+    limits: Supervision,
+    recent: VecDeque<Instant>,
+}
+
+impl RestartBudget {
+    fn new(limits: Supervision) -> Self {
+        Self {
+            limits,
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Records a restart at `now` unless the window is already full.
+    fn allow(&mut self, now: Instant) -> bool {
+        while self
+            .recent
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= self.limits.window)
+        {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= self.limits.max_restarts as usize {
+            return false;
+        }
+        self.recent.push_back(now);
+        true
+    }
+
+    /// Restarts within the current window.
+    fn used(&self) -> usize {
+        self.recent.len()
+    }
+}
+
+/// Directory of server PID files, `~/.beeno/servers/`, one file per Beeno session.
+pub fn server_pid_dir() -> Option<PathBuf> {
+    std::env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".beeno").join("servers"))
+}
+
+/// This session's PID file in [`server_pid_dir`], named after the Beeno process id
+/// so concurrent sessions do not overwrite each other's record.
+pub fn server_pid_path() -> Option<PathBuf> {
+    server_pid_dir().map(|dir| dir.join(format!("{}.json", std::process::id())))
+}
+
+/// Contents of a PID file: the server, the Beeno process that owns it, its
+/// port, and the temp module it runs, which identifies it after PID reuse.
+#[derive(Debug, Serialize, Deserialize)]
+struct PidRecord {
+    pid: u32,
+    owner: u32,
+    port: u16,
+    module: PathBuf,
+}
+
+/// Records `child` as the running server; failures are logged and otherwise ignored.
+fn write_pid_file(path: &Path, child: &Child, port: u16, module: &Path) {
+    let Some(pid) = child.id() else {
+        return;
+    };
+    let record = PidRecord {
+        pid,
+        owner: std::process::id(),
+        port,
+        module: module.to_path_buf(),
+    };
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(path, serde_json::to_string(&record)?));
+    if let Err(e) = written {
+        tracing::warn!(path = %path.display(), error = %e, "could not write server pid file");
+    }
+}
+
+/// Removes the PID file if it still describes server `pid`.
+fn clear_pid_file(path: &Path, pid: u32) {
+    if read_pid_file(path).is_some_and(|record| record.pid == pid) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn read_pid_file(path: &Path) -> Option<PidRecord> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// How long [`OrphanedServer::stop`] waits after SIGTERM before sending SIGKILL.
+pub const ORPHAN_STOP_GRACE: Duration = Duration::from_secs(3);
+
+/// A server left running by a Beeno session that exited without stopping it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanedServer {
+    pub pid: u32,
+    pub port: u16,
+    /// Temp module the server was started with.
+    module: PathBuf,
+}
+
+impl OrphanedServer {
+    /// Returns the server recorded in `pid_file` when it is still running but
+    /// the session that started it is gone.
+    ///
+    /// The process must still be the recorded `deno` server: one whose command
+    /// line no longer names the recorded temp module has exited and its PID was
+    /// reused. Files describing exited servers are removed. Always `None` off
+    /// unix, where processes cannot be inspected.
+    pub fn find(pid_file: &Path) -> Option<Self> {
+        let record = read_pid_file(pid_file)?;
+        let server = Self {
+            pid: record.pid,
+            port: record.port,
+            module: record.module,
+        };
+        if !server.is_running() {
+            let _ = fs::remove_file(pid_file);
+            return None;
+        }
+        (record.owner != std::process::id() && !process_alive(record.owner)).then_some(server)
+    }
+
+    /// Every orphaned server recorded in `pid_dir`, with the file recording it.
+    pub fn find_all(pid_dir: &Path) -> Vec<(PathBuf, Self)> {
+        let Ok(entries) = fs::read_dir(pid_dir) else {
+            return Vec::new();
+        };
+        let mut found: Vec<(PathBuf, Self)> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| Self::find(&path).map(|server| (path, server)))
+            .collect();
+        found.sort_by_key(|(_, server)| server.pid);
+        found
+    }
+
+    /// Whether the recorded process is alive and still running the recorded module.
+    fn is_running(&self) -> bool {
+        process_alive(self.pid)
+            && command_line(self.pid)
+                .is_some_and(|cmdline| cmdline.contains(self.module.to_string_lossy().as_ref()))
+    }
+
+    /// Sends SIGTERM, then SIGKILL if the server is still running after `grace`,
+    /// and removes `pid_file`.
+    ///
+    /// The process is checked again first, so a PID reused since [`Self::find`]
+    /// is never signalled.
+    pub fn stop(&self, pid_file: &Path, grace: Duration) -> std::io::Result<()> {
+        if !self.is_running() {
+            clear_pid_file(pid_file, self.pid);
+            return Ok(());
+        }
+        signal_process(self.pid, Signal::Terminate)?;
+        let deadline = Instant::now() + grace;
+        while self.is_running() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        if self.is_running() {
+            signal_process(self.pid, Signal::Kill)?;
+        }
+        clear_pid_file(pid_file, self.pid);
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for OrphanedServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "server (pid {}) on port {}", self.pid, self.port)
+    }
+}
+
+/// Warns about each [`OrphanedServer`] recorded in `pid_dir` and offers to stop it.
+pub fn offer_orphan_cleanup(pid_dir: &Path) -> anyhow::Result<()> {
+    for (pid_file, orphan) in OrphanedServer::find_all(pid_dir) {
+        println!("warning: {orphan} was left running by an earlier session");
+        if crate::prompt::confirm("stop it?")? {
+            orphan.stop(&pid_file, ORPHAN_STOP_GRACE)?;
+            println!("stopped {orphan}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists and can be signalled.
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    false
+}
+
+/// The command line of process `pid`: from `/proc` on Linux, otherwise from `ps`.
+#[cfg(unix)]
+fn command_line(pid: u32) -> Option<String> {
+    if let Ok(raw) = fs::read(format!("/proc/{pid}/cmdline")) {
+        return Some(String::from_utf8_lossy(&raw).replace('\0', " "));
+    }
+    let output = std::process::Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "command="])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(not(unix))]
+fn command_line(_pid: u32) -> Option<String> {
+    None
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Signal {
+    Terminate,
+    Kill,
+}
+
+#[cfg(unix)]
+fn signal_process(pid: u32, signal: Signal) -> std::io::Result<()> {
+    let signal = match signal {
+        Signal::Terminate => libc::SIGTERM,
+        Signal::Kill => libc::SIGKILL,
+    };
+    // SAFETY: `kill` has no memory-safety preconditions.
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn signal_process(_pid: u32, _signal: Signal) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "stopping orphaned servers is only supported on unix",
+    ))
+}
+
+fn lock_child(child: &SharedChild) -> std::sync::MutexGuard<'_, Option<Child>> {
+    // A panic while holding the lock cannot leave the slot inconsistent.
+    child
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Returns `true` when nothing is currently listening on `port`.
+///
+/// Probes with a short-lived bind on all interfaces, matching the address
+/// `Deno.serve` listens on by default.
+pub fn port_available(port: u16) -> bool {
+    TcpListener::bind(("0.0.0.0", port)).is_ok()
+}
+
+/// Asks the OS for a currently free port by binding port `0` and releasing it.
+pub fn pick_free_port() -> std::io::Result<u16> {
+    Ok(TcpListener::bind(("0.0.0.0", 0))?.local_addr()?.port())
 }
 
-fn temp_server_module_path() -> PathBuf {
-    let millis = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_millis())
-        .unwrap_or(0);
-    std::env::temp_dir().join(format!("beeno-server-{millis}-{}.ts", std::process::id()))
+fn is_addr_in_use(line: &str) -> bool {
+    line.contains("AddrInUse") || line.to_ascii_lowercase().contains("address already in use")
+}
+
+fn spawn_log_reader<R>(reader: R, logs: LogBuffer, is_stderr: bool)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if is_stderr {
+                logs.push_error(line);
+            } else {
+                logs.push(line);
+            }
+        }
+    });
+}
+
+/// Bounded undo/redo stacks of previously served sources.
+#[derive(Debug, Default)]
+struct SourceHistory {
+    undo: VecDeque<String>,
+    redo: Vec<String>,
+}
+
+impl SourceHistory {
+    /// Records that `previous` was replaced by `next`, discarding any redo steps.
+    fn record(&mut self, previous: Option<String>, next: &str) {
+        if let Some(previous) = previous.filter(|p| p != next) {
+            push_bounded(&mut self.undo, previous, HISTORY_CAPACITY);
+            self.redo.clear();
+        }
+    }
+
+    /// Moves one step back, returning the source to serve.
+    fn step_back(&mut self, current: Option<String>) -> Option<String> {
+        let target = self.undo.pop_back()?;
+        self.redo.extend(current);
+        Some(target)
+    }
+
+    /// Moves one step forward, returning the source to serve.
+    fn step_forward(&mut self, current: Option<String>) -> Option<String> {
+        let target = self.redo.pop()?;
+        if let Some(current) = current {
+            push_bounded(&mut self.undo, current, HISTORY_CAPACITY);
+        }
+        Some(target)
+    }
+}
+
+fn push_bounded(buf: &mut VecDeque<String>, line: String, capacity: usize) {
+    buf.push_back(line);
+    while buf.len() > capacity {
+        buf.pop_front();
+    }
+}
+
+/// Sends SIGTERM to the child, returning whether the signal was delivered.
+#[cfg(unix)]
+fn request_terminate(child: &Child) -> bool {
+    match child.id() {
+        // SAFETY: `kill` has no memory-safety preconditions; the pid belongs to our child.
+        Some(pid) => unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) == 0 },
+        None => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn request_terminate(_child: &Child) -> bool {
+    false
+}
+
+/// Kills everything in the server's process group; it was spawned as the group
+/// leader, so this also reaches processes the server started itself.
+#[cfg(unix)]
+fn kill_process_group(child: &Child) {
+    if let Some(pid) = child.id() {
+        // SAFETY: `kill` has no memory-safety preconditions; the group is our child's.
+        unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_child: &Child) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::parse_env_assignment;
+    use crate::util::unique_temp_path;
+
+    #[test]
+    fn warns_only_for_source_without_server_markers() {
+        assert!(server_source_warning("Deno.serve(() => new Response('ok'));").is_none());
+        assert!(server_source_warning("app.listen(8080);").is_none());
+        assert!(server_source_warning("console.log('hello');").is_some());
+    }
+
+    #[test]
+    fn extra_env_overrides_configured_env_until_cleared() {
+        let mut manager = ServerManager::default().with_env(BTreeMap::from([
+            ("UPSTREAM".to_string(), "http://config".to_string()),
+            ("MODE".to_string(), "dev".to_string()),
+        ]));
+        let (key, value) = parse_env_assignment("UPSTREAM=http://a=b").expect("valid");
+        manager.set_extra_env(key, value);
+        let env = manager.launch_env();
+        assert_eq!(env["UPSTREAM"], "http://a=b");
+        assert_eq!(env["MODE"], "dev");
+
+        assert!(parse_env_assignment("=x").is_err());
+        assert!(parse_env_assignment("NO_VALUE").is_err());
+
+        manager.clear_extra_env();
+        assert_eq!(manager.launch_env()["UPSTREAM"], "http://config");
+    }
+
+    #[test]
+    fn log_buffer_keeps_last_lines() {
+        let mut buf = VecDeque::new();
+        for i in 0..5 {
+            push_bounded(&mut buf, format!("line {i}"), 3);
+        }
+        assert_eq!(
+            buf.into_iter().collect::<Vec<_>>(),
+            vec!["line 2", "line 3", "line 4"]
+        );
+    }
+
+    #[test]
+    fn source_history_undoes_and_redoes() {
+        let mut history = SourceHistory::default();
+        history.record(None, "v1");
+        history.record(Some("v1".to_string()), "v2");
+        history.record(Some("v2".to_string()), "v2");
+        history.record(Some("v2".to_string()), "v3");
+        assert_eq!(history.undo, ["v1", "v2"]);
+
+        assert_eq!(
+            history.step_back(Some("v3".to_string())).as_deref(),
+            Some("v2")
+        );
+        assert_eq!(
+            history.step_back(Some("v2".to_string())).as_deref(),
+            Some("v1")
+        );
+        assert_eq!(history.step_back(Some("v1".to_string())), None);
+        assert_eq!(
+            history.step_forward(Some("v1".to_string())).as_deref(),
+            Some("v2")
+        );
+
+        history.record(Some("v2".to_string()), "v4");
+        assert!(history.redo.is_empty());
+        assert_eq!(history.step_forward(Some("v4".to_string())), None);
+    }
+
+    #[test]
+    fn source_history_is_bounded() {
+        let mut history = SourceHistory::default();
+        for i in 0..(HISTORY_CAPACITY + 5) {
+            history.record(Some(format!("v{i}")), &format!("v{}", i + 1));
+        }
+        assert_eq!(history.undo.len(), HISTORY_CAPACITY);
+        assert_eq!(history.undo.front().map(String::as_str), Some("v5"));
+    }
+
+    #[test]
+    fn port_probe_detects_bound_port() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).expect("bind ephemeral port");
+        let port = listener.local_addr().expect("local addr").port();
+        assert!(!port_available(port));
+    }
+
+    #[test]
+    fn picked_port_is_free_and_nonzero() {
+        let port = pick_free_port().expect("free port");
+        assert_ne!(port, 0);
+        assert!(port_available(port));
+        assert!(is_addr_in_use(
+            "error: Uncaught (in promise) AddrInUse: Address already in use (os error 98)"
+        ));
+        assert!(!is_addr_in_use(
+            "error: Uncaught ReferenceError: x is not defined"
+        ));
+    }
+
+    #[cfg(unix)]
+    fn spawn_sh(script: &str) -> Child {
+        Command::new("sh")
+            .args(["-c", script])
+            .kill_on_drop(true)
+            .spawn()
+            .expect("spawn sh")
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn graceful_stop_force_kills_after_grace() {
+        let mut manager = ServerManager::default();
+        *lock_child(&manager.child) = Some(spawn_sh("trap '' TERM; exec sleep 30"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let started = std::time::Instant::now();
+        manager
+            .stop_graceful(Duration::from_millis(300))
+            .await
+            .expect("stop");
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(300), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+        assert!(lock_child(&manager.child).is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn graceful_stop_returns_once_process_exits() {
+        let mut manager = ServerManager::default();
+        *lock_child(&manager.child) = Some(spawn_sh("exec sleep 30"));
+
+        let started = std::time::Instant::now();
+        manager
+            .stop_graceful(Duration::from_secs(10))
+            .await
+            .expect("stop");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dropping_the_manager_kills_the_server_and_clears_its_pid_file() {
+        let dir = unique_temp_path("beeno-pid-drop");
+        let pid_file = dir.join("session.json");
+        let manager = ServerManager::default().with_pid_file(Some(pid_file.clone()));
+        let child = spawn_sh("exec sleep 30");
+        let pid = child.id().expect("pid");
+        write_pid_file(&pid_file, &child, 8080, Path::new("beeno-server-drop.ts"));
+        *lock_child(&manager.child) = Some(child);
+
+        drop(manager);
+        assert!(!pid_file.exists());
+        // Either we reap the killed process or tokio's orphan reaper already did.
+        let mut status = 0;
+        // SAFETY: waiting on our own child pid.
+        let reaped = unsafe { libc::waitpid(pid as libc::pid_t, &mut status, 0) };
+        assert!(reaped == pid as libc::pid_t || !process_alive(pid));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn supervisor_retires_when_the_server_was_taken() {
+        let mut manager = ServerManager::default();
+        manager.set_supervision(3, Duration::from_secs(60));
+        manager.last_launch = Some(LaunchSpec {
+            module: Arc::new(TempModule::create("beeno-supervise", "").expect("module")),
+            port: 0,
+            permissions: DenoPermissions::default(),
+            env: BTreeMap::new(),
+            deno_config: None,
+            import_map: None,
+            no_remote: false,
+            cached_only: false,
+        });
+        *lock_child(&manager.child) = Some(spawn_sh("exec sleep 30"));
+        manager.spawn_supervisor();
+
+        // Taken without bumping the epoch, as a stop racing the supervisor's poll would.
+        let mut taken = lock_child(&manager.child).take().expect("child");
+        taken.kill().await.expect("kill");
+        tokio::time::sleep(SUPERVISE_POLL * 3).await;
+
+        assert!(lock_child(&manager.child).is_none());
+        let logs = manager.logs.snapshot();
+        assert!(
+            !logs.iter().any(|line| line.contains("restart")),
+            "{logs:?}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn kill_switch_stops_the_server_from_another_task() {
+        let manager = ServerManager::default();
+        let child = Command::new("sh")
+            .args(["-c", "exec sleep 30"])
+            .process_group(0)
+            .spawn()
+            .expect("spawn sh");
+        let pid = child.id().expect("pid");
+        let epoch = manager.epoch.load(Ordering::SeqCst);
+        *lock_child(&manager.child) = Some(child);
+
+        let switch = manager.kill_switch();
+        tokio::spawn(async move { switch.kill() })
+            .await
+            .expect("kill task");
+        assert!(lock_child(&manager.child).is_none());
+        assert_eq!(manager.epoch.load(Ordering::SeqCst), epoch + 1);
+        let mut status = 0;
+        // SAFETY: waiting on our own child pid.
+        let reaped = unsafe { libc::waitpid(pid as libc::pid_t, &mut status, 0) };
+        assert!(reaped == pid as libc::pid_t || !process_alive(pid));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn orphans_are_servers_whose_owner_is_gone() {
+        let dir = unique_temp_path("beeno-pid-orphan");
+        fs::create_dir_all(&dir).expect("create temp dir");
+        let pid_file = dir.join("1.json");
+        let module = dir.join("beeno-server-orphan.ts");
+        // `sh -c script $0`: the module path shows up in the command line like deno's argument.
+        let mut server = std::process::Command::new("sh")
+            .args(["-c", "sleep 30; :"])
+            .arg(&module)
+            .spawn()
+            .expect("spawn");
+        let mut exited = std::process::Command::new("true").spawn().expect("spawn");
+        exited.wait().expect("wait");
+        let (alive, dead) = (server.id(), exited.id());
+        let write = |pid: u32, owner: u32, module: &Path| {
+            let record = PidRecord {
+                pid,
+                owner,
+                port: 8080,
+                module: module.to_path_buf(),
+            };
+            fs::write(&pid_file, serde_json::to_string(&record).unwrap()).unwrap();
+        };
+
+        write(alive, dead, &module);
+        let orphans = OrphanedServer::find_all(&dir);
+        assert_eq!(
+            orphans,
+            vec![(
+                pid_file.clone(),
+                OrphanedServer {
+                    pid: alive,
+                    port: 8080,
+                    module: module.clone(),
+                }
+            )]
+        );
+        assert_eq!(
+            orphans[0].1.to_string(),
+            format!("server (pid {alive}) on port 8080")
+        );
+
+        // Still owned by a live session.
+        write(alive, std::process::id(), &module);
+        assert_eq!(OrphanedServer::find(&pid_file), None);
+
+        // The PID now belongs to some other program: never offered for killing.
+        write(alive, dead, &dir.join("another-module.ts"));
+        assert_eq!(OrphanedServer::find(&pid_file), None);
+        assert!(!pid_file.exists());
+
+        // Stopping sends SIGTERM, which ends the server well within the grace period.
+        write(alive, dead, &module);
+        let orphan = OrphanedServer::find(&pid_file).expect("orphan");
+        let started = Instant::now();
+        orphan
+            .stop(&pid_file, Duration::from_secs(10))
+            .expect("stop");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!pid_file.exists());
+        assert!(!server.wait().expect("reap").success());
+
+        // The server is gone: the stale file is removed.
+        write(dead, dead, &module);
+        assert_eq!(OrphanedServer::find(&pid_file), None);
+        assert!(!pid_file.exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    /// Accepts one connection, reads the request head, and writes `response`.
+    async fn respond_once(response: &'static str) -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        tokio::spawn(async move {
+            if let Ok((mut socket, _)) = listener.accept().await {
+                let _ = read_response_head(&mut socket).await;
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn health_check_reports_status_and_body_start() {
+        const HTTP_TEAPOT: &str =
+            "HTTP/1.1 418 I'm a teapot\r\ncontent-length: 12\r\nconnection: close\r\n\r\nshort and st";
+        let port = respond_once(HTTP_TEAPOT).await;
+        let report = check_health(&format!("http://127.0.0.1:{port}/"), "status")
+            .await
+            .expect("responds");
+        assert_eq!(report.url, format!("http://127.0.0.1:{port}/status"));
+        assert_eq!((report.status, report.body.as_str()), (418, "short and st"));
+        assert!(!report.truncated);
+        assert!(report
+            .to_string()
+            .starts_with(&format!("GET {} -> 418 I'm a teapot (", report.url)));
+    }
+
+    #[tokio::test]
+    async fn ready_checks_distinguish_http_and_websocket_servers() {
+        const HTTP_OK: &str = "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+        const WS_UPGRADE: &str = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+
+        let http = ReadyCheck::HttpGet {
+            path: "health".to_string(),
+        };
+        let ws = ReadyCheck::WebSocket {
+            path: "/ws".to_string(),
+        };
+        assert!(http.probe(respond_once(HTTP_OK).await).await);
+        assert!(!ws.probe(respond_once(HTTP_OK).await).await);
+        assert!(ws.probe(respond_once(WS_UPGRADE).await).await);
+        assert!(
+            ReadyCheck::TcpConnect
+                .probe(respond_once(HTTP_OK).await)
+                .await
+        );
+
+        let closed = pick_free_port().expect("free port");
+        assert!(!ReadyCheck::TcpConnect.probe(closed).await);
+        let refused = check_health(&format!("http://127.0.0.1:{closed}"), "/")
+            .await
+            .expect_err("nothing listening");
+        assert!(refused.to_string().starts_with("connection refused at"));
+        assert_eq!(http.to_string(), "http GET /health");
+    }
+
+    #[test]
+    fn restart_budget_limits_restarts_per_window() {
+        let mut budget = RestartBudget::new(Supervision {
+            max_restarts: 2,
+            window: Duration::from_secs(60),
+        });
+        let start = Instant::now();
+        assert!(budget.allow(start));
+        assert!(budget.allow(start + Duration::from_secs(10)));
+        assert!(!budget.allow(start + Duration::from_secs(20)));
+        assert_eq!(budget.used(), 2);
+        // The first restart leaves the window.
+        assert!(budget.allow(start + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn stderr_lines_track_last_error() {
+        let logs = LogBuffer::default();
+        logs.push("listening".to_string());
+        logs.push_error("error: AddrInUse".to_string());
+        assert_eq!(logs.last_error().as_deref(), Some("error: AddrInUse"));
+        assert_eq!(logs.snapshot().len(), 2);
+    }
 }
@@ -0,0 +1,249 @@
+//! Line mapping from inlined tagged scripts back to the user's file.
+//!
+//! `process_tagged_script` replaces each `/*nl ... */` block with generated code,
+//! which shifts every line after it. A [`SourceMap`] records which generated lines
+//! came from user-written text and which from an nl block, so Deno stack traces
+//! against the temp module can be rewritten to point at the original file.
+
+use serde::Serialize;
+use std::ops::Range;
+
+/// A run of generated lines and the original line it starts at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LineSpan {
+    /// 1-based generated lines, end-exclusive.
+    pub generated: Range<usize>,
+    /// 1-based original line of the first generated line (the `/*nl` tag for blocks).
+    pub original_line: usize,
+    /// Whether the lines were generated from an nl block.
+    pub nl_block: bool,
+}
+
+/// Where a generated line came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OriginalLine {
+    pub line: usize,
+    pub nl_block: bool,
+}
+
+/// Points Deno stack locations in a run's stderr back at the user's file; see
+/// [`crate::types::ExecutionRequest::stack_mapping`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackMapping {
+    pub source_map: SourceMap,
+    /// Name the rewritten locations use, usually the script's path.
+    pub original: String,
+}
+
+/// Block-level mapping from an inlined script to its source file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SourceMap {
+    pub spans: Vec<LineSpan>,
+}
+
+impl SourceMap {
+    /// Records `text` copied verbatim, starting at the given generated and original lines.
+    pub(crate) fn push_verbatim(
+        &mut self,
+        generated_line: usize,
+        original_line: usize,
+        text: &str,
+    ) {
+        if text.is_empty() {
+            return;
+        }
+        self.spans.push(LineSpan {
+            generated: generated_line..generated_line + line_count(text),
+            original_line,
+            nl_block: false,
+        });
+    }
+
+    /// Records generated `code` for the nl block tagged at `original_line`.
+    pub(crate) fn push_block(&mut self, generated_line: usize, original_line: usize, code: &str) {
+        self.spans.push(LineSpan {
+            generated: generated_line..generated_line + line_count(code),
+            original_line,
+            nl_block: true,
+        });
+    }
+
+    /// Makes room for `lines` inserted lines at generated line `at`, splitting a
+    /// span that runs across it, and adds `header`'s spans (numbered from 1) there.
+    pub(crate) fn insert(&mut self, at: usize, header: SourceMap, lines: usize) {
+        let mut spans = Vec::with_capacity(self.spans.len() + header.spans.len() + 1);
+        for span in self.spans.drain(..) {
+            let LineSpan {
+                generated,
+                original_line,
+                nl_block,
+            } = span;
+            if generated.end <= at {
+                spans.push(LineSpan {
+                    generated,
+                    original_line,
+                    nl_block,
+                });
+            } else if generated.start >= at {
+                spans.push(LineSpan {
+                    generated: generated.start + lines..generated.end + lines,
+                    original_line,
+                    nl_block,
+                });
+            } else {
+                spans.push(LineSpan {
+                    generated: generated.start..at,
+                    original_line,
+                    nl_block,
+                });
+                let skipped = if nl_block { 0 } else { at - generated.start };
+                spans.push(LineSpan {
+                    generated: at + lines..generated.end + lines,
+                    original_line: original_line + skipped,
+                    nl_block,
+                });
+            }
+        }
+        spans.extend(header.spans.into_iter().map(|span| LineSpan {
+            generated: span.generated.start + at - 1..span.generated.end + at - 1,
+            ..span
+        }));
+        spans.sort_by_key(|span| span.generated.start);
+        self.spans = spans;
+    }
+
+    /// Maps a 1-based generated line back to the original file.
+    ///
+    /// Lines shared by user text and an nl block (inline `/*nl */` tags) resolve to the block.
+    pub fn lookup(&self, generated_line: usize) -> Option<OriginalLine> {
+        let containing = |span: &&LineSpan| span.generated.contains(&generated_line);
+        if let Some(span) = self.spans.iter().filter(|s| s.nl_block).find(containing) {
+            return Some(OriginalLine {
+                line: span.original_line,
+                nl_block: true,
+            });
+        }
+        self.spans.iter().find(containing).map(|span| OriginalLine {
+            line: span.original_line + (generated_line - span.generated.start),
+            nl_block: false,
+        })
+    }
+
+    /// Rewrites `module:line:col` locations of the temp module in `text` to `original`.
+    ///
+    /// Locations inside an nl block point at the block's `/*nl` line and drop the column.
+    pub fn rewrite_locations(&self, text: &str, module_name: &str, original: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(pos) = rest.find(module_name) {
+            let token_start = rest[..pos]
+                .rfind(|c: char| c.is_whitespace() || matches!(c, '(' | '"' | '\'' | '`'))
+                .map(|i| i + 1)
+                .unwrap_or(0);
+            let after = &rest[pos + module_name.len()..];
+            let Some((line, col, consumed)) = parse_line_col(after) else {
+                out.push_str(&rest[..pos + module_name.len()]);
+                rest = after;
+                continue;
+            };
+            out.push_str(&rest[..token_start]);
+            match self.lookup(line) {
+                Some(OriginalLine {
+                    line,
+                    nl_block: true,
+                }) => out.push_str(&format!("{original}:{line} (in nl block)")),
+                Some(OriginalLine { line, .. }) => {
+                    out.push_str(&format!("{original}:{line}"));
+                    if let Some(col) = col {
+                        out.push_str(&format!(":{col}"));
+                    }
+                }
+                None => out.push_str(&rest[token_start..pos + module_name.len() + consumed]),
+            }
+            rest = &after[consumed..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Number of lines `text` touches, counting a partial last line.
+fn line_count(text: &str) -> usize {
+    text.matches('\n').count() + usize::from(!text.ends_with('\n'))
+}
+
+/// Parses `:line[:col]` at the start of `text`, returning the bytes consumed.
+fn parse_line_col(text: &str) -> Option<(usize, Option<usize>, usize)> {
+    let (line, mut consumed) = parse_number(text.strip_prefix(':')?)?;
+    consumed += 1;
+    let col = text[consumed..]
+        .strip_prefix(':')
+        .and_then(parse_number)
+        .map(|(col, len)| {
+            consumed += len + 1;
+            col
+        });
+    Some((line, col, consumed))
+}
+
+fn parse_number(text: &str) -> Option<(usize, usize)> {
+    let len = text.bytes().take_while(u8::is_ascii_digit).count();
+    text[..len].parse().ok().map(|n| (n, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_map() -> SourceMap {
+        // Original: line 1 user code, lines 2-4 an nl block, line 5 user code.
+        // Generated: line 1 user code, lines 2-3 block output, line 4 user code.
+        let mut map = SourceMap::default();
+        map.push_verbatim(1, 1, "const before = 1;\n");
+        map.push_block(2, 2, "const a = 1;\nthrow new Error(a);");
+        map.push_verbatim(3, 4, "\nconst after = 2;\n");
+        map
+    }
+
+    #[test]
+    fn lookup_resolves_user_lines_and_blocks() {
+        let map = sample_map();
+        assert_eq!(
+            map.lookup(1),
+            Some(OriginalLine {
+                line: 1,
+                nl_block: false
+            })
+        );
+        assert_eq!(
+            map.lookup(3),
+            Some(OriginalLine {
+                line: 2,
+                nl_block: true
+            })
+        );
+        assert_eq!(
+            map.lookup(4),
+            Some(OriginalLine {
+                line: 5,
+                nl_block: false
+            })
+        );
+        assert_eq!(map.lookup(9), None);
+    }
+
+    #[test]
+    fn stack_locations_point_at_the_original_file() {
+        let map = sample_map();
+        let trace = "error: Uncaught Error: 1\n    at file:///tmp/beeno-t.ts:3:7\n    at foo (file:///tmp/beeno-t.ts:4:1)\n";
+        let rewritten = map.rewrite_locations(trace, "beeno-t.ts", "main.ts");
+        assert_eq!(
+            rewritten,
+            "error: Uncaught Error: 1\n    at main.ts:2 (in nl block)\n    at foo (main.ts:5:1)\n"
+        );
+        assert_eq!(
+            map.rewrite_locations("see beeno-t.ts for details", "beeno-t.ts", "main.ts"),
+            "see beeno-t.ts for details"
+        );
+    }
+}
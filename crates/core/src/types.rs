@@ -1,6 +1,8 @@
+use crate::sourcemap::StackMapping;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::BTreeMap;
+use std::path::PathBuf;
 
 /// Optional metadata about the source being translated.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,20 +18,54 @@ pub struct TranslateRequest {
     pub mode: String,
     pub session_summary: SessionSummary,
     pub file_metadata: Option<FileMetadata>,
+    /// Correlates this translation with its execution in logs and output.
+    #[serde(default)]
+    pub trace_id: Option<String>,
 }
 
 /// Normalized translation output returned by provider adapters.
+///
+/// Fields may be added in minor releases, so providers outside this crate start
+/// from [`TranslateResult::new`] and set the fields they know.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct TranslateResult {
     pub code: String,
     pub explanation: Option<String>,
     pub confidence: Option<f32>,
     pub tokens: Option<u32>,
     pub raw_provider_meta: BTreeMap<String, Value>,
+    /// Caveats the model attached to the code, e.g. "requires network access".
+    #[serde(default)]
+    pub warnings: Vec<String>,
 }
 
-/// Safety classification for generated/executed source.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+impl TranslateResult {
+    /// A result carrying only `code`, with no explanation, confidence, token
+    /// count, metadata, or caveats.
+    pub fn new(code: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            explanation: None,
+            confidence: None,
+            tokens: None,
+            raw_provider_meta: BTreeMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// The model's caveats as [`WarningKind::Provider`] warnings.
+    pub fn caveats(&self) -> Vec<Warning> {
+        self.warnings
+            .iter()
+            .map(|caveat| Warning::new(WarningKind::Provider, caveat))
+            .collect()
+    }
+}
+
+/// Safety classification for generated/executed source, ordered from least to
+/// most severe.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, PartialOrd, Ord)]
 pub enum RiskLevel {
     Safe,
     Risky,
@@ -42,6 +78,91 @@ pub struct RiskReport {
     pub level: RiskLevel,
     pub reasons: Vec<String>,
     pub requires_confirmation: bool,
+    /// Where each entry of `reasons` came from, by index; empty when the policy
+    /// does not track it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<PolicySource>,
+}
+
+/// Origin of a [`RiskReport`] reason.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "location", rename_all = "lowercase")]
+pub enum PolicySource {
+    /// Built-in pattern lists.
+    Default,
+    /// Policy file loaded from this path.
+    File(String),
+    /// Remote policy fetched from (or cached for) this URL.
+    Url(String),
+    /// The source itself failed to parse; no pattern list was consulted.
+    Parser,
+}
+
+impl std::fmt::Display for PolicySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Default => f.write_str("default"),
+            Self::File(path) => write!(f, "file {path}"),
+            Self::Url(url) => write!(f, "url {url}"),
+            Self::Parser => f.write_str("parser"),
+        }
+    }
+}
+
+/// Category of a [`Warning`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningKind {
+    /// Code matched a risky policy pattern but was not blocked.
+    Risky,
+    /// The provider reported less confidence than `policy.min_confidence`.
+    LowConfidence,
+    /// A `/*nl` block without a closing `*/`, left unchanged.
+    UnterminatedBlock,
+    /// A caveat the model reported alongside its translation.
+    Provider,
+    /// Two imports bind the same name to different modules.
+    ImportConflict,
+    /// Code started as a server does not call a known server entry point.
+    NoServerEntryPoint,
+}
+
+/// Non-fatal notice raised while preparing or running code.
+///
+/// Printed to stderr in human mode and listed under `warnings` in `--json` output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+    /// Where the warning applies, such as `script.ts:12`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+impl Warning {
+    /// Creates a warning without a location.
+    pub fn new(kind: WarningKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            location: None,
+        }
+    }
+
+    /// Attaches the location the warning applies to.
+    pub fn at(mut self, location: impl Into<String>) -> Self {
+        self.location = Some(location.into());
+        self
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{location}: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
 }
 
 /// Execution request sent to the runtime backend.
@@ -50,10 +171,116 @@ pub struct ExecutionRequest {
     pub source: String,
     pub deno_permissions: DenoPermissions,
     pub origin: String,
+    /// Correlates this execution with the translation that produced it.
+    #[serde(default)]
+    pub trace_id: Option<String>,
+    /// Extra environment variables set on the Deno process.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// `deno.json` passed to Deno as `--config`.
+    #[serde(default)]
+    pub deno_config: Option<String>,
+    /// Import map passed to Deno as `--import-map`.
+    #[serde(default)]
+    pub import_map: Option<String>,
+    /// Pass `--no-remote` so only local modules can be imported.
+    #[serde(default)]
+    pub no_remote: bool,
+    /// Pass `--cached-only` so remote modules must already be in Deno's cache.
+    #[serde(default)]
+    pub cached_only: bool,
+    /// File that also receives the program's stdout and stderr (`--tee`).
+    #[serde(default)]
+    pub tee: Option<PathBuf>,
+    /// Pipe the program's output through beeno so a failure carries its tail, as
+    /// `--heal` needs. Otherwise stdio is inherited and the program sees the terminal.
+    #[serde(default)]
+    pub capture_output: bool,
+    /// Echo output to the terminal while the program runs, as the CLI does, instead
+    /// of only returning it in [`ExecutionOutput`].
+    #[serde(default)]
+    pub live: bool,
+    /// Rewrites temp-module locations on stderr so stack traces from an inlined
+    /// tagged script name the original file. Only applies to `live` runs.
+    #[serde(skip)]
+    pub stack_mapping: Option<StackMapping>,
+    /// Extension of the temp module the source is written to, such as `tsx` for a
+    /// JSX script, so Deno parses it as the original file; `None` means `ts`.
+    #[serde(default)]
+    pub module_extension: Option<String>,
+}
+
+impl ExecutionRequest {
+    /// A request to run `source` with `deno_permissions`, leaving every other
+    /// option at its default: no trace id, env, config, tee, or output capture.
+    ///
+    /// Set the rest with struct update syntax:
+    /// `ExecutionRequest { live: true, ..ExecutionRequest::new(source, permissions, "run") }`.
+    pub fn new(
+        source: impl Into<String>,
+        deno_permissions: DenoPermissions,
+        origin: impl Into<String>,
+    ) -> Self {
+        Self {
+            source: source.into(),
+            deno_permissions,
+            origin: origin.into(),
+            trace_id: None,
+            env: BTreeMap::new(),
+            deno_config: None,
+            import_map: None,
+            no_remote: false,
+            cached_only: false,
+            tee: None,
+            capture_output: false,
+            live: false,
+            stack_mapping: None,
+            module_extension: None,
+        }
+    }
+}
+
+/// Generates a fresh random trace id for correlating a request across phases.
+pub fn new_trace_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Captured result of a finished runtime execution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionOutput {
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// What a failed `deno run` printed, for callers that react to the error text.
+///
+/// Output was already echoed to the terminal while the process ran; `stdout`
+/// and `stderr` keep the last part of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionFailure {
+    /// `None` when the process was terminated by a signal.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl std::fmt::Display for ExecutionFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.exit_code {
+            Some(code) => write!(f, "deno run exited with code {code}"),
+            None => write!(f, "deno run was terminated by a signal"),
+        }
+    }
 }
 
 /// Coarse Deno permission model exposed by Beeno commands.
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+///
+/// A `"*"` entry in `allow_read`/`allow_write`/`allow_net` grants that
+/// capability without an allow list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct DenoPermissions {
     pub allow_read: Vec<String>,
     pub allow_write: Vec<String>,
@@ -62,6 +289,68 @@ pub struct DenoPermissions {
     pub allow_run: bool,
 }
 
+impl DenoPermissions {
+    /// Adds `other`'s grants to these: allow lists are unioned and flags OR-ed.
+    pub fn merged_with(mut self, other: DenoPermissions) -> Self {
+        for (list, extra) in [
+            (&mut self.allow_read, other.allow_read),
+            (&mut self.allow_write, other.allow_write),
+            (&mut self.allow_net, other.allow_net),
+        ] {
+            for entry in extra {
+                if !list.contains(&entry) {
+                    list.push(entry);
+                }
+            }
+        }
+        self.allow_env |= other.allow_env;
+        self.allow_run |= other.allow_run;
+        self
+    }
+
+    /// Updates a single grant (`read`, `write`, `net`, `env`, `run`).
+    ///
+    /// `value` is `on`/`off`, or for read/write/net a comma-separated allow list.
+    pub fn set_grant(&mut self, grant: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let list = match grant {
+            "read" => &mut self.allow_read,
+            "write" => &mut self.allow_write,
+            "net" => &mut self.allow_net,
+            "env" | "run" => {
+                let enabled = match value {
+                    "on" => true,
+                    "off" => false,
+                    other => return Err(format!("expected on|off for {grant}, got `{other}`")),
+                };
+                if grant == "env" {
+                    self.allow_env = enabled;
+                } else {
+                    self.allow_run = enabled;
+                }
+                return Ok(());
+            }
+            other => {
+                return Err(format!(
+                    "unknown permission `{other}`; expected read, write, net, env, or run"
+                ))
+            }
+        };
+        *list = match value {
+            "on" => vec!["*".to_string()],
+            "off" => Vec::new(),
+            "" => return Err(format!("missing value for {grant}")),
+            entries => entries
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(ToString::to_string)
+                .collect(),
+        };
+        Ok(())
+    }
+}
+
 /// Rolling context sent to providers during interactive sessions.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SessionSummary {
@@ -70,6 +359,9 @@ pub struct SessionSummary {
     pub side_effects: Vec<String>,
     pub recent_intents: Vec<String>,
     pub server: Option<ServerContext>,
+    /// Model-written summary of the session so far, when LLM summarization is on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub narrative: Option<String>,
 }
 
 /// Runtime web-server context attached to session summaries.
@@ -81,37 +373,86 @@ pub struct ServerContext {
     pub mode: String,
 }
 
+/// Version of the [`JsonEnvelope`] layout; bumped on breaking changes.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
 /// Standard JSON envelope used by machine-readable CLI output.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JsonEnvelope {
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
     pub status: String,
     pub phase: String,
     pub message: String,
     pub details: Value,
 }
 
+impl JsonEnvelope {
+    /// Builds an envelope stamped with the current [`JSON_SCHEMA_VERSION`].
+    pub fn new(
+        status: impl Into<String>,
+        phase: impl Into<String>,
+        message: impl Into<String>,
+        details: Value,
+    ) -> Self {
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            status: status.into(),
+            phase: phase.into(),
+            message: message.into(),
+            details,
+        }
+    }
+}
+
+fn default_schema_version() -> u32 {
+    JSON_SCHEMA_VERSION
+}
+
 /// REPL-related configuration values.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ReplConfig {
     pub summary_window: usize,
+    /// Ask the model to summarize the session every this many inputs; `0` disables it.
+    pub narrative_every: usize,
+    /// Save the session to `~/.beeno/session.json` on exit.
+    pub persist_session: bool,
+    /// Reload the saved session on start (implies `persist_session`).
+    pub resume: bool,
+    /// Longest single session-summary entry, in chars; longer ones are cut (`0` = no limit).
+    pub max_entry_chars: usize,
+    /// Total session-summary size in chars; oldest entries are dropped past it (`0` = no limit).
+    pub max_summary_chars: usize,
 }
 
 impl Default for ReplConfig {
     fn default() -> Self {
-        Self { summary_window: 8 }
+        Self {
+            summary_window: 8,
+            narrative_every: 0,
+            persist_session: false,
+            resume: false,
+            max_entry_chars: 500,
+            max_summary_chars: 4000,
+        }
     }
 }
 
 /// Timeout settings used by network/provider operations.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TimeoutConfig {
     pub translate_ms: u64,
+    /// How long a managed server gets to exit after SIGTERM before it is killed.
+    pub server_grace_ms: u64,
 }
 
 impl Default for TimeoutConfig {
     fn default() -> Self {
         Self {
             translate_ms: 15_000,
+            server_grace_ms: 5_000,
         }
     }
 }
@@ -125,8 +466,57 @@ pub struct LlmConfig {
     pub model: String,
     pub temperature: f32,
     pub max_tokens: u32,
+    /// Soft cap on `max_tokens`; larger values must be confirmed (`0` disables the check).
+    pub max_tokens_warn: u32,
     pub endpoint_env_var: String,
     pub api_key_env_var: String,
+    /// Ordered `provider` or `provider:model` entries used when `provider = "fallback"`.
+    pub fallback: Vec<String>,
+    /// Ask OpenAI-compatible providers for a `{"code", "explanation"}` JSON object.
+    pub json_mode: bool,
+    /// Stream Ollama generations chunk by chunk instead of waiting for one reply.
+    pub stream: bool,
+    /// Ask the provider to continue replies cut off by the token limit or a dropped stream.
+    pub auto_continue: bool,
+    /// Azure OpenAI deployment name; required when `provider = "azure"`.
+    pub deployment: Option<String>,
+    /// Azure OpenAI `api-version` query parameter (provider default when unset).
+    pub api_version: Option<String>,
+    /// Space provider requests to at most this many per minute; `0` disables throttling.
+    pub requests_per_minute: u32,
+    /// Language generated code is delivered in; `javascript` transpiles any TS the model returns.
+    pub target_language: TargetLanguage,
+    /// Local command run per request when `provider = "command"`.
+    pub command: LlmCommandConfig,
+    /// `[llm.aliases]`: short names expanded to full model strings.
+    pub aliases: BTreeMap<String, String>,
+    /// `[llm.endpoints]`: default endpoint per provider name, used when neither
+    /// `endpoint` nor its env var is set.
+    pub endpoints: BTreeMap<String, String>,
+}
+
+impl LlmConfig {
+    /// Expands `model` through `aliases`; names without an alias pass through unchanged.
+    pub fn resolve_model<'a>(&'a self, model: &'a str) -> &'a str {
+        self.aliases.get(model.trim()).map_or(model, String::as_str)
+    }
+
+    /// The `[llm.endpoints]` entry for `provider`, ignoring case and blank values.
+    pub fn endpoint_for(&self, provider: &str) -> Option<&str> {
+        self.endpoints
+            .iter()
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case(provider.trim()))
+            .map(|(_, url)| url.trim())
+            .filter(|url| !url.is_empty())
+    }
+}
+
+/// `[llm.command]`: a local program that reads the prompt on stdin and prints code.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct LlmCommandConfig {
+    pub command: String,
+    pub args: Vec<String>,
 }
 
 impl Default for LlmConfig {
@@ -137,10 +527,104 @@ impl Default for LlmConfig {
             model: "gpt-4.1-mini".to_string(),
             temperature: 0.1,
             max_tokens: 512,
+            max_tokens_warn: 4096,
             endpoint_env_var: "DENO_NL_ENDPOINT".to_string(),
             api_key_env_var: "DENO_NL_API_KEY".to_string(),
+            fallback: Vec::new(),
+            json_mode: false,
+            stream: false,
+            auto_continue: false,
+            deployment: None,
+            api_version: None,
+            requests_per_minute: 0,
+            target_language: TargetLanguage::TypeScript,
+            command: LlmCommandConfig::default(),
+            aliases: BTreeMap::new(),
+            endpoints: BTreeMap::new(),
+        }
+    }
+}
+
+/// Language providers are asked to generate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetLanguage {
+    /// JavaScript or TypeScript, run as returned.
+    #[default]
+    TypeScript,
+    /// Plain JavaScript; TypeScript syntax in replies is stripped by transpiling.
+    JavaScript,
+}
+
+impl TargetLanguage {
+    /// Parses `typescript`/`ts` or `javascript`/`js`.
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "typescript" | "ts" => Some(Self::TypeScript),
+            "javascript" | "js" => Some(Self::JavaScript),
+            _ => None,
+        }
+    }
+}
+
+/// When to ask before executing generated or analyzed code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConfirmMode {
+    /// Never prompt.
+    Never,
+    /// Prompt when the policy marks the code as requiring confirmation.
+    #[default]
+    Risky,
+    /// Prompt before every execution.
+    Always,
+    /// Prompt only when the code performs network or file-write operations.
+    NetOrWrite,
+}
+
+impl ConfirmMode {
+    /// Parses `never`, `risky`, `always`, or `net-or-write` (underscores accepted).
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "never" => Some(Self::Never),
+            "risky" => Some(Self::Risky),
+            "always" => Some(Self::Always),
+            "net-or-write" => Some(Self::NetOrWrite),
+            _ => None,
+        }
+    }
+}
+
+/// How much Beeno itself prints; errors and the program's own output always show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    /// `--quiet`: no banners, status acknowledgements, warnings, or success line.
+    Quiet,
+    #[default]
+    Normal,
+    /// `--verbose`: also report translation and execution timings.
+    Verbose,
+}
+
+impl Verbosity {
+    /// Maps the global `--quiet` / `--verbose` flags; quiet wins if both are set.
+    pub fn from_flags(quiet: bool, verbose: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Self::Quiet,
+            (false, true) => Self::Verbose,
+            (false, false) => Self::Normal,
         }
     }
+
+    /// Whether non-essential output such as banners and warnings should print.
+    pub fn chatty(self) -> bool {
+        self != Self::Quiet
+    }
+
+    /// Whether timings and other diagnostic detail should print.
+    pub fn is_verbose(self) -> bool {
+        self == Self::Verbose
+    }
 }
 
 /// Policy configuration controlling pre-execution checks.
@@ -148,7 +632,18 @@ impl Default for LlmConfig {
 #[serde(default)]
 pub struct PolicySettings {
     pub policy_path: Option<String>,
+    /// Legacy toggle; `false` behaves like `confirm_mode = "never"` when no mode is set.
     pub confirm_risky: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirm_mode: Option<ConfirmMode>,
+    /// Translations reporting a confidence below this value trigger a warning.
+    pub min_confidence: f32,
+    /// Risky patterns containing any of these substrings warn instead of prompting.
+    pub allow_risky: Vec<String>,
+    /// Set by `--explain-policy`; prints the resolved policy and tags each reason
+    /// with its source.
+    #[serde(skip)]
+    pub explain: bool,
 }
 
 impl Default for PolicySettings {
@@ -156,10 +651,25 @@ impl Default for PolicySettings {
         Self {
             policy_path: None,
             confirm_risky: true,
+            confirm_mode: None,
+            min_confidence: 0.5,
+            allow_risky: Vec::new(),
+            explain: false,
         }
     }
 }
 
+impl PolicySettings {
+    /// The confirmation mode in effect, falling back to `confirm_risky` when unset.
+    pub fn effective_confirm_mode(&self) -> ConfirmMode {
+        self.confirm_mode.unwrap_or(if self.confirm_risky {
+            ConfirmMode::Risky
+        } else {
+            ConfirmMode::Never
+        })
+    }
+}
+
 /// Self-heal behavior toggles and retry controls.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -198,12 +708,13 @@ impl Default for ArtifactConfig {
     }
 }
 
-/// Hard limits for auto-generated edits during self-heal.
+/// Hard limits for generated source: self-heal edits, translations, and `scaffold` projects.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct LimitsConfig {
     pub max_files: usize,
     pub max_changed_lines: usize,
+    pub max_source_bytes: usize,
 }
 
 impl Default for LimitsConfig {
@@ -211,6 +722,30 @@ impl Default for LimitsConfig {
         Self {
             max_files: 10,
             max_changed_lines: 500,
+            max_source_bytes: 256 * 1024,
+        }
+    }
+}
+
+/// Default permissions granted to code executed from each origin.
+///
+/// Flags given on the command line add to these; `--no-default-perms` ignores them.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct PermissionsConfig {
+    pub eval: DenoPermissions,
+    pub run: DenoPermissions,
+    pub repl: DenoPermissions,
+}
+
+impl PermissionsConfig {
+    /// Defaults for an execution origin (`eval`, `run`, `repl`); other origins get none.
+    pub fn for_origin(&self, origin: &str) -> DenoPermissions {
+        match origin {
+            "eval" => self.eval.clone(),
+            "run" => self.run.clone(),
+            "repl" => self.repl.clone(),
+            _ => DenoPermissions::default(),
         }
     }
 }
@@ -238,9 +773,135 @@ impl Default for ProtectConfig {
     }
 }
 
-/// Top-level Beeno configuration loaded from defaults/files/env/CLI.
+/// Prompt wording sent to providers.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
+pub struct PromptsConfig {
+    /// Replaces each provider's default prompt; supports `{mode}`, `{input}`,
+    /// `{summary}`, `{file}`, and `{language}` placeholders.
+    pub template: Option<String>,
+    /// Cap on the rendered `{summary}` context, in chars (`0` = no limit).
+    pub max_context_chars: usize,
+}
+
+impl Default for PromptsConfig {
+    fn default() -> Self {
+        Self {
+            template: None,
+            max_context_chars: 8000,
+        }
+    }
+}
+
+/// Settings applied to every spawned Deno process.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RuntimeConfig {
+    /// Variables set on executed code; `$NAME`/`${NAME}` expand from Beeno's environment.
+    pub env: BTreeMap<String, String>,
+    /// `deno.json` passed as `--config`, so import maps and compiler options apply.
+    pub deno_config: Option<String>,
+    /// Import map passed as `--import-map`.
+    pub import_map: Option<String>,
+    /// Run `deno check` on `run` output and refuse to execute it on type errors.
+    pub type_check_before_run: bool,
+    /// Run Deno with `--no-remote`, refusing imports that are not local files.
+    pub no_remote: bool,
+    /// Run Deno with `--cached-only`, refusing remote modules missing from its cache.
+    pub cached_only: bool,
+    /// Code prepended to every program prepared from a prompt or snippet
+    /// (not `run` scripts); it passes through the policy with the program.
+    pub prelude: Option<String>,
+}
+
+impl RuntimeConfig {
+    /// Returns `env` with parent-environment references expanded via `env_get`.
+    pub fn resolved_env<F>(&self, env_get: F) -> BTreeMap<String, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        self.env
+            .iter()
+            .map(|(key, value)| (key.clone(), expand_env_refs(value, &env_get)))
+            .collect()
+    }
+}
+
+/// Parses a `KEY=VALUE` environment assignment; the key must not be empty.
+pub fn parse_env_assignment(raw: &str) -> Result<(String, String), String> {
+    match raw.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got `{raw}`")),
+    }
+}
+
+/// Expands `$NAME` and `${NAME}` references; unset variables expand to an empty string.
+///
+/// A name starts with a letter or `_`, so text like `$5` is left as written.
+pub fn expand_env_refs<F>(value: &str, env_get: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let (name, consumed) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+        if !is_name {
+            out.push('$');
+            rest = after;
+        } else {
+            out.push_str(&env_get(name).unwrap_or_default());
+            rest = &after[consumed..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Provider identifiers accepted by `llm.provider`.
+pub const KNOWN_PROVIDERS: &[&str] = &[
+    "http",
+    "mock",
+    "ollama",
+    "chatgpt",
+    "openrouter",
+    "openai_compat",
+    "azure",
+    "command",
+    "fallback",
+];
+
+/// Splits an `llm.fallback` entry into its provider and optional model override.
+///
+/// The split happens at the first `:`, so Ollama tags such as `ollama:llama3:8b` keep
+/// their model name intact.
+pub fn fallback_entry(entry: &str) -> (&str, Option<&str>) {
+    match entry.trim().split_once(':') {
+        Some((provider, model)) if !model.is_empty() => (provider, Some(model)),
+        Some((provider, _)) => (provider, None),
+        None => (entry.trim(), None),
+    }
+}
+
+/// Top-level Beeno configuration loaded from defaults/files/env/CLI.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct AppConfig {
     pub llm: LlmConfig,
     pub policy: PolicySettings,
@@ -250,20 +911,122 @@ pub struct AppConfig {
     pub protect: ProtectConfig,
     pub repl: ReplConfig,
     pub timeouts: TimeoutConfig,
+    pub runtime: RuntimeConfig,
+    pub prompts: PromptsConfig,
+    pub permissions: PermissionsConfig,
+    /// Refuse anything that would reach beyond this machine: non-loopback providers,
+    /// remote policy fetches, and opening a browser.
+    pub offline: bool,
+    /// Set by `--quiet` / `--verbose`; gates banners, warnings, and timing output.
+    #[serde(skip)]
+    pub verbosity: Verbosity,
 }
 
-impl Default for AppConfig {
-    fn default() -> Self {
-        Self {
-            llm: LlmConfig::default(),
-            policy: PolicySettings::default(),
-            self_heal: SelfHealConfig::default(),
-            artifacts: ArtifactConfig::default(),
-            limits: LimitsConfig::default(),
-            protect: ProtectConfig::default(),
-            repl: ReplConfig::default(),
-            timeouts: TimeoutConfig::default(),
+impl AppConfig {
+    /// Checks value ranges and names, returning every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        if !(0.0..=2.0).contains(&self.llm.temperature) {
+            problems.push(format!(
+                "llm.temperature must be between 0 and 2 (got {})",
+                self.llm.temperature
+            ));
         }
+        if self.llm.max_tokens == 0 {
+            problems.push("llm.max_tokens must be greater than 0".to_string());
+        }
+        let provider = self.llm.provider.to_ascii_lowercase();
+        if !KNOWN_PROVIDERS.contains(&provider.as_str()) {
+            problems.push(format!(
+                "llm.provider `{}` is not supported; expected one of: {}",
+                self.llm.provider,
+                KNOWN_PROVIDERS.join(", ")
+            ));
+        }
+        if provider == "fallback" {
+            if self.llm.fallback.is_empty() {
+                problems.push(
+                    "llm.fallback must list at least one provider when llm.provider is `fallback`"
+                        .to_string(),
+                );
+            }
+            for entry in &self.llm.fallback {
+                let name = fallback_entry(entry).0.to_ascii_lowercase();
+                if name == "fallback" || !KNOWN_PROVIDERS.contains(&name.as_str()) {
+                    problems.push(format!(
+                        "llm.fallback entry `{entry}` does not name a supported provider"
+                    ));
+                }
+            }
+        }
+        let uses = |name: &str| {
+            provider == name
+                || (provider == "fallback"
+                    && self
+                        .llm
+                        .fallback
+                        .iter()
+                        .any(|entry| fallback_entry(entry).0.eq_ignore_ascii_case(name)))
+        };
+        if uses("command") && self.llm.command.command.trim().is_empty() {
+            problems.push(
+                "llm.command.command must name a program when using the `command` provider"
+                    .to_string(),
+            );
+        }
+        if uses("azure")
+            && self
+                .llm
+                .deployment
+                .as_deref()
+                .is_none_or(|d| d.trim().is_empty())
+        {
+            problems.push(
+                "llm.deployment must name the Azure OpenAI deployment when using the `azure` provider"
+                    .to_string(),
+            );
+        }
+        if self.repl.summary_window == 0 {
+            problems.push("repl.summary_window must be greater than 0".to_string());
+        }
+        if self.self_heal.max_attempts == 0 {
+            problems.push("self_heal.max_attempts must be at least 1".to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Serializes the config as TOML, leaving out unset options and empty tables.
+    pub fn to_toml_string(&self) -> Result<String, toml::ser::Error> {
+        let mut value = toml::Value::try_from(self)?;
+        tidy_toml(&mut value);
+        toml::to_string(&value)
+    }
+}
+
+/// Drops empty tables and trims `f32` fields back to their shortest form, since
+/// widening to `f64` during serialization turns `0.1` into `0.10000000149011612`.
+///
+/// Only floats an `f32` holds exactly are trimmed; any other value is already the
+/// `f64` it stands for and keeps its shortest round-trip form.
+fn tidy_toml(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            table.values_mut().for_each(tidy_toml);
+            table.retain(|_, v| !matches!(v, toml::Value::Table(t) if t.is_empty()));
+        }
+        toml::Value::Float(f) => {
+            let narrow = *f as f32;
+            if f64::from(narrow) == *f {
+                if let Ok(short) = narrow.to_string().parse() {
+                    *f = short;
+                }
+            }
+        }
+        _ => {}
     }
 }
 
@@ -271,17 +1034,215 @@ impl Default for AppConfig {
 mod tests {
     use super::*;
 
+    #[test]
+    fn verbosity_follows_quiet_and_verbose_flags() {
+        assert_eq!(Verbosity::from_flags(false, false), Verbosity::Normal);
+        assert_eq!(Verbosity::from_flags(true, true), Verbosity::Quiet);
+        assert!(!Verbosity::Quiet.chatty());
+        assert!(Verbosity::from_flags(false, true).is_verbose());
+        assert!(Verbosity::Verbose.chatty() && !Verbosity::Normal.is_verbose());
+    }
+
+    #[test]
+    fn model_aliases_expand_and_unknown_names_pass_through() {
+        let cfg: LlmConfig = toml::from_str(
+            "[aliases]\nfast = \"gpt-4.1-mini\"\nsonnet = \"anthropic/claude-3-5-sonnet\"\n",
+        )
+        .expect("parse");
+        assert_eq!(cfg.resolve_model("fast"), "gpt-4.1-mini");
+        assert_eq!(cfg.resolve_model("sonnet"), "anthropic/claude-3-5-sonnet");
+        assert_eq!(cfg.resolve_model("llama3:8b"), "llama3:8b");
+    }
+
+    #[test]
+    fn fallback_provider_requires_known_entries() {
+        let mut cfg = AppConfig::default();
+        cfg.llm.provider = "fallback".to_string();
+        assert_eq!(cfg.validate().expect_err("empty chain").len(), 1);
+
+        cfg.llm.fallback = vec!["chatgpt".to_string(), "ollama:llama3:8b".to_string()];
+        assert!(cfg.validate().is_ok());
+        assert_eq!(
+            fallback_entry("ollama:llama3:8b"),
+            ("ollama", Some("llama3:8b"))
+        );
+
+        cfg.llm.fallback.push("fallback".to_string());
+        assert_eq!(cfg.validate().expect_err("nested chain").len(), 1);
+    }
+
+    #[test]
+    fn azure_provider_requires_a_deployment() {
+        let mut cfg = AppConfig::default();
+        cfg.llm.provider = "azure".to_string();
+        let problems = cfg.validate().expect_err("missing deployment");
+        assert!(problems[0].contains("llm.deployment"));
+
+        cfg.llm.deployment = Some("gpt4o-prod".to_string());
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn requests_without_trace_id_still_deserialize() {
+        let req: ExecutionRequest = serde_json::from_str(
+            r#"{"source":"1","deno_permissions":{"allow_read":[],"allow_write":[],"allow_net":[],"allow_env":false,"allow_run":false},"origin":"eval"}"#,
+        )
+        .expect("deserialize");
+        assert!(req.trace_id.is_none());
+        assert_ne!(new_trace_id(), new_trace_id());
+    }
+
+    #[test]
+    fn json_envelope_carries_schema_version() {
+        let envelope = JsonEnvelope::new("ok", "translate", "done", Value::Null);
+        let encoded = serde_json::to_value(&envelope).expect("serialize");
+        assert_eq!(encoded["schema_version"], JSON_SCHEMA_VERSION);
+
+        let legacy: JsonEnvelope =
+            serde_json::from_str(r#"{"status":"ok","phase":"execute","message":"m","details":{}}"#)
+                .expect("deserialize");
+        assert_eq!(legacy.schema_version, JSON_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn confirm_mode_falls_back_to_confirm_risky() {
+        let mut policy = PolicySettings {
+            confirm_risky: false,
+            ..PolicySettings::default()
+        };
+        assert_eq!(policy.effective_confirm_mode(), ConfirmMode::Never);
+        policy.confirm_mode = Some(ConfirmMode::NetOrWrite);
+        assert_eq!(policy.effective_confirm_mode(), ConfirmMode::NetOrWrite);
+
+        let parsed: PolicySettings =
+            toml::from_str("confirm_mode = \"net-or-write\"").expect("parse");
+        assert_eq!(parsed.confirm_mode, Some(ConfirmMode::NetOrWrite));
+        assert_eq!(
+            ConfirmMode::parse("Net_Or_Write"),
+            Some(ConfirmMode::NetOrWrite)
+        );
+        assert_eq!(ConfirmMode::parse("sometimes"), None);
+    }
+
+    #[test]
+    fn cli_permissions_add_to_origin_defaults() {
+        let cfg: AppConfig = toml::from_str(
+            r#"
+[permissions]
+eval = { allow_net = ["localhost"], allow_env = true }
+run = { allow_read = ["."] }
+"#,
+        )
+        .expect("parse");
+        let cli = DenoPermissions {
+            allow_net: vec!["api.example.com".to_string(), "localhost".to_string()],
+            allow_run: true,
+            ..DenoPermissions::default()
+        };
+        let merged = cfg.permissions.for_origin("eval").merged_with(cli.clone());
+        assert_eq!(merged.allow_net, vec!["localhost", "api.example.com"]);
+        assert!(merged.allow_env);
+        assert!(merged.allow_run);
+        assert!(merged.allow_read.is_empty());
+
+        assert_eq!(cfg.permissions.for_origin("run").allow_read, vec!["."]);
+        assert_eq!(
+            cfg.permissions.for_origin("repl"),
+            DenoPermissions::default()
+        );
+        assert_eq!(
+            PermissionsConfig::default()
+                .for_origin("eval")
+                .merged_with(cli.clone()),
+            cli
+        );
+    }
+
     #[test]
     fn app_config_defaults_are_stable() {
         let cfg = AppConfig::default();
         assert_eq!(cfg.llm.provider, "http");
         assert!(cfg.policy.confirm_risky);
+        assert_eq!(cfg.policy.effective_confirm_mode(), ConfirmMode::Risky);
         assert!(cfg.self_heal.auto_on_run_failure);
         assert_eq!(cfg.self_heal.max_attempts, 3);
         assert_eq!(cfg.artifacts.dir, ".beeno/suggestions");
         assert_eq!(cfg.artifacts.keep_last, 20);
     }
 
+    #[test]
+    fn default_config_validates() {
+        assert!(AppConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_temperature() {
+        let mut cfg = AppConfig::default();
+        cfg.llm.temperature = 5.0;
+        let problems = cfg.validate().expect_err("must reject");
+        assert!(problems[0].contains("llm.temperature"));
+    }
+
+    #[test]
+    fn validate_rejects_zero_max_tokens() {
+        let mut cfg = AppConfig::default();
+        cfg.llm.max_tokens = 0;
+        let problems = cfg.validate().expect_err("must reject");
+        assert!(problems[0].contains("llm.max_tokens"));
+    }
+
+    #[test]
+    fn validate_rejects_unknown_provider() {
+        let mut cfg = AppConfig::default();
+        cfg.llm.provider = "gpt4".to_string();
+        let problems = cfg.validate().expect_err("must reject");
+        assert!(problems[0].contains("`gpt4`"));
+
+        cfg.llm.provider = "Ollama".to_string();
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_zero_summary_window() {
+        let mut cfg = AppConfig::default();
+        cfg.repl.summary_window = 0;
+        let problems = cfg.validate().expect_err("must reject");
+        assert!(problems[0].contains("repl.summary_window"));
+    }
+
+    #[test]
+    fn validate_rejects_zero_heal_attempts() {
+        let mut cfg = AppConfig::default();
+        cfg.self_heal.max_attempts = 0;
+        let problems = cfg.validate().expect_err("must reject");
+        assert!(problems[0].contains("self_heal.max_attempts"));
+    }
+
+    #[test]
+    fn validate_reports_all_problems() {
+        let mut cfg = AppConfig::default();
+        cfg.llm.temperature = -1.0;
+        cfg.llm.max_tokens = 0;
+        cfg.llm.provider = "nope".to_string();
+        assert_eq!(cfg.validate().expect_err("must reject").len(), 3);
+    }
+
+    #[test]
+    fn permission_grants_toggle() {
+        let mut perms = DenoPermissions::default();
+        perms.set_grant("net", "on").expect("net on");
+        perms.set_grant("read", "./a, ./b").expect("read list");
+        perms.set_grant("env", "on").expect("env on");
+        assert_eq!(perms.allow_net, vec!["*"]);
+        assert_eq!(perms.allow_read, vec!["./a", "./b"]);
+        assert!(perms.allow_env);
+
+        perms.set_grant("net", "off").expect("net off");
+        assert!(perms.allow_net.is_empty());
+        assert!(perms.set_grant("env", "maybe").is_err());
+        assert!(perms.set_grant("ffi", "on").is_err());
+    }
+
     #[test]
     fn partial_toml_parses_with_defaults() {
         let raw = r#"
@@ -297,4 +1258,52 @@ mod tests {
         assert_eq!(cfg.artifacts.keep_last, 5);
         assert_eq!(cfg.artifacts.dir, ".beeno/suggestions");
     }
+
+    #[test]
+    fn default_config_serializes_to_stable_toml() {
+        let raw = AppConfig::default().to_toml_string().expect("serialize");
+        assert!(raw.contains("temperature = 0.1\n"));
+        assert!(raw.contains("[repl]") && raw.contains("[timeouts]"));
+        assert!(!raw.contains("[llm.aliases]") && !raw.contains("[llm.endpoints]"));
+
+        let parsed: AppConfig = toml::from_str(&raw).expect("must parse");
+        assert_eq!(parsed.to_toml_string().expect("serialize"), raw);
+    }
+
+    #[test]
+    fn tidy_toml_keeps_f64_precision() {
+        let mut value: toml::Value = toml::from_str(&format!(
+            "precise = 0.95000001\nwidened = {}\n",
+            f64::from(0.1f32)
+        ))
+        .expect("parse");
+        tidy_toml(&mut value);
+        assert_eq!(
+            toml::to_string(&value).expect("serialize"),
+            "precise = 0.95000001\nwidened = 0.1\n"
+        );
+    }
+
+    #[test]
+    fn runtime_env_expands_parent_references() {
+        let raw = r#"
+        [runtime.env]
+        API_BASE = "https://api.example.com"
+        TOKEN = "$PARENT_TOKEN"
+        URL = "${HOST}:8080/$MISSING"
+        "#;
+        let cfg: AppConfig = toml::from_str(raw).expect("must parse");
+        let env = cfg.runtime.resolved_env(|k| match k {
+            "PARENT_TOKEN" => Some("secret".to_string()),
+            "HOST" => Some("localhost".to_string()),
+            _ => None,
+        });
+        assert_eq!(env["API_BASE"], "https://api.example.com");
+        assert_eq!(env["TOKEN"], "secret");
+        assert_eq!(env["URL"], "localhost:8080/");
+        assert_eq!(
+            expand_env_refs("cost: $5 and ${9} and $", |_| None),
+            "cost: $5 and ${9} and $"
+        );
+    }
 }
@@ -0,0 +1,239 @@
+//! WebAssembly policy plugins (`policy_path = "org-policy.wasm"`).
+//!
+//! A plugin is a core WebAssembly module with no imports that exports:
+//!
+//! - `memory`: its linear memory;
+//! - `alloc(len: i32) -> i32`: room for `len` bytes, where the source is written as UTF-8;
+//! - `analyze(ptr: i32, len: i32) -> i32`: the verdict, `0` safe, `1` risky, `2` blocked;
+//! - optionally `reasons() -> i64`: a newline-separated UTF-8 list of reasons for the
+//!   last verdict, as `ptr << 32 | len`.
+//!
+//! Every analysis runs in a fresh instance with a fuel budget, so plugins keep no
+//! state between calls and cannot loop forever. A plugin that traps, runs out of
+//! fuel, or answers anything else blocks the code: policies fail closed.
+//!
+//! Plugins add rules rather than replace them: the source is also checked by a
+//! base [`DefaultRiskPolicy`], and the more severe of the two verdicts wins.
+
+use crate::engine::{DefaultRiskPolicy, MediaType, RiskPolicy};
+use crate::types::{PolicySource, RiskLevel, RiskReport};
+use async_trait::async_trait;
+use std::path::Path;
+use wasmi::{Config, Engine, Instance, Linker, Memory, Module, Store};
+
+/// Instructions one analysis may execute before it is stopped.
+const FUEL: u64 = 100_000_000;
+
+/// A [`RiskPolicy`] implemented by a WebAssembly module on top of a
+/// [`DefaultRiskPolicy`].
+pub struct WasmPolicy {
+    engine: Engine,
+    module: Module,
+    source: PolicySource,
+    base: DefaultRiskPolicy,
+}
+
+impl WasmPolicy {
+    /// Compiles the module at `path` and checks that it has the plugin exports.
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes, PolicySource::File(path.display().to_string())).map_err(|e| {
+            e.context(format!(
+                "policy {} is not a usable WebAssembly policy",
+                path.display()
+            ))
+        })
+    }
+
+    fn from_bytes(bytes: &[u8], source: PolicySource) -> anyhow::Result<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, bytes)?;
+        let policy = Self {
+            engine,
+            module,
+            source,
+            base: DefaultRiskPolicy::default(),
+        };
+        let (store, instance) = policy.instantiate()?;
+        plugin_memory(&store, &instance)?;
+        for (name, found) in [
+            (
+                "alloc",
+                instance.get_typed_func::<i32, i32>(&store, "alloc").is_ok(),
+            ),
+            (
+                "analyze",
+                instance
+                    .get_typed_func::<(i32, i32), i32>(&store, "analyze")
+                    .is_ok(),
+            ),
+        ] {
+            if !found {
+                anyhow::bail!("missing or mistyped `{name}` export");
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Replaces the built-in [`DefaultRiskPolicy`] the plugin runs on top of.
+    pub fn with_base(mut self, base: DefaultRiskPolicy) -> Self {
+        self.base = base;
+        self
+    }
+
+    /// Where the module was loaded from.
+    pub fn source(&self) -> &PolicySource {
+        &self.source
+    }
+
+    fn instantiate(&self) -> anyhow::Result<(Store<()>, Instance)> {
+        let mut store = Store::new(&self.engine, ());
+        store.set_fuel(FUEL)?;
+        let linker = Linker::<()>::new(&self.engine);
+        let instance = linker
+            .instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+        Ok((store, instance))
+    }
+
+    fn run(&self, source: &str) -> anyhow::Result<(RiskLevel, Vec<String>)> {
+        let (mut store, instance) = self.instantiate()?;
+        let memory = plugin_memory(&store, &instance)?;
+        let len = i32::try_from(source.len())?;
+        let ptr = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")?
+            .call(&mut store, len)?;
+        memory.write(&mut store, usize::try_from(ptr)?, source.as_bytes())?;
+        let verdict = instance
+            .get_typed_func::<(i32, i32), i32>(&store, "analyze")?
+            .call(&mut store, (ptr, len))?;
+        let level = match verdict {
+            0 => RiskLevel::Safe,
+            1 => RiskLevel::Risky,
+            2 => RiskLevel::Blocked,
+            other => anyhow::bail!("analyze returned unknown verdict {other}"),
+        };
+        let mut reasons = Vec::new();
+        if let Ok(export) = instance.get_typed_func::<(), i64>(&store, "reasons") {
+            let packed = export.call(&mut store, ())? as u64;
+            let mut text = vec![0u8; (packed & 0xffff_ffff) as usize];
+            memory.read(&store, (packed >> 32) as usize, &mut text)?;
+            reasons = String::from_utf8_lossy(&text)
+                .lines()
+                .map(str::trim)
+                .filter(|reason| !reason.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if reasons.is_empty() && level != RiskLevel::Safe {
+            reasons.push(format!("flagged by wasm policy ({})", self.source));
+        }
+        Ok((level, reasons))
+    }
+}
+
+fn plugin_memory(store: &Store<()>, instance: &Instance) -> anyhow::Result<Memory> {
+    instance
+        .get_memory(store, "memory")
+        .ok_or_else(|| anyhow::anyhow!("missing `memory` export"))
+}
+
+#[async_trait]
+impl RiskPolicy for WasmPolicy {
+    async fn analyze(&self, source: &str) -> RiskReport {
+        self.analyze_with_media(source, MediaType::TypeScript).await
+    }
+
+    async fn analyze_with_media(&self, source: &str, media_type: MediaType) -> RiskReport {
+        let mut report = self.base.analyze_with_media(source, media_type).await;
+        let (level, reasons) = match self.run(source) {
+            Ok(verdict) => verdict,
+            Err(e) => (RiskLevel::Blocked, vec![format!("wasm policy failed: {e}")]),
+        };
+        // Confirmation is only asked for risky code, and then whenever either
+        // side wants it; `policy.allow_risky` only waives the base's reasons.
+        report.requires_confirmation = report.level.max(level) == RiskLevel::Risky
+            && (report.requires_confirmation || level == RiskLevel::Risky);
+        report.level = report.level.max(level);
+        report
+            .sources
+            .extend(std::iter::repeat(self.source.clone()).take(reasons.len()));
+        report.reasons.extend(reasons);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Blocks source containing `X`, otherwise reports it safe.
+    const BLOCK_X: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "contains X")
+          (func (export "alloc") (param i32) (result i32) i32.const 64)
+          (func (export "analyze") (param $ptr i32) (param $len i32) (result i32)
+            (local $i i32)
+            (block $done
+              (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+                (if (i32.eq (i32.load8_u (i32.add (local.get $ptr) (local.get $i))) (i32.const 88))
+                  (then (return (i32.const 2))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+            i32.const 0)
+          (func (export "reasons") (result i64) i64.const 10))
+    "#;
+
+    #[tokio::test]
+    async fn wasm_module_decides_the_verdict() {
+        let wasm = wat::parse_str(BLOCK_X).expect("valid wat");
+        let source = PolicySource::File("block-x.wasm".to_string());
+        let policy = WasmPolicy::from_bytes(&wasm, source.clone()).expect("load plugin");
+
+        let safe = policy.analyze("console.log(1);").await;
+        assert_eq!(safe.level, RiskLevel::Safe);
+        assert!(safe.reasons.is_empty());
+
+        let blocked = policy.analyze("const X = 1;").await;
+        assert_eq!(blocked.level, RiskLevel::Blocked);
+        assert_eq!(blocked.reasons, vec!["contains X"]);
+        assert_eq!(blocked.sources, vec![source]);
+    }
+
+    #[tokio::test]
+    async fn plugins_run_on_top_of_the_default_policy() {
+        let wasm = wat::parse_str(BLOCK_X).expect("valid wat");
+        let policy = WasmPolicy::from_bytes(&wasm, PolicySource::Default)
+            .expect("load plugin")
+            .with_base(DefaultRiskPolicy::default().with_allow_risky(vec!["eval(".to_string()]));
+
+        let blocked = policy.analyze("new Deno.Command('ls');").await;
+        assert_eq!(blocked.level, RiskLevel::Blocked);
+        assert!(blocked
+            .reasons
+            .iter()
+            .any(|reason| reason.contains("Deno.Command")));
+
+        let allowed = policy.analyze("eval('1');").await;
+        assert_eq!(allowed.level, RiskLevel::Risky);
+        assert!(!allowed.requires_confirmation);
+
+        let both = policy.analyze("eval('X');").await;
+        assert_eq!(both.level, RiskLevel::Blocked);
+        assert_eq!(both.reasons.len(), both.sources.len());
+        assert!(!both.requires_confirmation);
+    }
+
+    #[test]
+    fn modules_without_the_plugin_exports_are_rejected() {
+        let wasm = wat::parse_str(r#"(module (memory (export "memory") 1))"#).expect("valid wat");
+        let err = WasmPolicy::from_bytes(&wasm, PolicySource::Default)
+            .map(|_| ())
+            .expect_err("missing exports");
+        assert!(err.to_string().contains("alloc"), "{err}");
+    }
+}